@@ -0,0 +1,546 @@
+//! acp 模式：通过标准输入输出上的 JSON-RPC（Agent Client Protocol）为编辑器插件提供对话引擎
+//!
+//! 每行一条 JSON-RPC 2.0 消息。客户端调用 initialize、session/new、session/prompt，
+//! 发送 session/cancel 通知；本端用 session/update 通知推送回答文本、工具调用和文件改动，
+//! 需要批准时向客户端发出 session/request_permission 请求。
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::engine::{ConversationOutcome, Engine, Event, EventSink};
+use crate::error::{self, ApiClient};
+use crate::jsonrpc::{
+    self, Connection, Handler, RpcError, INTERNAL_ERROR, INVALID_PARAMS, INVALID_REQUEST,
+    METHOD_NOT_FOUND,
+};
+use crate::permissions::ApprovalRequest;
+use crate::tool_stats::ToolStats;
+
+const PROTOCOL_VERSION: u64 = 1;
+
+/// 会修改文件的工具，执行前后比较文件内容，以 diff 形式发给客户端
+const EDIT_TOOLS: &[&str] = &["write_file", "append_file", "apply_patch"];
+
+struct Agent {
+    api_client: ApiClient,
+    config: Config,
+    tool_stats: ToolStats,
+    /// 每条消息最多的工具调用轮数
+    max_rounds: usize,
+    client: Connection,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+}
+
+#[derive(Default)]
+struct Session {
+    /// 进行中的一轮对话持有这把锁
+    messages: Arc<tokio::sync::Mutex<Vec<Value>>>,
+    /// 取消进行中的一轮对话
+    cancel: Mutex<Option<CancellationToken>>,
+}
+
+/// 处理标准输入上的消息，直到客户端关闭输入
+pub async fn run(
+    output: Box<dyn Write + Send>,
+    api_client: ApiClient,
+    config: Config,
+    max_turns: usize,
+) -> Result<()> {
+    info!("Agent Client Protocol server ready on stdio");
+    jsonrpc::run(output, |client| Agent {
+        api_client,
+        config,
+        tool_stats: ToolStats::default(),
+        max_rounds: max_turns.saturating_sub(1),
+        client,
+        sessions: Mutex::default(),
+    })
+    .await
+}
+
+impl Handler for Agent {
+    fn connection(&self) -> &Connection {
+        &self.client
+    }
+
+    async fn request(self: Arc<Self>, method: String, params: Value) -> Result<Value, RpcError> {
+        match method.as_str() {
+            "initialize" => Ok(json!({
+                "protocolVersion": PROTOCOL_VERSION,
+                "agentCapabilities": {
+                    "loadSession": false,
+                    "promptCapabilities": { "image": false, "audio": false, "embeddedContext": true }
+                },
+                "authMethods": []
+            })),
+            // API key 来自本地配置，不需要客户端认证
+            "authenticate" => Ok(json!({})),
+            "session/new" => {
+                // 工具相对于进程的工作目录执行，客户端应在项目目录中启动本程序
+                if let Some(cwd) = params["cwd"].as_str() {
+                    let current = std::env::current_dir().unwrap_or_default();
+                    if Path::new(cwd) != current {
+                        warn!(
+                            "Client asked for {} but tools run in {}",
+                            cwd,
+                            current.display()
+                        );
+                    }
+                }
+                let id = Uuid::new_v4().to_string();
+                self.sessions
+                    .lock()
+                    .unwrap()
+                    .insert(id.clone(), Arc::default());
+                info!("Created session {}", id);
+                Ok(json!({ "sessionId": id }))
+            }
+            "session/prompt" => prompt(&self, &params).await,
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            )),
+        }
+    }
+
+    fn notification(&self, method: &str, params: &Value) {
+        if method != "session/cancel" {
+            info!("Ignoring notification {}", method);
+            return;
+        }
+        let session = params["sessionId"]
+            .as_str()
+            .and_then(|id| self.sessions.lock().unwrap().get(id).cloned());
+        if let Some(cancel) = session.and_then(|session| session.cancel.lock().unwrap().take()) {
+            cancel.cancel();
+        }
+    }
+}
+
+/// 运行一轮对话，过程通过 session/update 推送，结束时返回停止原因
+async fn prompt(agent: &Agent, params: &Value) -> Result<Value, RpcError> {
+    let session_id = params["sessionId"]
+        .as_str()
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing sessionId"))?;
+    let session = agent
+        .sessions
+        .lock()
+        .unwrap()
+        .get(session_id)
+        .cloned()
+        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "no session with this id"))?;
+    let text = prompt_text(&params["prompt"]);
+    if text.trim().is_empty() {
+        return Err(RpcError::new(INVALID_PARAMS, "empty prompt"));
+    }
+    let Ok(mut messages) = Arc::clone(&session.messages).try_lock_owned() else {
+        return Err(RpcError::new(
+            INVALID_REQUEST,
+            "a prompt is already running in this session",
+        ));
+    };
+    let cancel = CancellationToken::new();
+    *session.cancel.lock().unwrap() = Some(cancel.clone());
+
+    let updates = Updates::new(&agent.client, session_id);
+    let engine = Engine {
+        api_client: &agent.api_client,
+        config: &agent.config,
+        tool_stats: &agent.tool_stats,
+        events: &updates,
+        cancel: &cancel,
+        read_only: false,
+        fail_on_denial: false,
+    };
+    let before = messages.len();
+    let result = engine
+        .run_turn(&mut messages, json!(text), Some(agent.max_rounds))
+        .await;
+    session.cancel.lock().unwrap().take();
+
+    let stop_reason = match result {
+        Ok(ConversationOutcome::Completed) => "end_turn",
+        Ok(ConversationOutcome::TurnLimitReached) => "max_turn_requests",
+        // 已完成的工具调用保留在会话中，未完成的由引擎补上取消结果
+        Ok(ConversationOutcome::Cancelled) => "cancelled",
+        Err(e) => {
+            // 失败的一轮不留在会话中，客户端可以直接重新发送
+            messages.truncate(before);
+            warn!("Prompt failed in session {}: {:#}", session_id, e);
+            return Err(RpcError::new(INTERNAL_ERROR, format!("{:#}", e))
+                .with_data(json!({ "kind": error::kind(&e) })));
+        }
+    };
+    Ok(json!({ "stopReason": stop_reason }))
+}
+
+/// 把提示中的内容块合成一条文本；编辑器附带的文件内容放在代码块中
+fn prompt_text(blocks: &Value) -> String {
+    let mut parts = Vec::new();
+    for block in blocks.as_array().into_iter().flatten() {
+        match block["type"].as_str() {
+            Some("text") => parts.push(block["text"].as_str().unwrap_or_default().to_string()),
+            Some("resource") => {
+                let resource = &block["resource"];
+                if let Some(text) = resource["text"].as_str() {
+                    parts.push(format!(
+                        "{}:\n```\n{}\n```",
+                        resource["uri"].as_str().unwrap_or_default(),
+                        text
+                    ));
+                }
+            }
+            Some("resource_link") => {
+                parts.push(block["uri"].as_str().unwrap_or_default().to_string())
+            }
+            other => warn!("Ignoring unsupported prompt content {:?}", other),
+        }
+    }
+    parts.join("\n\n")
+}
+
+/// 编辑类工具执行前的文件内容，None 表示文件不存在
+struct Snapshot {
+    path: PathBuf,
+    before: Option<String>,
+}
+
+/// 一轮对话的事件接收方，把事件转成 session/update 通知
+struct Updates<'a> {
+    client: &'a Connection,
+    session_id: &'a str,
+    /// 正在执行的工具调用，批准请求需要它的 id
+    running: Mutex<Option<String>>,
+    snapshots: Mutex<HashMap<String, Snapshot>>,
+}
+
+impl<'a> Updates<'a> {
+    fn new(client: &'a Connection, session_id: &'a str) -> Self {
+        Self {
+            client,
+            session_id,
+            running: Mutex::default(),
+            snapshots: Mutex::default(),
+        }
+    }
+
+    fn update(&self, update: Value) {
+        self.client.notify(
+            "session/update",
+            json!({ "sessionId": self.session_id, "update": update }),
+        );
+    }
+}
+
+/// 客户端用来选择图标和展示方式的工具类别
+fn tool_kind(name: &str) -> &'static str {
+    match name {
+        "read_file" => "read",
+        "write_file" | "append_file" | "apply_patch" => "edit",
+        "execute_command" | "run_tests" | "coverage" | "cargo_check" | "cargo_add"
+        | "audit_dependencies" => "execute",
+        "cargo_metadata" => "read",
+        "list_files" | "search_files" => "search",
+        _ => "other",
+    }
+}
+
+/// 工具涉及的文件，协议要求绝对路径
+fn tool_path(input: &Value) -> Option<PathBuf> {
+    let path = Path::new(input["file_path"].as_str().or(input["path"].as_str())?);
+    Some(std::env::current_dir().unwrap_or_default().join(path))
+}
+
+fn tool_title(name: &str, input: &Value) -> String {
+    if let Some(command) = input["command"].as_str() {
+        return command.to_string();
+    }
+    match input["file_path"].as_str() {
+        Some(path) => format!("{} {}", name, path),
+        None => name.to_string(),
+    }
+}
+
+impl EventSink for Updates<'_> {
+    fn emit(&self, event: Event) {
+        match event {
+            Event::Text { text, footnotes } => {
+                let mut text = text;
+                for (index, footnote) in footnotes.iter().enumerate() {
+                    text.push_str(&format!("\n[{}] {}", index + 1, footnote));
+                }
+                self.update(json!({
+                    "sessionUpdate": "agent_message_chunk",
+                    "content": { "type": "text", "text": text }
+                }));
+            }
+            Event::ToolUse { id, name, input } => {
+                let path = tool_path(&input);
+                if let Some(path) = path.clone().filter(|_| EDIT_TOOLS.contains(&name.as_str())) {
+                    let before = std::fs::read_to_string(&path).ok();
+                    self.snapshots
+                        .lock()
+                        .unwrap()
+                        .insert(id.clone(), Snapshot { path, before });
+                }
+                self.update(json!({
+                    "sessionUpdate": "tool_call",
+                    "toolCallId": id,
+                    "title": tool_title(&name, &input),
+                    "kind": tool_kind(&name),
+                    "status": "pending",
+                    "locations": path.map(|path| vec![json!({ "path": path })]).unwrap_or_default(),
+                    "rawInput": input
+                }));
+            }
+            Event::ToolStarted { id, .. } => {
+                *self.running.lock().unwrap() = Some(id.clone());
+                self.update(json!({
+                    "sessionUpdate": "tool_call_update",
+                    "toolCallId": id,
+                    "status": "in_progress"
+                }));
+            }
+            Event::ToolFinished { id, ok, output, .. } => {
+                self.running.lock().unwrap().take();
+                let mut content = Vec::new();
+                // 快照在工具执行前读取，执行后读取新内容，有变化时作为 diff 发送
+                if let Some(snapshot) = self.snapshots.lock().unwrap().remove(&id) {
+                    let after = std::fs::read_to_string(&snapshot.path).ok();
+                    if let Some(after) = after.filter(|after| Some(after) != snapshot.before.as_ref()) {
+                        content.push(json!({
+                            "type": "diff",
+                            "path": snapshot.path,
+                            "oldText": snapshot.before,
+                            "newText": after
+                        }));
+                    }
+                }
+                content.push(json!({
+                    "type": "content",
+                    "content": { "type": "text", "text": output }
+                }));
+                self.update(json!({
+                    "sessionUpdate": "tool_call_update",
+                    "toolCallId": id,
+                    "status": if ok { "completed" } else { "failed" },
+                    "content": content
+                }));
+            }
+            Event::ContentFlagged { tool, findings } => self.update(json!({
+                "sessionUpdate": "agent_thought_chunk",
+                "content": {
+                    "type": "text",
+                    "text": format!("The output of {} contains text that looks like instructions: {}", tool, findings)
+                }
+            })),
+            Event::ContextSummarized {
+                summarized_messages,
+                ..
+            } => self.update(json!({
+                "sessionUpdate": "agent_thought_chunk",
+                "content": {
+                    "type": "text",
+                    "text": format!("Summarized {} earlier messages to stay within the context window.", summarized_messages)
+                }
+            })),
+            Event::ToolResultsElided { count, .. } => self.update(json!({
+                "sessionUpdate": "agent_thought_chunk",
+                "content": {
+                    "type": "text",
+                    "text": format!("Replaced {} earlier tool outputs with short summaries to stay within the context window.", count)
+                }
+            })),
+            Event::Waiting
+            | Event::Responded
+            | Event::ResponseTruncated
+            | Event::PermissionRequest { .. }
+            | Event::TurnComplete { .. }
+            | Event::Error { .. } => {}
+        }
+    }
+
+    /// 通过 session/request_permission 询问客户端；客户端取消或断开时视为拒绝
+    fn approve<'a>(
+        &'a self,
+        tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        let tool_call_id = self.running.lock().unwrap().clone().unwrap_or_default();
+        let title = match request {
+            ApprovalRequest::Command {
+                command,
+                reason: None,
+            } => command.clone(),
+            ApprovalRequest::Command {
+                command,
+                reason: Some(reason),
+            } => format!("{} ({})", command, reason),
+            ApprovalRequest::OutsideWorkspace(path) => {
+                format!("{} outside the workspace: {}", tool, path.display())
+            }
+            ApprovalRequest::ProtectedFile { path, reason: None } => {
+                format!("{} {}", tool, path.display())
+            }
+            ApprovalRequest::ProtectedFile {
+                path,
+                reason: Some(reason),
+            } => format!("{} {} ({})", tool, path.display(), reason),
+            ApprovalRequest::SuspiciousContent(findings) => {
+                format!(
+                    "Add {} output with suspicious instructions: {}",
+                    tool, findings
+                )
+            }
+        };
+        let params = json!({
+            "sessionId": self.session_id,
+            "toolCall": { "toolCallId": tool_call_id, "title": title, "kind": tool_kind(tool) },
+            "options": [
+                { "optionId": "allow", "name": "Allow", "kind": "allow_once" },
+                { "optionId": "reject", "name": "Reject", "kind": "reject_once" }
+            ]
+        });
+        Box::pin(async move {
+            let Some(result) = self
+                .client
+                .request("session/request_permission", params)
+                .await
+            else {
+                return false;
+            };
+            let outcome = &result["outcome"];
+            outcome["outcome"] == "selected" && outcome["optionId"] == "allow"
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserSettings;
+    use crate::error::RetryConfig;
+    use crate::jsonrpc::{serve, PARSE_ERROR};
+    use tokio::sync::mpsc;
+
+    fn agent(api_url: String) -> (Arc<Agent>, mpsc::UnboundedReceiver<Value>) {
+        let config = Config {
+            user_settings: UserSettings::default(),
+            api_key: "test-key".to_string(),
+            api_base_url: api_url.clone(),
+            api_timeout_ms: 5_000,
+            retry: RetryConfig::default(),
+            anthropic_version: "2023-06-01".to_string(),
+            anthropic_beta: Vec::new(),
+            sources: HashMap::new(),
+            warnings: Vec::new(),
+            managed: Default::default(),
+        };
+        let (client, queue) = Connection::channel();
+        let agent = Arc::new(Agent {
+            api_client: ApiClient::new("test-key".to_string(), api_url),
+            config,
+            tool_stats: ToolStats::default(),
+            max_rounds: 3,
+            client,
+            sessions: Mutex::default(),
+        });
+        (agent, queue)
+    }
+
+    /// 等待某个 id 的响应，返回此前收到的所有消息
+    async fn until_response(queue: &mut mpsc::UnboundedReceiver<Value>, id: u64) -> Vec<Value> {
+        let mut received = Vec::new();
+        while let Some(message) = queue.recv().await {
+            let done = message["id"] == id && message.get("method").is_none();
+            received.push(message);
+            if done {
+                break;
+            }
+        }
+        received
+    }
+
+    #[tokio::test]
+    async fn test_initialize_and_errors() {
+        let (agent, mut queue) = agent("http://127.0.0.1:9/v1/messages".to_string());
+        let input = concat!(
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":1}}"#,
+            "\nnot json\n",
+            r#"{"jsonrpc":"2.0","id":2,"method":"session/load","params":{}}"#,
+            "\n"
+        );
+        serve(input.as_bytes(), agent).await.unwrap();
+
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(queue.recv().await.unwrap());
+        }
+        let by_id = |id: Value| received.iter().find(|m| m["id"] == id).unwrap();
+        assert_eq!(
+            by_id(json!(1))["result"]["protocolVersion"],
+            PROTOCOL_VERSION
+        );
+        assert_eq!(by_id(Value::Null)["error"]["code"], PARSE_ERROR);
+        assert_eq!(by_id(json!(2))["error"]["code"], METHOD_NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_prompt_streams_updates() {
+        let mut api = mockito::Server::new_async().await;
+        api.mock("POST", "/v1/messages")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "pong"}], "stop_reason": "end_turn"}"#,
+            )
+            .create_async()
+            .await;
+        let (agent, mut queue) = agent(format!("{}/v1/messages", api.url()));
+
+        let new_session = r#"{"jsonrpc":"2.0","id":1,"method":"session/new","params":{"cwd":"/","mcpServers":[]}}"#;
+        serve(format!("{}\n", new_session).as_bytes(), Arc::clone(&agent))
+            .await
+            .unwrap();
+        let session =
+            until_response(&mut queue, 1).await.pop().unwrap()["result"]["sessionId"].clone();
+
+        let prompt = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "session/prompt",
+            "params": { "sessionId": session, "prompt": [{ "type": "text", "text": "ping" }] }
+        });
+        serve(format!("{}\n", prompt).as_bytes(), agent)
+            .await
+            .unwrap();
+        let received = until_response(&mut queue, 2).await;
+
+        assert_eq!(received[0]["method"], "session/update");
+        assert_eq!(
+            received[0]["params"]["update"]["sessionUpdate"],
+            "agent_message_chunk"
+        );
+        assert_eq!(received[0]["params"]["update"]["content"]["text"], "pong");
+        assert_eq!(received.last().unwrap()["result"]["stopReason"], "end_turn");
+    }
+
+    #[test]
+    fn test_prompt_text_includes_embedded_files() {
+        let text = prompt_text(&json!([
+            { "type": "text", "text": "explain" },
+            { "type": "resource", "resource": { "uri": "file:///a.rs", "text": "fn main() {}" } },
+            { "type": "image", "data": "" }
+        ]));
+        assert_eq!(text, "explain\n\nfile:///a.rs:\n```\nfn main() {}\n```");
+    }
+}
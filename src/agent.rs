@@ -0,0 +1,311 @@
+//! 供其他 Rust 程序嵌入的高层接口：用 builder 配置 agent，每次 run 返回一轮对话的事件流
+//!
+//! ```ignore
+//! let agent = Agent::builder()
+//!     .model("claude-sonnet-4-5")
+//!     .tools(["read_file", "search_files"])
+//!     .permission_handler(|tool: &str, _: &ApprovalRequest| tool != "execute_command")
+//!     .middleware(MyDlpScanner::new())
+//!     .build()?;
+//! let mut events = agent.run("Summarize src/main.rs");
+//! while let Some(event) = events.next().await { /* ... */ }
+//! ```
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::engine::{Engine, Event, EventSink};
+use crate::error::{self, ApiClient};
+use crate::middleware::{self, ToolMiddleware};
+use crate::permissions::ApprovalRequest;
+use crate::tool_stats::ToolStats;
+use crate::tools::ToolRegistry;
+
+/// 决定是否批准需要确认的工具调用
+pub trait PermissionHandler: Send + Sync {
+    fn approve(&self, tool: &str, request: &ApprovalRequest) -> bool;
+}
+
+impl<F> PermissionHandler for F
+where
+    F: Fn(&str, &ApprovalRequest) -> bool + Send + Sync,
+{
+    fn approve(&self, tool: &str, request: &ApprovalRequest) -> bool {
+        self(tool, request)
+    }
+}
+
+/// 没有设置处理方式时拒绝所有需要确认的调用
+struct DenyAll;
+
+impl PermissionHandler for DenyAll {
+    fn approve(&self, _tool: &str, _request: &ApprovalRequest) -> bool {
+        false
+    }
+}
+
+#[derive(Default)]
+pub struct AgentBuilder {
+    config: Option<Config>,
+    model: Option<String>,
+    tools: Option<Vec<String>>,
+    permission_handler: Option<Arc<dyn PermissionHandler>>,
+    max_turns: Option<usize>,
+}
+
+impl AgentBuilder {
+    /// 使用给定的配置，不设置时按命令行相同的规则加载
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    /// 只启用这些内置工具
+    pub fn tools<I, S>(mut self, tools: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tools = Some(tools.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn permission_handler(mut self, handler: impl PermissionHandler + 'static) -> Self {
+        self.permission_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// 在工具执行链中插入中间件；对本进程中之后的所有工具调用生效
+    pub fn middleware(self, middleware: impl ToolMiddleware + 'static) -> Self {
+        middleware::register(Arc::new(middleware));
+        self
+    }
+
+    /// 每次 run 最多的请求次数，与 `--max-turns` 相同
+    pub fn max_turns(mut self, max_turns: usize) -> Self {
+        self.max_turns = Some(max_turns);
+        self
+    }
+
+    pub fn build(self) -> Result<Agent> {
+        let mut config = match self.config {
+            Some(config) => config,
+            None => Config::load()?,
+        };
+        config.require_api_key()?;
+        if let Some(tools) = &self.tools {
+            let registry = ToolRegistry::builtin();
+            for name in registry.names() {
+                if !tools.iter().any(|tool| tool == name) {
+                    config.user_settings.disabled_tools.push(name.to_string());
+                }
+            }
+        }
+
+        let mut api_client = crate::api_client(&config);
+        if let Some(model) = self.model {
+            api_client.set_model(model);
+        }
+        crate::apply_request_settings(&mut api_client, &config);
+
+        Ok(Agent {
+            inner: Arc::new(Inner {
+                api_client,
+                config,
+                tool_stats: ToolStats::default(),
+                max_rounds: self.max_turns.map(|max| max.saturating_sub(1)),
+                permission_handler: self.permission_handler.unwrap_or(Arc::new(DenyAll)),
+                messages: tokio::sync::Mutex::default(),
+            }),
+        })
+    }
+}
+
+struct Inner {
+    api_client: ApiClient,
+    config: Config,
+    tool_stats: ToolStats,
+    max_rounds: Option<usize>,
+    permission_handler: Arc<dyn PermissionHandler>,
+    /// 多次 run 之间保留的对话
+    messages: tokio::sync::Mutex<Vec<Value>>,
+}
+
+/// 嵌入式的 agent；多次 run 属于同一段对话，依次执行
+#[derive(Clone)]
+pub struct Agent {
+    inner: Arc<Inner>,
+}
+
+impl Agent {
+    pub fn builder() -> AgentBuilder {
+        AgentBuilder::default()
+    }
+
+    /// 发送一条消息并在后台运行这一轮对话，最后一个事件是 TurnComplete 或 Error
+    pub fn run(&self, prompt: impl Into<String>) -> EventStream {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let inner = Arc::clone(&self.inner);
+        let prompt = prompt.into();
+        let cancel = CancellationToken::new();
+        let token = cancel.clone();
+        tokio::spawn(async move {
+            let sink = Sink {
+                sender,
+                permission_handler: Arc::clone(&inner.permission_handler),
+            };
+            let engine = Engine {
+                api_client: &inner.api_client,
+                config: &inner.config,
+                tool_stats: &inner.tool_stats,
+                events: &sink,
+                cancel: &token,
+                read_only: false,
+                fail_on_denial: false,
+            };
+            let mut messages = inner.messages.lock().await;
+            let before = messages.len();
+            let event = match engine
+                .run_turn(&mut messages, json!(prompt), inner.max_rounds)
+                .await
+            {
+                Ok(outcome) => Event::TurnComplete { outcome },
+                Err(e) => {
+                    // 失败的一轮不留在对话中
+                    messages.truncate(before);
+                    Event::Error {
+                        message: format!("{:#}", e),
+                        kind: error::kind(&e),
+                    }
+                }
+            };
+            sink.emit(event);
+        });
+        EventStream { receiver, cancel }
+    }
+
+    /// 目前为止的对话消息
+    pub async fn messages(&self) -> Vec<Value> {
+        self.inner.messages.lock().await.clone()
+    }
+}
+
+/// 一轮对话的事件，按发生顺序返回
+pub struct EventStream {
+    receiver: mpsc::UnboundedReceiver<Event>,
+    cancel: CancellationToken,
+}
+
+impl EventStream {
+    /// 下一个事件；这一轮结束后返回 None
+    pub async fn next(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+
+    /// 取消这一轮：放弃进行中的请求并终止正在运行的命令，最后一个事件是 TurnComplete(Cancelled)
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+}
+
+struct Sink {
+    sender: mpsc::UnboundedSender<Event>,
+    permission_handler: Arc<dyn PermissionHandler>,
+}
+
+impl EventSink for Sink {
+    fn emit(&self, event: Event) {
+        // 调用方丢弃事件流后继续运行，只是不再接收事件
+        let _ = self.sender.send(event);
+    }
+
+    fn approve<'a>(
+        &'a self,
+        tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(std::future::ready(
+            self.permission_handler.approve(tool, request),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserSettings;
+    use crate::engine::ConversationOutcome;
+    use crate::error::RetryConfig;
+    use std::collections::HashMap;
+
+    fn config(api_url: String) -> Config {
+        Config {
+            user_settings: UserSettings::default(),
+            api_key: "test-key".to_string(),
+            api_base_url: api_url,
+            api_timeout_ms: 5_000,
+            retry: RetryConfig::default(),
+            anthropic_version: "2023-06-01".to_string(),
+            anthropic_beta: Vec::new(),
+            sources: HashMap::new(),
+            warnings: Vec::new(),
+            managed: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_run_streams_events() {
+        let mut api = mockito::Server::new_async().await;
+        api.mock("POST", "/v1/messages")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "pong"}], "stop_reason": "end_turn"}"#,
+            )
+            .create_async()
+            .await;
+        let agent = Agent::builder()
+            .config(config(format!("{}/v1/messages", api.url())))
+            .model("claude-test")
+            .build()
+            .unwrap();
+
+        let mut events = agent.run("ping");
+        let mut received = Vec::new();
+        while let Some(event) = events.next().await {
+            received.push(event);
+        }
+        assert!(received
+            .iter()
+            .any(|event| matches!(event, Event::Text { text, .. } if text == "pong")));
+        assert!(matches!(
+            received.last(),
+            Some(Event::TurnComplete {
+                outcome: ConversationOutcome::Completed
+            })
+        ));
+        assert_eq!(agent.messages().await.len(), 2);
+    }
+
+    #[test]
+    fn test_tools_disables_the_rest() {
+        let agent = Agent::builder()
+            .config(config("http://127.0.0.1:9/v1/messages".to_string()))
+            .tools(["read_file"])
+            .build()
+            .unwrap();
+        let disabled = &agent.inner.config.user_settings.disabled_tools;
+        assert!(disabled.iter().any(|tool| tool == "execute_command"));
+        assert!(!disabled.iter().any(|tool| tool == "read_file"));
+    }
+}
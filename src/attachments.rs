@@ -0,0 +1,243 @@
+use anyhow::{bail, Context, Result};
+use base64::Engine;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+
+/// API 允许的单个 PDF 最大字节数
+pub const MAX_PDF_BYTES: u64 = 32 * 1024 * 1024;
+
+/// API 允许的单个 PDF 最大页数
+pub const MAX_PDF_PAGES: usize = 100;
+
+/// 超过该字节数的粘贴内容作为单独的内容块发送
+pub const LARGE_PASTE_BYTES: usize = 2_048;
+
+/// 超过该行数的粘贴内容作为单独的内容块发送
+pub const LARGE_PASTE_LINES: usize = 40;
+
+/// 随下一条用户消息发送的 PDF 附件
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub title: String,
+    pub pages: usize,
+    pub size: u64,
+    /// base64 编码后的文件内容
+    data: String,
+}
+
+impl Attachment {
+    /// 读取并校验 PDF，超出大小或页数限制时返回错误
+    pub fn load_pdf(path: &Path) -> Result<Self> {
+        let is_pdf = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("pdf"));
+        if !is_pdf {
+            bail!("Only PDF attachments are supported: {}", path.display());
+        }
+
+        let size = fs::metadata(path)
+            .with_context(|| format!("Failed to read attachment: {}", path.display()))?
+            .len();
+        if size > MAX_PDF_BYTES {
+            bail!(
+                "{} is {:.1} MB, larger than the {} MB limit for PDF attachments",
+                path.display(),
+                size as f64 / (1024.0 * 1024.0),
+                MAX_PDF_BYTES / (1024 * 1024)
+            );
+        }
+
+        let bytes = fs::read(path)
+            .with_context(|| format!("Failed to read attachment: {}", path.display()))?;
+        let pages = count_pages(&bytes)
+            .with_context(|| format!("Not a readable PDF file: {}", path.display()))?;
+        if pages > MAX_PDF_PAGES {
+            bail!(
+                "{} has {} pages, more than the {} page limit for PDF attachments",
+                path.display(),
+                pages,
+                MAX_PDF_PAGES
+            );
+        }
+
+        let title = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string());
+
+        Ok(Self {
+            title,
+            pages,
+            size,
+            data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+        })
+    }
+
+    /// 转换为 API 的 document 内容块，并开启引用标注
+    pub fn content_block(&self) -> serde_json::Value {
+        json!({
+            "type": "document",
+            "source": {
+                "type": "base64",
+                "media_type": "application/pdf",
+                "data": self.data
+            },
+            "title": self.title,
+            "citations": { "enabled": true }
+        })
+    }
+}
+
+/// 交互模式下粘贴的大段文本，不直接作为消息发送
+#[derive(Debug, Clone, PartialEq)]
+pub struct PastedText {
+    pub text: String,
+}
+
+impl PastedText {
+    /// 输入超过大小或行数阈值时视为大段粘贴
+    pub fn detect(input: &str) -> Option<Self> {
+        let lines = input.lines().count();
+        (input.len() >= LARGE_PASTE_BYTES || lines >= LARGE_PASTE_LINES).then(|| Self {
+            text: input.to_string(),
+        })
+    }
+
+    pub fn lines(&self) -> usize {
+        self.text.lines().count()
+    }
+
+    pub fn content_block(&self) -> serde_json::Value {
+        json!({
+            "type": "text",
+            "text": format!("<pasted_content>\n{}\n</pasted_content>", self.text)
+        })
+    }
+}
+
+#[cfg(feature = "pdf")]
+fn count_pages(bytes: &[u8]) -> Result<usize> {
+    if !bytes.starts_with(b"%PDF-") {
+        bail!("missing %PDF header");
+    }
+    let document = lopdf::Document::load_mem(bytes)?;
+    Ok(document.get_pages().len())
+}
+
+/// 未启用 pdf 功能时无法校验页数，不接受附件
+#[cfg(not(feature = "pdf"))]
+fn count_pages(_bytes: &[u8]) -> Result<usize> {
+    bail!("PDF attachments need a build with the `pdf` feature")
+}
+
+/// 构造用户消息内容：有附件时文档块和粘贴内容在前、文本在后，否则保持纯文本
+pub fn user_content(
+    text: &str,
+    attachments: &[Attachment],
+    pastes: &[PastedText],
+) -> serde_json::Value {
+    if attachments.is_empty() && pastes.is_empty() {
+        return json!(text);
+    }
+
+    let mut blocks: Vec<serde_json::Value> = attachments
+        .iter()
+        .map(Attachment::content_block)
+        .chain(pastes.iter().map(PastedText::content_block))
+        .collect();
+    blocks.push(json!({ "type": "text", "text": text }));
+    serde_json::Value::Array(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "pdf")]
+    use {
+        lopdf::{dictionary, Document, Object},
+        tempfile::TempDir,
+    };
+
+    #[cfg(feature = "pdf")]
+    fn write_pdf(path: &Path, pages: usize) {
+        let mut document = Document::with_version("1.5");
+        let pages_id = document.new_object_id();
+        let kids: Vec<Object> = (0..pages)
+            .map(|_| {
+                document
+                    .add_object(dictionary! { "Type" => "Page", "Parent" => pages_id })
+                    .into()
+            })
+            .collect();
+        document.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => kids,
+                "Count" => pages as i64,
+            }),
+        );
+        let catalog = document.add_object(dictionary! { "Type" => "Catalog", "Pages" => pages_id });
+        document.trailer.set("Root", catalog);
+
+        let mut bytes = Vec::new();
+        document.save_to(&mut bytes).unwrap();
+        fs::write(path, bytes).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "pdf")]
+    fn test_load_pdf_as_document_block() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("spec.pdf");
+        write_pdf(&path, 3);
+
+        let attachment = Attachment::load_pdf(&path).unwrap();
+        assert_eq!(attachment.pages, 3);
+        assert_eq!(attachment.title, "spec.pdf");
+
+        let content = user_content("Summarize this", &[attachment], &[]);
+        assert_eq!(content[0]["type"], "document");
+        assert_eq!(content[0]["source"]["media_type"], "application/pdf");
+        assert_eq!(content[1]["text"], "Summarize this");
+        assert_eq!(user_content("hi", &[], &[]), json!("hi"));
+    }
+
+    #[test]
+    fn test_large_paste_becomes_separate_block() {
+        assert!(PastedText::detect("short question").is_none());
+
+        let log = "error: something failed\n".repeat(LARGE_PASTE_LINES);
+        let paste = PastedText::detect(&log).unwrap();
+        assert_eq!(paste.lines(), LARGE_PASTE_LINES);
+        assert!(PastedText::detect(&"x".repeat(LARGE_PASTE_BYTES)).is_some());
+
+        let content = user_content("why does this fail?", &[], &[paste]);
+        assert!(content[0]["text"]
+            .as_str()
+            .unwrap()
+            .starts_with("<pasted_content>\nerror: something failed"));
+        assert_eq!(content[1]["text"], "why does this fail?");
+    }
+
+    #[test]
+    #[cfg(feature = "pdf")]
+    fn test_reject_invalid_attachments() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let long = temp_dir.path().join("long.pdf");
+        write_pdf(&long, MAX_PDF_PAGES + 1);
+        let err = Attachment::load_pdf(&long).unwrap_err();
+        assert!(err.to_string().contains("page limit"), "{}", err);
+
+        let fake = temp_dir.path().join("fake.pdf");
+        fs::write(&fake, "not a pdf").unwrap();
+        assert!(Attachment::load_pdf(&fake).is_err());
+
+        let text = temp_dir.path().join("notes.txt");
+        fs::write(&text, "notes").unwrap();
+        assert!(Attachment::load_pdf(&text).is_err());
+    }
+}
@@ -0,0 +1,523 @@
+//! audit_dependencies 工具：用 cargo audit、npm audit 或 pip-audit 检查依赖中已知的漏洞
+//!
+//! 三种工具的 JSON 报告整理成统一的漏洞列表，每个漏洞一行 JSON，方便模型据此升级依赖
+
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::{permissions, tasks};
+
+/// 最多列出的漏洞数
+const MAX_VULNERABILITIES: usize = 50;
+/// 漏洞标题最多保留的字符数，pip-audit 给出的是完整的描述
+const MAX_TITLE_CHARS: usize = 160;
+/// 报告无法解析时返回的输出行数
+const MAX_ERROR_LINES: usize = 20;
+
+/// 支持的包管理器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecosystem {
+    Cargo,
+    Npm,
+    Pip,
+}
+
+impl fmt::Display for Ecosystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Ecosystem::Cargo => "cargo audit",
+            Ecosystem::Npm => "npm audit",
+            Ecosystem::Pip => "pip-audit",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Ecosystem {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "cargo" | "rust" | "cargo audit" => Some(Ecosystem::Cargo),
+            "npm" | "node" | "npm audit" => Some(Ecosystem::Npm),
+            "pip" | "python" | "pip-audit" => Some(Ecosystem::Pip),
+            _ => None,
+        }
+    }
+
+    /// 目录中用到的所有包管理器
+    pub fn detect(dir: &Path) -> Vec<Self> {
+        let has = |names: &[&str]| names.iter().any(|name| dir.join(name).is_file());
+        let mut found = Vec::new();
+        if has(&["Cargo.toml"]) {
+            found.push(Ecosystem::Cargo);
+        }
+        if has(&["package.json"]) {
+            found.push(Ecosystem::Npm);
+        }
+        if has(&["requirements.txt", "pyproject.toml", "setup.py"]) {
+            found.push(Ecosystem::Pip);
+        }
+        found
+    }
+
+    fn command(self, dir: &Path) -> Command {
+        let mut cmd = match self {
+            Ecosystem::Cargo => {
+                let mut cmd = Command::new("cargo");
+                cmd.args(["audit", "--json"]);
+                cmd
+            }
+            Ecosystem::Npm => {
+                let mut cmd = Command::new("npm");
+                cmd.args(["audit", "--json"]);
+                cmd
+            }
+            Ecosystem::Pip => {
+                let mut cmd = Command::new("pip-audit");
+                cmd.args(["--format", "json", "--progress-spinner", "off"]);
+                // 没有 requirements.txt 时检查当前的 Python 环境
+                if dir.join("requirements.txt").is_file() {
+                    cmd.args(["--requirement", "requirements.txt"]);
+                }
+                cmd
+            }
+        };
+        cmd.current_dir(dir);
+        cmd
+    }
+
+    /// 检查工具是否安装，以及安装方法
+    fn installed(self) -> Result<()> {
+        let (program, args, hint): (&str, &[&str], &str) = match self {
+            Ecosystem::Cargo => (
+                "cargo",
+                &["audit", "--version"],
+                "cargo-audit is not installed (install it with `cargo install cargo-audit`)",
+            ),
+            Ecosystem::Npm => ("npm", &["--version"], "npm is not installed"),
+            Ecosystem::Pip => (
+                "pip-audit",
+                &["--version"],
+                "pip-audit is not installed (install it with `pip install pip-audit`)",
+            ),
+        };
+        let ok = Command::new(program)
+            .args(args)
+            .output()
+            .is_ok_and(|output| output.status.success());
+        if !ok {
+            bail!("{}", hint);
+        }
+        Ok(())
+    }
+}
+
+/// 工具参数中指定的包管理器，未指定时检测会话工作目录中用到的所有包管理器
+pub fn resolve(name: Option<&str>) -> Result<Vec<Ecosystem>> {
+    match name {
+        Some(name) => Ecosystem::parse(name)
+            .map(|ecosystem| vec![ecosystem])
+            .with_context(|| format!("Unknown ecosystem: {} (expected cargo, npm or pip)", name)),
+        None => {
+            let found = Ecosystem::detect(&permissions::working_dir()?);
+            if found.is_empty() {
+                bail!("Could not detect a package manager (no Cargo.toml, package.json, requirements.txt or pyproject.toml)");
+            }
+            Ok(found)
+        }
+    }
+}
+
+/// 显示给用户确认的命令行
+pub fn command_line(ecosystems: &[Ecosystem]) -> String {
+    let dir = permissions::working_dir().unwrap_or_default();
+    ecosystems
+        .iter()
+        .map(|ecosystem| {
+            let cmd = ecosystem.command(&dir);
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// 一个已知漏洞
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Vulnerability {
+    pub package: String,
+    /// 安装的版本；npm audit 只给出受影响的版本范围
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// 公告编号，例如 RUSTSEC-2020-0071、GHSA-xxxx、PYSEC-2023-1
+    pub id: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub aliases: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub severity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// 修复的方法，例如可以升级到的版本；没有修复时为 None
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+/// 一个包管理器的检查结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditReport {
+    pub ecosystem: Ecosystem,
+    pub vulnerabilities: Vec<Vulnerability>,
+    /// 不是漏洞但值得注意的问题，例如 cargo audit 报告的不再维护或被撤回的 crate
+    pub warnings: Vec<String>,
+}
+
+impl AuditReport {
+    fn new(ecosystem: Ecosystem) -> Self {
+        Self {
+            ecosystem,
+            vulnerabilities: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// 返回给模型的结果：摘要行，之后每个漏洞一行 JSON
+    pub fn render(&self) -> String {
+        let mut out = match self.vulnerabilities.len() {
+            0 => format!("{}: no known vulnerabilities", self.ecosystem),
+            1 => format!("{}: 1 vulnerability", self.ecosystem),
+            count => format!("{}: {} vulnerabilities", self.ecosystem, count),
+        };
+        for vulnerability in self.vulnerabilities.iter().take(MAX_VULNERABILITIES) {
+            out.push('\n');
+            out.push_str(&serde_json::to_string(vulnerability).unwrap_or_default());
+        }
+        if self.vulnerabilities.len() > MAX_VULNERABILITIES {
+            out.push_str(&format!(
+                "\n... and {} more",
+                self.vulnerabilities.len() - MAX_VULNERABILITIES
+            ));
+        }
+        for warning in &self.warnings {
+            out.push_str(&format!("\nwarning: {}", warning));
+        }
+        out
+    }
+}
+
+/// 依次运行各个包管理器的检查；只检查一个时直接返回它的错误，
+/// 检查多个时失败的那个在结果中说明，不影响其他的结果
+pub async fn run_all(ecosystems: &[Ecosystem], cancel: &CancellationToken) -> Result<String> {
+    let mut results = Vec::new();
+    for &ecosystem in ecosystems {
+        match run(ecosystem, cancel).await {
+            Ok(report) => results.push(report.render()),
+            Err(e) if ecosystems.len() == 1 => return Err(e),
+            Err(e) => results.push(format!("{}: {:#}", ecosystem, e)),
+        }
+    }
+    Ok(results.join("\n\n"))
+}
+
+/// 运行一个包管理器的检查
+async fn run(ecosystem: Ecosystem, cancel: &CancellationToken) -> Result<AuditReport> {
+    ecosystem.installed()?;
+    let output = tasks::output(ecosystem.command(&permissions::working_dir()?), cancel)
+        .await
+        .with_context(|| format!("Failed to run {}", ecosystem))?;
+    // 发现漏洞时三个工具都以非零状态退出，所以只看报告能否解析
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    match serde_json::from_str::<Value>(&stdout) {
+        Ok(json) => parse(ecosystem, &json)
+            .with_context(|| format!("{} did not produce a report", ecosystem)),
+        Err(_) => {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let lines: Vec<&str> = stderr.trim_end().lines().collect();
+            bail!(
+                "{} did not produce a report:\n{}",
+                ecosystem,
+                lines[lines.len().saturating_sub(MAX_ERROR_LINES)..].join("\n")
+            );
+        }
+    }
+}
+
+fn parse(ecosystem: Ecosystem, json: &Value) -> Result<AuditReport> {
+    match ecosystem {
+        Ecosystem::Cargo => parse_cargo(json),
+        Ecosystem::Npm => parse_npm(json),
+        Ecosystem::Pip => parse_pip(json),
+    }
+}
+
+fn strings(value: &Value) -> Vec<String> {
+    value
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|item| item.as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+fn string(value: &Value) -> Option<String> {
+    value.as_str().map(str::to_string)
+}
+
+/// 描述的第一行，过长时截断
+fn title(text: &str) -> Option<String> {
+    let line = text.trim().lines().next()?.trim();
+    let mut title: String = line.chars().take(MAX_TITLE_CHARS).collect();
+    if line.chars().count() > MAX_TITLE_CHARS {
+        title.push('…');
+    }
+    Some(title).filter(|title| !title.is_empty())
+}
+
+/// `cargo audit --json`
+fn parse_cargo(json: &Value) -> Result<AuditReport> {
+    let list = json["vulnerabilities"]["list"]
+        .as_array()
+        .context("missing vulnerabilities.list")?;
+    let mut report = AuditReport::new(Ecosystem::Cargo);
+    for item in list {
+        let advisory = &item["advisory"];
+        let patched = strings(&item["versions"]["patched"]);
+        report.vulnerabilities.push(Vulnerability {
+            package: string(&item["package"]["name"]).unwrap_or_default(),
+            version: string(&item["package"]["version"]),
+            id: string(&advisory["id"]).unwrap_or_default(),
+            aliases: strings(&advisory["aliases"]),
+            severity: None,
+            title: advisory["title"].as_str().and_then(title),
+            fix: (!patched.is_empty()).then(|| format!("upgrade to {}", patched.join(" or "))),
+            url: string(&advisory["url"]),
+        });
+    }
+    // warnings 按种类分组：unmaintained、unsound、yanked
+    for (kind, items) in json["warnings"].as_object().into_iter().flatten() {
+        for item in items.as_array().into_iter().flatten() {
+            let package = &item["package"];
+            let mut warning = format!(
+                "{} {} is {}",
+                package["name"].as_str().unwrap_or("?"),
+                package["version"].as_str().unwrap_or("?"),
+                kind
+            );
+            if let Some(id) = item["advisory"]["id"].as_str() {
+                warning.push_str(&format!(" ({})", id));
+            }
+            report.warnings.push(warning);
+        }
+    }
+    Ok(report)
+}
+
+/// `npm audit --json`（npm 7 及以后的格式）
+///
+/// 每个受影响的包列出它直接引用的公告；只是通过其他包间接受影响的条目不重复列出
+fn parse_npm(json: &Value) -> Result<AuditReport> {
+    if let Some(summary) = json["error"]["summary"].as_str() {
+        bail!("{}", summary);
+    }
+    let packages = json["vulnerabilities"]
+        .as_object()
+        .context("missing vulnerabilities")?;
+    let mut report = AuditReport::new(Ecosystem::Npm);
+    for (name, package) in packages {
+        let fix = match &package["fixAvailable"] {
+            Value::Bool(true) => Some("npm audit fix".to_string()),
+            Value::Object(fix) => Some(format!(
+                "upgrade {} to {}{}",
+                fix.get("name").and_then(Value::as_str).unwrap_or(name),
+                fix.get("version").and_then(Value::as_str).unwrap_or("?"),
+                if fix.get("isSemVerMajor") == Some(&Value::Bool(true)) {
+                    " (semver-major)"
+                } else {
+                    ""
+                }
+            )),
+            _ => None,
+        };
+        let advisories = package["via"].as_array().into_iter().flatten();
+        for advisory in advisories.filter(|via| via.is_object()) {
+            let url = string(&advisory["url"]);
+            // GitHub 公告的编号在地址的最后一段
+            let id = url
+                .as_deref()
+                .and_then(|url| url.rsplit('/').next())
+                .filter(|id| id.starts_with("GHSA-"))
+                .map(str::to_string)
+                .unwrap_or_else(|| advisory["source"].to_string());
+            report.vulnerabilities.push(Vulnerability {
+                package: name.clone(),
+                version: string(&advisory["range"]).or_else(|| string(&package["range"])),
+                id,
+                aliases: Vec::new(),
+                severity: string(&advisory["severity"]).or_else(|| string(&package["severity"])),
+                title: advisory["title"].as_str().and_then(title),
+                fix: fix.clone(),
+                url,
+            });
+        }
+    }
+    Ok(report)
+}
+
+/// `pip-audit --format json`；旧版本的报告直接是依赖的数组
+fn parse_pip(json: &Value) -> Result<AuditReport> {
+    let dependencies = json["dependencies"]
+        .as_array()
+        .or(json.as_array())
+        .context("missing dependencies")?;
+    let mut report = AuditReport::new(Ecosystem::Pip);
+    for dependency in dependencies {
+        if let Some(reason) = dependency["skip_reason"].as_str() {
+            report.warnings.push(format!(
+                "{} was not audited: {}",
+                dependency["name"].as_str().unwrap_or("?"),
+                reason
+            ));
+        }
+        for vuln in dependency["vulns"].as_array().into_iter().flatten() {
+            let fixes = strings(&vuln["fix_versions"]);
+            report.vulnerabilities.push(Vulnerability {
+                package: string(&dependency["name"]).unwrap_or_default(),
+                version: string(&dependency["version"]),
+                id: string(&vuln["id"]).unwrap_or_default(),
+                aliases: strings(&vuln["aliases"]),
+                severity: None,
+                title: vuln["description"].as_str().and_then(title),
+                fix: (!fixes.is_empty()).then(|| format!("upgrade to {}", fixes.join(" or "))),
+                url: None,
+            });
+        }
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_parse_cargo_audit() {
+        let json = json!({
+            "database": {"advisory-count": 600},
+            "vulnerabilities": {"found": true, "count": 1, "list": [{
+                "advisory": {
+                    "id": "RUSTSEC-2020-0071", "package": "time",
+                    "title": "Potential segfault in the time crate",
+                    "aliases": ["CVE-2020-26235"],
+                    "url": "https://github.com/time-rs/time/issues/293"
+                },
+                "versions": {"patched": [">=0.2.23"], "unaffected": ["=0.2.0"]},
+                "package": {"name": "time", "version": "0.1.45"}
+            }]},
+            "warnings": {"unmaintained": [{
+                "kind": "unmaintained",
+                "package": {"name": "ansi_term", "version": "0.12.1"},
+                "advisory": {"id": "RUSTSEC-2021-0139"}
+            }]}
+        });
+        let report = parse_cargo(&json).unwrap();
+        assert_eq!(
+            report.render(),
+            "cargo audit: 1 vulnerability\n\
+             {\"package\":\"time\",\"version\":\"0.1.45\",\"id\":\"RUSTSEC-2020-0071\",\
+             \"aliases\":[\"CVE-2020-26235\"],\"title\":\"Potential segfault in the time crate\",\
+             \"fix\":\"upgrade to >=0.2.23\",\"url\":\"https://github.com/time-rs/time/issues/293\"}\n\
+             warning: ansi_term 0.12.1 is unmaintained (RUSTSEC-2021-0139)"
+        );
+        assert!(parse_cargo(&json!({"error": "no Cargo.lock"})).is_err());
+    }
+
+    #[test]
+    fn test_parse_npm_audit() {
+        let json = json!({
+            "auditReportVersion": 2,
+            "vulnerabilities": {
+                "minimist": {
+                    "name": "minimist", "severity": "critical", "isDirect": false,
+                    "via": [{"source": 1097678, "name": "minimist", "title": "Prototype Pollution in minimist",
+                             "url": "https://github.com/advisories/GHSA-xvch-5gv4-984h",
+                             "severity": "critical", "range": "<0.2.4"}],
+                    "range": "<0.2.4",
+                    "fixAvailable": {"name": "mkdirp", "version": "1.0.4", "isSemVerMajor": true}
+                },
+                "mkdirp": {
+                    "name": "mkdirp", "severity": "critical", "isDirect": true,
+                    "via": ["minimist"], "range": "0.4.1 - 0.5.1", "fixAvailable": true
+                }
+            },
+            "metadata": {"vulnerabilities": {"critical": 2, "total": 2}}
+        });
+        let report = parse_npm(&json).unwrap();
+        assert_eq!(
+            report.vulnerabilities,
+            vec![Vulnerability {
+                package: "minimist".to_string(),
+                version: Some("<0.2.4".to_string()),
+                id: "GHSA-xvch-5gv4-984h".to_string(),
+                severity: Some("critical".to_string()),
+                title: Some("Prototype Pollution in minimist".to_string()),
+                fix: Some("upgrade mkdirp to 1.0.4 (semver-major)".to_string()),
+                url: Some("https://github.com/advisories/GHSA-xvch-5gv4-984h".to_string()),
+                ..Default::default()
+            }]
+        );
+        let missing_lock = json!({"error": {"code": "ENOLOCK", "summary": "This command requires an existing lockfile."}});
+        assert_eq!(
+            format!("{}", parse_npm(&missing_lock).unwrap_err()),
+            "This command requires an existing lockfile."
+        );
+    }
+
+    #[test]
+    fn test_parse_pip_audit_and_detect() {
+        let json = json!({"dependencies": [
+            {"name": "requests", "version": "2.19.0", "vulns": [{
+                "id": "PYSEC-2018-28", "fix_versions": ["2.20.0"], "aliases": ["CVE-2018-18074"],
+                "description": "The Requests package before 2.20.0 sends an HTTP Authorization header to an http URI upon receiving a same-hostname https-to-http redirect.\nMore details follow."
+            }]},
+            {"name": "flask", "version": "3.0.0", "vulns": []},
+            {"name": "mypkg", "skip_reason": "Dependency not found on PyPI"}
+        ]});
+        let report = parse_pip(&json).unwrap();
+        assert_eq!(report.vulnerabilities.len(), 1);
+        assert_eq!(
+            report.vulnerabilities[0].fix.as_deref(),
+            Some("upgrade to 2.20.0")
+        );
+        assert!(report.vulnerabilities[0]
+            .title
+            .as_deref()
+            .unwrap()
+            .ends_with("redirect."));
+        assert_eq!(
+            report.warnings,
+            vec!["mypkg was not audited: Dependency not found on PyPI"]
+        );
+        // 旧版本的格式
+        assert!(parse_pip(&json!([{"name": "flask", "version": "3.0.0", "vulns": []}])).is_ok());
+
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(Ecosystem::detect(dir.path()).is_empty());
+        std::fs::write(dir.path().join("Cargo.toml"), "").unwrap();
+        std::fs::write(dir.path().join("package.json"), "{}").unwrap();
+        assert_eq!(
+            Ecosystem::detect(dir.path()),
+            vec![Ecosystem::Cargo, Ecosystem::Npm]
+        );
+        assert_eq!(Ecosystem::parse("Python"), Some(Ecosystem::Pip));
+    }
+}
@@ -0,0 +1,370 @@
+use anyhow::{Context, Result};
+use console::style;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::context;
+use crate::file_cache;
+use crate::i18n::{t, tf, Msg};
+use crate::performance::FileProcessor;
+use crate::permissions::PermissionMode;
+use crate::security::InputValidator;
+
+/// 每个基准测试的采样次数
+const SAMPLES: usize = 50;
+/// 与基准线相比变化超过这个比例才算回退或改进
+const NOISE_THRESHOLD: f64 = 0.05;
+/// 基准线保存的目录，和 criterion 一样放在 target 下
+const BASELINE_DIR: &str = "target/bench";
+/// 未指定 --save-baseline 时保存和比较的基准线
+const DEFAULT_BASELINE: &str = "last";
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    /// Only run benchmarks whose name contains this string
+    filter: Option<String>,
+
+    /// Measurement time per benchmark in seconds
+    #[arg(long, value_name = "SECS", default_value_t = 1.0)]
+    measurement_time: f64,
+
+    /// Compare against this saved baseline (defaults to the previous run)
+    #[arg(long, value_name = "NAME")]
+    baseline: Option<String>,
+
+    /// Save the results under this baseline name (defaults to "last")
+    #[arg(long, value_name = "NAME")]
+    save_baseline: Option<String>,
+}
+
+/// 一个基准测试的统计结果，单位为每次迭代的纳秒数
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stats {
+    min: f64,
+    median: f64,
+    max: f64,
+    mean: f64,
+    std_dev: f64,
+}
+
+impl Stats {
+    fn from_samples(samples: &mut [f64]) -> Self {
+        samples.sort_by(f64::total_cmp);
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / n;
+        let mid = samples.len() / 2;
+        let median = if samples.len().is_multiple_of(2) {
+            (samples[mid - 1] + samples[mid]) / 2.0
+        } else {
+            samples[mid]
+        };
+        Self {
+            min: samples[0],
+            median,
+            max: samples[samples.len() - 1],
+            mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+}
+
+struct Bencher {
+    filter: Option<String>,
+    measurement_time: Duration,
+    baseline: BTreeMap<String, f64>,
+    results: BTreeMap<String, f64>,
+}
+
+impl Bencher {
+    /// 先预热估计单次耗时，再按测量时间分配每个样本的迭代次数
+    async fn bench<F, Fut>(&mut self, name: &str, bytes: Option<usize>, mut f: F)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = ()>,
+    {
+        if self
+            .filter
+            .as_deref()
+            .is_some_and(|filter| !name.contains(filter))
+        {
+            return;
+        }
+
+        let warm_up = self.measurement_time / 3;
+        let start = Instant::now();
+        let mut warm_up_iters = 0u64;
+        while start.elapsed() < warm_up {
+            f().await;
+            warm_up_iters += 1;
+        }
+        let per_iter = start.elapsed().as_nanos() as f64 / warm_up_iters as f64;
+        let sample_time = self.measurement_time.as_nanos() as f64 / SAMPLES as f64;
+        let iters = ((sample_time / per_iter) as u64).max(1);
+
+        let mut samples = Vec::with_capacity(SAMPLES);
+        for _ in 0..SAMPLES {
+            let start = Instant::now();
+            for _ in 0..iters {
+                f().await;
+            }
+            samples.push(start.elapsed().as_nanos() as f64 / iters as f64);
+        }
+        let stats = Stats::from_samples(&mut samples);
+        self.report(name, bytes, &stats);
+        self.results.insert(name.to_string(), stats.median);
+    }
+
+    fn report(&self, name: &str, bytes: Option<usize>, stats: &Stats) {
+        println!(
+            "{:<32} {}: [{} {} {}]  ±{}",
+            style(name).bold(),
+            t(Msg::BenchTime),
+            format_time(stats.min),
+            style(format_time(stats.median)).bold(),
+            format_time(stats.max),
+            format_time(stats.std_dev)
+        );
+        if let Some(bytes) = bytes {
+            println!(
+                "{:<32} {}: {}",
+                "",
+                t(Msg::BenchThroughput),
+                format_throughput(bytes, stats.median)
+            );
+        }
+        if let Some(&old) = self.baseline.get(name) {
+            let change = stats.median / old - 1.0;
+            let text = format!(
+                "{:<32} {}: {:+.2}%",
+                "",
+                t(Msg::BenchChange),
+                change * 100.0
+            );
+            if change > NOISE_THRESHOLD {
+                println!(
+                    "{}  {}",
+                    style(text).red(),
+                    style(t(Msg::BenchRegressed)).red()
+                );
+            } else if change < -NOISE_THRESHOLD {
+                println!(
+                    "{}  {}",
+                    style(text).green(),
+                    style(t(Msg::BenchImproved)).green()
+                );
+            } else {
+                println!("{}", style(text).dim());
+            }
+        }
+    }
+}
+
+/// 运行所有基准测试并打印报告
+pub async fn run(args: BenchArgs) -> Result<()> {
+    let baseline_name = args.baseline.as_deref().unwrap_or(DEFAULT_BASELINE);
+    let mut bencher = Bencher {
+        filter: args.filter,
+        measurement_time: Duration::from_secs_f64(args.measurement_time.max(0.01)),
+        baseline: load_baseline(&baseline_path(baseline_name)),
+        results: BTreeMap::new(),
+    };
+    println!("{}", style(t(Msg::BenchLegend)).dim());
+
+    let dir = std::env::temp_dir().join(format!("rust-claude-code-bench-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir)?;
+    let result = run_benchmarks(&mut bencher, &dir).await;
+    let _ = std::fs::remove_dir_all(&dir);
+    result?;
+
+    let save_name = args.save_baseline.as_deref().unwrap_or(DEFAULT_BASELINE);
+    let path = baseline_path(save_name);
+    save_baseline(&path, &bencher.results)?;
+    println!(
+        "{}",
+        style(tf(Msg::BenchSaved, &[&save_name, &path.display()])).dim()
+    );
+    Ok(())
+}
+
+async fn run_benchmarks(b: &mut Bencher, dir: &Path) -> Result<()> {
+    let processor = FileProcessor::new();
+    let small = source_text(64 * 1024);
+    let medium = source_text(4 * 1024 * 1024);
+    let small_path = dir.join("small.rs");
+    let medium_path = dir.join("medium.rs");
+    std::fs::write(&small_path, &small)?;
+    std::fs::write(&medium_path, &medium)?;
+
+    b.bench("file/read_sync/64KiB", Some(small.len()), || async {
+        black_box(processor.read_file_sync(&small_path).unwrap());
+    })
+    .await;
+    b.bench("file/read_async/64KiB", Some(small.len()), || async {
+        black_box(processor.read_file_efficiently(&small_path).await.unwrap());
+    })
+    .await;
+    b.bench("file/read_cached/64KiB", Some(small.len()), || async {
+        black_box(file_cache::read(&small_path).unwrap());
+    })
+    .await;
+    b.bench("file/read_async/4MiB", Some(medium.len()), || async {
+        black_box(processor.read_file_efficiently(&medium_path).await.unwrap());
+    })
+    .await;
+
+    let out_path = dir.join("out.rs");
+    b.bench("file/write_sync/64KiB", Some(small.len()), || async {
+        processor.write_file_sync(&out_path, &small).unwrap();
+    })
+    .await;
+    b.bench("file/write_async/64KiB", Some(small.len()), || async {
+        processor
+            .write_file_efficiently(&out_path, &small)
+            .await
+            .unwrap();
+    })
+    .await;
+    b.bench("file/write_async/4MiB", Some(medium.len()), || async {
+        processor
+            .write_file_efficiently(&out_path, &medium)
+            .await
+            .unwrap();
+    })
+    .await;
+
+    // 100 条约 2KB 的消息，相当于一段中等长度的对话
+    let messages: Vec<serde_json::Value> = (0..100)
+        .map(|i| {
+            json!({
+                "role": if i % 2 == 0 { "user" } else { "assistant" },
+                "content": [{"type": "text", "text": source_text(2048)}]
+            })
+        })
+        .collect();
+    let message_bytes = messages.iter().map(|m| m.to_string().len()).sum();
+    b.bench(
+        "tokens/estimate/100_messages",
+        Some(message_bytes),
+        || async {
+            black_box(context::estimate_tokens(black_box(&messages)));
+        },
+    )
+    .await;
+
+    let command = "cargo test --workspace 2>&1 | grep -E 'FAILED|panicked' | head -50";
+    b.bench("validate/command", None, || async {
+        black_box(InputValidator::validate_command(black_box(command)).ok());
+    })
+    .await;
+    b.bench("validate/glob_pattern", None, || async {
+        black_box(InputValidator::validate_glob_pattern(black_box("src/**/*.rs")).ok());
+    })
+    .await;
+
+    // acceptEdits 模式下每次写文件前都会检查路径是否在工作区内
+    let roots = vec![dir.to_path_buf()];
+    let input = json!({"file_path": out_path, "content": ""});
+    b.bench("validate/approval_request", None, || async {
+        black_box(PermissionMode::AcceptEdits.approval_request(
+            "write_file",
+            black_box(&input),
+            &roots,
+        ));
+    })
+    .await;
+    Ok(())
+}
+
+/// 生成类似源代码的 ASCII 文本
+fn source_text(bytes: usize) -> String {
+    let line = "    let value = compute(input, &config).context(\"failed\")?; // ok\n";
+    line.repeat(bytes / line.len() + 1)[..bytes].to_string()
+}
+
+fn baseline_path(name: &str) -> PathBuf {
+    Path::new(BASELINE_DIR).join(format!("{}.json", name))
+}
+
+fn load_baseline(path: &Path) -> BTreeMap<String, f64> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_baseline(path: &Path, results: &BTreeMap<String, f64>) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // 只运行部分基准测试时保留其余的旧结果
+    let mut merged = load_baseline(path);
+    merged.extend(results.iter().map(|(name, ns)| (name.clone(), *ns)));
+    std::fs::write(path, serde_json::to_string_pretty(&merged)?)
+        .with_context(|| format!("Failed to write baseline: {}", path.display()))
+}
+
+fn format_time(ns: f64) -> String {
+    if ns < 1_000.0 {
+        format!("{:.2} ns", ns)
+    } else if ns < 1_000_000.0 {
+        format!("{:.2} µs", ns / 1_000.0)
+    } else if ns < 1_000_000_000.0 {
+        format!("{:.2} ms", ns / 1_000_000.0)
+    } else {
+        format!("{:.2} s", ns / 1_000_000_000.0)
+    }
+}
+
+fn format_throughput(bytes: usize, ns: f64) -> String {
+    let per_second = bytes as f64 / (ns / 1_000_000_000.0);
+    if per_second >= 1024.0 * 1024.0 * 1024.0 {
+        format!("{:.2} GiB/s", per_second / (1024.0 * 1024.0 * 1024.0))
+    } else {
+        format!("{:.2} MiB/s", per_second / (1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_from_samples() {
+        let stats = Stats::from_samples(&mut [4.0, 1.0, 3.0, 2.0]);
+        assert_eq!(stats.min, 1.0);
+        assert_eq!(stats.max, 4.0);
+        assert_eq!(stats.median, 2.5);
+        assert_eq!(stats.mean, 2.5);
+        assert!((stats.std_dev - 1.118).abs() < 0.001);
+    }
+
+    #[test]
+    fn test_format_measurements() {
+        assert_eq!(format_time(12.345), "12.35 ns");
+        assert_eq!(format_time(12_345.0), "12.35 µs");
+        assert_eq!(format_time(2_500_000.0), "2.50 ms");
+        assert_eq!(
+            format_throughput(1024 * 1024, 1_000_000_000.0),
+            "1.00 MiB/s"
+        );
+        assert_eq!(source_text(100).len(), 100);
+    }
+
+    #[test]
+    fn test_save_baseline_merges_results() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("bench/last.json");
+        save_baseline(&path, &BTreeMap::from([("a".to_string(), 1.0)])).unwrap();
+        save_baseline(&path, &BTreeMap::from([("b".to_string(), 2.0)])).unwrap();
+        assert_eq!(
+            load_baseline(&path),
+            BTreeMap::from([("a".to_string(), 1.0), ("b".to_string(), 2.0)])
+        );
+    }
+}
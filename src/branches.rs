@@ -0,0 +1,163 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 会话开始时所在的分支
+pub const MAIN_BRANCH: &str = "main";
+
+/// 会话内的对话分支，每个分支保存一份消息列表快照，持久化到 .claude/sessions
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BranchStore {
+    #[serde(skip)]
+    path: PathBuf,
+    current: String,
+    branches: BTreeMap<String, Vec<serde_json::Value>>,
+}
+
+impl BranchStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            current: MAIN_BRANCH.to_string(),
+            branches: BTreeMap::new(),
+        }
+    }
+
+    /// 本次会话的分支文件：.claude/sessions/session_<timestamp>.json
+    pub fn session_path(claude_dir: &Path, started_at: u64) -> PathBuf {
+        claude_dir
+            .join("sessions")
+            .join(format!("session_{}.json", started_at))
+    }
+
+    pub fn current(&self) -> &str {
+        &self.current
+    }
+
+    /// 分支名、消息数、是否为当前分支；当前分支的消息数取自 `messages`
+    pub fn list(&self, messages: &[serde_json::Value]) -> Vec<(String, usize, bool)> {
+        let mut names: Vec<&String> = self.branches.keys().collect();
+        if !self.branches.contains_key(&self.current) {
+            names.push(&self.current);
+            names.sort();
+        }
+        names
+            .into_iter()
+            .map(|name| {
+                let is_current = *name == self.current;
+                let len = if is_current {
+                    messages.len()
+                } else {
+                    self.branches[name].len()
+                };
+                (name.clone(), len, is_current)
+            })
+            .collect()
+    }
+
+    /// 以当前消息列表创建新分支并切换过去
+    pub fn branch(&mut self, name: &str, messages: &[serde_json::Value]) -> Result<()> {
+        validate_name(name)?;
+        if name == self.current || self.branches.contains_key(name) {
+            bail!("Branch '{}' already exists", name);
+        }
+
+        self.branches
+            .insert(self.current.clone(), messages.to_vec());
+        self.branches.insert(name.to_string(), messages.to_vec());
+        self.current = name.to_string();
+        self.save()
+    }
+
+    /// 保存当前分支的消息，并把 `messages` 替换为目标分支的快照
+    pub fn checkout(&mut self, name: &str, messages: &mut Vec<serde_json::Value>) -> Result<()> {
+        if name == self.current {
+            return Ok(());
+        }
+        let Some(target) = self.branches.get(name).cloned() else {
+            bail!("Unknown branch '{}'", name);
+        };
+
+        self.branches
+            .insert(self.current.clone(), std::mem::replace(messages, target));
+        self.current = name.to_string();
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create sessions directory")?;
+        }
+        let content = serde_json::to_string_pretty(self).context("Failed to serialize branches")?;
+        fs::write(&self.path, content)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'));
+    if !valid {
+        bail!(
+            "Invalid branch name '{}': use letters, digits, '-', '_', '.' or '/'",
+            name
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn message(text: &str) -> serde_json::Value {
+        json!({"role": "user", "content": text})
+    }
+
+    #[test]
+    fn test_branch_and_checkout() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut store = BranchStore::new(BranchStore::session_path(temp_dir.path(), 42));
+
+        let mut messages = vec![message("design the API")];
+        store.branch("grpc", &messages).unwrap();
+        assert_eq!(store.current(), "grpc");
+        messages.push(message("use gRPC"));
+
+        store.checkout(MAIN_BRANCH, &mut messages).unwrap();
+        assert_eq!(messages, vec![message("design the API")]);
+        assert_eq!(
+            store.list(&messages),
+            vec![
+                ("grpc".to_string(), 2, false),
+                ("main".to_string(), 1, true)
+            ]
+        );
+
+        store.checkout("grpc", &mut messages).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert!(store.checkout("missing", &mut messages).is_err());
+        assert!(store.branch("grpc", &messages).is_err());
+        assert!(store.branch("bad name", &messages).is_err());
+    }
+
+    #[test]
+    fn test_branches_persisted() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = BranchStore::session_path(temp_dir.path(), 7);
+        let mut store = BranchStore::new(path.clone());
+        store.branch("alt", &[message("hi")]).unwrap();
+
+        let saved: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved["current"], "alt");
+        assert_eq!(saved["branches"]["main"][0]["content"], "hi");
+        assert_eq!(saved["branches"]["alt"].as_array().unwrap().len(), 1);
+    }
+}
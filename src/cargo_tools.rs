@@ -0,0 +1,542 @@
+//! cargo_check、cargo_add、cargo_metadata 工具：运行 cargo 的 JSON 输出，整理成简短的摘要交给模型
+//!
+//! cargo 的原始输出（带源码片段的诊断、索引更新的进度、完整的元数据）大部分对模型没有用，
+//! 却会占用大量 token
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::path::Path;
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
+
+use crate::{permissions, tasks};
+
+/// 错误和警告各自最多列出的条数
+const MAX_DIAGNOSTICS: usize = 20;
+/// 每条诊断最多附带的 help 和 note
+const MAX_CHILDREN: usize = 2;
+/// cargo 没有给出诊断就失败时返回的输出行数
+const MAX_ERROR_LINES: usize = 30;
+
+/// cargo_add 的参数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AddArgs {
+    /// 要添加的 crate，可以带版本，例如 `serde@1`
+    pub crates: Vec<String>,
+    pub features: Vec<String>,
+    /// 加入 dev-dependencies
+    pub dev: bool,
+    /// 工作区中要修改的包
+    pub package: Option<String>,
+}
+
+impl AddArgs {
+    pub fn from_input(input: &Value) -> Result<Self> {
+        let strings = |key: &str| -> Vec<String> {
+            input[key]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|item| item.as_str())
+                .map(str::to_string)
+                .collect()
+        };
+        let crates = strings("crates");
+        if crates.is_empty() {
+            bail!("Missing crates");
+        }
+        Ok(Self {
+            crates,
+            features: strings("features"),
+            dev: input["dev"].as_bool().unwrap_or(false),
+            package: input["package"].as_str().map(str::to_string),
+        })
+    }
+}
+
+/// 在会话的工作目录（/cd）中运行的 cargo 命令
+fn cargo(args: &[&str]) -> Command {
+    let mut cmd = Command::new("cargo");
+    cmd.args(args);
+    if let Ok(dir) = permissions::working_dir() {
+        cmd.current_dir(dir);
+    }
+    cmd
+}
+
+fn check_command(package: Option<&str>) -> Command {
+    let mut cmd = cargo(&["check", "--all-targets", "--message-format=json"]);
+    if let Some(package) = package {
+        cmd.args(["--package", package]);
+    }
+    cmd
+}
+
+fn add_command(args: &AddArgs) -> Command {
+    let mut cmd = cargo(&["add"]);
+    cmd.args(&args.crates);
+    if args.dev {
+        cmd.arg("--dev");
+    }
+    if !args.features.is_empty() {
+        cmd.args(["--features", &args.features.join(",")]);
+    }
+    if let Some(package) = &args.package {
+        cmd.args(["--package", package]);
+    }
+    cmd
+}
+
+/// 显示给用户确认的命令行；cargo_metadata 只读，不需要确认
+pub fn command_line(name: &str, input: &Value) -> Option<String> {
+    let cmd = match name {
+        "cargo_check" => check_command(input["package"].as_str()),
+        "cargo_add" => add_command(&AddArgs::from_input(input).ok()?),
+        _ => return None,
+    };
+    Some(
+        std::iter::once(cmd.get_program())
+            .chain(cmd.get_args())
+            .map(|arg| arg.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+async fn output(cmd: Command, cancel: &CancellationToken) -> Result<(bool, String, String)> {
+    let output = tasks::output(cmd, cancel)
+        .await
+        .context("Failed to run cargo")?;
+    Ok((
+        output.status.success(),
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    ))
+}
+
+/// 运行 cargo check，返回错误和警告的摘要
+pub async fn check(package: Option<&str>, cancel: &CancellationToken) -> Result<String> {
+    let (success, stdout, stderr) = output(check_command(package), cancel).await?;
+    Ok(parse_check(&stdout, &stderr, success).render())
+}
+
+/// 运行 cargo add，返回加入的依赖和版本
+pub async fn add(args: &AddArgs, cancel: &CancellationToken) -> Result<String> {
+    let (success, _, stderr) = output(add_command(args), cancel).await?;
+    if !success {
+        return Ok(format!(
+            "cargo add failed, Cargo.toml unchanged:\n{}",
+            last_lines(&stderr, MAX_ERROR_LINES)
+        ));
+    }
+    Ok(summarize_add(&stderr))
+}
+
+/// 运行 cargo metadata（不解析依赖图），返回工作区的布局
+pub async fn metadata(cancel: &CancellationToken) -> Result<String> {
+    let cmd = cargo(&["metadata", "--format-version", "1", "--no-deps"]);
+    let (success, stdout, stderr) = output(cmd, cancel).await?;
+    if !success {
+        bail!(
+            "cargo metadata failed:\n{}",
+            last_lines(&stderr, MAX_ERROR_LINES)
+        );
+    }
+    let metadata: Value =
+        serde_json::from_str(&stdout).context("Failed to parse cargo metadata output")?;
+    Ok(render_metadata(&metadata))
+}
+
+/// cargo check 的结果
+#[derive(Debug, Default, PartialEq)]
+struct CheckSummary {
+    success: bool,
+    errors: Vec<String>,
+    warnings: Vec<String>,
+    /// 失败但没有编译器诊断时（例如 Cargo.toml 写错了）输出的最后几行
+    failure: Option<String>,
+}
+
+impl CheckSummary {
+    fn render(&self) -> String {
+        if let Some(failure) = &self.failure {
+            return format!("cargo check failed before compiling:\n{}", failure);
+        }
+        let mut out = match (self.success, self.warnings.len()) {
+            (true, 0) => return "cargo check passed with no warnings.".to_string(),
+            (true, warnings) => format!("cargo check passed with {} warning(s).", warnings),
+            (false, warnings) => format!(
+                "cargo check failed: {} error(s), {} warning(s).",
+                self.errors.len(),
+                warnings
+            ),
+        };
+        for (title, diagnostics) in [("Errors", &self.errors), ("Warnings", &self.warnings)] {
+            if diagnostics.is_empty() {
+                continue;
+            }
+            out.push_str(&format!("\n\n{}:", title));
+            for diagnostic in diagnostics.iter().take(MAX_DIAGNOSTICS) {
+                out.push_str(&format!("\n{}", diagnostic));
+            }
+            if diagnostics.len() > MAX_DIAGNOSTICS {
+                out.push_str(&format!(
+                    "\n... and {} more",
+                    diagnostics.len() - MAX_DIAGNOSTICS
+                ));
+            }
+        }
+        out
+    }
+}
+
+/// 解析 `cargo check --message-format=json` 的输出；同一条诊断在多个目标中出现时只保留一次
+fn parse_check(stdout: &str, stderr: &str, success: bool) -> CheckSummary {
+    let mut summary = CheckSummary {
+        success,
+        ..Default::default()
+    };
+    for line in stdout.lines() {
+        let Ok(message) = serde_json::from_str::<Value>(line) else {
+            continue;
+        };
+        if message["reason"] != "compiler-message" {
+            continue;
+        }
+        let diagnostic = &message["message"];
+        let list = match diagnostic["level"].as_str() {
+            Some("error") | Some("error: internal compiler error") => &mut summary.errors,
+            Some("warning") => &mut summary.warnings,
+            _ => continue,
+        };
+        let Some(text) = format_diagnostic(diagnostic) else {
+            continue;
+        };
+        if !list.contains(&text) {
+            list.push(text);
+        }
+    }
+    if !success && summary.errors.is_empty() {
+        summary.failure = Some(last_lines(stderr, MAX_ERROR_LINES));
+    }
+    summary
+}
+
+/// 一条诊断：`文件:行:列: 级别[代码]: 消息 (标注)`，后面跟几条缩进的 help 和 note
+///
+/// 没有位置的汇总消息（"aborting due to ..."、"N warnings emitted"）返回 None
+fn format_diagnostic(diagnostic: &Value) -> Option<String> {
+    let spans = diagnostic["spans"].as_array()?;
+    let primary = spans
+        .iter()
+        .find(|span| span["is_primary"] == true)
+        .or(spans.first())?;
+    // 宏展开中的位置（例如 vec! 的定义）换成调用宏的位置
+    let mut span = primary;
+    while span["expansion"]["span"].is_object() {
+        span = &span["expansion"]["span"];
+    }
+    let mut text = format!(
+        "{}:{}:{}: {}",
+        span["file_name"].as_str().unwrap_or("?"),
+        span["line_start"],
+        span["column_start"],
+        diagnostic["level"].as_str().unwrap_or("error")
+    );
+    if let Some(code) = diagnostic["code"]["code"].as_str() {
+        text.push_str(&format!("[{}]", code));
+    }
+    text.push_str(&format!(": {}", one_line(diagnostic["message"].as_str()?)));
+    if let Some(label) = primary["label"].as_str() {
+        text.push_str(&format!(" ({})", one_line(label)));
+    }
+
+    let children = diagnostic["children"].as_array().into_iter().flatten();
+    // `#[warn(...)] on by default` 之类的说明对修复没有帮助
+    let children = children.filter(|child| {
+        !child["message"]
+            .as_str()
+            .is_some_and(|message| message.starts_with("`#["))
+    });
+    for child in children.take(MAX_CHILDREN) {
+        let mut line = format!(
+            "\n  {}: {}",
+            child["level"].as_str().unwrap_or("note"),
+            one_line(child["message"].as_str().unwrap_or(""))
+        );
+        let replacement = child["spans"]
+            .as_array()
+            .and_then(|spans| spans.first())
+            .and_then(|span| span["suggested_replacement"].as_str());
+        if let Some(replacement) = replacement.filter(|r| !r.contains('\n')) {
+            line.push_str(&format!(": `{}`", replacement));
+        }
+        text.push_str(&line);
+    }
+    Some(text)
+}
+
+/// 把多行消息合并为一行
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// cargo add 的摘要：加入清单的依赖、版本和启用的 feature，以及锁文件新增的包数量
+fn summarize_add(stderr: &str) -> String {
+    let mut added: Vec<String> = Vec::new();
+    let mut features: Vec<String> = Vec::new();
+    let mut locked = 0;
+    let flush = |added: &mut Vec<String>, features: &mut Vec<String>| {
+        if let (Some(last), false) = (added.last_mut(), features.is_empty()) {
+            last.push_str(&format!(" (features: {})", features.join(", ")));
+        }
+        features.clear();
+    };
+    for line in stderr.lines().map(str::trim) {
+        if let Some(rest) = line.strip_prefix("Adding ") {
+            if rest.contains(" to ") {
+                flush(&mut added, &mut features);
+                added.push(format!("Added {}", rest));
+            } else {
+                // 没有 "to ..." 的是写入 Cargo.lock 的包
+                locked += 1;
+            }
+        } else if let Some(feature) = line.strip_prefix("+ ") {
+            features.push(feature.to_string());
+        }
+    }
+    flush(&mut added, &mut features);
+    if added.is_empty() {
+        return format!(
+            "cargo add finished:\n{}",
+            last_lines(stderr, MAX_ERROR_LINES)
+        );
+    }
+    let mut out = added.join("\n");
+    if locked > 0 {
+        out.push_str(&format!("\nCargo.lock: {} package(s) added", locked));
+    }
+    out
+}
+
+/// 工作区的布局：每个成员包的版本、清单位置、目标、feature 和直接依赖
+fn render_metadata(metadata: &Value) -> String {
+    let root = Path::new(metadata["workspace_root"].as_str().unwrap_or(""));
+    let relative = |path: &str| {
+        Path::new(path)
+            .strip_prefix(root)
+            .map_or_else(|_| path.to_string(), |path| path.display().to_string())
+    };
+    let packages: Vec<&Value> = metadata["packages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .collect();
+    let mut out = format!(
+        "Workspace root: {} ({} package(s))",
+        root.display(),
+        packages.len()
+    );
+    for package in packages {
+        out.push_str(&format!(
+            "\n\n{} {} ({}, edition {})",
+            package["name"].as_str().unwrap_or("?"),
+            package["version"].as_str().unwrap_or("?"),
+            relative(package["manifest_path"].as_str().unwrap_or("")),
+            package["edition"].as_str().unwrap_or("?")
+        ));
+
+        let targets: Vec<String> = package["targets"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|target| {
+                let kind = target["kind"][0].as_str().unwrap_or("?");
+                format!(
+                    "{} {} ({})",
+                    kind,
+                    target["name"].as_str().unwrap_or("?"),
+                    relative(target["src_path"].as_str().unwrap_or(""))
+                )
+            })
+            .collect();
+        out.push_str(&format!("\n  targets: {}", targets.join(", ")));
+
+        if let Some(features) = package["features"].as_object().filter(|f| !f.is_empty()) {
+            let features: Vec<String> = features
+                .iter()
+                .map(|(name, enables)| match enables.as_array() {
+                    Some(enables) if !enables.is_empty() => {
+                        let enables: Vec<&str> =
+                            enables.iter().filter_map(|item| item.as_str()).collect();
+                        format!("{} = [{}]", name, enables.join(", "))
+                    }
+                    _ => name.clone(),
+                })
+                .collect();
+            out.push_str(&format!("\n  features: {}", features.join(", ")));
+        }
+
+        for (kind, title) in [
+            (None, "dependencies"),
+            (Some("dev"), "dev-dependencies"),
+            (Some("build"), "build-dependencies"),
+        ] {
+            let dependencies: Vec<String> = package["dependencies"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|dependency| dependency["kind"].as_str() == kind)
+                .map(|dependency| {
+                    let mut text = format!(
+                        "{} {}",
+                        dependency["name"].as_str().unwrap_or("?"),
+                        dependency["req"].as_str().unwrap_or("*")
+                    );
+                    if let Some(path) = dependency["path"].as_str() {
+                        text.push_str(&format!(" (path {})", relative(path)));
+                    }
+                    if dependency["optional"] == true {
+                        text.push_str(" (optional)");
+                    }
+                    text
+                })
+                .collect();
+            if !dependencies.is_empty() {
+                out.push_str(&format!("\n  {}: {}", title, dependencies.join(", ")));
+            }
+        }
+    }
+    out
+}
+
+fn last_lines(text: &str, count: usize) -> String {
+    let lines: Vec<&str> = text.trim_end().lines().collect();
+    lines[lines.len().saturating_sub(count)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_check_summary_deduplicates_diagnostics() {
+        let error = json!({"reason": "compiler-message", "message": {
+            "level": "error", "message": "mismatched types", "code": {"code": "E0308"},
+            "spans": [
+                {"file_name": "src/main.rs", "line_start": 4, "column_start": 12, "is_primary": false, "label": "expected due to this"},
+                {"file_name": "/rustc/library/alloc/src/macros.rs", "line_start": 58, "column_start": 9, "is_primary": true,
+                 "label": "expected `String`, found `Vec<{integer}>`",
+                 "expansion": {"span": {"file_name": "src/main.rs", "line_start": 4, "column_start": 21}}}],
+            "children": [{"level": "note", "message": "expected struct `String`\n   found struct `Vec<{integer}>`", "spans": []}]}});
+        let warning = json!({"reason": "compiler-message", "message": {
+            "level": "warning", "message": "unused variable: `unused`", "code": {"code": "unused_variables"},
+            "spans": [{"file_name": "src/main.rs", "line_start": 2, "column_start": 9, "is_primary": true, "label": null}],
+            "children": [
+                {"level": "note", "message": "`#[warn(unused_variables)]` on by default", "spans": []},
+                {"level": "help", "message": "if this is intentional, prefix it with an underscore",
+                 "spans": [{"suggested_replacement": "_unused"}]}]}});
+        let aborting = json!({"reason": "compiler-message", "message": {
+            "level": "error", "message": "aborting due to 1 previous error", "spans": [], "children": []}});
+        let stdout = [
+            &error,
+            &warning,
+            &error,
+            &aborting,
+            &json!({"reason": "build-finished", "success": false}),
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+        let summary = parse_check(&stdout, "error: could not compile `demo`", false);
+        assert_eq!(summary.errors.len(), 1);
+        assert_eq!(
+            summary.render(),
+            "cargo check failed: 1 error(s), 1 warning(s).\n\n\
+             Errors:\n\
+             src/main.rs:4:21: error[E0308]: mismatched types (expected `String`, found `Vec<{integer}>`)\n  \
+             note: expected struct `String` found struct `Vec<{integer}>`\n\n\
+             Warnings:\n\
+             src/main.rs:2:9: warning[unused_variables]: unused variable: `unused`\n  \
+             help: if this is intentional, prefix it with an underscore: `_unused`"
+        );
+
+        let broken = parse_check("", "error: failed to parse manifest at `Cargo.toml`", false);
+        assert!(broken
+            .render()
+            .starts_with("cargo check failed before compiling:\nerror: failed to parse manifest"));
+        assert_eq!(
+            parse_check("", "", true).render(),
+            "cargo check passed with no warnings."
+        );
+    }
+
+    #[test]
+    fn test_add_summary_lists_versions_and_features() {
+        let stderr = "    Updating crates.io index
+      Adding serde v1.0.229 to dependencies
+             Features:
+             + derive
+             + std
+             - alloc
+      Adding tempfile v3.23.0 to dev-dependencies
+     Locking 3 packages to latest Rust 1.95.0 compatible versions
+      Adding serde v1.0.229
+      Adding serde_derive v1.0.229
+      Adding tempfile v3.23.0
+";
+        assert_eq!(
+            summarize_add(stderr),
+            "Added serde v1.0.229 to dependencies (features: derive, std)\n\
+             Added tempfile v3.23.0 to dev-dependencies\n\
+             Cargo.lock: 3 package(s) added"
+        );
+
+        let args = AddArgs::from_input(
+            &json!({"crates": ["tokio@1"], "features": ["rt", "macros"], "dev": true}),
+        )
+        .unwrap();
+        let line = command_line(
+            "cargo_add",
+            &json!({"crates": ["tokio@1"], "features": ["rt", "macros"], "dev": true}),
+        );
+        assert_eq!(args.crates, vec!["tokio@1"]);
+        assert_eq!(
+            line.as_deref(),
+            Some("cargo add tokio@1 --dev --features rt,macros")
+        );
+        assert!(AddArgs::from_input(&json!({})).is_err());
+    }
+
+    #[test]
+    fn test_metadata_describes_workspace_layout() {
+        let metadata = json!({
+            "workspace_root": "/work",
+            "packages": [{
+                "name": "api", "version": "0.2.0", "edition": "2021",
+                "manifest_path": "/work/crates/api/Cargo.toml",
+                "targets": [
+                    {"kind": ["lib"], "name": "api", "src_path": "/work/crates/api/src/lib.rs"},
+                    {"kind": ["test"], "name": "routes", "src_path": "/work/crates/api/tests/routes.rs"}],
+                "features": {"default": ["json"], "json": []},
+                "dependencies": [
+                    {"name": "serde", "req": "^1", "kind": null, "optional": false},
+                    {"name": "core", "req": "*", "kind": null, "optional": false, "path": "/work/crates/core"},
+                    {"name": "simd-json", "req": "^0.13", "kind": null, "optional": true},
+                    {"name": "tempfile", "req": "^3", "kind": "dev", "optional": false}]
+            }]
+        });
+        assert_eq!(
+            render_metadata(&metadata),
+            "Workspace root: /work (1 package(s))\n\n\
+             api 0.2.0 (crates/api/Cargo.toml, edition 2021)\n  \
+             targets: lib api (crates/api/src/lib.rs), test routes (crates/api/tests/routes.rs)\n  \
+             features: default = [json], json\n  \
+             dependencies: serde ^1, core * (path crates/core), simd-json ^0.13 (optional)\n  \
+             dev-dependencies: tempfile ^3"
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! 汇总本次会话改动了什么：写入工具修改的文件、执行的命令，以及在 git 仓库中由命令改动的文件
+//!
+//! 写入工具第一次修改某个文件前记下原内容，会话结束时与当前内容比较；命令改动的文件
+//! 靠会话开始和结束时的 `git status` 发现，不在 git 仓库中时只能列出执行过的命令。
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// 文件在本次会话中的变化
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl ChangeKind {
+    /// 与 git status 相同的单字母标记
+    pub fn letter(self) -> char {
+        match self {
+            ChangeKind::Added => 'A',
+            ChangeKind::Modified => 'M',
+            ChangeKind::Deleted => 'D',
+        }
+    }
+}
+
+/// 一个改动过的文件
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileChange {
+    /// 相对当前目录的路径，目录外的文件为绝对路径
+    pub path: String,
+    pub kind: ChangeKind,
+    /// 增加和删除的行数；二进制文件或会话开始前就有未提交改动的文件没有
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub added: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub removed: Option<usize>,
+}
+
+/// 会话结束时的改动汇总，也写入会话记录的 metadata
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SessionChanges {
+    pub files: Vec<FileChange>,
+    /// 按执行顺序排列的命令
+    pub commands: Vec<String>,
+}
+
+impl SessionChanges {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.commands.is_empty()
+    }
+
+    /// 每个文件一行，例如 `M src/main.rs +3 -1`
+    pub fn file_lines(&self) -> Vec<String> {
+        let width = self.files.iter().map(|f| f.path.len()).max().unwrap_or(0);
+        self.files
+            .iter()
+            .map(|file| {
+                let stats = [
+                    file.added.filter(|&n| n > 0).map(|n| format!("+{}", n)),
+                    file.removed.filter(|&n| n > 0).map(|n| format!("-{}", n)),
+                ]
+                .into_iter()
+                .flatten()
+                .collect::<Vec<_>>()
+                .join(" ");
+                format!("{} {:<width$} {}", file.kind.letter(), file.path, stats)
+                    .trim_end()
+                    .to_string()
+            })
+            .collect()
+    }
+}
+
+#[derive(Default)]
+struct Tracker {
+    /// 写入工具第一次修改前的内容，None 表示文件原本不存在
+    originals: BTreeMap<PathBuf, Option<Vec<u8>>>,
+    commands: Vec<String>,
+    /// 会话开始时 git 仓库的根目录和有改动的文件（路径相对根目录，值为内容哈希）
+    git: Option<(PathBuf, HashMap<String, Option<u64>>)>,
+}
+
+static TRACKER: Lazy<Mutex<Tracker>> = Lazy::new(|| Mutex::new(Tracker::default()));
+
+/// 会话开始时记下当前目录所在 git 仓库中已有的改动
+pub fn start() {
+    let git = git_root().map(|root| {
+        let dirty = git_status(&root)
+            .into_iter()
+            .map(|(path, _)| {
+                let hash = content_hash(&root.join(&path));
+                (path, hash)
+            })
+            .collect();
+        (root, dirty)
+    });
+    TRACKER.lock().unwrap().git = git;
+}
+
+/// 写入工具修改 `path` 前调用，只记录第一次修改前的内容
+pub fn before_write(path: &Path) {
+    let path = absolute(path);
+    TRACKER
+        .lock()
+        .unwrap()
+        .originals
+        .entry(path)
+        .or_insert_with_key(|path| fs::read(path).ok());
+}
+
+/// 记录执行过的命令
+pub fn record_command(command: &str) {
+    TRACKER.lock().unwrap().commands.push(command.to_string());
+}
+
+/// 汇总到目前为止的改动，内容改回原样的文件不列出
+pub fn collect() -> SessionChanges {
+    let tracker = TRACKER.lock().unwrap();
+    let cwd = std::env::current_dir().unwrap_or_default();
+    let mut files: BTreeMap<PathBuf, FileChange> = BTreeMap::new();
+
+    for (path, original) in &tracker.originals {
+        let current = fs::read(path).ok();
+        if let Some(change) = compare(
+            &display(path, &cwd),
+            original.as_deref(),
+            current.as_deref(),
+        ) {
+            files.insert(path.clone(), change);
+        }
+    }
+
+    if let Some((root, before)) = &tracker.git {
+        let after: HashMap<String, ChangeKind> = git_status(root).into_iter().collect();
+        // 结束时有改动的文件，以及开始时有改动、结束时已恢复的文件
+        let paths = after
+            .keys()
+            .chain(before.keys().filter(|p| !after.contains_key(*p)));
+        for relative in paths {
+            let path = root.join(relative);
+            if files.contains_key(&path) || tracker.originals.contains_key(&path) {
+                continue;
+            }
+            let change = match (before.get(relative), after.get(relative)) {
+                (None, Some(&kind)) => git_change(root, relative, kind, &display(&path, &cwd)),
+                (Some(&hash), kind) if hash != content_hash(&path) => FileChange {
+                    path: display(&path, &cwd),
+                    kind: match kind {
+                        Some(ChangeKind::Deleted) => ChangeKind::Deleted,
+                        _ => ChangeKind::Modified,
+                    },
+                    added: None,
+                    removed: None,
+                },
+                _ => continue,
+            };
+            files.insert(path, change);
+        }
+    }
+
+    SessionChanges {
+        files: files.into_values().collect(),
+        commands: tracker.commands.clone(),
+    }
+}
+
+/// 比较原内容和当前内容，None 表示文件不存在
+fn compare(path: &str, original: Option<&[u8]>, current: Option<&[u8]>) -> Option<FileChange> {
+    let kind = match (original, current) {
+        (None, None) => return None,
+        (None, Some(_)) => ChangeKind::Added,
+        (Some(_), None) => ChangeKind::Deleted,
+        (Some(a), Some(b)) if a == b => return None,
+        (Some(_), Some(_)) => ChangeKind::Modified,
+    };
+    fn text(bytes: Option<&[u8]>) -> Option<&str> {
+        match bytes {
+            Some(bytes) => std::str::from_utf8(bytes).ok(),
+            None => Some(""),
+        }
+    }
+    let stats = text(original)
+        .zip(text(current))
+        .map(|(a, b)| line_stats(a, b));
+    Some(FileChange {
+        path: path.to_string(),
+        kind,
+        added: stats.map(|(added, _)| added),
+        removed: stats.map(|(_, removed)| removed),
+    })
+}
+
+/// 增加和删除的行数：去掉相同的开头和结尾后，按行的多重集合比较
+fn line_stats(old: &str, new: &str) -> (usize, usize) {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old = &old[prefix..old.len() - suffix];
+    let new = &new[prefix..new.len() - suffix];
+
+    let mut remaining: HashMap<&str, usize> = HashMap::new();
+    for line in old {
+        *remaining.entry(line).or_default() += 1;
+    }
+    let mut kept = 0;
+    for line in new {
+        if let Some(count) = remaining.get_mut(line).filter(|count| **count > 0) {
+            *count -= 1;
+            kept += 1;
+        }
+    }
+    (new.len() - kept, old.len() - kept)
+}
+
+/// 会话开始时没有改动的文件，行数取自 git diff；未跟踪的新文件按当前内容计算
+fn git_change(root: &Path, relative: &str, kind: ChangeKind, path: &str) -> FileChange {
+    let stats = if kind == ChangeKind::Added {
+        fs::read_to_string(root.join(relative))
+            .ok()
+            .map(|content| (content.lines().count(), 0))
+    } else {
+        Command::new("git")
+            .args(["diff", "--numstat", "HEAD", "--", relative])
+            .current_dir(root)
+            .output()
+            .ok()
+            .and_then(|output| {
+                let output = String::from_utf8_lossy(&output.stdout).into_owned();
+                let mut fields = output.split_whitespace();
+                // 二进制文件的行数是 "-"
+                Some((fields.next()?.parse().ok()?, fields.next()?.parse().ok()?))
+            })
+    };
+    FileChange {
+        path: path.to_string(),
+        kind,
+        added: stats.map(|(added, _)| added),
+        removed: stats.map(|(_, removed)| removed),
+    }
+}
+
+fn git_root() -> Option<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let root = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    PathBuf::from(root).canonicalize().ok()
+}
+
+/// `git status` 中有改动的文件，路径相对仓库根目录
+fn git_status(root: &Path) -> Vec<(String, ChangeKind)> {
+    let Ok(output) = Command::new("git")
+        .args(["status", "--porcelain=v1", "-z", "--untracked-files=all"])
+        .current_dir(root)
+        .output()
+    else {
+        return Vec::new();
+    };
+    parse_status(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// 解析 `git status --porcelain=v1 -z`：每项为 `XY path`，改名的项后面还有原路径
+fn parse_status(output: &str) -> Vec<(String, ChangeKind)> {
+    let mut entries = Vec::new();
+    let mut fields = output.split('\0');
+    while let Some(entry) = fields.next() {
+        let (Some(code), Some(path)) = (entry.get(..2), entry.get(3..)) else {
+            continue;
+        };
+        let kind = if code == "??" || code.contains('A') {
+            ChangeKind::Added
+        } else if code.contains('D') {
+            ChangeKind::Deleted
+        } else {
+            ChangeKind::Modified
+        };
+        if code.contains('R') || code.contains('C') {
+            fields.next();
+        }
+        entries.push((path.to_string(), kind));
+    }
+    entries
+}
+
+fn content_hash(path: &Path) -> Option<u64> {
+    let content = fs::read(path).ok()?;
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// 展开符号链接；文件不存在时展开所在目录
+fn absolute(path: &Path) -> PathBuf {
+    let path = std::env::current_dir().unwrap_or_default().join(path);
+    if let Ok(path) = path.canonicalize() {
+        return path;
+    }
+    match (
+        path.parent().and_then(|p| p.canonicalize().ok()),
+        path.file_name(),
+    ) {
+        (Some(parent), Some(name)) => parent.join(name),
+        _ => path,
+    }
+}
+
+fn display(path: &Path, cwd: &Path) -> String {
+    path.strip_prefix(cwd).unwrap_or(path).display().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_line_stats() {
+        assert_eq!(line_stats("a\nb\nc\n", "a\nb\nc\n"), (0, 0));
+        assert_eq!(line_stats("a\nb\nc\n", "a\nx\nc\nd\n"), (2, 1));
+        assert_eq!(line_stats("", "one\ntwo\n"), (2, 0));
+        // 移动的行不算改动
+        assert_eq!(line_stats("a\nb\nc\n", "c\na\nb\n"), (0, 0));
+    }
+
+    #[test]
+    fn test_compare_and_render() {
+        let changes = SessionChanges {
+            files: [
+                compare("src/new.rs", None, Some(b"fn main() {}\n")),
+                compare("src/lib.rs", Some(b"a\nb\n"), Some(b"a\nc\n")),
+                compare("old.txt", Some(b"x\ny\n"), None),
+                compare("same.txt", Some(b"x\n"), Some(b"x\n")),
+                compare("logo.png", Some(b"\xff\x00"), Some(b"\xff\x01")),
+            ]
+            .into_iter()
+            .flatten()
+            .collect(),
+            commands: vec!["cargo test".to_string()],
+        };
+        assert_eq!(
+            changes.file_lines(),
+            [
+                "A src/new.rs +1",
+                "M src/lib.rs +1 -1",
+                "D old.txt    -2",
+                "M logo.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_status() {
+        assert_eq!(
+            parse_status(" M src/main.rs\0?? notes.txt\0R  new.rs\0old.rs\0 D gone.txt\0"),
+            [
+                ("src/main.rs".to_string(), ChangeKind::Modified),
+                ("notes.txt".to_string(), ChangeKind::Added),
+                ("new.rs".to_string(), ChangeKind::Modified),
+                ("gone.txt".to_string(), ChangeKind::Deleted),
+            ]
+        );
+    }
+}
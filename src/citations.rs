@@ -0,0 +1,167 @@
+use serde::Deserialize;
+
+/// 脚注中引用原文最多显示的字符数
+const CITED_TEXT_CHARS: usize = 120;
+
+/// 文本块中的引用标注（来自 document 或 web_search 结果）
+#[derive(Debug, Clone, Deserialize)]
+pub struct Citation {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub cited_text: Option<String>,
+    #[serde(default)]
+    pub document_title: Option<String>,
+    #[serde(default)]
+    pub document_index: Option<usize>,
+    #[serde(default)]
+    pub start_page_number: Option<u32>,
+    /// 不包含在引用范围内
+    #[serde(default)]
+    pub end_page_number: Option<u32>,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl Citation {
+    /// 来源描述，例如 "spec.pdf, p. 3" 或 "Title <https://...>"
+    pub fn source(&self) -> String {
+        let document = self
+            .document_title
+            .clone()
+            .or_else(|| self.document_index.map(|i| format!("document {}", i + 1)))
+            .unwrap_or_else(|| "document".to_string());
+
+        match self.kind.as_str() {
+            "page_location" => match (self.start_page_number, self.end_page_number) {
+                (Some(start), Some(end)) if end > start + 1 => {
+                    format!("{}, pp. {}-{}", document, start, end - 1)
+                }
+                (Some(start), _) => format!("{}, p. {}", document, start),
+                _ => document,
+            },
+            "web_search_result_location" => match (&self.title, &self.url) {
+                (Some(title), Some(url)) => format!("{} <{}>", title, url),
+                (None, Some(url)) => url.clone(),
+                (Some(title), None) => title.clone(),
+                (None, None) => "web result".to_string(),
+            },
+            _ => document,
+        }
+    }
+
+    fn footnote(&self) -> String {
+        match self.cited_text.as_deref().map(str::trim) {
+            Some(text) if !text.is_empty() => {
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                let clipped: String = text.chars().take(CITED_TEXT_CHARS).collect();
+                let ellipsis = if clipped.len() < text.len() {
+                    "…"
+                } else {
+                    ""
+                };
+                format!("{}: \"{}{}\"", self.source(), clipped, ellipsis)
+            }
+            _ => self.source(),
+        }
+    }
+}
+
+/// 合并后的回答：正文带 [n] 标记，脚注按编号排列
+#[derive(Debug, Default, PartialEq)]
+pub struct RenderedAnswer {
+    pub text: String,
+    pub footnotes: Vec<String>,
+}
+
+/// 把连续的文本块拼接为一段回答，引用相同来源和原文的标注共用一个编号
+pub fn render<'a>(segments: impl IntoIterator<Item = (&'a str, &'a [Citation])>) -> RenderedAnswer {
+    let mut answer = RenderedAnswer::default();
+
+    for (text, citations) in segments {
+        answer.text.push_str(text);
+        if citations.is_empty() {
+            continue;
+        }
+
+        let mut markers = Vec::new();
+        for citation in citations {
+            let footnote = citation.footnote();
+            let number = match answer.footnotes.iter().position(|f| *f == footnote) {
+                Some(index) => index + 1,
+                None => {
+                    answer.footnotes.push(footnote);
+                    answer.footnotes.len()
+                }
+            };
+            if !markers.contains(&number) {
+                markers.push(number);
+            }
+        }
+
+        // 标记放在行尾空白之前
+        let trailing = answer.text.len() - answer.text.trim_end().len();
+        let insert_at = answer.text.len() - trailing;
+        let marker: String = markers.iter().map(|n| format!("[{}]", n)).collect();
+        answer.text.insert_str(insert_at, &marker);
+    }
+
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn citations(value: serde_json::Value) -> Vec<Citation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_citation_sources() {
+        let parsed = citations(json!([
+            {"type": "page_location", "document_title": "spec.pdf", "document_index": 0,
+             "start_page_number": 3, "end_page_number": 4, "cited_text": "x"},
+            {"type": "page_location", "document_index": 1,
+             "start_page_number": 2, "end_page_number": 5, "cited_text": "y"},
+            {"type": "web_search_result_location", "url": "https://example.com",
+             "title": "Example", "cited_text": "z"}
+        ]));
+        assert_eq!(parsed[0].source(), "spec.pdf, p. 3");
+        assert_eq!(parsed[1].source(), "document 2, pp. 2-4");
+        assert_eq!(parsed[2].source(), "Example <https://example.com>");
+    }
+
+    #[test]
+    fn test_render_numbered_footnotes() {
+        let first = citations(
+            json!([{"type": "page_location", "document_title": "spec.pdf",
+            "start_page_number": 1, "end_page_number": 2, "cited_text": "Limits are 32 MB."}]),
+        );
+        let second = citations(
+            json!([{"type": "char_location", "document_title": "notes.txt",
+            "cited_text": "Retries use backoff."}]),
+        );
+
+        let answer = render([
+            ("According to the spec, ", &[][..]),
+            ("the limit is 32 MB. ", &first[..]),
+            ("Retries back off", &second[..]),
+            (", and again the limit is 32 MB.", &first[..]),
+        ]);
+        assert_eq!(
+            answer.text,
+            "According to the spec, the limit is 32 MB.[1] Retries back off[2], and again the limit is 32 MB.[1]"
+        );
+        assert_eq!(
+            answer.footnotes,
+            [
+                "spec.pdf, p. 1: \"Limits are 32 MB.\"",
+                "notes.txt: \"Retries use backoff.\""
+            ]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+//! 时间和随机数的来源：默认使用系统时间和系统熵，测试和需要重现的运行中可以替换
+//!
+//! 设置 `SOURCE_DATE_EPOCH` 时时钟固定在这一刻，历史文件名和元数据中的时间戳都可以重复；
+//! `--seed` 让重试抖动等随机行为可以重现。
+
+use once_cell::sync::Lazy;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// 停在某一时刻的时钟
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
+
+static CLOCK: Lazy<RwLock<Arc<dyn Clock>>> = Lazy::new(|| RwLock::new(Arc::new(SystemClock)));
+static SEED: OnceLock<u64> = OnceLock::new();
+
+#[cfg(test)]
+thread_local! {
+    static THREAD_CLOCK: std::cell::RefCell<Option<Arc<dyn Clock>>> =
+        const { std::cell::RefCell::new(None) };
+}
+
+pub fn set(clock: Arc<dyn Clock>) {
+    *CLOCK.write().unwrap() = clock;
+}
+
+/// 按 `SOURCE_DATE_EPOCH` 固定时钟；没有设置或不是数字时使用系统时间
+pub fn init_from_env() {
+    if let Some(secs) = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+    {
+        set(Arc::new(FixedClock(UNIX_EPOCH + Duration::from_secs(secs))));
+    }
+}
+
+pub fn now() -> SystemTime {
+    #[cfg(test)]
+    if let Some(clock) = THREAD_CLOCK.with(|clock| clock.borrow().clone()) {
+        return clock.now();
+    }
+    CLOCK.read().unwrap().now()
+}
+
+pub fn unix_secs() -> u64 {
+    now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+pub fn unix_millis() -> u64 {
+    now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// 只对当前线程替换时钟，返回值被丢弃时恢复；并行的其他测试不受影响
+#[cfg(test)]
+pub fn set_for_thread(clock: Arc<dyn Clock>) -> ThreadClockGuard {
+    THREAD_CLOCK.with(|current| *current.borrow_mut() = Some(clock));
+    ThreadClockGuard
+}
+
+#[cfg(test)]
+pub struct ThreadClockGuard;
+
+#[cfg(test)]
+impl Drop for ThreadClockGuard {
+    fn drop(&mut self) {
+        THREAD_CLOCK.with(|current| *current.borrow_mut() = None);
+    }
+}
+
+/// 设置随机行为使用的种子，只有第一次调用生效
+pub fn set_seed(seed: u64) {
+    let _ = SEED.set(seed);
+}
+
+/// 新的随机数生成器：设置了种子时每次得到相同的序列
+pub fn rng() -> StdRng {
+    match SEED.get() {
+        Some(seed) => StdRng::seed_from_u64(*seed),
+        None => StdRng::from_entropy(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_clock_overrides_and_restores() {
+        let fixed = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        {
+            let _guard = set_for_thread(Arc::new(FixedClock(fixed)));
+            assert_eq!(unix_secs(), 1_700_000_000);
+            assert_eq!(unix_millis(), 1_700_000_000_123);
+        }
+        assert!(unix_secs() > 1_700_000_000);
+    }
+}
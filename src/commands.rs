@@ -0,0 +1,395 @@
+use anyhow::{anyhow, Result};
+use std::path::PathBuf;
+
+use crate::engine::MAX_VARIANTS;
+use crate::i18n::{t, tf, Msg};
+use crate::output_style::OutputStyle;
+use crate::permissions::PermissionMode;
+use crate::share::Target;
+
+/// /variants 不带参数时请求的回答数
+const DEFAULT_VARIANTS: usize = 3;
+
+/// 交互模式下的斜杠命令
+#[derive(Debug, Clone, PartialEq)]
+pub enum SlashCommand {
+    /// 查看或修改最大对话轮数
+    Turns(Option<usize>),
+    /// 显示 API 和工具调用统计
+    Stats,
+    /// 显示请求中各部分估算的 token 数
+    Context,
+    /// 附加 PDF，随下一条消息发送
+    Attach(PathBuf),
+    /// 在外部编辑器中编写下一条消息
+    Editor,
+    /// 查看等待发送的粘贴内容
+    Expand,
+    /// 以当前对话创建分支并切换过去；不带名称时列出分支
+    Branch(Option<String>),
+    /// 切换到已有分支
+    Checkout(String),
+    /// 丢弃最后一轮回答并重新请求
+    Retry(RetryOptions),
+    /// 在编辑器中修改上一条提示词后重新发送
+    Edit,
+    /// 用另一个模型回答上一条提示词，不改变当前对话
+    Compare(String),
+    /// 以不同的温度请求多个回答，选中的回答替换最后一轮
+    Variants(usize),
+    /// 切换计划模式
+    Plan,
+    /// 查看后台任务
+    Tasks,
+    /// 启动开发服务器；不带命令时显示服务器状态和最近的输出
+    Serve(Option<String>),
+    /// 停止开发服务器
+    ServeStop,
+    /// 在浏览器中打开 URL；不带参数时打开最近在命令输出中发现的本地地址
+    Open(Option<String>),
+    /// 运行项目的测试，可以只运行名称匹配的用例
+    Test(Option<String>),
+    /// 运行项目的构建命令
+    Build,
+    /// 运行项目的代码检查命令
+    Lint,
+    /// 显示模型、API 地址、认证方式和连通性
+    Status,
+    /// 立即保存对话，不受 auto_save 影响；可以指定文件，.md 保存为 Markdown
+    Save(Option<PathBuf>),
+    /// 分享去除密钥的会话记录：上传 gist 或写成 HTML 文件；不指定时按有没有 GitHub token 选择
+    Share(Option<Target>),
+    /// 切换回答风格并保存到配置；不带参数时列出可用的风格
+    OutputStyle(Option<OutputStyle>),
+    /// 把目录加入工作区；不带路径时列出工作区的目录
+    AddDir(Option<PathBuf>),
+    /// 改变执行命令的工作目录（必须在工作区内）；不带路径时显示当前的工作目录
+    Cd(Option<PathBuf>),
+    /// 固定文件，每轮对话都附带它的最新内容
+    Pin(PathBuf),
+    /// 列出固定的文件，可以取消固定
+    Pins,
+    /// 切换到指定的权限模式；不带参数时切换到下一个模式
+    Mode(Option<PermissionMode>),
+    /// 列出从 .env 文件加入命令环境的变量
+    Env,
+    /// 显示可用命令
+    Help,
+}
+
+/// /retry 只对这一次请求生效的参数
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RetryOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f64>,
+}
+
+impl RetryOptions {
+    fn parse(args: &[&str]) -> Result<Self> {
+        let mut options = RetryOptions::default();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args.next().ok_or_else(|| anyhow!(t(Msg::UsageRetry)))?;
+            match *flag {
+                "--model" => options.model = Some(value.to_string()),
+                "--temperature" => {
+                    let temperature = value
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|t| (0.0..=1.0).contains(t))
+                        .ok_or_else(|| anyhow!(t(Msg::UsageRetry)))?;
+                    options.temperature = Some(temperature);
+                }
+                _ => return Err(anyhow!(t(Msg::UsageRetry))),
+            }
+        }
+        Ok(options)
+    }
+}
+
+impl SlashCommand {
+    /// 解析用户输入；不是斜杠命令时返回 None
+    pub fn parse(input: &str) -> Option<Result<SlashCommand>> {
+        let input = input.trim();
+        let rest = input.strip_prefix('/')?;
+
+        let mut parts = rest.split_whitespace();
+        let name = parts.next().unwrap_or("");
+        let args: Vec<&str> = parts.collect();
+
+        let command = match name {
+            "turns" => match args.as_slice() {
+                [] => Ok(SlashCommand::Turns(None)),
+                [n] => n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| *n > 0)
+                    .map(|n| SlashCommand::Turns(Some(n)))
+                    .ok_or_else(|| anyhow!(t(Msg::UsageTurns))),
+                _ => Err(anyhow!(t(Msg::UsageTurns))),
+            },
+            "stats" => Ok(SlashCommand::Stats),
+            "context" => Ok(SlashCommand::Context),
+            "attach" if !args.is_empty() => Ok(SlashCommand::Attach(PathBuf::from(args.join(" ")))),
+            "attach" => Err(anyhow!(t(Msg::UsageAttach))),
+            "editor" => Ok(SlashCommand::Editor),
+            "expand" => Ok(SlashCommand::Expand),
+            "branch" => match args.as_slice() {
+                [] => Ok(SlashCommand::Branch(None)),
+                [name] => Ok(SlashCommand::Branch(Some(name.to_string()))),
+                _ => Err(anyhow!(t(Msg::UsageBranch))),
+            },
+            "checkout" => match args.as_slice() {
+                [name] => Ok(SlashCommand::Checkout(name.to_string())),
+                _ => Err(anyhow!(t(Msg::UsageCheckout))),
+            },
+            "retry" => RetryOptions::parse(&args).map(SlashCommand::Retry),
+            "edit" => Ok(SlashCommand::Edit),
+            "variants" => match args.as_slice() {
+                [] => Ok(SlashCommand::Variants(DEFAULT_VARIANTS)),
+                [n] => n
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|n| (2..=MAX_VARIANTS).contains(n))
+                    .map(SlashCommand::Variants)
+                    .ok_or_else(|| anyhow!(tf(Msg::UsageVariants, &[&MAX_VARIANTS]))),
+                _ => Err(anyhow!(tf(Msg::UsageVariants, &[&MAX_VARIANTS]))),
+            },
+            "compare" => match args.as_slice() {
+                [model] => Ok(SlashCommand::Compare(model.to_string())),
+                _ => Err(anyhow!(t(Msg::UsageCompare))),
+            },
+            "plan" => Ok(SlashCommand::Plan),
+            "tasks" => Ok(SlashCommand::Tasks),
+            "open" => match args.as_slice() {
+                [] => Ok(SlashCommand::Open(None)),
+                [url] => Ok(SlashCommand::Open(Some(url.to_string()))),
+                _ => Err(anyhow!(t(Msg::UsageOpen))),
+            },
+            "build" => Ok(SlashCommand::Build),
+            "lint" => Ok(SlashCommand::Lint),
+            "test" if args.is_empty() => Ok(SlashCommand::Test(None)),
+            "test" => Ok(SlashCommand::Test(Some(args.join(" ")))),
+            "status" => Ok(SlashCommand::Status),
+            "save" if args.is_empty() => Ok(SlashCommand::Save(None)),
+            "save" => Ok(SlashCommand::Save(Some(PathBuf::from(args.join(" "))))),
+            "share" => match args.as_slice() {
+                [] => Ok(SlashCommand::Share(None)),
+                [target] => Target::parse(target)
+                    .map(|target| SlashCommand::Share(Some(target)))
+                    .ok_or_else(|| anyhow!(t(Msg::UsageShare))),
+                _ => Err(anyhow!(t(Msg::UsageShare))),
+            },
+            "serve" => match args.as_slice() {
+                [] => Ok(SlashCommand::Serve(None)),
+                ["stop"] => Ok(SlashCommand::ServeStop),
+                _ => Ok(SlashCommand::Serve(Some(args.join(" ")))),
+            },
+            "output-style" => match args.as_slice() {
+                [] => Ok(SlashCommand::OutputStyle(None)),
+                [name] => OutputStyle::parse(name)
+                    .map(|style| SlashCommand::OutputStyle(Some(style)))
+                    .ok_or_else(|| anyhow!(t(Msg::UsageOutputStyle))),
+                _ => Err(anyhow!(t(Msg::UsageOutputStyle))),
+            },
+            "pin" if args.is_empty() => Err(anyhow!(t(Msg::UsagePin))),
+            "pin" => Ok(SlashCommand::Pin(PathBuf::from(args.join(" ")))),
+            "pins" => Ok(SlashCommand::Pins),
+            "add-dir" if args.is_empty() => Ok(SlashCommand::AddDir(None)),
+            "add-dir" => Ok(SlashCommand::AddDir(Some(PathBuf::from(args.join(" "))))),
+            "cd" if args.is_empty() => Ok(SlashCommand::Cd(None)),
+            "cd" => Ok(SlashCommand::Cd(Some(PathBuf::from(args.join(" "))))),
+            "mode" => match args.as_slice() {
+                [] => Ok(SlashCommand::Mode(None)),
+                [mode] => PermissionMode::parse(mode)
+                    .map(|mode| SlashCommand::Mode(Some(mode)))
+                    .ok_or_else(|| anyhow!(t(Msg::UsageMode))),
+                _ => Err(anyhow!(t(Msg::UsageMode))),
+            },
+            "env" => Ok(SlashCommand::Env),
+            "help" => Ok(SlashCommand::Help),
+            _ => Err(anyhow!(tf(Msg::UnknownCommand, &[&name]))),
+        };
+
+        Some(command)
+    }
+
+    /// 命令帮助信息
+    pub fn help() -> Vec<(&'static str, &'static str)> {
+        vec![
+            ("/turns [n]", t(Msg::HelpTurns)),
+            ("/stats", t(Msg::HelpStats)),
+            ("/context", t(Msg::HelpContext)),
+            ("/attach <file>", t(Msg::HelpAttach)),
+            ("/editor", t(Msg::HelpEditor)),
+            ("/expand", t(Msg::HelpExpand)),
+            ("/branch [name]", t(Msg::HelpBranch)),
+            ("/checkout <name>", t(Msg::HelpCheckout)),
+            ("/retry", t(Msg::HelpRetry)),
+            ("/edit", t(Msg::HelpEdit)),
+            ("/compare <model>", t(Msg::HelpCompare)),
+            ("/variants [n]", t(Msg::HelpVariants)),
+            ("/plan", t(Msg::HelpPlan)),
+            ("/mode [mode]", t(Msg::HelpMode)),
+            ("/add-dir [path]", t(Msg::HelpAddDir)),
+            ("/cd [path]", t(Msg::HelpCd)),
+            ("/pin <file>", t(Msg::HelpPin)),
+            ("/pins", t(Msg::HelpPins)),
+            ("/output-style [style]", t(Msg::HelpOutputStyle)),
+            ("/tasks", t(Msg::HelpTasks)),
+            ("/serve [cmd|stop]", t(Msg::HelpServe)),
+            ("/open [url]", t(Msg::HelpOpen)),
+            ("/test [filter]", t(Msg::HelpTest)),
+            ("/build", t(Msg::HelpBuild)),
+            ("/lint", t(Msg::HelpLint)),
+            ("/status", t(Msg::HelpStatus)),
+            ("/save [path]", t(Msg::HelpSave)),
+            ("/share [gist|html]", t(Msg::HelpShare)),
+            ("/env", t(Msg::HelpEnv)),
+            ("/help", t(Msg::HelpHelp)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_turns() {
+        assert_eq!(
+            SlashCommand::parse("/turns 50").unwrap().unwrap(),
+            SlashCommand::Turns(Some(50))
+        );
+        assert_eq!(
+            SlashCommand::parse("  /turns ").unwrap().unwrap(),
+            SlashCommand::Turns(None)
+        );
+        assert!(SlashCommand::parse("/turns 0").unwrap().is_err());
+        assert!(SlashCommand::parse("/turns abc").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_non_command() {
+        assert!(SlashCommand::parse("hello /turns").is_none());
+        assert!(SlashCommand::parse("/unknown").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_attach() {
+        assert_eq!(
+            SlashCommand::parse("/attach docs/My Spec.pdf")
+                .unwrap()
+                .unwrap(),
+            SlashCommand::Attach(PathBuf::from("docs/My Spec.pdf"))
+        );
+        assert!(SlashCommand::parse("/attach").unwrap().is_err());
+        assert_eq!(
+            SlashCommand::parse("/expand").unwrap().unwrap(),
+            SlashCommand::Expand
+        );
+        assert_eq!(
+            SlashCommand::parse("/branch alt").unwrap().unwrap(),
+            SlashCommand::Branch(Some("alt".to_string()))
+        );
+        assert!(SlashCommand::parse("/checkout").unwrap().is_err());
+        assert_eq!(
+            SlashCommand::parse("/save notes/chat.md").unwrap().unwrap(),
+            SlashCommand::Save(Some(PathBuf::from("notes/chat.md")))
+        );
+        assert_eq!(
+            SlashCommand::parse("/add-dir ../shared").unwrap().unwrap(),
+            SlashCommand::AddDir(Some(PathBuf::from("../shared")))
+        );
+        assert_eq!(
+            SlashCommand::parse("/cd crates/api").unwrap().unwrap(),
+            SlashCommand::Cd(Some(PathBuf::from("crates/api")))
+        );
+        assert_eq!(
+            SlashCommand::parse("/pin src/my types.rs")
+                .unwrap()
+                .unwrap(),
+            SlashCommand::Pin(PathBuf::from("src/my types.rs"))
+        );
+        assert!(SlashCommand::parse("/pin").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_retry() {
+        assert_eq!(
+            SlashCommand::parse("/retry").unwrap().unwrap(),
+            SlashCommand::Retry(RetryOptions::default())
+        );
+        assert_eq!(
+            SlashCommand::parse("/retry --model claude-opus-4 --temperature 0.2")
+                .unwrap()
+                .unwrap(),
+            SlashCommand::Retry(RetryOptions {
+                model: Some("claude-opus-4".to_string()),
+                temperature: Some(0.2),
+            })
+        );
+        assert!(SlashCommand::parse("/retry --temperature 3")
+            .unwrap()
+            .is_err());
+        assert!(SlashCommand::parse("/retry --model").unwrap().is_err());
+
+        assert_eq!(
+            SlashCommand::parse("/compare claude-opus-4")
+                .unwrap()
+                .unwrap(),
+            SlashCommand::Compare("claude-opus-4".to_string())
+        );
+        assert!(SlashCommand::parse("/compare").unwrap().is_err());
+        assert_eq!(
+            SlashCommand::parse("/variants").unwrap().unwrap(),
+            SlashCommand::Variants(3)
+        );
+        assert_eq!(
+            SlashCommand::parse("/variants 4").unwrap().unwrap(),
+            SlashCommand::Variants(4)
+        );
+        assert!(SlashCommand::parse("/variants 1").unwrap().is_err());
+        assert!(SlashCommand::parse("/variants 50").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_parse_mode() {
+        assert_eq!(
+            SlashCommand::parse("/mode").unwrap().unwrap(),
+            SlashCommand::Mode(None)
+        );
+        assert_eq!(
+            SlashCommand::parse("/mode accept-edits").unwrap().unwrap(),
+            SlashCommand::Mode(Some(PermissionMode::AcceptEdits))
+        );
+        assert_eq!(
+            SlashCommand::parse("/mode Normal").unwrap().unwrap(),
+            SlashCommand::Mode(Some(PermissionMode::Default))
+        );
+        assert!(SlashCommand::parse("/mode yolo").unwrap().is_err());
+        assert_eq!(
+            SlashCommand::parse("/output-style concise")
+                .unwrap()
+                .unwrap(),
+            SlashCommand::OutputStyle(Some(OutputStyle::Concise))
+        );
+        assert!(SlashCommand::parse("/output-style loud").unwrap().is_err());
+        assert_eq!(
+            SlashCommand::parse("/serve npm run dev").unwrap().unwrap(),
+            SlashCommand::Serve(Some("npm run dev".to_string()))
+        );
+        assert_eq!(
+            SlashCommand::parse("/serve stop").unwrap().unwrap(),
+            SlashCommand::ServeStop
+        );
+        assert_eq!(
+            SlashCommand::parse("/test parse_").unwrap().unwrap(),
+            SlashCommand::Test(Some("parse_".to_string()))
+        );
+        assert_eq!(
+            SlashCommand::parse("/status").unwrap().unwrap(),
+            SlashCommand::Status
+        );
+    }
+}
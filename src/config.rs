@@ -1,11 +1,59 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::error::{ClaudeCodeError, RetryConfig};
+use crate::i18n::{self, Msg};
+use crate::injection::GuardMode;
+use crate::managed::{self, ManagedSettings};
+use crate::output_style::OutputStyle;
+use crate::policy::{CommandRule, Policy, WritePolicy, WriteRule};
+use crate::profiles;
+use crate::tools::{self, ToolFilter};
+
+/// 当前 settings.json 的 schema 版本
+pub const CURRENT_SCHEMA_VERSION: u32 = 1;
+
+/// 默认使用的模型
+pub const DEFAULT_MODEL: &str = "claude-sonnet-4-5-20250929";
+
+/// 默认的 API 地址
+pub const DEFAULT_API_BASE_URL: &str = "https://api.anthropic.com/v1/messages";
+
+/// 默认的 anthropic-version 请求头
+pub const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// token_efficient_tools 开启时附加的 beta 功能：更省 token 的工具调用和细粒度的工具输入流式传输
+pub const TOKEN_EFFICIENT_TOOLS_BETAS: &[&str] = &[
+    "token-efficient-tools-2025-02-19",
+    "fine-grained-tool-streaming-2025-05-14",
+];
+
+/// 修改后需要重启才能生效的配置项
+pub const RESTART_REQUIRED_KEYS: &[&str] = &[
+    "anthropic_api_key",
+    "api_base_url",
+    "retry",
+    "anthropic_version",
+    "anthropic_beta",
+    "accessible",
+    "language",
+    "edit_mode",
+    "token_efficient_tools",
+    "rate_limit_pacing",
+];
 
 /// 用户配置文件结构 (.claude/settings.json)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UserSettings {
+    /// 配置文件 schema 版本
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+
     /// 界面主题
     #[serde(default = "default_theme")]
     pub theme: String,
@@ -33,6 +81,201 @@ pub struct UserSettings {
     /// 启用的插件列表
     #[serde(default)]
     pub enabled_plugins: Vec<String>,
+
+    /// 使用的模型（为空时使用默认模型）
+    #[serde(default)]
+    pub model: Option<String>,
+
+    /// 工具输出超过该字节数时写入 .claude/tool-output 并截断
+    #[serde(default = "default_tool_output_limit")]
+    pub tool_output_limit: usize,
+
+    /// 覆盖文件前保留 .bak 备份
+    #[serde(default)]
+    pub backup_on_write: bool,
+
+    /// 拒绝写入本次会话中未读取过的已有文件（关闭时只警告）
+    #[serde(default = "default_require_read_before_write")]
+    pub require_read_before_write: bool,
+
+    /// API 请求重试策略
+    #[serde(default)]
+    pub retry: RetrySettings,
+
+    /// anthropic-version 请求头（为空时使用默认版本）
+    #[serde(default)]
+    pub anthropic_version: Option<String>,
+
+    /// 通过 anthropic-beta 请求头启用的 beta 功能
+    #[serde(default)]
+    pub anthropic_beta: Vec<String>,
+
+    /// 输入行的键位模式：emacs 或 vi
+    #[serde(default = "default_edit_mode")]
+    pub edit_mode: String,
+
+    /// 交互模式下超出终端高度的回答通过 $PAGER 分页显示
+    #[serde(default = "default_pager")]
+    pub pager: bool,
+
+    /// 屏幕阅读器友好的输出模式（无颜色、无 emoji，使用纯文本标签）
+    #[serde(default)]
+    pub accessible: bool,
+
+    /// 界面语言（en / zh），未设置时根据 LANG 等环境变量推断
+    #[serde(default)]
+    pub language: Option<String>,
+
+    /// 按请求用途选择模型，未设置的用途使用 model
+    #[serde(default)]
+    pub model_routing: ModelRouting,
+
+    /// 模型重试后仍然过载时，本轮剩余请求使用的备用模型
+    #[serde(default)]
+    pub fallback_model: Option<String>,
+
+    /// 启用 token-efficient-tools beta，并以精简格式发送工具定义
+    #[serde(default)]
+    pub token_efficient_tools: bool,
+
+    /// 不提供给模型的工具，例如 ["execute_command"]
+    #[serde(default)]
+    pub disabled_tools: Vec<String>,
+
+    /// 回答风格：default、concise、explanatory 或 teaching
+    #[serde(default)]
+    pub output_style: OutputStyle,
+
+    /// 交互模式下在输入提示符上方显示模型、git 分支、上下文用量和权限模式
+    #[serde(default = "default_status_line")]
+    pub status_line: bool,
+
+    /// 对话记录以 zstd 压缩保存为 .json.zst
+    #[serde(default)]
+    pub compress_history: bool,
+
+    /// 交互模式启动时检查是否有新版本（每天最多一次）
+    #[serde(default = "default_update_check")]
+    pub update_check: bool,
+
+    /// 是否上报匿名使用统计；未设置表示还没有询问过用户，不上报
+    #[serde(default)]
+    pub telemetry: Option<bool>,
+
+    /// 使用统计的上报地址；未设置时不上报也不询问
+    #[serde(default)]
+    pub telemetry_endpoint: Option<String>,
+
+    /// 工作区的其他根目录（相对路径基于当前目录），例如 ["./backend", "../shared-lib"]
+    #[serde(default)]
+    pub workspaces: Vec<String>,
+
+    /// 每轮对话都附带最新内容的文件（相对路径基于当前目录）
+    #[serde(default)]
+    pub pinned_files: Vec<String>,
+
+    /// 同时执行的命令（execute_command、run_tests、coverage、cargo 工具、audit_dependencies）上限，0 表示不限制
+    #[serde(default = "default_max_concurrent_commands")]
+    pub max_concurrent_commands: usize,
+
+    /// 各工具每分钟最多调用的次数，例如 {"execute_command": 30}
+    #[serde(default)]
+    pub tool_rate_limits: BTreeMap<String, u32>,
+
+    /// 响应头显示 API 额度快用完时，在下一个请求前等到额度重置
+    #[serde(default = "default_rate_limit_pacing")]
+    pub rate_limit_pacing: bool,
+
+    /// 执行命令前按顺序匹配的审批规则，见 policy.rs
+    #[serde(default)]
+    pub command_rules: Vec<CommandRule>,
+
+    /// 写入文件前匹配的规则，例如禁止修改 *.pem，见 policy.rs
+    #[serde(default)]
+    pub write_rules: Vec<WriteRule>,
+
+    /// execute_command 在伪终端中运行，程序的输出与在终端中一致
+    #[serde(default = "default_pty")]
+    pub pty: bool,
+
+    /// 工具输出中有像是写给模型的指令时：off 不检查，warn 提醒，confirm 询问后才交给模型
+    #[serde(default)]
+    pub injection_guard: GuardMode,
+
+    /// --prompt 模式下缓存温度为 0 的请求的回答，相同的请求直接使用缓存
+    #[serde(default)]
+    pub response_cache: bool,
+
+    /// 缓存的回答保留的秒数
+    #[serde(default = "default_response_cache_ttl_secs")]
+    pub response_cache_ttl_secs: u64,
+
+    /// 把项目的 .env 和 .claude/.env 加入工具执行的命令的环境
+    #[serde(default = "default_load_env_files")]
+    pub load_env_files: bool,
+}
+
+/// 请求的用途，用于按 model_routing 选择模型
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestPurpose {
+    /// 回答用户和决定下一步操作
+    Main,
+    /// 总结较早的对话以压缩上下文
+    Summarize,
+    /// 只读工具（read_file、list_files）返回结果后的后续请求
+    ToolResult,
+}
+
+impl RequestPurpose {
+    /// 执行完工具 `tool_name` 后的后续请求的用途
+    pub fn after_tool(tool_name: &str) -> Self {
+        // 只读工具的结果通常只需要简单整理，可以交给更便宜的模型
+        if tools::is_read_only(tool_name) {
+            RequestPurpose::ToolResult
+        } else {
+            RequestPurpose::Main
+        }
+    }
+}
+
+/// settings.json 中的模型路由，例如把总结交给 haiku、主要推理仍使用 sonnet/opus
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelRouting {
+    /// 总结对话使用的模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub summarize: Option<String>,
+
+    /// 只读工具结果的后续请求使用的模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_results: Option<String>,
+}
+
+impl ModelRouting {
+    /// 该用途配置的模型；未配置时返回 None，由调用方使用主模型
+    pub fn model_for(&self, purpose: RequestPurpose) -> Option<&str> {
+        let model = match purpose {
+            RequestPurpose::Main => None,
+            RequestPurpose::Summarize => self.summarize.as_deref(),
+            RequestPurpose::ToolResult => self.tool_results.as_deref(),
+        };
+        model.filter(|m| !m.is_empty())
+    }
+}
+
+/// settings.json 中的重试策略，未设置的项使用默认值
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetrySettings {
+    /// 首次请求之后最多重试的次数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+
+    /// 第一次重试前的等待时间（毫秒）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initial_delay_ms: Option<u64>,
+
+    /// 所有重试的总时间上限（秒）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_elapsed_secs: Option<u64>,
 }
 
 /// 本地配置文件结构 (.claude/settings.local.json)
@@ -53,9 +296,61 @@ pub struct Config {
     pub api_key: String,
     pub api_base_url: String,
     pub api_timeout_ms: u64,
+    /// API 请求重试策略
+    pub retry: RetryConfig,
+    /// anthropic-version 请求头
+    pub anthropic_version: String,
+    /// anthropic-beta 请求头中的 beta 功能列表
+    pub anthropic_beta: Vec<String>,
+    /// 每个配置项的来源
+    pub sources: HashMap<&'static str, ConfigSource>,
+    /// 加载过程中产生的警告（未知键、迁移等）
+    pub warnings: Vec<String>,
+    /// 系统目录中管理员下发的设置
+    pub managed: ManagedSettings,
+}
+
+/// 配置值的来源
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigSource {
+    Default,
+    UserSettings,
+    LocalSettings,
+    Env(&'static str),
+    Cli(&'static str),
+    /// 管理员下发的 managed-settings.json
+    Managed,
+    /// --profile 选择的配置档
+    Profile(String),
+}
+
+impl fmt::Display for ConfigSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigSource::Default => write!(f, "default"),
+            ConfigSource::UserSettings => write!(f, ".claude/settings.json"),
+            ConfigSource::LocalSettings => write!(f, ".claude/settings.local.json"),
+            ConfigSource::Env(var) => write!(f, "env {}", var),
+            ConfigSource::Cli(flag) => write!(f, "{}", flag),
+            ConfigSource::Managed => write!(f, "{}", managed::MANAGED_SETTINGS_FILE),
+            ConfigSource::Profile(name) => write!(f, "profile {}", name),
+        }
+    }
+}
+
+/// 生效配置中的一项，用于 --show-config 输出
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigEntry {
+    pub key: &'static str,
+    pub value: serde_json::Value,
+    pub source: String,
 }
 
 // 默认值函数
+fn default_schema_version() -> u32 {
+    CURRENT_SCHEMA_VERSION
+}
+
 fn default_theme() -> String {
     "default".to_string()
 }
@@ -72,9 +367,101 @@ fn default_confidence_threshold() -> f64 {
     0.8
 }
 
+fn default_edit_mode() -> String {
+    "emacs".to_string()
+}
+
+fn default_pager() -> bool {
+    true
+}
+
+fn default_status_line() -> bool {
+    true
+}
+
+fn default_update_check() -> bool {
+    true
+}
+
+fn default_tool_output_limit() -> usize {
+    30_000
+}
+
+fn default_require_read_before_write() -> bool {
+    true
+}
+
+fn default_max_concurrent_commands() -> usize {
+    4
+}
+
+fn default_rate_limit_pacing() -> bool {
+    true
+}
+
+fn default_pty() -> bool {
+    true
+}
+
+fn default_load_env_files() -> bool {
+    true
+}
+
+fn default_response_cache_ttl_secs() -> u64 {
+    7 * 24 * 60 * 60
+}
+
+impl UserSettings {
+    /// settings.json 中所有已知的键
+    pub const KEYS: &'static [&'static str] = &[
+        "schema_version",
+        "theme",
+        "auto_save",
+        "ai_enabled",
+        "anthropic_api_key",
+        "api_base_url",
+        "confidence_threshold",
+        "enabled_plugins",
+        "model",
+        "tool_output_limit",
+        "backup_on_write",
+        "require_read_before_write",
+        "retry",
+        "anthropic_version",
+        "anthropic_beta",
+        "edit_mode",
+        "pager",
+        "accessible",
+        "language",
+        "model_routing",
+        "fallback_model",
+        "token_efficient_tools",
+        "disabled_tools",
+        "output_style",
+        "status_line",
+        "compress_history",
+        "update_check",
+        "telemetry",
+        "telemetry_endpoint",
+        "workspaces",
+        "pinned_files",
+        "max_concurrent_commands",
+        "tool_rate_limits",
+        "rate_limit_pacing",
+        "command_rules",
+        "write_rules",
+        "pty",
+        "injection_guard",
+        "response_cache",
+        "response_cache_ttl_secs",
+        "load_env_files",
+    ];
+}
+
 impl Default for UserSettings {
     fn default() -> Self {
         UserSettings {
+            schema_version: default_schema_version(),
             theme: default_theme(),
             auto_save: default_auto_save(),
             ai_enabled: default_ai_enabled(),
@@ -82,6 +469,39 @@ impl Default for UserSettings {
             api_base_url: None,
             confidence_threshold: default_confidence_threshold(),
             enabled_plugins: vec!["rust-analyzer-lsp@claude-plugins-official".to_string()],
+            model: None,
+            tool_output_limit: default_tool_output_limit(),
+            backup_on_write: false,
+            require_read_before_write: default_require_read_before_write(),
+            retry: RetrySettings::default(),
+            anthropic_version: None,
+            anthropic_beta: Vec::new(),
+            edit_mode: default_edit_mode(),
+            pager: default_pager(),
+            accessible: false,
+            language: None,
+            model_routing: ModelRouting::default(),
+            fallback_model: None,
+            token_efficient_tools: false,
+            disabled_tools: Vec::new(),
+            output_style: OutputStyle::default(),
+            status_line: default_status_line(),
+            compress_history: false,
+            update_check: default_update_check(),
+            telemetry: None,
+            telemetry_endpoint: None,
+            workspaces: Vec::new(),
+            pinned_files: Vec::new(),
+            max_concurrent_commands: default_max_concurrent_commands(),
+            tool_rate_limits: BTreeMap::new(),
+            rate_limit_pacing: default_rate_limit_pacing(),
+            command_rules: Vec::new(),
+            write_rules: Vec::new(),
+            pty: default_pty(),
+            injection_guard: GuardMode::default(),
+            response_cache: false,
+            response_cache_ttl_secs: default_response_cache_ttl_secs(),
+            load_env_files: default_load_env_files(),
         }
     }
 }
@@ -96,39 +516,370 @@ impl Default for LocalSettings {
 }
 
 impl Config {
-    /// 加载配置，按优先级合并各个配置源
-    pub fn load() -> Result<Self> {
-        // 1. 加载用户配置
-        let user_settings = Self::load_user_settings().unwrap_or_else(|_| UserSettings::default());
+    /// 加载配置，按优先级合并各个配置源；失败时是 ClaudeCodeError::Config
+    ///
+    /// 此处不要求 API 密钥存在，调用方在真正发起请求前应调用 `require_api_key`
+    pub fn load() -> Result<Self, ClaudeCodeError> {
+        Self::load_sources().map_err(ClaudeCodeError::Config)
+    }
+
+    fn load_sources() -> Result<Self> {
+        let mut sources = HashMap::new();
+
+        // 1. 加载用户配置（解析失败直接报错，而不是静默回退到默认值），托管的设置覆盖其中的值
+        let managed = ManagedSettings::load()?;
+        let LoadedUserSettings {
+            settings: mut user_settings,
+            explicit_keys,
+            profile_keys,
+            warnings,
+        } = Self::load_user_settings()?;
+        managed.apply(&mut user_settings)?;
+        Policy::new(&user_settings.command_rules)?;
+        WritePolicy::new(&user_settings.write_rules)?;
+        record_settings_sources(&mut sources, &explicit_keys, &profile_keys, &managed);
 
         // 2. 加载本地配置
-        let local_settings =
-            Self::load_local_settings().unwrap_or_else(|_| LocalSettings::default());
+        let local_settings = Self::load_local_settings()?;
+
+        // 3. 从环境变量加载配置；配置档是按次明确选择的，其中的密钥优先于环境变量
+        let profile_source = |key: &str| {
+            profile_keys
+                .iter()
+                .any(|k| k == key)
+                .then(|| ConfigSource::Profile(profiles::active().unwrap_or_default()))
+        };
+        let profile_key = user_settings
+            .anthropic_api_key
+            .clone()
+            .filter(|key| !key.is_empty())
+            .zip(profile_source("anthropic_api_key"));
+        let (api_key, key_source) = profile_key
+            .or_else(|| Self::get_api_key(&user_settings, &local_settings))
+            .unwrap_or_else(|| (String::new(), ConfigSource::Default));
+        let (api_base_url, mut url_source) = Self::get_api_base_url(&user_settings);
+        if url_source == ConfigSource::UserSettings {
+            url_source = profile_source("api_base_url").unwrap_or(url_source);
+        }
+        let (api_timeout_ms, timeout_source) = Self::get_api_timeout();
 
-        // 3. 从环境变量加载配置
-        let api_key = Self::get_api_key(&user_settings, &local_settings)?;
-        let api_base_url = Self::get_api_base_url(&user_settings);
-        let api_timeout_ms = Self::get_api_timeout();
+        sources.insert("api_key", key_source);
+        sources.insert(
+            "api_base_url",
+            if managed.overrides("api_base_url") {
+                ConfigSource::Managed
+            } else {
+                url_source
+            },
+        );
+        sources.insert("api_timeout_ms", timeout_source);
+        let retry = Self::get_retry_config(&user_settings.retry, &mut sources);
+        let anthropic_version = user_settings
+            .anthropic_version
+            .clone()
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| DEFAULT_ANTHROPIC_VERSION.to_string());
+        let mut anthropic_beta = user_settings.anthropic_beta.clone();
+        if user_settings.token_efficient_tools {
+            for beta in TOKEN_EFFICIENT_TOOLS_BETAS {
+                if !anthropic_beta.iter().any(|b| b == beta) {
+                    anthropic_beta.push(beta.to_string());
+                }
+            }
+        }
 
         Ok(Config {
             user_settings,
             api_key,
             api_base_url,
             api_timeout_ms,
+            retry,
+            anthropic_version,
+            anthropic_beta,
+            sources,
+            warnings,
+            managed,
         })
     }
 
+    /// 应用命令行参数覆盖（优先级最高）
+    pub fn apply_cli_overrides(
+        &mut self,
+        api_key: Option<String>,
+        api_url: Option<String>,
+        timeout_secs: Option<u64>,
+    ) {
+        if let Some(key) = api_key {
+            self.api_key = key;
+            self.sources
+                .insert("api_key", ConfigSource::Cli("--api-key"));
+        }
+
+        if let Some(url) = api_url {
+            if self.managed.overrides("api_base_url") {
+                self.warnings.push(format!(
+                    "--api-url is ignored because api_base_url is set by {}",
+                    managed::MANAGED_SETTINGS_FILE
+                ));
+            } else {
+                self.api_base_url = url;
+                self.sources
+                    .insert("api_base_url", ConfigSource::Cli("--api-url"));
+            }
+        }
+
+        if let Some(secs) = timeout_secs {
+            self.api_timeout_ms = secs.saturating_mul(1000);
+            self.sources
+                .insert("api_timeout_ms", ConfigSource::Cli("--timeout"));
+        }
+    }
+
+    /// 应用命令行中的 API 版本和 beta 功能；--beta 追加到 settings.json 的列表之后
+    pub fn apply_api_header_overrides(&mut self, version: Option<String>, betas: Vec<String>) {
+        if let Some(version) = version {
+            self.anthropic_version = version;
+            self.sources.insert(
+                "anthropic_version",
+                ConfigSource::Cli("--anthropic-version"),
+            );
+        }
+
+        if !betas.is_empty() {
+            for beta in betas {
+                if !self.anthropic_beta.contains(&beta) {
+                    self.anthropic_beta.push(beta);
+                }
+            }
+            self.sources
+                .insert("anthropic_beta", ConfigSource::Cli("--beta"));
+        }
+    }
+
+    /// 检查最终生效的配置是否符合托管配置的限制
+    pub fn check_managed(&self) -> Result<()> {
+        self.managed.check_api_url(&self.api_base_url)
+    }
+
+    /// 确定界面语言（--lang > settings.json > 环境变量）并切换到该语言
+    pub fn apply_language(&mut self, cli_lang: Option<String>) {
+        if let Some(lang) = cli_lang {
+            self.user_settings.language = Some(lang);
+            self.sources.insert("language", ConfigSource::Cli("--lang"));
+        }
+
+        let (lang, warning) = i18n::resolve(self.user_settings.language.as_deref());
+        i18n::set_lang(lang);
+        self.warnings.extend(warning);
+    }
+
+    /// 应用 --accessible，返回是否启用无障碍输出
+    pub fn apply_accessible(&mut self, cli_accessible: bool) -> bool {
+        if cli_accessible {
+            self.user_settings.accessible = true;
+            self.sources
+                .insert("accessible", ConfigSource::Cli("--accessible"));
+        }
+        self.user_settings.accessible
+    }
+
+    /// 重新加载 settings.json，返回值发生变化的键
+    ///
+    /// 解析失败时保留当前配置不变
+    pub fn reload_user_settings(&mut self) -> Result<Vec<&'static str>> {
+        let mut loaded = Self::load_user_settings()?;
+        self.managed.apply(&mut loaded.settings)?;
+        Policy::new(&loaded.settings.command_rules)?;
+        WritePolicy::new(&loaded.settings.write_rules)?;
+
+        let old = serde_json::to_value(&self.user_settings)?;
+        let new = serde_json::to_value(&loaded.settings)?;
+        let changed = UserSettings::KEYS
+            .iter()
+            .copied()
+            .filter(|key| old.get(*key) != new.get(*key))
+            .collect();
+
+        record_settings_sources(
+            &mut self.sources,
+            &loaded.explicit_keys,
+            &loaded.profile_keys,
+            &self.managed,
+        );
+        self.user_settings = loaded.settings;
+        self.warnings = loaded.warnings;
+
+        Ok(changed)
+    }
+
+    /// 按当前配置筛选发送给模型的工具
+    pub fn tool_filter(&self) -> ToolFilter {
+        ToolFilter {
+            read_only: false,
+            disabled: self.user_settings.disabled_tools.clone(),
+        }
+    }
+
+    /// 当前使用的模型
+    pub fn model(&self) -> &str {
+        self.user_settings
+            .model
+            .as_deref()
+            .filter(|m| !m.is_empty())
+            .unwrap_or(DEFAULT_MODEL)
+    }
+
+    /// 确保已配置 API 密钥
+    pub fn require_api_key(&self) -> Result<()> {
+        if self.api_key.is_empty() {
+            anyhow::bail!(i18n::t(Msg::ApiKeyMissing));
+        }
+        Ok(())
+    }
+
+    /// 列出所有生效的配置项及其来源，密钥会被遮蔽
+    pub fn effective_entries(&self) -> Vec<ConfigEntry> {
+        let settings = &self.user_settings;
+        let api_key = if self.api_key.is_empty() {
+            serde_json::Value::Null
+        } else {
+            serde_json::Value::String(mask_secret(&self.api_key))
+        };
+
+        let values: Vec<(&'static str, serde_json::Value)> = vec![
+            ("api_key", api_key),
+            ("api_base_url", self.api_base_url.clone().into()),
+            ("api_timeout_ms", self.api_timeout_ms.into()),
+            ("retry.max_retries", self.retry.max_retries.into()),
+            (
+                "retry.initial_delay_ms",
+                (self.retry.initial_delay.as_millis() as u64).into(),
+            ),
+            (
+                "retry.max_elapsed_secs",
+                self.retry.max_elapsed.as_secs().into(),
+            ),
+            ("anthropic_version", self.anthropic_version.clone().into()),
+            ("anthropic_beta", self.anthropic_beta.clone().into()),
+            ("model", self.model().into()),
+            (
+                "model_routing",
+                serde_json::to_value(&settings.model_routing).unwrap_or_default(),
+            ),
+            ("fallback_model", settings.fallback_model.clone().into()),
+            (
+                "token_efficient_tools",
+                settings.token_efficient_tools.into(),
+            ),
+            ("disabled_tools", settings.disabled_tools.clone().into()),
+            ("workspaces", settings.workspaces.clone().into()),
+            ("pinned_files", settings.pinned_files.clone().into()),
+            (
+                "max_concurrent_commands",
+                settings.max_concurrent_commands.into(),
+            ),
+            (
+                "tool_rate_limits",
+                serde_json::to_value(&settings.tool_rate_limits).unwrap_or_default(),
+            ),
+            ("rate_limit_pacing", settings.rate_limit_pacing.into()),
+            (
+                "command_rules",
+                serde_json::to_value(&settings.command_rules).unwrap_or_default(),
+            ),
+            (
+                "write_rules",
+                serde_json::to_value(&settings.write_rules).unwrap_or_default(),
+            ),
+            ("pty", settings.pty.into()),
+            ("injection_guard", settings.injection_guard.name().into()),
+            ("response_cache", settings.response_cache.into()),
+            (
+                "response_cache_ttl_secs",
+                settings.response_cache_ttl_secs.into(),
+            ),
+            ("load_env_files", settings.load_env_files.into()),
+            ("output_style", settings.output_style.name().into()),
+            ("language", i18n::lang().code().into()),
+            ("edit_mode", settings.edit_mode.clone().into()),
+            ("pager", settings.pager.into()),
+            ("status_line", settings.status_line.into()),
+            ("accessible", settings.accessible.into()),
+            ("theme", settings.theme.clone().into()),
+            ("auto_save", settings.auto_save.into()),
+            ("compress_history", settings.compress_history.into()),
+            ("update_check", settings.update_check.into()),
+            ("telemetry", settings.telemetry.into()),
+            (
+                "telemetry_endpoint",
+                settings.telemetry_endpoint.clone().into(),
+            ),
+            ("ai_enabled", settings.ai_enabled.into()),
+            ("confidence_threshold", settings.confidence_threshold.into()),
+            ("enabled_plugins", settings.enabled_plugins.clone().into()),
+            ("tool_output_limit", settings.tool_output_limit.into()),
+            ("backup_on_write", settings.backup_on_write.into()),
+            (
+                "require_read_before_write",
+                settings.require_read_before_write.into(),
+            ),
+        ];
+
+        values
+            .into_iter()
+            .map(|(key, value)| ConfigEntry {
+                key,
+                value,
+                source: self
+                    .sources
+                    .get(key)
+                    .cloned()
+                    .unwrap_or(ConfigSource::Default)
+                    .to_string(),
+            })
+            .collect()
+    }
+
+    /// 修改 settings.json 中的一项并写回文件，其余内容保持不变
+    pub fn save_setting(&mut self, key: &'static str, value: serde_json::Value) -> Result<()> {
+        if self.managed.overrides(key) {
+            anyhow::bail!("{} is set by {}", key, managed::MANAGED_SETTINGS_FILE);
+        }
+        write_setting(&Self::settings_path()?, key, value)?;
+        self.sources.insert(key, ConfigSource::UserSettings);
+        Ok(())
+    }
+
     /// 获取 .claude 目录路径
-    fn get_claude_dir() -> Result<PathBuf> {
+    pub fn get_claude_dir() -> Result<PathBuf> {
         let current_dir = std::env::current_dir().context("Failed to get current directory")?;
 
         Ok(current_dir.join(".claude"))
     }
 
-    /// 加载用户配置文件
-    fn load_user_settings() -> Result<UserSettings> {
+    /// 获取用户配置文件路径
+    pub fn settings_path() -> Result<PathBuf> {
+        Ok(Self::get_claude_dir()?.join("settings.json"))
+    }
+
+    /// 加载用户配置文件，再覆盖上选中的配置档
+    fn load_user_settings() -> Result<LoadedUserSettings> {
+        let mut loaded = Self::read_user_settings()?;
+        if let Some(profile) = profiles::load_active()? {
+            let mut value = serde_json::to_value(&loaded.settings)?;
+            for (key, setting) in &profile {
+                value[key] = setting.clone();
+            }
+            loaded.settings = serde_json::from_value(value)?;
+            loaded.profile_keys = profile.keys().cloned().collect();
+        }
+        Ok(loaded)
+    }
+
+    /// 读取 settings.json，必要时迁移旧版本 schema
+    fn read_user_settings() -> Result<LoadedUserSettings> {
         let claude_dir = Self::get_claude_dir()?;
-        let settings_path = claude_dir.join("settings.json");
+        let settings_path = Self::settings_path()?;
 
         if !settings_path.exists() {
             // 创建默认配置文件
@@ -142,16 +893,87 @@ impl Config {
             fs::write(&settings_path, settings_content)
                 .with_context(|| format!("Failed to write settings file: {:?}", settings_path))?;
 
-            return Ok(default_settings);
+            return Ok(LoadedUserSettings {
+                settings: default_settings,
+                explicit_keys: Vec::new(),
+                profile_keys: Vec::new(),
+                warnings: Vec::new(),
+            });
         }
 
         let content = fs::read_to_string(&settings_path)
             .with_context(|| format!("Failed to read settings file: {:?}", settings_path))?;
 
-        let settings: UserSettings = serde_json::from_str(&content)
+        let raw: serde_json::Value = serde_json::from_str(&content)
             .with_context(|| format!("Failed to parse settings file: {:?}", settings_path))?;
 
-        Ok(settings)
+        let mut warnings = Vec::new();
+        let (raw, migrated_from) = migrate_settings(raw)
+            .with_context(|| format!("Failed to migrate settings file: {:?}", settings_path))?;
+
+        if let Some(from) = migrated_from {
+            // 保留旧文件作为备份，然后写回迁移后的内容
+            let backup_path = settings_path.with_extension(format!("json.v{}.bak", from));
+            fs::copy(&settings_path, &backup_path)
+                .with_context(|| format!("Failed to back up settings file: {:?}", backup_path))?;
+            let migrated = serde_json::to_string_pretty(&raw)
+                .context("Failed to serialize migrated settings")?;
+            fs::write(&settings_path, migrated)
+                .with_context(|| format!("Failed to write settings file: {:?}", settings_path))?;
+
+            warnings.push(format!(
+                "Migrated .claude/settings.json from schema v{} to v{} (backup: {})",
+                from,
+                CURRENT_SCHEMA_VERSION,
+                backup_path.display()
+            ));
+        }
+
+        let obj = raw
+            .as_object()
+            .with_context(|| format!("Settings file must be a JSON object: {:?}", settings_path))?;
+
+        let version = obj
+            .get("schema_version")
+            .and_then(|v| v.as_u64())
+            .unwrap_or(0);
+        if version > CURRENT_SCHEMA_VERSION as u64 {
+            warnings.push(format!(
+                ".claude/settings.json has schema_version {} but this build only understands {}; \
+                newer settings may be ignored",
+                version, CURRENT_SCHEMA_VERSION
+            ));
+        }
+
+        for key in obj.keys() {
+            if UserSettings::KEYS.contains(&key.as_str()) {
+                continue;
+            }
+            let warning = match suggest_key(key) {
+                Some(suggestion) => format!(
+                    "Unknown setting '{}' in .claude/settings.json (did you mean '{}'?)",
+                    key, suggestion
+                ),
+                None => format!("Unknown setting '{}' in .claude/settings.json", key),
+            };
+            warnings.push(warning);
+        }
+
+        let explicit_keys = obj
+            .iter()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, _)| k.clone())
+            .collect();
+
+        let settings: UserSettings = serde_json::from_value(raw)
+            .with_context(|| format!("Invalid settings file: {:?}", settings_path))?;
+
+        Ok(LoadedUserSettings {
+            settings,
+            explicit_keys,
+            profile_keys: Vec::new(),
+            warnings,
+        })
     }
 
     /// 加载本地配置文件
@@ -173,55 +995,225 @@ impl Config {
     }
 
     /// 获取 API 密钥（按优先级）
-    fn get_api_key(user_settings: &UserSettings, local_settings: &LocalSettings) -> Result<String> {
+    fn get_api_key(
+        user_settings: &UserSettings,
+        local_settings: &LocalSettings,
+    ) -> Option<(String, ConfigSource)> {
         // 优先级：命令行参数 > 环境变量 > 本地配置 > 用户配置
         if let Ok(key) = std::env::var("ANTHROPIC_API_KEY") {
-            return Ok(key);
+            return Some((key, ConfigSource::Env("ANTHROPIC_API_KEY")));
         }
 
         if let Ok(key) = std::env::var("ANTHROPIC_AUTH_TOKEN") {
-            return Ok(key);
+            return Some((key, ConfigSource::Env("ANTHROPIC_AUTH_TOKEN")));
         }
 
         if let Some(key) = &local_settings.anthropic_auth_token {
             if !key.is_empty() {
-                return Ok(key.clone());
+                return Some((key.clone(), ConfigSource::LocalSettings));
             }
         }
 
         if let Some(key) = &user_settings.anthropic_api_key {
             if !key.is_empty() {
-                return Ok(key.clone());
+                return Some((key.clone(), ConfigSource::UserSettings));
             }
         }
 
-        anyhow::bail!(
-            "API key not found. Please set ANTHROPIC_API_KEY environment variable \
-            or configure it in .claude/settings.json"
-        )
+        None
     }
 
     /// 获取 API 基础 URL
-    fn get_api_base_url(user_settings: &UserSettings) -> String {
+    fn get_api_base_url(user_settings: &UserSettings) -> (String, ConfigSource) {
         if let Some(url) = &user_settings.api_base_url {
             if !url.is_empty() {
-                return url.clone();
+                return (url.clone(), ConfigSource::UserSettings);
             }
         }
 
-        std::env::var("ANTHROPIC_BASE_URL")
-            .unwrap_or_else(|_| "https://api.anthropic.com/v1/messages".to_string())
+        match std::env::var("ANTHROPIC_BASE_URL") {
+            Ok(url) => (url, ConfigSource::Env("ANTHROPIC_BASE_URL")),
+            Err(_) => (DEFAULT_API_BASE_URL.to_string(), ConfigSource::Default),
+        }
     }
 
     /// 获取 API 超时时间
-    fn get_api_timeout() -> u64 {
-        std::env::var("API_TIMEOUT_MS")
+    fn get_api_timeout() -> (u64, ConfigSource) {
+        match std::env::var("API_TIMEOUT_MS")
             .ok()
             .and_then(|s| s.parse().ok())
-            .unwrap_or(120_000) // 默认 120 秒
+        {
+            Some(ms) => (ms, ConfigSource::Env("API_TIMEOUT_MS")),
+            None => (120_000, ConfigSource::Default), // 默认 120 秒
+        }
+    }
+
+    /// 获取重试策略（环境变量 > settings.json > 默认值）
+    fn get_retry_config(
+        settings: &RetrySettings,
+        sources: &mut HashMap<&'static str, ConfigSource>,
+    ) -> RetryConfig {
+        let mut retry = RetryConfig::default();
+
+        let mut resolve = |key: &'static str, env_var: &'static str, setting: Option<u64>| {
+            if let Some(value) = std::env::var(env_var).ok().and_then(|s| s.parse().ok()) {
+                sources.insert(key, ConfigSource::Env(env_var));
+                Some(value)
+            } else if setting.is_some() {
+                sources.insert(key, ConfigSource::UserSettings);
+                setting
+            } else {
+                sources.insert(key, ConfigSource::Default);
+                None
+            }
+        };
+
+        if let Some(n) = resolve(
+            "retry.max_retries",
+            "API_MAX_RETRIES",
+            settings.max_retries.map(u64::from),
+        ) {
+            retry.max_retries = n as u32;
+        }
+        if let Some(ms) = resolve(
+            "retry.initial_delay_ms",
+            "API_RETRY_INITIAL_DELAY_MS",
+            settings.initial_delay_ms,
+        ) {
+            retry.initial_delay = Duration::from_millis(ms);
+        }
+        if let Some(secs) = resolve(
+            "retry.max_elapsed_secs",
+            "API_RETRY_MAX_ELAPSED_SECS",
+            settings.max_elapsed_secs,
+        ) {
+            retry.max_elapsed = Duration::from_secs(secs);
+        }
+
+        retry
     }
 }
 
+/// 用户配置文件的加载结果
+struct LoadedUserSettings {
+    settings: UserSettings,
+    /// 文件中显式设置的键
+    explicit_keys: Vec<String>,
+    /// 配置档中设置的键
+    profile_keys: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// 记录 settings.json 中各键的来源
+fn record_settings_sources(
+    sources: &mut HashMap<&'static str, ConfigSource>,
+    explicit_keys: &[String],
+    profile_keys: &[String],
+    managed: &ManagedSettings,
+) {
+    for key in UserSettings::KEYS {
+        let source = if managed.overrides(key) {
+            ConfigSource::Managed
+        } else if profile_keys.iter().any(|k| k == key) {
+            ConfigSource::Profile(profiles::active().unwrap_or_default())
+        } else if explicit_keys.iter().any(|k| k == key) {
+            ConfigSource::UserSettings
+        } else {
+            ConfigSource::Default
+        };
+        sources.insert(*key, source);
+    }
+}
+
+fn write_setting(path: &Path, key: &str, value: serde_json::Value) -> Result<()> {
+    let mut raw: serde_json::Value = match fs::read_to_string(path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse settings file: {:?}", path))?,
+        Err(_) => serde_json::json!({}),
+    };
+    raw.as_object_mut()
+        .with_context(|| format!("Settings file must be a JSON object: {:?}", path))?
+        .insert(key.to_string(), value);
+
+    let content = serde_json::to_string_pretty(&raw).context("Failed to serialize settings")?;
+    fs::write(path, content).with_context(|| format!("Failed to write settings file: {:?}", path))
+}
+
+/// 将旧版本的 settings.json 逐步迁移到当前 schema
+///
+/// 返回迁移后的值，以及迁移前的版本（未迁移时为 None）
+fn migrate_settings(mut raw: serde_json::Value) -> Result<(serde_json::Value, Option<u32>)> {
+    let obj = raw
+        .as_object_mut()
+        .context("Settings file must be a JSON object")?;
+
+    let original = obj
+        .get("schema_version")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0) as u32;
+    let mut version = original;
+
+    while version < CURRENT_SCHEMA_VERSION {
+        match version {
+            // v0 -> v1: 引入 schema_version，enabledPlugins 改为 enabled_plugins
+            0 => {
+                if let Some(plugins) = obj.remove("enabledPlugins") {
+                    obj.entry("enabled_plugins").or_insert(plugins);
+                }
+            }
+            _ => unreachable!("missing migration from schema v{}", version),
+        }
+        version += 1;
+    }
+
+    if version == original {
+        return Ok((raw, None));
+    }
+
+    obj.insert("schema_version".to_string(), version.into());
+    Ok((raw, Some(original)))
+}
+
+/// 为未知的配置键寻找最接近的已知键
+pub fn suggest_key(key: &str) -> Option<&'static str> {
+    let normalized = key.to_lowercase().replace('-', "_");
+    UserSettings::KEYS
+        .iter()
+        .map(|known| (*known, edit_distance(&normalized, known)))
+        .filter(|(known, distance)| *distance <= (known.len() / 3).max(2))
+        .min_by_key(|(_, distance)| *distance)
+        .map(|(known, _)| known)
+}
+
+/// 计算两个字符串的编辑距离
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b_chars: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b_chars.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1; b_chars.len() + 1];
+        for (j, cb) in b_chars.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            current[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1);
+        }
+        prev = current;
+    }
+
+    prev[b_chars.len()]
+}
+
+/// 遮蔽密钥，只保留前缀和末尾几位
+pub fn mask_secret(secret: &str) -> String {
+    let chars: Vec<char> = secret.chars().collect();
+    if chars.len() <= 12 {
+        return "*".repeat(chars.len().max(4));
+    }
+
+    let head: String = chars[..7].iter().collect();
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("{}…{}", head, tail)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -241,4 +1233,134 @@ mod tests {
         let json = serde_json::to_string(&settings).unwrap();
         let _deserialized: UserSettings = serde_json::from_str(&json).unwrap();
     }
+
+    #[test]
+    fn test_mask_secret() {
+        assert_eq!(mask_secret("sk-ant-api03-abcdefghijkl"), "sk-ant-…ijkl");
+        assert_eq!(mask_secret("short"), "*****");
+        assert!(!mask_secret("sk-ant-api03-secretsecret").contains("secret"));
+    }
+
+    #[test]
+    fn test_cli_overrides_record_source() {
+        let mut config = Config {
+            user_settings: UserSettings::default(),
+            api_key: String::new(),
+            api_base_url: "https://api.anthropic.com/v1/messages".to_string(),
+            api_timeout_ms: 120_000,
+            retry: RetryConfig::default(),
+            anthropic_version: DEFAULT_ANTHROPIC_VERSION.to_string(),
+            anthropic_beta: vec!["prompt-caching-2024-07-31".to_string()],
+            sources: HashMap::new(),
+            warnings: Vec::new(),
+            managed: Default::default(),
+        };
+        assert!(config.require_api_key().is_err());
+
+        config.apply_cli_overrides(Some("sk-ant-cli".to_string()), None, Some(30));
+        assert!(config.require_api_key().is_ok());
+        assert_eq!(config.api_timeout_ms, 30_000);
+        assert_eq!(config.sources["api_key"], ConfigSource::Cli("--api-key"));
+
+        let entries = config.effective_entries();
+        let timeout = entries.iter().find(|e| e.key == "api_timeout_ms").unwrap();
+        assert_eq!(timeout.source, "--timeout");
+        config.apply_cli_overrides(None, None, Some(u64::MAX));
+        assert_eq!(config.api_timeout_ms, u64::MAX);
+
+        config.apply_api_header_overrides(
+            Some("2024-01-01".to_string()),
+            vec![
+                "prompt-caching-2024-07-31".to_string(),
+                "context-1m-2025-08-07".to_string(),
+            ],
+        );
+        assert_eq!(config.anthropic_version, "2024-01-01");
+        assert_eq!(
+            config.anthropic_beta,
+            ["prompt-caching-2024-07-31", "context-1m-2025-08-07"]
+        );
+        assert_eq!(
+            config.sources["anthropic_beta"],
+            ConfigSource::Cli("--beta")
+        );
+    }
+
+    #[test]
+    fn test_retry_settings_from_file() {
+        let settings: UserSettings = serde_json::from_value(serde_json::json!({
+            "retry": { "max_retries": 5, "initial_delay_ms": 250 }
+        }))
+        .unwrap();
+
+        let mut sources = HashMap::new();
+        let retry = Config::get_retry_config(&settings.retry, &mut sources);
+        assert_eq!(retry.max_retries, 5);
+        assert_eq!(retry.initial_delay, Duration::from_millis(250));
+        assert_eq!(retry.max_elapsed, RetryConfig::default().max_elapsed);
+        assert_eq!(sources["retry.max_retries"], ConfigSource::UserSettings);
+        assert_eq!(sources["retry.max_elapsed_secs"], ConfigSource::Default);
+    }
+
+    #[test]
+    fn test_write_setting_keeps_other_keys() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("settings.json");
+        fs::write(&path, r#"{"model": "claude-opus-4", "pager": false}"#).unwrap();
+
+        write_setting(&path, "output_style", "teaching".into()).unwrap();
+        let settings: UserSettings =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(settings.output_style, OutputStyle::Teaching);
+        assert_eq!(settings.model.as_deref(), Some("claude-opus-4"));
+        assert!(!settings.pager);
+    }
+
+    #[test]
+    fn test_migrate_v0_settings() {
+        let raw = serde_json::json!({
+            "theme": "dark",
+            "enabledPlugins": ["example@plugins"]
+        });
+        let (migrated, from) = migrate_settings(raw).unwrap();
+        assert_eq!(from, Some(0));
+        assert_eq!(migrated["schema_version"], CURRENT_SCHEMA_VERSION);
+        assert_eq!(migrated["enabled_plugins"][0], "example@plugins");
+        assert!(migrated.get("enabledPlugins").is_none());
+
+        let (_, from) = migrate_settings(migrated).unwrap();
+        assert_eq!(from, None);
+    }
+
+    #[test]
+    fn test_model_routing() {
+        let settings: UserSettings = serde_json::from_value(serde_json::json!({
+            "model_routing": { "summarize": "claude-haiku-4-5", "tool_results": "" }
+        }))
+        .unwrap();
+        let routing = &settings.model_routing;
+        assert_eq!(
+            routing.model_for(RequestPurpose::Summarize),
+            Some("claude-haiku-4-5")
+        );
+        assert_eq!(routing.model_for(RequestPurpose::ToolResult), None);
+        assert_eq!(routing.model_for(RequestPurpose::Main), None);
+
+        assert_eq!(
+            RequestPurpose::after_tool("read_file"),
+            RequestPurpose::ToolResult
+        );
+        assert_eq!(
+            RequestPurpose::after_tool("execute_command"),
+            RequestPurpose::Main
+        );
+    }
+
+    #[test]
+    fn test_suggest_unknown_key() {
+        assert_eq!(suggest_key("them"), Some("theme"));
+        assert_eq!(suggest_key("autosave"), Some("auto_save"));
+        assert_eq!(suggest_key("api-base-url"), Some("api_base_url"));
+        assert_eq!(suggest_key("completely_unrelated"), None);
+    }
 }
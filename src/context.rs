@@ -0,0 +1,643 @@
+use anyhow::{Context, Result};
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::RequestPurpose;
+use crate::dedup;
+use crate::error::ApiClient;
+
+/// 模型的上下文窗口大小（token）
+pub const CONTEXT_WINDOW_TOKENS: usize = 200_000;
+
+/// 估算的上下文占用超过该比例时自动总结早期对话
+pub const SUMMARIZE_THRESHOLD: f64 = 0.8;
+
+/// 总结后保留的最近消息最多占用的 token 数
+const KEEP_RECENT_TOKENS: usize = CONTEXT_WINDOW_TOKENS / 4;
+
+/// 生成总结请求时，每个内容块最多保留的字符数
+const TRANSCRIPT_BLOCK_CHARS: usize = 2_000;
+
+const SUMMARY_PROMPT: &str = "Summarize the following conversation between a user and an AI \
+coding assistant so that the assistant can continue the work. Preserve the user's goals, \
+decisions made, files read or modified, commands run and their key results, and any \
+unfinished tasks. Be concise but do not omit details needed to continue.";
+
+/// 短于这个长度的工具输出不替换，摘要并不会短多少
+const MIN_ELIDE_CHARS: usize = 2_000;
+
+/// 替换后的工具输出保留开头的行数
+const ELIDED_HEAD_LINES: usize = 3;
+
+const ELIDED_PREFIX: &str = "[Earlier output elided to save context.";
+
+/// PDF 附件按文件大小估算 token，约每 10 字节一个 token
+const DOCUMENT_BYTES_PER_TOKEN: usize = 10;
+
+/// 粗略估算消息占用的 token 数（约 4 个字符一个 token）
+pub fn estimate_tokens(messages: &[serde_json::Value]) -> usize {
+    messages.iter().map(estimate_message_tokens).sum()
+}
+
+/// base64 编码的文档不按字符数计算，否则一个 PDF 就会触发自动总结
+fn estimate_message_tokens(message: &serde_json::Value) -> usize {
+    let Some(blocks) = message["content"].as_array() else {
+        return message.to_string().len().div_ceil(4);
+    };
+    if !blocks.iter().any(|block| block["type"] == "document") {
+        return message.to_string().len().div_ceil(4);
+    }
+
+    blocks
+        .iter()
+        .map(|block| match block["source"]["data"].as_str() {
+            Some(data) if block["type"] == "document" => {
+                (data.len() / 4 * 3).div_ceil(DOCUMENT_BYTES_PER_TOKEN)
+            }
+            _ => block.to_string().len().div_ceil(4),
+        })
+        .sum()
+}
+
+/// 一次自动压缩的结果
+#[derive(Debug)]
+pub struct Compaction {
+    /// 被替换为摘要的较早工具输出数
+    pub elided_results: usize,
+    /// 被总结的消息数；只替换工具输出就足够时为 0
+    pub summarized_messages: usize,
+    pub tokens_before: usize,
+    pub tokens_after: usize,
+}
+
+/// 上下文接近上限时先把较早的工具输出替换为简短摘要，仍然不够时再把较早的消息替换为一条总结
+///
+/// 只在 assistant 消息处切分，保证保留部分中每个 tool_result 都能找到对应的 tool_use
+pub async fn compact_if_needed(
+    api_client: &ApiClient,
+    messages: &mut Vec<serde_json::Value>,
+    cancel: &CancellationToken,
+) -> Result<Option<Compaction>> {
+    let over_threshold =
+        |tokens: usize| tokens as f64 >= CONTEXT_WINDOW_TOKENS as f64 * SUMMARIZE_THRESHOLD;
+    let tokens_before = estimate_tokens(messages);
+    if !over_threshold(tokens_before) {
+        return Ok(None);
+    }
+
+    // 文件内容和命令日志通常占了大部分上下文，替换掉它们往往就足够了
+    let elided_results = elide_tool_results(messages, KEEP_RECENT_TOKENS);
+    let tokens_after = estimate_tokens(messages);
+    let elided = (elided_results > 0).then_some(Compaction {
+        elided_results,
+        summarized_messages: 0,
+        tokens_before,
+        tokens_after,
+    });
+    if !over_threshold(tokens_after) {
+        return Ok(elided);
+    }
+
+    let Some(boundary) = compaction_boundary(messages, KEEP_RECENT_TOKENS) else {
+        return Ok(elided);
+    };
+
+    let request = json!([{
+        "role": "user",
+        "content": format!("{}\n\n<conversation>\n{}</conversation>",
+            SUMMARY_PROMPT, render_transcript(&messages[..boundary]))
+    }]);
+    let response = api_client
+        .call_claude_with_retry(RequestPurpose::Summarize, &request, false, cancel)
+        .await
+        .context("Failed to summarize conversation")?;
+    let summary = response_text(&response).context("Summary response contained no text")?;
+
+    messages.splice(..boundary, [summary_message(&summary)]);
+
+    Ok(Some(Compaction {
+        elided_results,
+        summarized_messages: boundary,
+        tokens_before,
+        tokens_after: estimate_tokens(messages),
+    }))
+}
+
+/// 把最近 `keep_tokens` 以外的较长工具输出替换为摘要，返回替换的个数
+///
+/// 摘要包含调用的工具和参数、输出的大小、哈希和开头几行；tool_result 块本身保留，
+/// 与 tool_use 的配对不受影响
+pub fn elide_tool_results(messages: &mut [serde_json::Value], keep_tokens: usize) -> usize {
+    let mut kept_tokens = 0;
+    let mut elided = 0;
+    for index in (0..messages.len()).rev() {
+        kept_tokens += estimate_tokens(std::slice::from_ref(&messages[index]));
+        if kept_tokens <= keep_tokens || index == 0 {
+            continue;
+        }
+        let (earlier, rest) = messages.split_at_mut(index);
+        let Some(blocks) = rest[0]["content"].as_array_mut() else {
+            continue;
+        };
+        for block in blocks.iter_mut().filter(|b| b["type"] == "tool_result") {
+            let output = tool_result_text(&block["content"]);
+            if output.len() < MIN_ELIDE_CHARS || output.starts_with(ELIDED_PREFIX) {
+                continue;
+            }
+            let id = block["tool_use_id"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            let call = earlier[index - 1]["content"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find(|b| b["type"] == "tool_use" && b["id"] == id.as_str());
+            block["content"] = json!(elided_summary(call, &output));
+            // 原内容已不在对话中，dedup 不能再引用它
+            dedup::forget(&id);
+            elided += 1;
+        }
+    }
+    elided
+}
+
+/// tool_result 的文本内容；图片等非文本块不计
+fn tool_result_text(content: &serde_json::Value) -> String {
+    match content {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block["text"].as_str())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn elided_summary(call: Option<&serde_json::Value>, output: &str) -> String {
+    let tool = match call {
+        Some(call) => {
+            let name = call["name"].as_str().unwrap_or("tool");
+            let argument = ["file_path", "path", "command", "pattern"]
+                .iter()
+                .find_map(|key| call["input"][key].as_str());
+            match argument {
+                Some(argument) => format!("{} {}", name, argument),
+                None => name.to_string(),
+            }
+        }
+        None => "tool".to_string(),
+    };
+    let mut hasher = std::hash::DefaultHasher::new();
+    std::hash::Hash::hash(output, &mut hasher);
+    let head: Vec<String> = output
+        .lines()
+        .take(ELIDED_HEAD_LINES)
+        .map(|line| line.chars().take(120).collect())
+        .collect();
+    format!(
+        "{} Output of {}: {} lines, {} bytes, hash {:016x}. It began:\n{}\n\
+         Run the tool again if you need the full output.]",
+        ELIDED_PREFIX,
+        tool,
+        output.lines().count(),
+        output.len(),
+        std::hash::Hasher::finish(&hasher),
+        head.join("\n")
+    )
+}
+
+/// 选择总结的切分点：返回保留部分第一条 assistant 消息的下标
+///
+/// 在不超过 `keep_tokens` 的前提下尽量多保留最近的消息，但至少保留最后一次助手回复
+fn compaction_boundary(messages: &[serde_json::Value], keep_tokens: usize) -> Option<usize> {
+    let mut kept_tokens = 0;
+    let mut boundary = None;
+
+    for (index, message) in messages.iter().enumerate().rev() {
+        kept_tokens += estimate_tokens(std::slice::from_ref(message));
+        if index == 0 {
+            break;
+        }
+        if message["role"] != "assistant" {
+            continue;
+        }
+        if boundary.is_some() && kept_tokens > keep_tokens {
+            break;
+        }
+        boundary = Some(index);
+    }
+
+    boundary
+}
+
+/// 限制对话历史的消息数量，且不拆散 tool_use/tool_result 配对
+pub fn trim_history(messages: &mut Vec<serde_json::Value>, max_messages: usize) {
+    if messages.len() <= max_messages {
+        return;
+    }
+    let earliest = messages.len() - max_messages;
+
+    // 优先从普通的用户消息开始保留
+    if let Some(start) = (earliest..messages.len()).find(|&i| is_plain_user_message(&messages[i])) {
+        messages.drain(..start);
+        return;
+    }
+
+    // 否则从 assistant 消息开始保留，并补一条用户消息使对话仍以 user 开头
+    if let Some(start) = (earliest..messages.len()).find(|&i| messages[i]["role"] == "assistant") {
+        messages.splice(
+            ..start,
+            [json!({
+                "role": "user",
+                "content": "[Earlier messages were removed to limit conversation length]"
+            })],
+        );
+    }
+}
+
+/// 最后一轮对话的起点：最后一条由用户输入（而非工具结果）的消息
+pub fn last_turn_start(messages: &[serde_json::Value]) -> Option<usize> {
+    messages.iter().rposition(is_plain_user_message)
+}
+
+/// 用户消息中输入的文字；带附件时取最后一个文本块
+pub fn prompt_text(content: &serde_json::Value) -> String {
+    match content {
+        serde_json::Value::String(text) => text.clone(),
+        serde_json::Value::Array(blocks) => blocks
+            .iter()
+            .rev()
+            .find(|block| block["type"] == "text")
+            .and_then(|block| block["text"].as_str())
+            .unwrap_or_default()
+            .to_string(),
+        _ => String::new(),
+    }
+}
+
+/// 一轮对话中模型回答的文字，多次回答之间空一行
+pub fn answer_text(messages: &[serde_json::Value]) -> String {
+    messages
+        .iter()
+        .filter(|message| message["role"] == "assistant")
+        .flat_map(|message| match &message["content"] {
+            serde_json::Value::String(text) => vec![text.as_str()],
+            content => content
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|block| block["type"] == "text")
+                .filter_map(|block| block["text"].as_str())
+                .collect(),
+        })
+        .filter(|text| !text.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// 替换用户消息中输入的文字，附件和粘贴内容保持不变
+pub fn replace_prompt_text(content: &serde_json::Value, text: &str) -> serde_json::Value {
+    let mut content = content.clone();
+    match content.as_array_mut().and_then(|blocks| {
+        blocks
+            .iter_mut()
+            .rev()
+            .find(|block| block["type"] == "text")
+    }) {
+        Some(block) => block["text"] = json!(text),
+        None => content = json!(text),
+    }
+    content
+}
+
+/// /context 显示的请求构成，均为估算的 token 数
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContextUsage {
+    /// 系统提示词，包含固定的文件
+    pub system: usize,
+    /// 每个固定文件在系统提示词中占用的部分
+    pub pinned: Vec<(String, usize)>,
+    pub tool_count: usize,
+    pub tools: usize,
+    pub turns: Vec<TurnUsage>,
+}
+
+/// 一轮对话：用户输入的一条消息及其后的回答和工具调用
+#[derive(Debug, Clone, PartialEq)]
+pub struct TurnUsage {
+    /// 提示词的第一行
+    pub prompt: String,
+    pub messages: usize,
+    pub tokens: usize,
+}
+
+impl ContextUsage {
+    pub fn measure(
+        system_prompt: Option<&str>,
+        pinned: Vec<(String, usize)>,
+        tools: &serde_json::Value,
+        messages: &[serde_json::Value],
+    ) -> Self {
+        let mut turns: Vec<TurnUsage> = Vec::new();
+        for message in messages {
+            let tokens = estimate_tokens(std::slice::from_ref(message));
+            match turns.last_mut() {
+                Some(turn) if !is_plain_user_message(message) => {
+                    turn.messages += 1;
+                    turn.tokens += tokens;
+                }
+                _ => turns.push(TurnUsage {
+                    prompt: prompt_text(&message["content"])
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    messages: 1,
+                    tokens,
+                }),
+            }
+        }
+        Self {
+            system: system_prompt.map_or(0, |prompt| prompt.len().div_ceil(4)),
+            pinned,
+            tool_count: tools.as_array().map_or(0, Vec::len),
+            tools: tools.to_string().len().div_ceil(4),
+            turns,
+        }
+    }
+
+    pub fn messages(&self) -> usize {
+        self.turns.iter().map(|turn| turn.tokens).sum()
+    }
+
+    pub fn total(&self) -> usize {
+        self.system + self.tools + self.messages()
+    }
+
+    /// 占上下文窗口的百分比
+    pub fn percent(&self) -> usize {
+        self.total() * 100 / CONTEXT_WINDOW_TOKENS
+    }
+}
+
+/// 不包含 tool_result 的用户消息
+fn is_plain_user_message(message: &serde_json::Value) -> bool {
+    message["role"] == "user"
+        && !message["content"]
+            .as_array()
+            .is_some_and(|blocks| blocks.iter().any(|b| b["type"] == "tool_result"))
+}
+
+fn summary_message(summary: &str) -> serde_json::Value {
+    json!({
+        "role": "user",
+        "content": format!("[Summary of the earlier conversation]\n\n{}", summary)
+    })
+}
+
+/// 把消息渲染为纯文本记录，供总结使用（不含工具定义，因此不能直接发送 tool_use 块）
+fn render_transcript(messages: &[serde_json::Value]) -> String {
+    let mut transcript = String::new();
+
+    for message in messages {
+        let role = message["role"].as_str().unwrap_or("unknown");
+        match &message["content"] {
+            serde_json::Value::String(text) => {
+                transcript.push_str(&format!("{}: {}\n\n", role, clip(text)));
+            }
+            serde_json::Value::Array(blocks) => {
+                for block in blocks {
+                    let line = match block["type"].as_str() {
+                        Some("text") => clip(block["text"].as_str().unwrap_or("")),
+                        Some("tool_use") => format!(
+                            "[called tool {} with {}]",
+                            block["name"].as_str().unwrap_or("?"),
+                            clip(&block["input"].to_string())
+                        ),
+                        Some("document") => format!(
+                            "[attached document {}]",
+                            block["title"].as_str().unwrap_or("untitled")
+                        ),
+                        Some("tool_result") => {
+                            let content = match &block["content"] {
+                                serde_json::Value::String(s) => s.clone(),
+                                other => other.to_string(),
+                            };
+                            format!("[tool result]\n{}", clip(&content))
+                        }
+                        _ => continue,
+                    };
+                    transcript.push_str(&format!("{}: {}\n\n", role, line));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    transcript
+}
+
+fn clip(text: &str) -> String {
+    if text.chars().count() <= TRANSCRIPT_BLOCK_CHARS {
+        return text.to_string();
+    }
+    let clipped: String = text.chars().take(TRANSCRIPT_BLOCK_CHARS).collect();
+    format!("{}… [truncated]", clipped)
+}
+
+fn response_text(response: &serde_json::Value) -> Option<String> {
+    let text: Vec<&str> = response["content"]
+        .as_array()?
+        .iter()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tool_exchange(id: &str, output: &str) -> Vec<serde_json::Value> {
+        vec![
+            json!({"role": "assistant", "content": [
+                {"type": "tool_use", "id": id, "name": "read_file", "input": {"file_path": "/a"}}
+            ]}),
+            json!({"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": id, "content": output}
+            ]}),
+        ]
+    }
+
+    /// 每个 tool_result 之前的消息必须包含对应的 tool_use
+    fn assert_pairing(messages: &[serde_json::Value]) {
+        assert_eq!(messages[0]["role"], "user");
+        for (i, message) in messages.iter().enumerate() {
+            for block in message["content"].as_array().into_iter().flatten() {
+                if block["type"] == "tool_result" {
+                    let id = &block["tool_use_id"];
+                    let previous = &messages[i - 1]["content"];
+                    assert!(previous
+                        .as_array()
+                        .unwrap()
+                        .iter()
+                        .any(|b| b["type"] == "tool_use" && &b["id"] == id));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_last_turn_boundary() {
+        let mut messages = vec![
+            json!({"role": "user", "content": "first"}),
+            json!({"role": "assistant", "content": [{"type": "text", "text": "ok"}]}),
+            json!({"role": "user", "content": [
+                {"type": "document", "title": "spec.pdf"},
+                {"type": "text", "text": "read the spec"}
+            ]}),
+        ];
+        messages.extend(tool_exchange("toolu_1", "done"));
+        messages
+            .push(json!({"role": "assistant", "content": [{"type": "text", "text": "all good"}]}));
+
+        let start = last_turn_start(&messages).unwrap();
+        assert_eq!(start, 2);
+        assert_eq!(answer_text(&messages[start..]), "all good");
+        assert_eq!(answer_text(&messages), "ok\n\nall good");
+        let content = &messages[start]["content"];
+        assert_eq!(prompt_text(content), "read the spec");
+
+        let edited = replace_prompt_text(content, "summarize the spec");
+        assert_eq!(edited[0]["type"], "document");
+        assert_eq!(edited[1]["text"], "summarize the spec");
+        assert_eq!(replace_prompt_text(&json!("a"), "b"), json!("b"));
+        assert_eq!(last_turn_start(&[]), None);
+    }
+
+    #[test]
+    fn test_context_usage_groups_turns() {
+        let mut messages = vec![json!({"role": "user", "content": "first question\nwith details"})];
+        messages.extend(tool_exchange("toolu_1", &"x".repeat(400)));
+        messages.push(json!({"role": "user", "content": "second"}));
+        let tools = json!([{"name": "read_file"}, {"name": "list_files"}]);
+        let usage = ContextUsage::measure(Some(&"s".repeat(40)), Vec::new(), &tools, &messages);
+
+        assert_eq!(usage.system, 10);
+        assert_eq!(usage.tool_count, 2);
+        assert_eq!(usage.turns.len(), 2);
+        assert_eq!(usage.turns[0].prompt, "first question");
+        assert_eq!(usage.turns[0].messages, 3);
+        assert!(usage.turns[0].tokens > 100);
+        assert_eq!(
+            usage.total(),
+            usage.system + usage.tools + estimate_tokens(&messages)
+        );
+        assert_eq!(usage.percent(), 0);
+    }
+
+    #[test]
+    fn test_elide_old_tool_results() {
+        let dump = "line of a large file\n".repeat(200);
+        let mut messages = vec![json!({"role": "user", "content": "read both files"})];
+        messages.push(json!({"role": "assistant", "content": [
+            {"type": "tool_use", "id": "toolu_old", "name": "read_file", "input": {"file_path": "src/old.rs"}}
+        ]}));
+        messages.push(json!({"role": "user", "content": [
+            {"type": "tool_result", "tool_use_id": "toolu_old", "content": dump}
+        ]}));
+        messages.extend(tool_exchange("toolu_new", &dump));
+        let before = estimate_tokens(&messages);
+
+        let keep = estimate_tokens(&messages[3..]);
+        assert_eq!(elide_tool_results(&mut messages, keep), 1);
+        let summary = messages[2]["content"][0]["content"].as_str().unwrap();
+        assert!(summary.starts_with(ELIDED_PREFIX), "{}", summary);
+        assert!(
+            summary.contains("read_file src/old.rs: 200 lines"),
+            "{}",
+            summary
+        );
+        assert!(summary.contains("line of a large file\n"), "{}", summary);
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "toolu_old");
+        // 最近的输出原样保留
+        assert_eq!(messages[4]["content"][0]["content"], json!(dump));
+        assert!(estimate_tokens(&messages) < before * 2 / 3);
+        assert_pairing(&messages);
+
+        // 已经替换过的不再处理
+        assert_eq!(elide_tool_results(&mut messages, keep), 0);
+    }
+
+    #[test]
+    fn test_trim_history_keeps_tool_pairs() {
+        let mut messages = vec![json!({"role": "user", "content": "start"})];
+        for i in 0..10 {
+            messages.extend(tool_exchange(&format!("toolu_{}", i), "ok"));
+        }
+
+        trim_history(&mut messages, 6);
+        assert!(messages.len() <= 7);
+        assert_pairing(&messages);
+
+        let mut messages = vec![json!({"role": "user", "content": "first"})];
+        messages.push(json!({"role": "assistant", "content": "reply"}));
+        messages.push(json!({"role": "user", "content": "second"}));
+        messages.extend(tool_exchange("toolu_x", "ok"));
+        trim_history(&mut messages, 4);
+        assert_eq!(messages[0]["content"], "second");
+        assert_pairing(&messages);
+    }
+
+    #[test]
+    fn test_compaction_boundary_keeps_latest_exchange() {
+        let mut messages = vec![json!({"role": "user", "content": "start"})];
+        for i in 0..5 {
+            messages.extend(tool_exchange(&format!("toolu_{}", i), &"x".repeat(400)));
+        }
+
+        // 预算不足时仍保留最后一次工具调用
+        let boundary = compaction_boundary(&messages, 1).unwrap();
+        assert_eq!(boundary, messages.len() - 2);
+
+        let boundary = compaction_boundary(&messages, 350).unwrap();
+        assert_eq!(boundary, messages.len() - 4);
+
+        let mut compacted = vec![summary_message("summary")];
+        compacted.extend_from_slice(&messages[boundary..]);
+        assert_pairing(&compacted);
+
+        assert_eq!(compaction_boundary(&messages[..1], 1), None);
+    }
+
+    #[test]
+    fn test_render_transcript_clips_tool_output() {
+        let mut messages = vec![json!({"role": "user", "content": "read /a"})];
+        messages.extend(tool_exchange("toolu_1", &"y".repeat(5_000)));
+
+        let transcript = render_transcript(&messages);
+        assert!(transcript.starts_with("user: read /a"));
+        assert!(transcript.contains("[called tool read_file"));
+        assert!(transcript.contains("[truncated]"));
+        assert!(transcript.len() < 3_000);
+    }
+
+    #[test]
+    fn test_document_tokens_estimated_from_size() {
+        let message = json!({"role": "user", "content": [
+            {"type": "document", "title": "spec.pdf",
+             "source": {"type": "base64", "media_type": "application/pdf", "data": "A".repeat(40_000)}},
+            {"type": "text", "text": "summarize"}
+        ]});
+        let tokens = estimate_tokens(std::slice::from_ref(&message));
+        assert!(tokens < 4_000, "{}", tokens);
+        assert!(render_transcript(&[message]).contains("[attached document spec.pdf]"));
+    }
+}
@@ -0,0 +1,243 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::tasks;
+
+/// 最多列出的文件数，按未覆盖行数从多到少排列
+const MAX_FILES: usize = 20;
+
+/// 一个源文件的行覆盖情况
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileCoverage {
+    pub path: String,
+    pub covered: usize,
+    pub total: usize,
+    /// 未覆盖的行，合并为连续的区间（含两端）
+    pub uncovered: Vec<(u32, u32)>,
+}
+
+impl FileCoverage {
+    fn percent(&self) -> f64 {
+        percent(self.covered, self.total)
+    }
+
+    fn uncovered_lines(&self) -> usize {
+        self.total - self.covered
+    }
+}
+
+fn percent(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        covered as f64 * 100.0 / total as f64
+    }
+}
+
+fn command(report: &Path) -> Command {
+    let mut cmd = Command::new("cargo");
+    // 测试失败时仍然生成报告
+    cmd.args(["llvm-cov", "--lcov", "--ignore-run-fail", "--output-path"])
+        .arg(report);
+    cmd
+}
+
+/// 显示给用户确认的命令行
+pub fn command_line() -> String {
+    "cargo llvm-cov --lcov --ignore-run-fail".to_string()
+}
+
+/// 运行 cargo llvm-cov，返回各文件的覆盖情况
+pub async fn run(cancel: &CancellationToken) -> Result<Vec<FileCoverage>> {
+    let installed = Command::new("cargo")
+        .args(["llvm-cov", "--version"])
+        .output()
+        .is_ok_and(|output| output.status.success());
+    if !installed {
+        bail!("cargo-llvm-cov is not installed (install it with `cargo install cargo-llvm-cov`)");
+    }
+
+    let report = std::env::temp_dir().join(format!("coverage-{}.lcov", Uuid::new_v4()));
+    let output = tasks::output(command(&report), cancel)
+        .await
+        .context("Failed to run cargo llvm-cov")?;
+    let lcov = std::fs::read_to_string(&report);
+    let _ = std::fs::remove_file(&report);
+    let Ok(lcov) = lcov else {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<&str> = stderr.trim_end().lines().collect();
+        bail!(
+            "cargo llvm-cov did not produce a report:\n{}",
+            lines[lines.len().saturating_sub(20)..].join("\n")
+        );
+    };
+
+    let cwd = std::env::current_dir()?;
+    Ok(parse_lcov(&lcov, &cwd))
+}
+
+/// 解析 lcov 报告，路径改为相对 `base`
+fn parse_lcov(lcov: &str, base: &Path) -> Vec<FileCoverage> {
+    let mut files = Vec::new();
+    let mut path: Option<String> = None;
+    let mut lines: Vec<(u32, bool)> = Vec::new();
+
+    for line in lcov.lines() {
+        if let Some(source) = line.strip_prefix("SF:") {
+            let source = Path::new(source);
+            path = Some(
+                source
+                    .strip_prefix(base)
+                    .unwrap_or(source)
+                    .display()
+                    .to_string(),
+            );
+            lines.clear();
+        } else if let Some(data) = line.strip_prefix("DA:") {
+            let mut fields = data.split(',');
+            if let (Some(Ok(number)), Some(hits)) =
+                (fields.next().map(str::parse::<u32>), fields.next())
+            {
+                lines.push((number, hits.trim() != "0"));
+            }
+        } else if line == "end_of_record" {
+            if let Some(path) = path.take() {
+                lines.sort_unstable();
+                lines.dedup_by_key(|(number, _)| *number);
+                files.push(FileCoverage {
+                    path,
+                    covered: lines.iter().filter(|(_, hit)| *hit).count(),
+                    total: lines.len(),
+                    uncovered: uncovered_ranges(&lines),
+                });
+            }
+        }
+    }
+    files
+}
+
+/// 把未覆盖的行合并为区间；中间只隔着没有代码的行（不在报告中）也算连续
+fn uncovered_ranges(lines: &[(u32, bool)]) -> Vec<(u32, u32)> {
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    let mut previous_hit = true;
+    for &(line, hit) in lines {
+        if !hit {
+            match ranges.last_mut() {
+                Some((_, end)) if !previous_hit => *end = line,
+                _ => ranges.push((line, line)),
+            }
+        }
+        previous_hit = hit;
+    }
+    ranges
+}
+
+/// 返回给模型的摘要；`filter` 只保留路径包含该字符串的文件
+pub fn render(files: &[FileCoverage], filter: Option<&str>) -> String {
+    let mut files: Vec<&FileCoverage> = files
+        .iter()
+        .filter(|file| filter.is_none_or(|filter| file.path.contains(filter)))
+        .collect();
+    if files.is_empty() {
+        return match filter {
+            Some(filter) => format!("No coverage data for files matching '{}'.", filter),
+            None => "No coverage data was reported.".to_string(),
+        };
+    }
+
+    let covered: usize = files.iter().map(|file| file.covered).sum();
+    let total: usize = files.iter().map(|file| file.total).sum();
+    let mut out = format!(
+        "Line coverage: {:.1}% ({}/{} lines in {} files)",
+        percent(covered, total),
+        covered,
+        total,
+        files.len()
+    );
+
+    files.retain(|file| file.uncovered_lines() > 0);
+    files.sort_by_key(|file| std::cmp::Reverse(file.uncovered_lines()));
+    for file in files.iter().take(MAX_FILES) {
+        let ranges: Vec<String> = file
+            .uncovered
+            .iter()
+            .map(|&(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{}-{}", start, end)
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "\n{}: {:.1}% ({}/{}), uncovered lines: {}",
+            file.path,
+            file.percent(),
+            file.covered,
+            file.total,
+            ranges.join(", ")
+        ));
+    }
+    if files.len() > MAX_FILES {
+        out.push_str(&format!(
+            "\n... and {} more files with uncovered lines",
+            files.len() - MAX_FILES
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LCOV: &str = "SF:/work/src/lib.rs\n\
+FN:1,add\n\
+DA:1,3\n\
+DA:2,3\n\
+DA:4,0\n\
+DA:5,0\n\
+DA:6,0\n\
+DA:8,0\n\
+DA:9,1\n\
+DA:12,0\n\
+end_of_record\n\
+SF:/work/src/main.rs\n\
+DA:1,1\n\
+DA:2,1\n\
+end_of_record\n";
+
+    #[test]
+    fn test_parse_lcov() {
+        let files = parse_lcov(LCOV, Path::new("/work"));
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            files[0],
+            FileCoverage {
+                path: "src/lib.rs".to_string(),
+                covered: 3,
+                total: 8,
+                uncovered: vec![(4, 8), (12, 12)],
+            }
+        );
+        assert_eq!(files[1].uncovered, Vec::new());
+    }
+
+    #[test]
+    fn test_render_coverage() {
+        let files = parse_lcov(LCOV, Path::new("/work"));
+        assert_eq!(
+            render(&files, None),
+            "Line coverage: 50.0% (5/10 lines in 2 files)\n\
+             src/lib.rs: 37.5% (3/8), uncovered lines: 4-8, 12"
+        );
+        assert!(render(&files, Some("main.rs")).starts_with("Line coverage: 100.0%"));
+        assert_eq!(
+            render(&files, Some("tools")),
+            "No coverage data for files matching 'tools'."
+        );
+    }
+}
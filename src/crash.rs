@@ -0,0 +1,179 @@
+use console::style;
+use dialoguer::Confirm;
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::backtrace::Backtrace;
+use std::fs;
+use std::io::IsTerminal;
+use std::panic::PanicHookInfo;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::clock;
+use crate::config::Config;
+use crate::i18n::{t, tf, Msg};
+use crate::{ui, urls};
+
+const ISSUE_URL: &str = "https://github.com/redoop/rust-claude-code/issues/new";
+/// 预填的 issue 正文上限，过长的 URL 会被浏览器或 GitHub 拒绝
+const MAX_ISSUE_BODY_CHARS: usize = 6000;
+
+/// 只处理第一次 panic，其他线程随后的 panic 不再重复生成报告
+static REPORTED: AtomicBool = AtomicBool::new(false);
+
+/// 双引号中的字符串（例如 Debug 输出的 String）可能是对话或文件内容
+static QUOTED: Lazy<Regex> = Lazy::new(|| Regex::new(r#""(?:[^"\\]|\\.)*""#).unwrap());
+static BACKTICKED: Lazy<Regex> = Lazy::new(|| Regex::new(r"`[^`]*`").unwrap());
+static API_KEY: Lazy<Regex> = Lazy::new(|| Regex::new(r"sk-ant-[A-Za-z0-9_\-]+").unwrap());
+
+/// 安装 panic hook：在默认输出之后保存崩溃报告，并询问是否提交 issue
+pub fn install_hook() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if REPORTED.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let report = render_report(info, &Backtrace::force_capture());
+        handle_report(&report);
+    }));
+}
+
+fn handle_report(report: &str) {
+    match save_report(report) {
+        Ok(path) => eprintln!(
+            "\n{}",
+            style(tf(Msg::CrashReportSaved, &[&path.display()])).yellow()
+        ),
+        Err(e) => eprintln!("\n{} {}", style(t(Msg::WarningLabel)).yellow(), e),
+    }
+
+    let url = issue_url(report);
+    let interactive = std::io::stdin().is_terminal() && std::io::stderr().is_terminal();
+    let open = interactive
+        && Confirm::with_theme(ui::dialog_theme().as_ref())
+            .with_prompt(t(Msg::CrashOpenIssue))
+            .default(false)
+            .interact()
+            .unwrap_or(false);
+    if !open || urls::open_in_browser(&url).is_err() {
+        eprintln!("{}", tf(Msg::CrashIssueLink, &[&ISSUE_URL]));
+    }
+}
+
+fn save_report(report: &str) -> std::io::Result<PathBuf> {
+    let dir = Config::get_claude_dir()
+        .map_err(std::io::Error::other)?
+        .join("crash");
+    fs::create_dir_all(&dir)?;
+    let path = dir.join(format!("crash_{}.txt", clock::unix_secs()));
+    fs::write(&path, report)?;
+    Ok(path)
+}
+
+/// 崩溃报告：版本、系统、线程、位置、panic 信息和调用栈，全部经过脱敏
+fn render_report(info: &PanicHookInfo, backtrace: &Backtrace) -> String {
+    let message = info
+        .payload()
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| info.payload().downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "(non-string panic payload)".to_string());
+    let location = info
+        .location()
+        .map(|l| format!("{}:{}:{}", l.file(), l.line(), l.column()))
+        .unwrap_or_default();
+    let thread = std::thread::current()
+        .name()
+        .unwrap_or("<unnamed>")
+        .to_string();
+
+    redact(&format!(
+        "rust-claude-code {} crashed\n\n\
+         os: {} {}\n\
+         thread: {}\n\
+         location: {}\n\
+         message: {}\n\n\
+         backtrace:\n{}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        thread,
+        location,
+        message,
+        backtrace
+    ))
+}
+
+/// 去掉可能来自对话或用户环境的内容：引号中的字符串、API key 和主目录路径
+fn redact(text: &str) -> String {
+    let text = QUOTED.replace_all(text, "\"<redacted>\"");
+    // 反引号中含空白或较长的内容是数据而不是代码名，例如字符边界 panic 中的整段文本
+    let text = BACKTICKED.replace_all(&text, |caps: &regex::Captures| {
+        let code = &caps[0];
+        if code.len() > 42 || code.contains(char::is_whitespace) {
+            "`<redacted>`".to_string()
+        } else {
+            code.to_string()
+        }
+    });
+    let text = API_KEY.replace_all(&text, "sk-ant-<redacted>");
+    match std::env::var("HOME") {
+        Ok(home) if home.len() > 1 => text.replace(&home, "~"),
+        _ => text.into_owned(),
+    }
+}
+
+/// 预填了标题和报告的 GitHub issue 地址
+fn issue_url(report: &str) -> String {
+    let title = report
+        .lines()
+        .find_map(|line| line.strip_prefix("message: "))
+        .unwrap_or("crash")
+        .chars()
+        .take(80)
+        .collect::<String>();
+    let mut body: String = report.chars().take(MAX_ISSUE_BODY_CHARS).collect();
+    if body.len() < report.len() {
+        body.push_str("\n...");
+    }
+    let body = format!("```\n{}\n```", body);
+    reqwest::Url::parse_with_params(
+        ISSUE_URL,
+        &[("title", format!("Crash: {}", title)), ("body", body)],
+    )
+    .map(String::from)
+    .unwrap_or_else(|_| ISSUE_URL.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact_conversation_content() {
+        let message =
+            "called `Result::unwrap()` on an `Err` value: Error(\"expected value\", line: 1)";
+        assert_eq!(
+            redact(message),
+            "called `Result::unwrap()` on an `Err` value: Error(\"<redacted>\", line: 1)"
+        );
+
+        let boundary =
+            "byte index 5 is not a char boundary; it is inside 'é' of `please refactor my code`";
+        assert!(!redact(boundary).contains("refactor"));
+        assert!(redact("key sk-ant-api03-abcDEF_123 leaked").contains("sk-ant-<redacted> leaked"));
+    }
+
+    #[test]
+    fn test_issue_url_is_bounded() {
+        let report = format!(
+            "rust-claude-code 0.1.0 crashed\nmessage: index out of bounds\n\nbacktrace:\n{}",
+            "   0: frame\n".repeat(2000)
+        );
+        let url = issue_url(&report);
+        assert!(url.starts_with(ISSUE_URL));
+        assert!(url.contains("title=Crash%3A+index+out+of+bounds"));
+        assert!(url.len() < 3 * MAX_ISSUE_BODY_CHARS + 500);
+    }
+}
@@ -0,0 +1,109 @@
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Mutex;
+
+/// 小于这个长度的内容直接重新返回，引用说明并不会更短
+const MIN_DEDUP_BYTES: usize = 1024;
+
+/// 本次会话中 read_file 结果的内容哈希，按 tool_use_id 记录
+static READ_HASHES: Lazy<Mutex<HashMap<String, u64>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn content_hash(content: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// 记录放入对话的 read_file 结果
+pub fn record(tool_use_id: &str, content: &str) {
+    if content.len() >= MIN_DEDUP_BYTES {
+        READ_HASHES
+            .lock()
+            .unwrap()
+            .insert(tool_use_id.to_string(), content_hash(content));
+    }
+}
+
+/// 读取结果已从对话中移除或被替换，之后不能再引用
+pub fn forget(tool_use_id: &str) {
+    READ_HASHES.lock().unwrap().remove(tool_use_id);
+}
+
+/// 对话中已有完全相同的读取结果时，返回引用它的说明来代替 `content`
+///
+/// 只查找仍在 `messages` 中的结果，被总结、重试或切换分支移除的不算
+pub fn earlier_read(
+    messages: &[serde_json::Value],
+    file_path: &str,
+    content: &str,
+) -> Option<String> {
+    if content.len() < MIN_DEDUP_BYTES {
+        return None;
+    }
+    let hash = content_hash(content);
+    let hashes = READ_HASHES.lock().unwrap();
+
+    let mut turn = 0;
+    for message in messages.iter().filter(|message| message["role"] == "user") {
+        let results: Vec<&str> = message["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|block| block["type"] == "tool_result")
+            .filter_map(|block| block["tool_use_id"].as_str())
+            .collect();
+        // 不含工具结果的用户消息是一轮新的提问
+        if results.is_empty() {
+            turn += 1;
+            continue;
+        }
+        if let Some(id) = results
+            .into_iter()
+            .find(|id| hashes.get(*id) == Some(&hash))
+        {
+            return Some(format!(
+                "[{} is unchanged since it was read in turn {} (tool_use_id {}); \
+                 that result is still current, so refer to it instead of reading the file again.]",
+                file_path, turn, id
+            ));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tool_result(id: &str, content: &str) -> serde_json::Value {
+        json!({
+            "role": "user",
+            "content": [{"type": "tool_result", "tool_use_id": id, "content": content}]
+        })
+    }
+
+    #[test]
+    fn test_unchanged_read_references_earlier_result() {
+        let content = "fn main() {}\n".repeat(200);
+        let mut messages = vec![
+            json!({"role": "user", "content": "first"}),
+            json!({"role": "user", "content": "second"}),
+            tool_result("toolu_dedup_1", &content),
+        ];
+        record("toolu_dedup_1", &content);
+
+        let reference = earlier_read(&messages, "src/main.rs", &content).unwrap();
+        assert!(reference.contains("turn 2"), "{}", reference);
+        assert!(reference.contains("toolu_dedup_1"), "{}", reference);
+
+        // 内容变化或很短时照常返回
+        assert!(earlier_read(&messages, "src/main.rs", &format!("{}x", content)).is_none());
+        assert!(earlier_read(&messages, "a.txt", "short").is_none());
+
+        // 之前的结果已不在对话中（例如被总结）时不能引用
+        messages.truncate(2);
+        assert!(earlier_read(&messages, "src/main.rs", &content).is_none());
+    }
+}
@@ -0,0 +1,84 @@
+use anyhow::Result;
+use std::sync::Mutex;
+
+use crate::permissions;
+use crate::tasks::{self, TaskStatus};
+
+/// dev_server_output 默认返回的行数
+pub const DEFAULT_TAIL_LINES: usize = 50;
+
+/// 与会话一起运行的开发服务器（后台任务 ID 和命令）
+static SERVER: Mutex<Option<(usize, String)>> = Mutex::new(None);
+
+/// 启动开发服务器；已有服务器在运行时先停止它
+pub fn start(command: &str) -> Result<usize> {
+    stop()?;
+    let id = tasks::spawn(command, &permissions::working_dir()?)?;
+    *SERVER.lock().unwrap() = Some((id, command.to_string()));
+    Ok(id)
+}
+
+/// 停止开发服务器；没有服务器时返回 false
+pub fn stop() -> Result<bool> {
+    let Some((id, _)) = SERVER.lock().unwrap().take() else {
+        return Ok(false);
+    };
+    tasks::kill(id)?;
+    Ok(true)
+}
+
+/// 当前开发服务器的命令
+pub fn command() -> Option<String> {
+    SERVER
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|(_, command)| command.clone())
+}
+
+/// 开发服务器的状态和最近的输出
+pub fn recent_output(lines: usize) -> Option<Result<(TaskStatus, String)>> {
+    let id = SERVER.lock().unwrap().as_ref().map(|(id, _)| *id)?;
+    Some(tasks::tail(id, lines))
+}
+
+/// 附加到系统提示词的说明，让模型知道可以查看服务器输出
+pub fn system_prompt() -> Option<String> {
+    command().map(|command| {
+        format!(
+            "A development server is running alongside this session (`{}`). \
+             Use dev_server_output to check its recent output, for example after editing files \
+             to see rebuild errors, instead of starting the server yourself.",
+            command
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_start_and_stop_server() {
+        start("echo listening on 3000; sleep 30").unwrap();
+        assert!(system_prompt().unwrap().contains("sleep 30"));
+
+        let mut output = String::new();
+        for _ in 0..100 {
+            let (status, text) = recent_output(10).unwrap().unwrap();
+            assert_eq!(status, TaskStatus::Running);
+            output = text;
+            if !output.is_empty() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(output, "listening on 3000\n");
+
+        assert!(stop().unwrap());
+        assert!(recent_output(10).is_none());
+        assert!(!stop().unwrap());
+    }
+}
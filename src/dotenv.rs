@@ -0,0 +1,205 @@
+//! 项目的 `.env` 和 `.claude/.env`：其中的变量只加入工具执行的命令的环境，不会发送给 API
+
+use anyhow::{anyhow, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::Mutex;
+
+/// 按顺序读取的文件，后面的文件覆盖前面的同名变量
+const FILES: &[&str] = &[".env", ".claude/.env"];
+
+/// 从 .env 文件加载的一个变量
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvVar {
+    pub key: String,
+    pub value: String,
+    /// 定义它的文件
+    pub source: PathBuf,
+}
+
+impl EnvVar {
+    /// 启动时的环境中已经有同名变量；以环境中的值为准
+    pub fn shadowed(&self) -> bool {
+        std::env::var_os(&self.key).is_some()
+    }
+}
+
+static LOADED: Mutex<Vec<EnvVar>> = Mutex::new(Vec::new());
+
+/// 重新读取 `dir` 下的 .env 文件，`enabled` 为 false 时清空；返回无法解析的行的错误
+pub fn load(dir: &Path, enabled: bool) -> Vec<anyhow::Error> {
+    let mut vars: Vec<EnvVar> = Vec::new();
+    let mut errors = Vec::new();
+    for file in FILES.iter().filter(|_| enabled) {
+        let path = dir.join(file);
+        let Ok(text) = std::fs::read_to_string(&path) else {
+            continue;
+        };
+        for (index, line) in text.lines().enumerate() {
+            match parse_line(line) {
+                Ok(Some((key, value))) => {
+                    vars.retain(|var| var.key != key);
+                    vars.push(EnvVar {
+                        key,
+                        value,
+                        source: PathBuf::from(file),
+                    });
+                }
+                Ok(None) => {}
+                Err(e) => errors.push(e.context(format!("Ignoring {}:{}", file, index + 1))),
+            }
+        }
+    }
+    *LOADED.lock().unwrap() = vars;
+    errors
+}
+
+/// 加载的所有变量，/env 显示
+pub fn vars() -> Vec<EnvVar> {
+    LOADED.lock().unwrap().clone()
+}
+
+/// 把加载的变量加入命令的环境；启动时环境中已有的变量不覆盖
+pub fn apply(cmd: &mut Command) {
+    for var in LOADED.lock().unwrap().iter() {
+        if !var.shadowed() {
+            cmd.env(&var.key, &var.value);
+        }
+    }
+}
+
+/// 解析一行：`KEY=VALUE`、`export KEY=VALUE`，值可以加单引号或双引号；空行和注释返回 None
+fn parse_line(line: &str) -> Result<Option<(String, String)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+    let line = line.strip_prefix("export ").unwrap_or(line);
+    let (key, value) = line
+        .split_once('=')
+        .ok_or_else(|| anyhow!("expected KEY=VALUE"))?;
+    let key = key.trim();
+    if key.is_empty()
+        || key.starts_with(|c: char| c.is_ascii_digit())
+        || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(anyhow!("'{}' is not a valid variable name", key));
+    }
+    let value = value.trim();
+    let value = if let Some(rest) = value.strip_prefix('"') {
+        let end = rest
+            .rfind('"')
+            .ok_or_else(|| anyhow!("unterminated double quote"))?;
+        unescape(&rest[..end])
+    } else if let Some(rest) = value.strip_prefix('\'') {
+        let end = rest
+            .rfind('\'')
+            .ok_or_else(|| anyhow!("unterminated single quote"))?;
+        rest[..end].to_string()
+    } else {
+        // 不加引号时 ` #` 之后是注释
+        match value.find(" #") {
+            Some(comment) => value[..comment].trim_end().to_string(),
+            None => value.to_string(),
+        }
+    };
+    Ok(Some((key.to_string(), value)))
+}
+
+/// 双引号中支持常见的转义；从左到右处理一遍，`\\n` 是反斜杠加 n
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('"') => result.push('"'),
+            Some('\\') => result.push('\\'),
+            Some(other) => {
+                result.push('\\');
+                result.push(other);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_line() {
+        let parse = |line| parse_line(line).unwrap();
+        assert_eq!(parse("# comment"), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(
+            parse("DATABASE_URL=postgres://localhost:5432/dev # local db"),
+            Some((
+                "DATABASE_URL".to_string(),
+                "postgres://localhost:5432/dev".to_string()
+            ))
+        );
+        assert_eq!(
+            parse("export PORT = 3000"),
+            Some(("PORT".to_string(), "3000".to_string()))
+        );
+        assert_eq!(
+            parse(r#"GREETING="hello # not a comment\nbye""#),
+            Some((
+                "GREETING".to_string(),
+                "hello # not a comment\nbye".to_string()
+            ))
+        );
+        assert_eq!(
+            parse(r#"ESCAPED="a\\nb""#),
+            Some(("ESCAPED".to_string(), "a\\nb".to_string()))
+        );
+        assert_eq!(
+            parse("RAW='a\\nb'"),
+            Some(("RAW".to_string(), "a\\nb".to_string()))
+        );
+        assert!(parse_line("no equals sign").is_err());
+        assert!(parse_line("1KEY=x").is_err());
+        assert!(parse_line("KEY=\"open").is_err());
+    }
+
+    #[test]
+    fn test_claude_env_overrides_project_env() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join(".env"),
+            "PORT=3000\nAPI_URL=http://localhost:3000\nbroken line\n",
+        )
+        .unwrap();
+        std::fs::create_dir(dir.path().join(".claude")).unwrap();
+        std::fs::write(dir.path().join(".claude/.env"), "PORT=4000\n").unwrap();
+
+        let errors = load(dir.path(), true);
+        assert_eq!(errors.len(), 1);
+        assert_eq!(format!("{}", errors[0]), "Ignoring .env:3");
+        let loaded = vars();
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded[0].key, "API_URL");
+        assert_eq!(
+            (loaded[1].value.as_str(), loaded[1].source.as_path()),
+            ("4000", Path::new(".claude/.env"))
+        );
+
+        let mut cmd = Command::new("true");
+        apply(&mut cmd);
+        assert!(cmd
+            .get_envs()
+            .any(|(key, value)| key == "PORT" && value == Some("4000".as_ref())));
+
+        assert!(load(dir.path(), false).is_empty());
+        assert!(vars().is_empty());
+    }
+}
@@ -0,0 +1,987 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::citations::{self, Citation};
+use crate::config::{Config, RequestPurpose};
+use crate::context;
+use crate::dedup;
+use crate::error::{ApiClient, ClaudeCodeError};
+use crate::permissions::ApprovalRequest;
+use crate::telemetry;
+use crate::tool_output;
+use crate::tool_stats::ToolStats;
+
+const MAX_CONVERSATION_HISTORY: usize = 50;
+
+/// 响应因 max_tokens 被截断时最多自动续写的次数
+const MAX_CONTINUATIONS: usize = 3;
+
+/// /variants 最多同时请求的候选回答数
+pub const MAX_VARIANTS: usize = 5;
+
+/// 对话引擎在一轮对话中发出的事件，终端据此输出，serve 模式转发给客户端
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// 开始等待模型回答
+    Waiting,
+    /// 收到模型回答
+    Responded,
+    /// 回答中一段连续的文本，引用整理为脚注
+    Text {
+        text: String,
+        footnotes: Vec<String>,
+    },
+    /// 模型请求调用工具
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+    },
+    ToolStarted {
+        id: String,
+        name: String,
+    },
+    /// 工具执行结束；失败时 output 是错误信息，本轮对话随之结束
+    ToolFinished {
+        id: String,
+        name: String,
+        ok: bool,
+        output: String,
+    },
+    /// 执行工具前需要用户批准，通过 approve 回复
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    PermissionRequest {
+        id: u64,
+        tool: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        command: Option<String>,
+        /// 命令规则给出的询问原因
+        #[serde(skip_serializing_if = "Option::is_none")]
+        reason: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        path: Option<PathBuf>,
+    },
+    /// 工具输出中有像是写给模型的指令，findings 是 injection::describe 的摘要
+    ContentFlagged {
+        tool: String,
+        findings: String,
+    },
+    ContextSummarized {
+        summarized_messages: usize,
+        tokens_before: usize,
+        tokens_after: usize,
+    },
+    /// 只把较早的工具输出替换为摘要就回到了上限以下
+    ToolResultsElided {
+        count: usize,
+        tokens_before: usize,
+        tokens_after: usize,
+    },
+    /// 续写后回答仍被截断
+    ResponseTruncated,
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    TurnComplete {
+        outcome: ConversationOutcome,
+    },
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    Error {
+        message: String,
+        /// error::kind 给出的类别，例如 api、tool_failed
+        kind: &'static str,
+    },
+}
+
+/// /variants 请求 `n` 个回答时使用的温度，在 0 到 1 之间均匀分布
+pub fn variant_temperatures(n: usize) -> Vec<f64> {
+    let steps = n.saturating_sub(1).max(1) as f64;
+    (0..n)
+        .map(|i| (i as f64 / steps * 100.0).round() / 100.0)
+        .collect()
+}
+
+/// 事件的接收方，同时负责向用户询问是否批准工具调用
+pub trait EventSink: Sync {
+    fn emit(&self, event: Event);
+
+    /// 询问是否允许执行 `tool`；无法询问时应返回 false
+    fn approve<'a>(
+        &'a self,
+        tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>>;
+}
+
+// Claude API 响应结构
+#[derive(serde::Deserialize)]
+pub struct ClaudeResponse {
+    pub content: Vec<ContentBlock>,
+    #[serde(default)]
+    stop_reason: Option<String>,
+}
+
+impl ClaudeResponse {
+    /// 回答中的文字，不含工具调用
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|block| block.text.as_deref())
+            .collect()
+    }
+
+    /// 回答中请求调用的工具
+    pub fn tool_names(&self) -> Vec<&str> {
+        self.content
+            .iter()
+            .filter(|block| block.content_type == "tool_use")
+            .filter_map(|block| block.name.as_deref())
+            .collect()
+    }
+
+    fn is_truncated(&self) -> bool {
+        self.stop_reason.as_deref() == Some("max_tokens")
+    }
+
+    /// 截断的响应能否续写：只有以文本结尾时才能继续，被截断的 tool_use 输入无法拼接
+    fn trailing_text(&self) -> Option<&str> {
+        self.content
+            .last()
+            .filter(|block| block.content_type == "text")
+            .and_then(|block| block.text.as_deref())
+    }
+
+    /// 将续写请求的响应拼接到当前响应末尾
+    fn append_continuation(&mut self, prefix: &str, continuation: ClaudeResponse) {
+        let mut blocks = continuation.content.into_iter().peekable();
+
+        if let Some(last) = self.content.last_mut() {
+            let next_is_text = blocks
+                .peek()
+                .is_some_and(|block| block.content_type == "text");
+            if next_is_text {
+                let next = blocks
+                    .next()
+                    .and_then(|block| block.text)
+                    .unwrap_or_default();
+                last.text = Some(format!("{}{}", prefix, next));
+            }
+        }
+
+        self.content.extend(blocks);
+        self.stop_reason = continuation.stop_reason;
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ContentBlock {
+    #[serde(rename = "type")]
+    pub content_type: String,
+    pub text: Option<String>,
+    pub name: Option<String>,
+    pub id: Option<String>,
+    pub input: Option<serde_json::Value>,
+    #[serde(default)]
+    citations: Vec<Citation>,
+}
+
+// 工具使用任务结构
+struct ToolUseTask {
+    tool_use_id: String,
+    tool_name: String,
+    tool_input: serde_json::Value,
+}
+
+/// 对话（或工具调用循环）的结束方式
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversationOutcome {
+    Completed,
+    TurnLimitReached,
+    /// 调用方取消了这一轮
+    Cancelled,
+}
+
+/// 与终端无关的对话核心：发送消息、执行工具、维护消息列表，过程通过事件通知调用方
+pub struct Engine<'a> {
+    pub api_client: &'a ApiClient,
+    pub config: &'a Config,
+    pub tool_stats: &'a ToolStats,
+    pub events: &'a dyn EventSink,
+    /// 取消后进行中的请求被放弃、正在运行的命令被终止，这一轮以 Cancelled 结束
+    pub cancel: &'a CancellationToken,
+    /// 只执行只读工具，其他工具调用被拒绝；/compare 的对话副本使用
+    pub read_only: bool,
+    /// 用户拒绝工具调用时结束这一轮并返回 ToolDenied，而不是把原因告诉模型；-p 模式据此以退出码 4 结束
+    pub fail_on_denial: bool,
+}
+
+impl Engine<'_> {
+    /// 发送一条用户消息，执行模型请求的工具，直到模型不再调用工具
+    ///
+    /// `max_rounds` 限制工具调用后的后续请求次数，None 表示不限制
+    pub async fn run_turn(
+        &self,
+        messages: &mut Vec<serde_json::Value>,
+        content: serde_json::Value,
+        max_rounds: Option<usize>,
+    ) -> Result<ConversationOutcome> {
+        let before = messages.len();
+        match self.run_turn_inner(messages, content, max_rounds).await {
+            Err(e) if self.cancel.is_cancelled() => {
+                info!("Turn cancelled: {:#}", e);
+                settle_cancelled(messages, before);
+                Ok(ConversationOutcome::Cancelled)
+            }
+            result => result,
+        }
+    }
+
+    /// 以不同的温度同时请求多个回答，不执行工具，也不改变 `messages`
+    ///
+    /// 每个温度对应一个结果，单个请求失败不影响其他请求
+    pub async fn variants(
+        &self,
+        messages: &[serde_json::Value],
+        content: &serde_json::Value,
+        temperatures: &[f64],
+    ) -> Vec<Result<ClaudeResponse>> {
+        let mut request = messages.to_vec();
+        request.push(json!({
+            "role": "user",
+            "content": content
+        }));
+        self.events.emit(Event::Waiting);
+        let responses =
+            futures_util::future::join_all(temperatures.iter().map(|temperature| {
+                self.request(RequestPurpose::Main, &request, Some(*temperature))
+            }))
+            .await;
+        self.events.emit(Event::Responded);
+        responses
+    }
+
+    /// 把用户消息和已经得到的回答加入对话，再像 run_turn 一样执行回答中的工具
+    ///
+    /// 回答的文字已经显示过，不再输出
+    pub async fn accept_response(
+        &self,
+        messages: &mut Vec<serde_json::Value>,
+        content: serde_json::Value,
+        response: ClaudeResponse,
+        max_rounds: Option<usize>,
+    ) -> Result<ConversationOutcome> {
+        let before = messages.len();
+        messages.push(json!({
+            "role": "user",
+            "content": content
+        }));
+        match self.respond(messages, response, max_rounds, false).await {
+            Err(e) if self.cancel.is_cancelled() => {
+                info!("Turn cancelled: {:#}", e);
+                settle_cancelled(messages, before);
+                Ok(ConversationOutcome::Cancelled)
+            }
+            result => result,
+        }
+    }
+
+    async fn run_turn_inner(
+        &self,
+        messages: &mut Vec<serde_json::Value>,
+        content: serde_json::Value,
+        max_rounds: Option<usize>,
+    ) -> Result<ConversationOutcome> {
+        messages.push(json!({
+            "role": "user",
+            "content": content
+        }));
+        self.compact(messages).await;
+
+        let response = self
+            .call(RequestPurpose::Main, messages)
+            .await
+            .context("API call failed")?;
+        self.respond(messages, response, max_rounds, true).await
+    }
+
+    /// 处理模型的回答：输出文字、执行请求的工具，并把回答加入对话
+    async fn respond(
+        &self,
+        messages: &mut Vec<serde_json::Value>,
+        response: ClaudeResponse,
+        max_rounds: Option<usize>,
+        echo: bool,
+    ) -> Result<ConversationOutcome> {
+        let mut outcome = ConversationOutcome::Completed;
+        for (index, block) in response.content.iter().enumerate() {
+            match block.content_type.as_str() {
+                // 带引用的回答会被拆成多个文本块，从第一块开始整段输出
+                "text"
+                    if echo
+                        && (index == 0 || response.content[index - 1].content_type != "text") =>
+                {
+                    self.emit_text_run(&response.content[index..]);
+                }
+                "tool_use" => {
+                    let name = block.name.as_ref().context("Missing tool name")?;
+                    let id = block.id.as_ref().context("Missing tool id")?;
+                    let input = block.input.as_ref().context("Missing tool input")?;
+
+                    info!("Tool execution requested: {}", name);
+                    self.events.emit(Event::ToolUse {
+                        id: id.clone(),
+                        name: name.clone(),
+                        input: input.clone(),
+                    });
+
+                    messages.push(json!({
+                        "role": "assistant",
+                        "content": [{
+                            "type": "tool_use",
+                            "id": id,
+                            "name": name,
+                            "input": input
+                        }]
+                    }));
+
+                    outcome = self
+                        .process_tool_use(
+                            messages,
+                            ToolUseTask {
+                                tool_use_id: id.clone(),
+                                tool_name: name.clone(),
+                                tool_input: input.clone(),
+                            },
+                            max_rounds,
+                        )
+                        .await?;
+
+                    if outcome == ConversationOutcome::TurnLimitReached {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // 如果没有工具使用，添加助手响应到历史
+        if !response
+            .content
+            .iter()
+            .any(|b| b.content_type == "tool_use")
+        {
+            let assistant_content: Vec<serde_json::Value> = response
+                .content
+                .iter()
+                .map(|block| {
+                    json!({
+                        "type": block.content_type,
+                        "text": block.text
+                    })
+                })
+                .collect();
+
+            messages.push(json!({
+                "role": "assistant",
+                "content": assistant_content
+            }));
+        }
+
+        Ok(outcome)
+    }
+
+    /// 请求模型回答，回答因 max_tokens 被截断时自动续写
+    pub async fn call(
+        &self,
+        purpose: RequestPurpose,
+        messages: &[serde_json::Value],
+    ) -> Result<ClaudeResponse> {
+        self.events.emit(Event::Waiting);
+        let response = self
+            .request(purpose, messages, self.api_client.temperature())
+            .await?;
+        self.events.emit(Event::Responded);
+        Ok(response)
+    }
+
+    /// 以指定的温度请求回答，续写请求使用同一温度
+    async fn request(
+        &self,
+        purpose: RequestPurpose,
+        messages: &[serde_json::Value],
+        temperature: Option<f64>,
+    ) -> Result<ClaudeResponse> {
+        let response_json = self
+            .api_client
+            .call_claude_with_temperature(purpose, &json!(messages), true, temperature, self.cancel)
+            .await?;
+
+        let mut claude_response: ClaudeResponse = serde_json::from_value(response_json)?;
+
+        // 响应被 max_tokens 截断时，把已生成的文本作为 assistant 前缀请求续写
+        let mut continuations = 0;
+        while claude_response.is_truncated() && continuations < MAX_CONTINUATIONS {
+            let Some(text) = claude_response.trailing_text() else {
+                break;
+            };
+            // API 不接受以空白结尾的 assistant 前缀
+            let prefix = text.trim_end().to_string();
+
+            continuations += 1;
+            info!(
+                "Response truncated at max_tokens, requesting continuation ({}/{})",
+                continuations, MAX_CONTINUATIONS
+            );
+
+            let mut continued = messages.to_vec();
+            continued.push(json!({
+                "role": "assistant",
+                "content": prefix
+            }));
+
+            let response_json = self
+                .api_client
+                .call_claude_with_temperature(
+                    purpose,
+                    &json!(continued),
+                    true,
+                    temperature,
+                    self.cancel,
+                )
+                .await?;
+            let continuation: ClaudeResponse = serde_json::from_value(response_json)?;
+            claude_response.append_continuation(&prefix, continuation);
+        }
+
+        if claude_response.is_truncated() {
+            warn!(
+                "Response still truncated after {} continuations",
+                continuations
+            );
+            self.events.emit(Event::ResponseTruncated);
+        }
+        Ok(claude_response)
+    }
+
+    // 上下文接近上限时自动总结早期对话
+    async fn compact(&self, messages: &mut Vec<serde_json::Value>) {
+        match context::compact_if_needed(self.api_client, messages, self.cancel).await {
+            Ok(Some(compaction)) if compaction.summarized_messages == 0 => {
+                info!(
+                    "Elided {} earlier tool results ({} -> {} estimated tokens)",
+                    compaction.elided_results, compaction.tokens_before, compaction.tokens_after
+                );
+                self.events.emit(Event::ToolResultsElided {
+                    count: compaction.elided_results,
+                    tokens_before: compaction.tokens_before,
+                    tokens_after: compaction.tokens_after,
+                });
+            }
+            Ok(Some(compaction)) => {
+                info!(
+                    "Summarized {} messages after eliding {} tool results ({} -> {} estimated tokens)",
+                    compaction.summarized_messages,
+                    compaction.elided_results,
+                    compaction.tokens_before,
+                    compaction.tokens_after
+                );
+                self.events.emit(Event::ContextSummarized {
+                    summarized_messages: compaction.summarized_messages,
+                    tokens_before: compaction.tokens_before,
+                    tokens_after: compaction.tokens_after,
+                });
+            }
+            Ok(None) => {}
+            Err(e) => warn!("Automatic summarization failed: {:#}", e),
+        }
+    }
+
+    /// 输出从 `blocks` 开头起连续的文本块，引用整理为脚注
+    fn emit_text_run(&self, blocks: &[ContentBlock]) {
+        let answer = citations::render(
+            blocks
+                .iter()
+                .take_while(|block| block.content_type == "text")
+                .map(|block| {
+                    (
+                        block.text.as_deref().unwrap_or(""),
+                        block.citations.as_slice(),
+                    )
+                }),
+        );
+        self.events.emit(Event::Text {
+            text: answer.text,
+            footnotes: answer.footnotes,
+        });
+    }
+
+    async fn process_tool_use(
+        &self,
+        messages: &mut Vec<serde_json::Value>,
+        initial_task: ToolUseTask,
+        max_rounds: Option<usize>,
+    ) -> Result<ConversationOutcome> {
+        let mut task_stack = vec![initial_task];
+        let mut rounds = 0;
+
+        while let Some(task) = task_stack.pop() {
+            if max_rounds.is_some_and(|max| rounds >= max) {
+                return Ok(ConversationOutcome::TurnLimitReached);
+            }
+            rounds += 1;
+
+            self.events.emit(Event::ToolStarted {
+                id: task.tool_use_id.clone(),
+                name: task.tool_name.clone(),
+            });
+            let started = std::time::Instant::now();
+            let tool_result = crate::execute_tool(
+                &task.tool_name,
+                &task.tool_input,
+                self.config,
+                self.events,
+                self.cancel,
+                self.read_only,
+            )
+            .await;
+            // 被拒绝的调用不算失败：把原因作为结果告诉模型，这一轮继续
+            let tool_result = match tool_result {
+                Err(ClaudeCodeError::ToolDenied {
+                    reason, by_user, ..
+                }) if !(by_user && self.fail_on_denial) => Ok(reason),
+                result => result,
+            };
+            self.tool_stats.record(
+                &task.tool_name,
+                started.elapsed(),
+                tool_result.as_ref().ok().map(|output| output.len()),
+            );
+            telemetry::record(
+                if tool_result.is_ok() {
+                    "tool"
+                } else {
+                    "tool_error"
+                },
+                &task.tool_name,
+            );
+            let tool_result = match tool_result {
+                Ok(output) => output,
+                Err(e) => {
+                    let output = match &e {
+                        ClaudeCodeError::ToolFailed { error, .. } => format!("{:#}", error),
+                        e => e.to_string(),
+                    };
+                    self.events.emit(Event::ToolFinished {
+                        id: task.tool_use_id,
+                        name: task.tool_name,
+                        ok: false,
+                        output,
+                    });
+                    return Err(e.into());
+                }
+            };
+            let tool_result = finish_output(
+                messages,
+                &Config::get_claude_dir()?,
+                &task,
+                tool_result,
+                self.config.user_settings.tool_output_limit,
+            )?;
+            self.events.emit(Event::ToolFinished {
+                id: task.tool_use_id.clone(),
+                name: task.tool_name.clone(),
+                ok: true,
+                output: tool_result.clone(),
+            });
+
+            messages.push(json!({
+                "role": "user",
+                "content": [
+                    {
+                        "type": "tool_result",
+                        "tool_use_id": task.tool_use_id,
+                        "content": tool_result
+                    }
+                ]
+            }));
+
+            context::trim_history(messages, MAX_CONVERSATION_HISTORY);
+            self.compact(messages).await;
+
+            let purpose = RequestPurpose::after_tool(&task.tool_name);
+            let response = self.call(purpose, messages).await?;
+
+            // 收集新的工具使用任务
+            let mut new_tool_tasks = Vec::new();
+
+            // 处理响应中的所有内容块
+            for (index, block) in response.content.iter().enumerate() {
+                match block.content_type.as_str() {
+                    // 带引用的回答会被拆成多个文本块，从第一块开始整段输出
+                    "text" if index == 0 || response.content[index - 1].content_type != "text" => {
+                        self.emit_text_run(&response.content[index..]);
+                    }
+                    "tool_use" => {
+                        let name = block.name.as_ref().context("Missing tool name")?.clone();
+                        let id = block.id.as_ref().context("Missing tool id")?.clone();
+                        let input = block.input.as_ref().context("Missing tool input")?.clone();
+
+                        self.events.emit(Event::ToolUse {
+                            id: id.clone(),
+                            name: name.clone(),
+                            input: input.clone(),
+                        });
+
+                        // 将新任务添加到临时列表
+                        new_tool_tasks.push(ToolUseTask {
+                            tool_use_id: id,
+                            tool_name: name,
+                            tool_input: input,
+                        });
+                    }
+                    _ => {}
+                }
+            }
+
+            // 添加助手响应到历史
+            let assistant_content: Vec<serde_json::Value> = response
+                .content
+                .iter()
+                .map(|block| {
+                    json!({
+                        "type": block.content_type,
+                        "text": block.text,
+                        "name": block.name,
+                        "id": block.id,
+                        "input": block.input
+                    })
+                })
+                .collect();
+
+            messages.push(json!({
+                "role": "assistant",
+                "content": assistant_content
+            }));
+
+            // 限制对话历史长度
+            context::trim_history(messages, MAX_CONVERSATION_HISTORY);
+
+            // 将新工具任务添加到栈中（逆序添加以保持执行顺序）
+            for task in new_tool_tasks.into_iter().rev() {
+                task_stack.push(task);
+            }
+        }
+
+        Ok(ConversationOutcome::Completed)
+    }
+}
+
+/// 整理工具输出：重复读取未改动的文件时引用对话中已有的结果，不再重复放入全文，
+/// 否则按上限截断。去重按原始输出计算，超出上限被转存的读取也能匹配
+fn finish_output(
+    messages: &[serde_json::Value],
+    claude_dir: &std::path::Path,
+    task: &ToolUseTask,
+    output: String,
+    limit: usize,
+) -> Result<String> {
+    if task.tool_name != "read_file" {
+        return tool_output::limit_tool_output(claude_dir, &task.tool_use_id, output, limit);
+    }
+    let file_path = task.tool_input["file_path"].as_str().unwrap_or_default();
+    if let Some(reference) = dedup::earlier_read(messages, file_path, &output) {
+        return Ok(reference);
+    }
+    dedup::record(&task.tool_use_id, &output);
+    tool_output::limit_tool_output(claude_dir, &task.tool_use_id, output, limit)
+}
+
+/// 整理被取消的一轮留下的消息：还没有得到任何回答时撤回用户消息，
+/// 否则为最后一条助手消息中未完成的工具调用补上结果，保留已经完成的部分
+fn settle_cancelled(messages: &mut Vec<serde_json::Value>, before: usize) {
+    if messages.len() <= before + 1 {
+        messages.truncate(before);
+        return;
+    }
+    let Some(last) = messages
+        .last()
+        .filter(|message| message["role"] == "assistant")
+    else {
+        return;
+    };
+    let results: Vec<serde_json::Value> = last["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter(|block| block["type"] == "tool_use")
+        .map(|block| {
+            json!({
+                "type": "tool_result",
+                "tool_use_id": block["id"],
+                "content": "Cancelled by the user before this tool finished",
+                "is_error": true
+            })
+        })
+        .collect();
+    if !results.is_empty() {
+        messages.push(json!({
+            "role": "user",
+            "content": results
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(value: serde_json::Value) -> ClaudeResponse {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_append_continuation_merges_text() {
+        let mut first = response(json!({
+            "content": [{"type": "text", "text": "fn main() {\n    println!(  "}],
+            "stop_reason": "max_tokens"
+        }));
+        assert!(first.is_truncated());
+
+        let prefix = first.trailing_text().unwrap().trim_end().to_string();
+        let second = response(json!({
+            "content": [{"type": "text", "text": "\"hi\");\n}"}],
+            "stop_reason": "end_turn"
+        }));
+        first.append_continuation(&prefix, second);
+
+        assert!(!first.is_truncated());
+        assert_eq!(first.content.len(), 1);
+        assert_eq!(
+            first.content[0].text.as_deref(),
+            Some("fn main() {\n    println!(\"hi\");\n}")
+        );
+    }
+
+    #[test]
+    fn test_variant_temperatures_spread_evenly() {
+        assert_eq!(variant_temperatures(2), vec![0.0, 1.0]);
+        assert_eq!(variant_temperatures(4), vec![0.0, 0.33, 0.67, 1.0]);
+
+        let response = response(json!({
+            "content": [
+                {"type": "text", "text": "Let me check."},
+                {"type": "tool_use", "id": "toolu_1", "name": "read_file", "input": {}}
+            ]
+        }));
+        assert_eq!(response.text(), "Let me check.");
+        assert_eq!(response.tool_names(), vec!["read_file"]);
+    }
+
+    #[test]
+    fn test_truncated_tool_use_cannot_continue() {
+        let truncated = response(json!({
+            "content": [
+                {"type": "text", "text": "Writing the file"},
+                {"type": "tool_use", "id": "toolu_1", "name": "write_file", "input": {}}
+            ],
+            "stop_reason": "max_tokens"
+        }));
+        assert!(truncated.trailing_text().is_none());
+    }
+
+    #[test]
+    fn test_events_serialize_with_type_tag() {
+        let event = Event::PermissionRequest {
+            id: 3,
+            tool: "execute_command".to_string(),
+            command: Some("cargo test".to_string()),
+            reason: None,
+            path: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            json!({"type": "permission_request", "id": 3, "tool": "execute_command", "command": "cargo test"})
+        );
+        assert_eq!(
+            serde_json::to_value(Event::TurnComplete {
+                outcome: ConversationOutcome::TurnLimitReached
+            })
+            .unwrap(),
+            json!({"type": "turn_complete", "outcome": "turn_limit_reached"})
+        );
+    }
+
+    #[test]
+    fn test_cancelled_turn_rolls_back_or_closes_tool_uses() {
+        let mut messages = vec![json!({"role": "user", "content": "hi"})];
+        messages.push(json!({"role": "user", "content": "run the tests"}));
+        settle_cancelled(&mut messages, 1);
+        assert_eq!(messages.len(), 1);
+
+        messages.push(json!({"role": "user", "content": "run the tests"}));
+        messages.push(json!({"role": "assistant", "content": [
+            {"type": "tool_use", "id": "toolu_1", "name": "execute_command", "input": {}}
+        ]}));
+        settle_cancelled(&mut messages, 1);
+        assert_eq!(messages.len(), 4);
+        assert_eq!(messages[3]["content"][0]["tool_use_id"], "toolu_1");
+        assert_eq!(messages[3]["content"][0]["is_error"], true);
+    }
+
+    /// 按 `.0` 回答所有批准请求
+    struct Approve(bool);
+
+    impl EventSink for Approve {
+        fn emit(&self, _event: Event) {}
+
+        fn approve<'a>(
+            &'a self,
+            _tool: &'a str,
+            _request: &'a ApprovalRequest,
+        ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+            Box::pin(std::future::ready(self.0))
+        }
+    }
+
+    #[test]
+    fn test_oversized_reread_is_deduplicated() {
+        let claude_dir = tempfile::TempDir::new().unwrap();
+        let content = "x".repeat(4096);
+        let read = |id: &str| ToolUseTask {
+            tool_use_id: id.to_string(),
+            tool_name: "read_file".to_string(),
+            tool_input: json!({"file_path": "big.txt"}),
+        };
+
+        let mut messages = vec![json!({"role": "user", "content": "read big.txt"})];
+        let first = finish_output(
+            &messages,
+            claude_dir.path(),
+            &read("toolu_big_1"),
+            content.clone(),
+            1000,
+        )
+        .unwrap();
+        assert!(first.len() < content.len());
+        messages.push(json!({
+            "role": "user",
+            "content": [{"type": "tool_result", "tool_use_id": "toolu_big_1", "content": first}]
+        }));
+
+        let second = finish_output(
+            &messages,
+            claude_dir.path(),
+            &read("toolu_big_2"),
+            content,
+            1000,
+        )
+        .unwrap();
+        assert!(second.contains("is unchanged since"));
+        assert!(second.contains("toolu_big_1"));
+    }
+
+    /// 模型先调用 write_file 写 `target`，再回答 ok；用户拒绝所有批准请求
+    async fn write_turn(
+        target: &std::path::Path,
+        user_settings: crate::config::UserSettings,
+        read_only: bool,
+        fail_on_denial: bool,
+    ) -> (Result<ConversationOutcome>, Vec<serde_json::Value>) {
+        let mut api = mockito::Server::new_async().await;
+        api.mock("POST", "/v1/messages")
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "content": [{
+                        "type": "tool_use",
+                        "id": "toolu_1",
+                        "name": "write_file",
+                        "input": {"file_path": target, "content": "changed"}
+                    }],
+                    "stop_reason": "tool_use"
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        api.mock("POST", "/v1/messages")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "ok"}], "stop_reason": "end_turn"}"#,
+            )
+            .create_async()
+            .await;
+
+        let config = Config {
+            user_settings,
+            api_key: "test-key".to_string(),
+            api_base_url: format!("{}/v1/messages", api.url()),
+            api_timeout_ms: 5_000,
+            retry: Default::default(),
+            anthropic_version: "2023-06-01".to_string(),
+            anthropic_beta: Vec::new(),
+            sources: Default::default(),
+            warnings: Vec::new(),
+            managed: Default::default(),
+        };
+        let api_client = ApiClient::new(config.api_key.clone(), config.api_base_url.clone());
+        let mut messages = Vec::new();
+        let result = Engine {
+            api_client: &api_client,
+            config: &config,
+            tool_stats: &ToolStats::default(),
+            events: &Approve(false),
+            cancel: &CancellationToken::new(),
+            read_only,
+            fail_on_denial,
+        }
+        .run_turn(&mut messages, json!("write the notes"), None)
+        .await;
+        (result, messages)
+    }
+
+    #[tokio::test]
+    async fn test_read_only_turn_does_not_write() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let target = dir.path().join("notes.txt");
+        let (outcome, messages) = write_turn(&target, Default::default(), true, false).await;
+
+        assert_eq!(outcome.unwrap(), ConversationOutcome::Completed);
+        assert!(!target.exists());
+        let result = messages[2]["content"][0]["content"].to_string();
+        assert!(result.contains("Only read-only tools are available"));
+    }
+
+    #[tokio::test]
+    async fn test_user_denial_can_end_the_turn() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let target = dir.path().join("notes.txt");
+        let settings = || crate::config::UserSettings {
+            write_rules: vec![crate::policy::WriteRule {
+                pattern: "*.txt".to_string(),
+                action: crate::policy::Action::Ask,
+                reason: None,
+            }],
+            ..Default::default()
+        };
+
+        // 交互模式下拒绝的原因交给模型，这一轮继续
+        let (outcome, _) = write_turn(&target, settings(), false, false).await;
+        assert_eq!(outcome.unwrap(), ConversationOutcome::Completed);
+        // -p 模式下以退出码 4 结束
+        let (outcome, _) = write_turn(&target, settings(), false, true).await;
+        let err = outcome.unwrap_err();
+        assert_eq!(crate::error::kind(&err), "tool_denied");
+        assert_eq!(
+            crate::exit_code::ExitStatus::from_error(&err),
+            crate::exit_code::ExitStatus::PermissionDenied
+        );
+        assert!(!target.exists());
+    }
+}
@@ -1,13 +1,28 @@
 use anyhow::{Context, Result};
-use backoff::{future::retry, ExponentialBackoff};
+use backoff::{backoff::Backoff, future::retry, ExponentialBackoff};
+use console::style;
+use rand::rngs::StdRng;
+use rand::Rng;
 use reqwest::Client;
 use serde_json::json;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
+use crate::clock;
+use crate::config::{ModelRouting, RequestPurpose, DEFAULT_ANTHROPIC_VERSION, DEFAULT_MODEL};
+use crate::history::TokenUsage;
+use crate::i18n::{t, tf, Msg};
+use crate::rate_limit::{Pacer, RateLimits};
+use crate::response_cache::{self, ResponseCache};
+use crate::tools::{self, ToolFilter, ToolRegistry};
+
+/// 每个请求的 max_tokens
+pub const MAX_OUTPUT_TOKENS: u64 = 8192;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ApiError {
     #[error("API request failed with status {0}: {1}")]
@@ -29,20 +44,101 @@ pub enum ApiError {
     Network(#[from] reqwest::Error),
 
     #[error("Timeout after {0} seconds")]
-    #[allow(dead_code)]
     Timeout(u64),
 
     #[error("Response parsing error: {0}")]
     ParseError(#[from] serde_json::Error),
 }
 
+/// 按类别区分的错误：嵌入方、serve 和 acp 模式的错误事件以及退出码据此判断失败原因
+///
+/// `Config::load`、工具执行链和 `execute_tool` 直接返回它；其余代码仍然使用 anyhow，`kind` 从错误链中找出类别
+#[derive(Debug, thiserror::Error)]
+pub enum ClaudeCodeError {
+    /// 配置文件无法读取或解析
+    #[error("Invalid configuration: {0:#}")]
+    Config(anyhow::Error),
+
+    /// 访问工作区外的路径
+    #[error("{0}")]
+    Sandbox(String),
+
+    /// 工具调用被拒绝，reason 是告诉模型的原因；by_user 表示用户没有批准，而不是被规则或模式拒绝
+    #[error("{tool} was not run: {reason}")]
+    ToolDenied {
+        tool: String,
+        reason: String,
+        by_user: bool,
+    },
+
+    /// 工具执行失败，这一轮对话因此中断
+    #[error("Tool {tool} failed: {error:#}")]
+    ToolFailed { tool: String, error: anyhow::Error },
+
+    #[error(transparent)]
+    Api(#[from] ApiError),
+
+    /// 一轮对话被调用方取消：请求被放弃，正在运行的命令被终止
+    #[error("Cancelled")]
+    Interrupted,
+}
+
+impl ClaudeCodeError {
+    /// 机器可读的类别名称
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClaudeCodeError::Config(_) => "config",
+            ClaudeCodeError::Sandbox(_) => "sandbox",
+            ClaudeCodeError::ToolDenied { .. } => "tool_denied",
+            ClaudeCodeError::ToolFailed { .. } => "tool_failed",
+            ClaudeCodeError::Api(_) => "api",
+            ClaudeCodeError::Interrupted => "interrupted",
+        }
+    }
+
+    /// 工具执行中的错误；已经有类别的错误（例如取消、访问工作区外的目录）保持原来的类别
+    pub fn tool_failed(tool: &str, error: anyhow::Error) -> Self {
+        match error.downcast::<ClaudeCodeError>() {
+            Ok(error) => error,
+            Err(error) => ClaudeCodeError::ToolFailed {
+                tool: tool.to_string(),
+                error,
+            },
+        }
+    }
+
+    /// 错误链中第一个有类别的错误
+    pub fn find(err: &anyhow::Error) -> Option<&ClaudeCodeError> {
+        err.chain()
+            .find_map(|cause| cause.downcast_ref::<ClaudeCodeError>())
+    }
+}
+
+/// 错误的类别：config、sandbox、tool_denied、tool_failed、api、interrupted，
+/// 都不是时为 internal；链中只有 ApiError 或请求超时也算 api
+pub fn kind(err: &anyhow::Error) -> &'static str {
+    if let Some(error) = ClaudeCodeError::find(err) {
+        return error.name();
+    }
+    let api = err
+        .chain()
+        .any(|cause| cause.is::<ApiError>() || cause.is::<tokio::time::error::Elapsed>());
+    if api {
+        "api"
+    } else {
+        "internal"
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RetryConfig {
-    #[allow(dead_code)]
+    /// 首次请求之后最多重试的次数
     pub max_retries: u32,
     pub initial_delay: Duration,
     pub max_delay: Duration,
     pub multiplier: f64,
+    /// 所有重试的总时间上限
+    pub max_elapsed: Duration,
 }
 
 impl Default for RetryConfig {
@@ -52,10 +148,70 @@ impl Default for RetryConfig {
             initial_delay: Duration::from_millis(1000),
             max_delay: Duration::from_secs(30),
             multiplier: 2.0,
+            max_elapsed: Duration::from_secs(120),
         }
     }
 }
 
+/// 判断错误是否可以重试：返回重试原因，以及服务端要求的等待时间
+fn retry_policy(error: &ApiError) -> Option<(&'static str, Option<Duration>)> {
+    match error {
+        // 429 始终遵循 retry-after
+        ApiError::RateLimit(secs) => Some((
+            "Rate limit hit",
+            Some(Duration::from_secs(u64::from(*secs))),
+        )),
+        ApiError::Overloaded(_) => Some(("Model overloaded", None)),
+        ApiError::Network(_) => Some(("Network error", None)),
+        ApiError::Timeout(_) => Some(("Timeout", None)),
+        ApiError::HttpError(status, _) if *status >= 500 => Some(("Server error", None)),
+        // 400、认证失败等重试也不会成功
+        _ => None,
+    }
+}
+
+/// 错误链中是否包含模型过载错误
+fn is_overloaded(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<ApiError>(),
+            Some(ApiError::Overloaded(_))
+        )
+    })
+}
+
+/// 在指数退避的间隔上加随机抖动，随机数来自 `clock::rng`，设置 `--seed` 时可以重现
+struct Jittered {
+    inner: ExponentialBackoff,
+    /// 间隔在 [1 - factor, 1 + factor] 倍之间浮动
+    factor: f64,
+    rng: StdRng,
+}
+
+impl Jittered {
+    fn new(mut inner: ExponentialBackoff) -> Self {
+        let factor = inner.randomization_factor;
+        inner.randomization_factor = 0.0;
+        Self {
+            inner,
+            factor,
+            rng: clock::rng(),
+        }
+    }
+}
+
+impl Backoff for Jittered {
+    fn reset(&mut self) {
+        self.inner.reset();
+    }
+
+    fn next_backoff(&mut self) -> Option<Duration> {
+        let interval = self.inner.next_backoff()?;
+        let scale = 1.0 - self.factor + 2.0 * self.factor * self.rng.gen::<f64>();
+        Some(interval.mul_f64(scale))
+    }
+}
+
 /// 性能统计数据
 #[derive(Debug, Default)]
 pub struct PerformanceStats {
@@ -63,6 +219,8 @@ pub struct PerformanceStats {
     pub successful_requests: AtomicU64,
     pub failed_requests: AtomicU64,
     pub total_duration_ms: AtomicU64,
+    pub input_tokens: AtomicU64,
+    pub output_tokens: AtomicU64,
 }
 
 impl PerformanceStats {
@@ -73,6 +231,27 @@ impl PerformanceStats {
             .fetch_add(duration_ms, Ordering::SeqCst);
     }
 
+    /// 累计响应中 usage 报告的 token 数
+    pub fn record_usage(&self, response: &serde_json::Value) {
+        let usage = &response["usage"];
+        self.input_tokens.fetch_add(
+            usage["input_tokens"].as_u64().unwrap_or(0),
+            Ordering::SeqCst,
+        );
+        self.output_tokens.fetch_add(
+            usage["output_tokens"].as_u64().unwrap_or(0),
+            Ordering::SeqCst,
+        );
+    }
+
+    /// 目前累计的 token 用量
+    pub fn usage(&self) -> TokenUsage {
+        TokenUsage {
+            input_tokens: self.input_tokens.load(Ordering::SeqCst),
+            output_tokens: self.output_tokens.load(Ordering::SeqCst),
+        }
+    }
+
     pub fn record_failure(&self) {
         self.total_requests.fetch_add(1, Ordering::SeqCst);
         self.failed_requests.fetch_add(1, Ordering::SeqCst);
@@ -102,8 +281,36 @@ pub struct ApiClient {
     client: Client,
     api_key: String,
     api_url: String,
+    model: String,
+    /// 按请求用途选择的模型
+    routing: ModelRouting,
+    /// 主模型重试后仍然过载时使用的备用模型
+    fallback_model: Option<String>,
+    /// 本轮是否已切换到备用模型
+    fallback_active: AtomicBool,
+    /// 未设置时使用 API 的默认值
+    temperature: Option<f64>,
+    /// 随每个请求发送的系统提示词
+    system_prompt: Option<String>,
+    /// 作为 metadata.user_id 发送
+    user_id: Option<String>,
     retry_config: RetryConfig,
-    request_id: String,
+    /// 单次请求（每次重试分别计算）的超时时间
+    request_timeout: Duration,
+    anthropic_version: String,
+    /// 通过 anthropic-beta 请求头启用的 beta 功能
+    anthropic_beta: Vec<String>,
+    /// 可以提供给模型的工具
+    tools: ToolRegistry,
+    /// 每次请求实际发送的工具
+    tool_filter: ToolFilter,
+    /// 以精简格式发送工具定义（token_efficient_tools）
+    compact_tools: bool,
+    /// 额度快用完时在请求前等待（rate_limit_pacing）
+    rate_limit_pacing: bool,
+    pacer: Pacer,
+    /// 温度为 0 的请求先查本地缓存（--prompt 模式且开启 response_cache）
+    response_cache: Option<ResponseCache>,
     stats: Arc<PerformanceStats>,
 }
 
@@ -113,8 +320,23 @@ impl ApiClient {
             client: Client::new(),
             api_key,
             api_url,
+            model: DEFAULT_MODEL.to_string(),
+            routing: ModelRouting::default(),
+            fallback_model: None,
+            fallback_active: AtomicBool::new(false),
+            temperature: None,
+            system_prompt: None,
+            user_id: None,
             retry_config: RetryConfig::default(),
-            request_id: Uuid::new_v4().to_string(),
+            request_timeout: Duration::from_secs(120),
+            anthropic_version: DEFAULT_ANTHROPIC_VERSION.to_string(),
+            anthropic_beta: Vec::new(),
+            tools: ToolRegistry::builtin(),
+            tool_filter: ToolFilter::default(),
+            compact_tools: false,
+            rate_limit_pacing: true,
+            pacer: Pacer::default(),
+            response_cache: None,
             stats: Arc::new(PerformanceStats::default()),
         }
     }
@@ -123,97 +345,415 @@ impl ApiClient {
         Arc::clone(&self.stats)
     }
 
-    #[allow(dead_code)]
+    /// 切换后续请求使用的模型
+    pub fn set_model(&mut self, model: String) {
+        self.model = model;
+    }
+
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+
+    pub fn set_routing(&mut self, routing: ModelRouting) {
+        self.routing = routing;
+    }
+
+    pub fn set_fallback_model(&mut self, model: Option<String>) {
+        self.fallback_model = model;
+    }
+
+    pub fn set_tool_filter(&mut self, filter: ToolFilter) {
+        self.tool_filter = filter;
+    }
+
+    pub fn set_system_prompt(&mut self, prompt: Option<String>) {
+        self.system_prompt = prompt;
+    }
+
+    pub fn set_user_id(&mut self, user_id: Option<String>) {
+        self.user_id = user_id;
+    }
+
+    pub fn system_prompt(&self) -> Option<&str> {
+        self.system_prompt.as_deref()
+    }
+
+    /// 请求中实际发送的工具定义
+    pub fn tool_definitions(&self) -> serde_json::Value {
+        let definitions = self.tools.definitions(&self.tool_filter);
+        if self.compact_tools {
+            tools::compact(definitions)
+        } else {
+            definitions
+        }
+    }
+
+    /// 新的一轮对话开始时重新使用主模型
+    pub fn reset_fallback(&self) {
+        self.fallback_active.store(false, Ordering::SeqCst);
+    }
+
+    pub fn set_temperature(&mut self, temperature: Option<f64>) {
+        self.temperature = temperature;
+    }
+
+    pub fn temperature(&self) -> Option<f64> {
+        self.temperature
+    }
+
+    pub fn set_response_cache(&mut self, cache: Option<ResponseCache>) {
+        self.response_cache = cache;
+    }
+
     pub fn with_retry_config(mut self, config: RetryConfig) -> Self {
         self.retry_config = config;
         self
     }
 
-    /// 调用 Claude API 并带有重试机制
+    /// 设置单次请求的超时时间，超时的请求会按 ApiError::Timeout 重试
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// 设置 anthropic-version 请求头和需要启用的 beta 功能
+    pub fn with_api_version(mut self, version: String, betas: Vec<String>) -> Self {
+        self.anthropic_version = version;
+        self.anthropic_beta = betas;
+        self
+    }
+
+    /// 以精简格式发送工具定义，与 token-efficient-tools beta 一起使用
+    pub fn with_compact_tools(mut self, compact: bool) -> Self {
+        self.compact_tools = compact;
+        self
+    }
+
+    /// 额度快用完时是否在请求前主动等待
+    pub fn with_rate_limit_pacing(mut self, pacing: bool) -> Self {
+        self.rate_limit_pacing = pacing;
+        self
+    }
+
+    /// 最近一次响应报告的限流额度
+    pub fn rate_limits(&self) -> Option<RateLimits> {
+        self.pacer.latest()
+    }
+
+    /// 发送请求体为空的 messages 请求，检查端点和认证；不会消耗 token
+    ///
+    /// 返回 HTTP 状态码和耗时
+    pub async fn probe(&self, timeout: Duration) -> Result<(u16, Duration), reqwest::Error> {
+        let start_time = Instant::now();
+        let response = self
+            .client
+            .post(&self.api_url)
+            .header("x-api-key", &self.api_key)
+            .header("anthropic-version", &self.anthropic_version)
+            .header("content-type", "application/json")
+            .body("{}")
+            .timeout(timeout)
+            .send()
+            .await?;
+        Ok((response.status().as_u16(), start_time.elapsed()))
+    }
+
+    /// 把 reqwest 的超时错误归类为 ApiError::Timeout
+    fn network_error(&self, e: reqwest::Error) -> ApiError {
+        if e.is_timeout() {
+            ApiError::Timeout(self.request_timeout.as_secs())
+        } else {
+            ApiError::Network(e)
+        }
+    }
+
+    /// 调用 Claude API 并带有重试机制，按请求用途选择模型
     pub async fn call_claude_with_retry(
         &self,
+        purpose: RequestPurpose,
+        messages: &serde_json::Value,
+        tools: bool,
+        cancel: &CancellationToken,
+    ) -> Result<serde_json::Value> {
+        self.call_claude_with_temperature(purpose, messages, tools, self.temperature, cancel)
+            .await
+    }
+
+    /// 与 call_claude_with_retry 相同，但使用指定的温度，不影响之后的请求
+    pub async fn call_claude_with_temperature(
+        &self,
+        purpose: RequestPurpose,
         messages: &serde_json::Value,
         tools: bool,
+        temperature: Option<f64>,
+        cancel: &CancellationToken,
     ) -> Result<serde_json::Value> {
-        let request_id = self.request_id.clone();
-        info!("Starting API call (request_id: {})", request_id);
+        // 每次调用生成新的请求 ID，同一调用的各次重试共用
+        let request_id = Uuid::new_v4().to_string();
+        let model = self.model_for(purpose);
+        let cache = self
+            .response_cache
+            .as_ref()
+            .filter(|_| ResponseCache::accepts(temperature))
+            .map(|cache| {
+                let body = self.request_body(&model, messages, tools, temperature);
+                (cache, response_cache::key(&body))
+            });
+        if let Some(hit) = cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+            info!("Using cached response for model {}", model);
+            eprintln!(
+                "{}",
+                style(tf(
+                    Msg::CachedResponse,
+                    &[&humantime::format_duration(hit.age)]
+                ))
+                .dim()
+            );
+            return Ok(hit.response);
+        }
+        info!(
+            "Starting API call (request_id: {}, model: {})",
+            request_id, model
+        );
 
-        let backoff = ExponentialBackoff {
+        // 取消时放弃进行中的请求和重试等待
+        let result = tokio::select! {
+            result = self.call_with_backoff(&model, messages, tools, temperature, &request_id) => result,
+            _ = cancel.cancelled() => {
+                info!("API call cancelled (request_id: {})", request_id);
+                return Err(ClaudeCodeError::Interrupted.into());
+            }
+        };
+        let mut fell_back = false;
+        let result = match result {
+            // 重试后仍然过载时，本轮剩余的请求改用备用模型
+            Err(e) if is_overloaded(&e) => match self.fallback_for(&model) {
+                Some(fallback) => {
+                    warn!(
+                        "Model {} still overloaded, falling back to {} (request_id: {})",
+                        model, fallback, request_id
+                    );
+                    eprintln!(
+                        "{} {}",
+                        style(t(Msg::WarningLabel)).yellow(),
+                        tf(Msg::FallbackModel, &[&model, &fallback])
+                    );
+                    self.fallback_active.store(true, Ordering::SeqCst);
+                    fell_back = true;
+                    self.call_with_backoff(fallback, messages, tools, temperature, &request_id)
+                        .await
+                }
+                None => Err(e),
+            },
+            other => other,
+        };
+
+        let result = result.with_context(|| {
+            format!(
+                "API call failed after all retries (request_id: {})",
+                request_id
+            )
+        })?;
+
+        info!("API call successful (request_id: {})", request_id);
+        // 备用模型的回答不能当作请求的模型的回答保存
+        if let Some((cache, key)) = cache.filter(|_| !fell_back) {
+            cache.put(&key, &model, &result);
+        }
+        Ok(result)
+    }
+
+    /// 本次请求使用的模型：已切换到备用模型时优先使用备用模型
+    fn model_for(&self, purpose: RequestPurpose) -> String {
+        if self.fallback_active.load(Ordering::SeqCst) {
+            if let Some(fallback) = &self.fallback_model {
+                return fallback.clone();
+            }
+        }
+        self.routing
+            .model_for(purpose)
+            .unwrap_or(&self.model)
+            .to_string()
+    }
+
+    /// `model` 过载时可以切换到的备用模型
+    fn fallback_for(&self, model: &str) -> Option<&str> {
+        self.fallback_model
+            .as_deref()
+            .filter(|fallback| !fallback.is_empty() && *fallback != model)
+    }
+
+    /// 按重试策略发送请求，返回最后一次失败的错误
+    async fn call_with_backoff(
+        &self,
+        model: &str,
+        messages: &serde_json::Value,
+        tools: bool,
+        temperature: Option<f64>,
+        request_id: &str,
+    ) -> Result<serde_json::Value> {
+        let backoff = Jittered::new(ExponentialBackoff {
             initial_interval: self.retry_config.initial_delay,
             max_interval: self.retry_config.max_delay,
             multiplier: self.retry_config.multiplier,
-            max_elapsed_time: Some(Duration::from_secs(120)), // 总超时时间
+            max_elapsed_time: Some(self.retry_config.max_elapsed),
             ..Default::default()
-        };
+        });
+
+        let max_retries = self.retry_config.max_retries;
+        let attempts = AtomicU32::new(0);
 
         let operation = || async {
-            self.call_claude_once(messages, tools).await.map_err(|e| {
-                self.stats.record_failure();
-                match &e {
-                    ApiError::RateLimit(_) => {
-                        warn!("Rate limit hit, will retry (request_id: {})", request_id);
-                        backoff::Error::transient(anyhow::anyhow!("{}", e))
-                    }
-                    ApiError::Overloaded(_) => {
-                        warn!("Model overloaded, will retry (request_id: {})", request_id);
-                        backoff::Error::transient(anyhow::anyhow!("{}", e))
-                    }
-                    ApiError::Network(_) => {
-                        warn!("Network error, will retry (request_id: {})", request_id);
-                        backoff::Error::transient(anyhow::anyhow!("{}", e))
-                    }
-                    ApiError::Timeout(_) => {
-                        warn!("Timeout, will retry (request_id: {})", request_id);
-                        backoff::Error::transient(anyhow::anyhow!("{}", e))
-                    }
-                    _ => {
-                        error!("Non-retryable error (request_id: {}): {}", request_id, e);
-                        backoff::Error::permanent(e.into())
+            let attempt = attempts.fetch_add(1, Ordering::SeqCst);
+            self.pace().await;
+            self.call_claude_once(model, messages, tools, temperature, request_id)
+                .await
+                .map_err(|e| {
+                    self.stats.record_failure();
+                    // 保留原始的 ApiError，以便上层根据错误类型决定退出码
+                    match retry_policy(&e) {
+                        Some((reason, _)) if attempt >= max_retries => {
+                            error!(
+                                "{}, giving up after {} retries (request_id: {})",
+                                reason, max_retries, request_id
+                            );
+                            backoff::Error::permanent(anyhow::Error::from(e))
+                        }
+                        Some((reason, Some(retry_after))) => {
+                            warn!(
+                                "{}, retrying in {:?} ({}/{}, request_id: {})",
+                                reason,
+                                retry_after,
+                                attempt + 1,
+                                max_retries,
+                                request_id
+                            );
+                            backoff::Error::retry_after(anyhow::Error::from(e), retry_after)
+                        }
+                        Some((reason, None)) => {
+                            warn!(
+                                "{}, will retry ({}/{}, request_id: {})",
+                                reason,
+                                attempt + 1,
+                                max_retries,
+                                request_id
+                            );
+                            backoff::Error::transient(anyhow::Error::from(e))
+                        }
+                        None => {
+                            error!("Non-retryable error (request_id: {}): {}", request_id, e);
+                            backoff::Error::permanent(anyhow::Error::from(e))
+                        }
                     }
-                }
-            })
+                })
         };
 
-        let result = retry(backoff, operation)
-            .await
-            .context("API call failed after all retries")?;
+        retry(backoff, operation).await
+    }
 
-        info!("API call successful (request_id: {})", request_id);
-        Ok(result)
+    /// 最近的响应显示额度快用完时，等到额度重置再发送
+    async fn pace(&self) {
+        if !self.rate_limit_pacing {
+            return;
+        }
+        let Some(wait) = self.pacer.delay() else {
+            return;
+        };
+        info!("Rate limit nearly exhausted, waiting {:?}", wait);
+        eprintln!(
+            "{}",
+            style(tf(
+                Msg::RateLimitWait,
+                &[&(wait.as_secs_f64().ceil() as u64)]
+            ))
+            .yellow()
+        );
+        tokio::time::sleep(wait).await;
     }
 
-    async fn call_claude_once(
+    /// 发送给 API 的请求体，不含只用于统计的 metadata
+    fn request_body(
         &self,
+        model: &str,
         messages: &serde_json::Value,
         tools: bool,
-    ) -> Result<serde_json::Value, ApiError> {
+        temperature: Option<f64>,
+    ) -> serde_json::Value {
         let mut request_body = json!({
-            "model": "claude-sonnet-4-5-20250929",
-            "max_tokens": 8192,
+            "model": model,
+            "max_tokens": MAX_OUTPUT_TOKENS,
             "messages": messages
         });
 
         if tools {
-            request_body["tools"] = get_tools();
+            request_body["tools"] = self.tool_definitions();
+        }
+        if let Some(system) = &self.system_prompt {
+            request_body["system"] = json!(system);
+        }
+        if let Some(temperature) = temperature {
+            request_body["temperature"] = json!(temperature);
+        }
+        request_body
+    }
+
+    async fn call_claude_once(
+        &self,
+        model: &str,
+        messages: &serde_json::Value,
+        tools: bool,
+        temperature: Option<f64>,
+        request_id: &str,
+    ) -> Result<serde_json::Value, ApiError> {
+        let mut request_body = self.request_body(model, messages, tools, temperature);
+        if let Some(user_id) = &self.user_id {
+            request_body["metadata"] = json!({ "user_id": user_id });
         }
 
         let start_time = Instant::now();
 
-        let response = self
+        let mut request = self
             .client
             .post(&self.api_url)
             .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
+            .header("anthropic-version", &self.anthropic_version)
             .header("content-type", "application/json")
-            .header("x-request-id", &self.request_id)
+            .header("x-request-id", request_id);
+        if !self.anthropic_beta.is_empty() {
+            request = request.header("anthropic-beta", self.anthropic_beta.join(","));
+        }
+
+        let response = request
+            .timeout(self.request_timeout)
             .json(&request_body)
             .send()
-            .await?;
+            .await
+            .map_err(|e| self.network_error(e))?;
 
         let elapsed = start_time.elapsed();
         info!("API request completed in {:?}", elapsed);
 
         let status = response.status();
+        self.pacer.update(response.headers());
+
+        // 服务端返回的 request-id，附加到错误信息中便于向服务方反馈问题
+        let server_request_id = response
+            .headers()
+            .get("request-id")
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+        let with_server_id = |msg: String| match &server_request_id {
+            Some(id) => format!("{} [request-id: {}]", msg, id),
+            None => msg,
+        };
+        if !status.is_success() {
+            warn!(
+                "API returned {} (request_id: {}, server request-id: {})",
+                status,
+                request_id,
+                server_request_id.as_deref().unwrap_or("none")
+            );
+        }
 
         if status == 429 {
             let retry_after = response
@@ -233,7 +773,10 @@ impl ApiClient {
                                 .get("message")
                                 .and_then(|m| m.as_str())
                                 .unwrap_or("Insufficient balance");
-                            return Err(ApiError::HttpError(status.as_u16(), msg.to_string()));
+                            return Err(ApiError::HttpError(
+                                status.as_u16(),
+                                with_server_id(msg.to_string()),
+                            ));
                         }
                     }
                 }
@@ -249,12 +792,12 @@ impl ApiClient {
 
         if status == 400 {
             let error_text = response.text().await?;
-            return Err(ApiError::InvalidRequest(error_text));
+            return Err(ApiError::InvalidRequest(with_server_id(error_text)));
         }
 
         if status == 529 {
             let error_text = response.text().await?;
-            return Err(ApiError::Overloaded(error_text));
+            return Err(ApiError::Overloaded(with_server_id(error_text)));
         }
 
         if !status.is_success() {
@@ -262,98 +805,39 @@ impl ApiClient {
 
             if let Ok(error_json) = serde_json::from_str::<serde_json::Value>(&error_text) {
                 if let Some(msg) = error_json.get("msg").and_then(|m| m.as_str()) {
-                    return Err(ApiError::HttpError(status.as_u16(), msg.to_string()));
+                    return Err(ApiError::HttpError(
+                        status.as_u16(),
+                        with_server_id(msg.to_string()),
+                    ));
                 }
                 if let Some(error) = error_json.get("error") {
                     if let Some(msg) = error.get("message").and_then(|m| m.as_str()) {
-                        return Err(ApiError::HttpError(status.as_u16(), msg.to_string()));
+                        return Err(ApiError::HttpError(
+                            status.as_u16(),
+                            with_server_id(msg.to_string()),
+                        ));
                     }
                 }
             }
 
-            return Err(ApiError::HttpError(status.as_u16(), error_text));
+            return Err(ApiError::HttpError(
+                status.as_u16(),
+                with_server_id(error_text),
+            ));
         }
 
-        let response_json: serde_json::Value = response.json().await?;
+        let response_json: serde_json::Value =
+            response.json().await.map_err(|e| self.network_error(e))?;
 
         let duration = start_time.elapsed();
         self.stats.record_success(duration.as_millis() as u64);
+        self.stats.record_usage(&response_json);
         info!("API call completed in {:?}", duration);
 
         Ok(response_json)
     }
 }
 
-/// 获取工具定义
-fn get_tools() -> serde_json::Value {
-    json!([
-        {
-            "name": "read_file",
-            "description": "Read a file from the filesystem. Returns the file contents as a string.",
-            "input_schema": {
-                "type": "object",
-                "properties": {
-                    "file_path": {
-                        "type": "string",
-                        "description": "Absolute path to the file to read"
-                    }
-                },
-                "required": ["file_path"]
-            }
-        },
-        {
-            "name": "write_file",
-            "description": "Write content to a file, overwriting if it exists. Returns confirmation message.",
-            "input_schema": {
-                "type": "object",
-                "properties": {
-                    "file_path": {
-                        "type": "string",
-                        "description": "Absolute path to the file to write"
-                    },
-                    "content": {
-                        "type": "string",
-                        "description": "Content to write to the file"
-                    }
-                },
-                "required": ["file_path", "content"]
-            }
-        },
-        {
-            "name": "execute_command",
-            "description": "Execute a shell command and return its output. Use for terminal operations like git, npm, cargo, etc.",
-            "input_schema": {
-                "type": "object",
-                "properties": {
-                    "command": {
-                        "type": "string",
-                        "description": "The shell command to execute"
-                    }
-                },
-                "required": ["command"]
-            }
-        },
-        {
-            "name": "list_files",
-            "description": "List files in a directory using glob patterns",
-            "input_schema": {
-                "type": "object",
-                "properties": {
-                    "pattern": {
-                        "type": "string",
-                        "description": "Glob pattern (e.g., '*.rs', 'src/**/*.rs')"
-                    },
-                    "path": {
-                        "type": "string",
-                        "description": "Base directory path (defaults to current directory)"
-                    }
-                },
-                "required": ["pattern"]
-            }
-        }
-    ])
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -367,6 +851,227 @@ mod tests {
         assert_eq!(config.multiplier, 2.0);
     }
 
+    #[test]
+    fn test_error_kind() {
+        let failed = anyhow::Error::from(ClaudeCodeError::ToolFailed {
+            tool: "read_file".to_string(),
+            error: anyhow::anyhow!("No such file"),
+        })
+        .context("Turn failed");
+        assert_eq!(kind(&failed), "tool_failed");
+        assert_eq!(kind(&anyhow::Error::from(ApiError::Timeout(30))), "api");
+        assert_eq!(
+            kind(&ClaudeCodeError::Config(anyhow::anyhow!("bad toml")).into()),
+            "config"
+        );
+        // 工具执行中的取消保持原来的类别
+        let cancelled = anyhow::Error::from(ClaudeCodeError::Interrupted).context("Command failed");
+        assert_eq!(
+            ClaudeCodeError::tool_failed("execute_command", cancelled).name(),
+            "interrupted"
+        );
+        assert_eq!(
+            ClaudeCodeError::tool_failed("read_file", anyhow::anyhow!("No such file")).name(),
+            "tool_failed"
+        );
+        assert_eq!(kind(&anyhow::anyhow!("oops")), "internal");
+    }
+
+    #[test]
+    fn test_seeded_jitter_is_reproducible() {
+        let intervals = || {
+            let mut backoff = Jittered::new(ExponentialBackoff::default());
+            backoff.rng = rand::SeedableRng::seed_from_u64(7);
+            (0..4)
+                .map(|_| backoff.next_backoff().unwrap())
+                .collect::<Vec<_>>()
+        };
+        let first = intervals();
+        assert_eq!(first, intervals());
+        // 默认初始间隔 500ms，抖动 ±50%
+        assert!(first[0] >= Duration::from_millis(250) && first[0] <= Duration::from_millis(750));
+    }
+
+    #[test]
+    fn test_retry_policy_by_error_class() {
+        assert!(retry_policy(&ApiError::InvalidRequest("bad".to_string())).is_none());
+        assert!(retry_policy(&ApiError::Authentication).is_none());
+        assert!(retry_policy(&ApiError::HttpError(404, String::new())).is_none());
+
+        let (_, retry_after) = retry_policy(&ApiError::RateLimit(42)).unwrap();
+        assert_eq!(retry_after, Some(Duration::from_secs(42)));
+
+        let (_, retry_after) = retry_policy(&ApiError::HttpError(503, String::new())).unwrap();
+        assert_eq!(retry_after, None);
+    }
+
+    #[tokio::test]
+    async fn test_slow_response_is_timeout() {
+        // 接受连接但从不响应的服务端
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/messages", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _connection = listener.accept();
+            std::thread::sleep(Duration::from_secs(2));
+        });
+
+        let client =
+            ApiClient::new("test_key".to_string(), url).with_timeout(Duration::from_millis(100));
+        let result = client
+            .call_claude_once(DEFAULT_MODEL, &json!([]), false, None, "req")
+            .await;
+        assert!(matches!(result, Err(ApiError::Timeout(_))), "{:?}", result);
+    }
+
+    #[tokio::test]
+    async fn test_server_request_id_in_error() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/messages", listener.local_addr().unwrap());
+        let (sender, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            use std::io::{Read, Write};
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let n = stream.read(&mut buf).unwrap_or(0);
+            let _ = sender.send(String::from_utf8_lossy(&buf[..n]).to_lowercase());
+            let body = r#"{"error":{"message":"bad model"}}"#;
+            let response = format!(
+                "HTTP/1.1 400 Bad Request\r\nrequest-id: req_server_123\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+
+        let client = ApiClient::new("test_key".to_string(), url).with_api_version(
+            "2023-06-01".to_string(),
+            vec!["beta-a".to_string(), "beta-b".to_string()],
+        );
+        let result = client
+            .call_claude_once(DEFAULT_MODEL, &json!([]), false, None, "req")
+            .await;
+        let request = received.recv().unwrap();
+        assert!(request.contains("anthropic-version: 2023-06-01"));
+        assert!(request.contains("anthropic-beta: beta-a,beta-b"));
+        match result {
+            Err(ApiError::InvalidRequest(message)) => {
+                assert!(
+                    message.contains("[request-id: req_server_123]"),
+                    "{}",
+                    message
+                )
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    /// 读取一个完整的 HTTP 请求（请求头和 content-length 指定的请求体）
+    fn read_request(stream: &mut std::net::TcpStream) -> String {
+        use std::io::Read;
+        let mut data = Vec::new();
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = stream.read(&mut buf).unwrap_or(0);
+            if n == 0 {
+                break;
+            }
+            data.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&data).to_string();
+            if let Some(header_end) = text.find("\r\n\r\n") {
+                let length = text[..header_end]
+                    .lines()
+                    .find_map(|line| {
+                        let (name, value) = line.split_once(':')?;
+                        name.eq_ignore_ascii_case("content-length")
+                            .then(|| value.trim().parse::<usize>().ok())?
+                    })
+                    .unwrap_or(0);
+                if data.len() >= header_end + 4 + length {
+                    return text;
+                }
+            }
+        }
+        String::from_utf8_lossy(&data).to_string()
+    }
+
+    #[tokio::test]
+    async fn test_fallback_model_on_overload() {
+        // primary 始终返回 529，其他模型正常返回
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/messages", listener.local_addr().unwrap());
+        let (sender, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            use std::io::Write;
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                let overloaded = request.contains(r#""model":"primary""#);
+                let _ = sender.send(overloaded);
+                let (status, body) = if overloaded {
+                    ("529 Overloaded", r#"{"error":{"type":"overloaded_error"}}"#)
+                } else {
+                    ("200 OK", r#"{"content":[{"type":"text","text":"ok"}]}"#)
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        let mut client =
+            ApiClient::new("test_key".to_string(), url).with_retry_config(RetryConfig {
+                max_retries: 1,
+                initial_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(1),
+                ..RetryConfig::default()
+            });
+        client.set_model("primary".to_string());
+        client.set_fallback_model(Some("secondary".to_string()));
+
+        let response = client
+            .call_claude_with_retry(
+                RequestPurpose::Main,
+                &json!([]),
+                false,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response["content"][0]["text"], "ok");
+        // 两次过载（首次请求和一次重试）后改用备用模型
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), [true, true, false]);
+
+        // 同一轮的后续请求直接使用备用模型
+        client
+            .call_claude_with_retry(
+                RequestPurpose::Main,
+                &json!([]),
+                false,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(received.try_iter().collect::<Vec<_>>(), [false]);
+
+        // 新的一轮重新尝试主模型；没有备用模型时返回过载错误
+        client.reset_fallback();
+        client.set_fallback_model(None);
+        let err = client
+            .call_claude_with_retry(
+                RequestPurpose::Main,
+                &json!([]),
+                false,
+                &CancellationToken::new(),
+            )
+            .await
+            .unwrap_err();
+        assert!(is_overloaded(&err), "{:#}", err);
+    }
+
     #[test]
     fn test_api_client_creation() {
         let client = ApiClient::new(
@@ -0,0 +1,400 @@
+//! eval：在临时工作区中运行一组任务，用断言脚本判断结果，和基准线比较通过率与花费
+//!
+//! 每个任务是任务目录下的一个子目录：
+//! - `task.json`：`{"prompt": "...", "max_turns": 10}`，max_turns 可省略
+//! - `fixture/`：可选，复制到临时工作区作为初始文件
+//! - `check.sh`：可选，在工作区中用 sh 运行，退出码为 0 即通过；没有时这一轮正常结束就算通过
+
+use anyhow::{Context, Result};
+use console::style;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::engine::{ConversationOutcome, Engine, Event, EventSink};
+use crate::exit_code::ExitStatus;
+use crate::i18n::{t, tf, Msg};
+use crate::permissions::ApprovalRequest;
+use crate::pricing;
+use crate::tool_stats::ToolStats;
+
+/// 断言失败时保留的输出行数
+const CHECK_OUTPUT_LINES: usize = 10;
+
+#[derive(clap::Args, Debug)]
+pub struct EvalArgs {
+    /// Directory containing one subdirectory per task
+    #[arg(default_value = "evals")]
+    dir: PathBuf,
+
+    /// Only run tasks whose name contains this string
+    #[arg(long)]
+    filter: Option<String>,
+
+    /// Compare against results saved by an earlier run
+    #[arg(long, value_name = "FILE")]
+    baseline: Option<PathBuf>,
+
+    /// Write the results as JSON to this file
+    #[arg(long, value_name = "FILE")]
+    save: Option<PathBuf>,
+
+    /// Keep the temporary workspaces for inspection
+    #[arg(long)]
+    keep: bool,
+}
+
+#[derive(Deserialize)]
+struct Task {
+    prompt: String,
+    #[serde(default)]
+    max_turns: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TaskResult {
+    passed: bool,
+    input_tokens: u64,
+    output_tokens: u64,
+    cost_usd: f64,
+    duration_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// 运行任务目录中的所有任务；有基准线时出现回退、没有时出现失败返回 Failure
+pub async fn run(args: EvalArgs, config: &Config, max_turns: usize) -> Result<ExitStatus> {
+    // 运行任务时会切换当前目录，任务路径需要是绝对路径
+    let dir = args
+        .dir
+        .canonicalize()
+        .with_context(|| format!("Failed to read eval directory: {}", args.dir.display()))?;
+    let tasks = find_tasks(&dir, args.filter.as_deref())?;
+    if tasks.is_empty() {
+        anyhow::bail!("No eval tasks found in {}", args.dir.display());
+    }
+    let baseline = match &args.baseline {
+        Some(path) => Some(load_results(path)?),
+        None => None,
+    };
+
+    let original_dir = std::env::current_dir()?;
+    let mut results = BTreeMap::new();
+    for (name, task_dir) in &tasks {
+        let workspace =
+            std::env::temp_dir().join(format!("rust-claude-code-eval-{}", Uuid::new_v4()));
+        let result = run_task(task_dir, &workspace, config, max_turns).await;
+        // 任务中途出错也要回到原来的目录
+        std::env::set_current_dir(&original_dir)?;
+        if args.keep {
+            println!(
+                "{}",
+                style(format!(
+                    "  {}",
+                    tf(Msg::EvalWorkspace, &[&workspace.display()])
+                ))
+                .dim()
+            );
+        } else {
+            let _ = std::fs::remove_dir_all(&workspace);
+        }
+        let result = result.unwrap_or_else(|e| TaskResult {
+            passed: false,
+            input_tokens: 0,
+            output_tokens: 0,
+            cost_usd: 0.0,
+            duration_ms: 0,
+            error: Some(format!("{:#}", e)),
+        });
+        print_result(name, &result, baseline.as_ref().and_then(|b| b.get(name)));
+        results.insert(name.clone(), result);
+    }
+
+    let passed = results.values().filter(|result| result.passed).count();
+    let cost: f64 = results.values().map(|result| result.cost_usd).sum();
+    println!(
+        "\n{}",
+        tf(
+            Msg::EvalSummary,
+            &[&passed, &(results.len() - passed), &format!("{:.4}", cost)]
+        )
+    );
+
+    if let Some(path) = &args.save {
+        std::fs::write(path, serde_json::to_string_pretty(&results)?)
+            .with_context(|| format!("Failed to write eval results: {}", path.display()))?;
+        println!("{}", style(tf(Msg::EvalSaved, &[&path.display()])).dim());
+    }
+
+    let failed = match &baseline {
+        Some(baseline) => {
+            let regressions = regressions(baseline, &results);
+            if !regressions.is_empty() {
+                println!(
+                    "{}",
+                    style(tf(Msg::EvalRegressions, &[&regressions.join(", ")])).red()
+                );
+            }
+            !regressions.is_empty()
+        }
+        None => passed < results.len(),
+    };
+    Ok(if failed {
+        ExitStatus::Failure
+    } else {
+        ExitStatus::Success
+    })
+}
+
+/// 包含 task.json 的子目录，按名称排序
+fn find_tasks(dir: &Path, filter: Option<&str>) -> Result<Vec<(String, PathBuf)>> {
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read eval directory: {}", dir.display()))?;
+    let mut tasks: Vec<(String, PathBuf)> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.join("task.json").is_file())
+        .filter_map(|path| {
+            let name = path.file_name()?.to_string_lossy().into_owned();
+            Some((name, path))
+        })
+        .filter(|(name, _)| filter.is_none_or(|filter| name.contains(filter)))
+        .collect();
+    tasks.sort();
+    Ok(tasks)
+}
+
+async fn run_task(
+    task_dir: &Path,
+    workspace: &Path,
+    config: &Config,
+    max_turns: usize,
+) -> Result<TaskResult> {
+    let path = task_dir.join("task.json");
+    let task: Task = serde_json::from_str(
+        &std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?,
+    )
+    .with_context(|| format!("Invalid task definition: {}", path.display()))?;
+
+    std::fs::create_dir_all(workspace)?;
+    let fixture = task_dir.join("fixture");
+    if fixture.is_dir() {
+        copy_dir(&fixture, workspace)?;
+    }
+    // 工作区限制和系统提示词都以当前目录为准
+    std::env::set_current_dir(workspace)?;
+    let mut api_client = crate::api_client(config);
+    crate::apply_request_settings(&mut api_client, config);
+
+    let started = Instant::now();
+    let engine = Engine {
+        api_client: &api_client,
+        config,
+        tool_stats: &ToolStats::default(),
+        events: &Sandbox,
+        cancel: &CancellationToken::new(),
+        read_only: false,
+        fail_on_denial: false,
+    };
+    let mut messages = Vec::new();
+    let max_rounds = task.max_turns.unwrap_or(max_turns).saturating_sub(1);
+    let outcome = engine
+        .run_turn(&mut messages, json!(task.prompt), Some(max_rounds))
+        .await;
+
+    let stats = api_client.get_stats();
+    let input_tokens = stats.input_tokens.load(Ordering::SeqCst);
+    let output_tokens = stats.output_tokens.load(Ordering::SeqCst);
+    let error = match outcome {
+        Ok(ConversationOutcome::Completed) => check(task_dir, workspace)?,
+        Ok(ConversationOutcome::TurnLimitReached) => Some("turn limit reached".to_string()),
+        Ok(ConversationOutcome::Cancelled) => Some("cancelled".to_string()),
+        Err(e) => Some(format!("{:#}", e)),
+    };
+    Ok(TaskResult {
+        passed: error.is_none(),
+        input_tokens,
+        output_tokens,
+        cost_usd: pricing::cost_usd(api_client.model(), input_tokens, output_tokens),
+        duration_ms: started.elapsed().as_millis() as u64,
+        error,
+    })
+}
+
+/// 运行断言脚本，失败时返回它的最后几行输出
+fn check(task_dir: &Path, workspace: &Path) -> Result<Option<String>> {
+    let script = task_dir.join("check.sh");
+    if !script.is_file() {
+        return Ok(None);
+    }
+    let script = script.canonicalize()?;
+    let output = std::process::Command::new("sh")
+        .arg(&script)
+        .current_dir(workspace)
+        .output()
+        .with_context(|| format!("Failed to run {}", script.display()))?;
+    if output.status.success() {
+        return Ok(None);
+    }
+    let combined = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let lines: Vec<&str> = combined.lines().collect();
+    let tail = lines[lines.len().saturating_sub(CHECK_OUTPUT_LINES)..].join("\n");
+    let mut error = format!("check.sh failed ({})", output.status);
+    if !tail.trim().is_empty() {
+        error.push_str(&format!(": {}", tail));
+    }
+    Ok(Some(error))
+}
+
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            std::fs::create_dir_all(&target)?;
+            copy_dir(&entry.path(), &target)?;
+        } else {
+            std::fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+fn load_results(path: &Path) -> Result<BTreeMap<String, TaskResult>> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read baseline: {}", path.display()))?;
+    serde_json::from_str(&content).with_context(|| format!("Invalid baseline: {}", path.display()))
+}
+
+/// 基准线中通过、这次失败的任务
+fn regressions(
+    baseline: &BTreeMap<String, TaskResult>,
+    results: &BTreeMap<String, TaskResult>,
+) -> Vec<String> {
+    results
+        .iter()
+        .filter(|(name, result)| {
+            !result.passed && baseline.get(*name).is_some_and(|before| before.passed)
+        })
+        .map(|(name, _)| name.clone())
+        .collect()
+}
+
+fn print_result(name: &str, result: &TaskResult, before: Option<&TaskResult>) {
+    let status = if result.passed {
+        style(t(Msg::EvalPass)).green()
+    } else {
+        style(t(Msg::EvalFail)).red()
+    };
+    let tokens = tf(
+        Msg::EvalTokens,
+        &[
+            &format!("{:>7}", result.input_tokens),
+            &format!("{:>6}", result.output_tokens),
+        ],
+    );
+    let mut line = format!(
+        "{} {:30} {:>8.1}s  {}  ${:.4}",
+        status,
+        name,
+        result.duration_ms as f64 / 1000.0,
+        tokens,
+        result.cost_usd
+    );
+    if let Some(before) = before {
+        let change = match (before.passed, result.passed) {
+            (true, false) => style(format!(" {}", t(Msg::EvalRegressed))).red(),
+            (false, true) => style(format!(" {}", t(Msg::EvalFixed))).green(),
+            _ => style(format!(" {:+.4}", result.cost_usd - before.cost_usd)).dim(),
+        };
+        line.push_str(&change.to_string());
+    }
+    println!("{}", line);
+    if let Some(error) = &result.error {
+        println!("{}", style(format!("     {}", error)).dim());
+    }
+}
+
+/// 任务在临时工作区中无人值守运行：批准命令，拒绝访问工作区外的路径
+struct Sandbox;
+
+impl EventSink for Sandbox {
+    fn emit(&self, _event: Event) {}
+
+    fn approve<'a>(
+        &'a self,
+        _tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        let approved = matches!(request, ApprovalRequest::Command { .. });
+        Box::pin(std::future::ready(approved))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(passed: bool, cost_usd: f64) -> TaskResult {
+        TaskResult {
+            passed,
+            input_tokens: 0,
+            output_tokens: 0,
+            cost_usd,
+            duration_ms: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_regressions_only_count_previously_passing_tasks() {
+        let baseline = BTreeMap::from([
+            ("a".to_string(), result(true, 0.01)),
+            ("b".to_string(), result(false, 0.01)),
+        ]);
+        let results = BTreeMap::from([
+            ("a".to_string(), result(false, 0.02)),
+            ("b".to_string(), result(false, 0.02)),
+            ("new".to_string(), result(false, 0.02)),
+        ]);
+        assert_eq!(regressions(&baseline, &results), vec!["a".to_string()]);
+    }
+
+    #[test]
+    fn test_find_tasks_and_check_script() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b_fix", "a_add"] {
+            std::fs::create_dir_all(dir.path().join(name)).unwrap();
+            std::fs::write(dir.path().join(name).join("task.json"), "{}").unwrap();
+        }
+        std::fs::create_dir_all(dir.path().join("notes")).unwrap();
+        let tasks = find_tasks(dir.path(), None).unwrap();
+        let names: Vec<&str> = tasks.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a_add", "b_fix"]);
+        assert_eq!(find_tasks(dir.path(), Some("fix")).unwrap().len(), 1);
+
+        let task = dir.path().join("a_add");
+        let workspace = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            task.join("check.sh"),
+            "test -f done.txt || { echo missing; exit 1; }",
+        )
+        .unwrap();
+        let error = check(&task, workspace.path()).unwrap().unwrap();
+        assert!(error.contains("missing"), "{}", error);
+        std::fs::write(workspace.path().join("done.txt"), "").unwrap();
+        assert_eq!(check(&task, workspace.path()).unwrap(), None);
+    }
+}
@@ -0,0 +1,109 @@
+use std::process::ExitCode;
+
+use crate::error::{ApiError, ClaudeCodeError};
+use crate::i18n::Msg;
+
+/// 进程退出码，供脚本区分失败原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// 正常结束
+    Success = 0,
+    /// 其他错误
+    Failure = 1,
+    /// API 认证失败
+    AuthFailed = 2,
+    /// 重试后仍然触发速率限制
+    RateLimited = 3,
+    /// 单次提示模式下用户拒绝了工具权限
+    PermissionDenied = 4,
+    /// 请求超时
+    TimedOut = 6,
+    /// 单次提示模式下达到最大轮数
+    TurnLimit = 7,
+    /// 命令行参数错误
+    Usage = 64,
+}
+
+impl ExitStatus {
+    /// 根据错误链中的具体错误类型推断退出码
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        for cause in err.chain() {
+            let api_error = match cause.downcast_ref::<ClaudeCodeError>() {
+                Some(ClaudeCodeError::ToolDenied { .. }) => return ExitStatus::PermissionDenied,
+                Some(ClaudeCodeError::Api(api_error)) => Some(api_error),
+                _ => cause.downcast_ref::<ApiError>(),
+            };
+            if let Some(api_error) = api_error {
+                return match api_error {
+                    ApiError::Authentication => ExitStatus::AuthFailed,
+                    ApiError::HttpError(401 | 403, _) => ExitStatus::AuthFailed,
+                    ApiError::RateLimit(_) => ExitStatus::RateLimited,
+                    ApiError::Timeout(_) => ExitStatus::TimedOut,
+                    ApiError::Network(e) if e.is_timeout() => ExitStatus::TimedOut,
+                    _ => ExitStatus::Failure,
+                };
+            }
+
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return ExitStatus::TimedOut;
+            }
+        }
+
+        ExitStatus::Failure
+    }
+
+    /// 针对失败原因给出的处理建议
+    pub fn hint(self) -> Option<Msg> {
+        match self {
+            ExitStatus::AuthFailed => Some(Msg::HintAuth),
+            ExitStatus::RateLimited => Some(Msg::HintRateLimited),
+            ExitStatus::TimedOut => Some(Msg::HintTimedOut),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExitStatus> for ExitCode {
+    fn from(status: ExitStatus) -> Self {
+        ExitCode::from(status as u8)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_exit_status_from_api_error() {
+        let err: anyhow::Result<()> =
+            Err(ApiError::Authentication).context("API call failed after all retries");
+        assert_eq!(
+            ExitStatus::from_error(&err.unwrap_err()),
+            ExitStatus::AuthFailed
+        );
+
+        let err = anyhow::Error::from(ApiError::RateLimit(60)).context("API call failed");
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::RateLimited);
+
+        let err = anyhow::Error::from(ApiError::Timeout(30));
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::TimedOut);
+
+        let err = anyhow::Error::from(ClaudeCodeError::Api(ApiError::Authentication));
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::AuthFailed);
+    }
+
+    #[test]
+    fn test_exit_status_fallback() {
+        let err = anyhow::anyhow!("something else went wrong");
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::Failure);
+
+        let err = anyhow::Error::from(ClaudeCodeError::ToolDenied {
+            tool: "execute_command".to_string(),
+            reason: "The user did not approve this execute_command call".to_string(),
+            by_user: true,
+        });
+        assert_eq!(ExitStatus::from_error(&err), ExitStatus::PermissionDenied);
+        assert_eq!(ExitStatus::Usage as u8, 64);
+    }
+}
@@ -0,0 +1,199 @@
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use once_cell::sync::Lazy;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tracing::warn;
+
+/// 超过这个大小的文件不缓存，大文件的按行读取走内存映射
+const MAX_ENTRY_BYTES: usize = 1024 * 1024;
+/// 缓存的总大小上限，超出时淘汰最久未使用的文件
+const MAX_CACHE_BYTES: usize = 64 * 1024 * 1024;
+
+/// 本次会话中读取过的文件内容
+static CACHE: Lazy<Mutex<FileCache>> = Lazy::new(|| Mutex::new(FileCache::default()));
+
+/// 监听缓存文件所在的目录，文件变化时立即失效；监听失败时仍有修改时间检查
+///
+/// 与 CACHE 分开加锁：监听回调会锁 CACHE，持有 CACHE 时调用 watch 可能死锁
+static WATCHER: Lazy<Mutex<DirWatcher>> = Lazy::new(|| Mutex::new(DirWatcher::default()));
+
+/// 文件的修改时间和大小；与缓存时不同说明文件已被修改
+type Stamp = (Option<SystemTime>, u64);
+
+struct Entry {
+    content: String,
+    stamp: Stamp,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct FileCache {
+    entries: HashMap<PathBuf, Entry>,
+    bytes: usize,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct DirWatcher {
+    watcher: Option<RecommendedWatcher>,
+    dirs: HashSet<PathBuf>,
+}
+
+impl FileCache {
+    fn get(&mut self, path: &Path, stamp: Stamp) -> Option<String> {
+        self.tick += 1;
+        let tick = self.tick;
+        let entry = self.entries.get_mut(path)?;
+        if entry.stamp != stamp {
+            self.remove(path);
+            return None;
+        }
+        entry.last_used = tick;
+        Some(entry.content.clone())
+    }
+
+    fn insert(&mut self, path: PathBuf, stamp: Stamp, content: String) {
+        self.remove(&path);
+        self.tick += 1;
+        self.bytes += content.len();
+        self.entries.insert(
+            path,
+            Entry {
+                content,
+                stamp,
+                last_used: self.tick,
+            },
+        );
+
+        while self.bytes > MAX_CACHE_BYTES {
+            let Some(oldest) = self
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            self.remove(&oldest);
+        }
+    }
+
+    fn remove(&mut self, path: &Path) {
+        if let Some(entry) = self.entries.remove(path) {
+            self.bytes -= entry.content.len();
+        }
+    }
+}
+
+impl DirWatcher {
+    fn watch(&mut self, dir: &Path) {
+        if self.dirs.contains(dir) {
+            return;
+        }
+        if self.watcher.is_none() {
+            match notify::recommended_watcher(|res: notify::Result<Event>| match res {
+                Ok(event) if !event.kind.is_access() => {
+                    let mut cache = CACHE.lock().unwrap();
+                    for path in &event.paths {
+                        cache.remove(path);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("File cache watcher error: {}", e),
+            }) {
+                Ok(watcher) => self.watcher = Some(watcher),
+                Err(e) => {
+                    warn!("Failed to create file cache watcher: {}", e);
+                    return;
+                }
+            }
+        }
+        if let Some(watcher) = self.watcher.as_mut() {
+            match watcher.watch(dir, RecursiveMode::NonRecursive) {
+                Ok(()) => {
+                    self.dirs.insert(dir.to_path_buf());
+                }
+                Err(e) => warn!("Failed to watch {}: {}", dir.display(), e),
+            }
+        }
+    }
+}
+
+fn stamp(metadata: &fs::Metadata) -> Stamp {
+    (metadata.modified().ok(), metadata.len())
+}
+
+/// 读取文本文件；文件未变化时直接返回缓存内容的副本
+pub fn read(path: &Path) -> io::Result<String> {
+    let key = fs::canonicalize(path)?;
+    let metadata = fs::metadata(&key)?;
+    let stamp = stamp(&metadata);
+    if let Some(content) = CACHE.lock().unwrap().get(&key, stamp) {
+        return Ok(content);
+    }
+
+    let content = fs::read_to_string(&key)?;
+    // 读取期间文件被修改时不缓存
+    if content.len() <= MAX_ENTRY_BYTES && content.len() as u64 == metadata.len() {
+        let dir = key.parent().map(Path::to_path_buf);
+        CACHE.lock().unwrap().insert(key, stamp, content.clone());
+        if let Some(dir) = dir {
+            WATCHER.lock().unwrap().watch(&dir);
+        }
+    }
+    Ok(content)
+}
+
+/// 本工具写入文件后调用，使缓存失效
+pub fn invalidate(path: &Path) {
+    let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    CACHE.lock().unwrap().remove(&key);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn cached(path: &Path) -> bool {
+        let key = fs::canonicalize(path).unwrap();
+        CACHE.lock().unwrap().entries.contains_key(&key)
+    }
+
+    #[test]
+    fn test_cached_read_follows_file_changes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lib.rs");
+        fs::write(&path, "fn a() {}").unwrap();
+
+        assert_eq!(read(&path).unwrap(), "fn a() {}");
+        assert!(cached(&path));
+        assert_eq!(read(&path).unwrap(), "fn a() {}");
+
+        // 即使监听没有及时通知，修改时间或大小变化也会重新读取
+        fs::write(&path, "fn a() { b() }").unwrap();
+        assert_eq!(read(&path).unwrap(), "fn a() { b() }");
+
+        invalidate(&path);
+        assert!(!cached(&path));
+        assert!(read(&dir.path().join("missing.rs")).is_err());
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = FileCache::default();
+        let big = "x".repeat(MAX_CACHE_BYTES / 2);
+        cache.insert(PathBuf::from("/a"), (None, 0), big.clone());
+        cache.insert(PathBuf::from("/b"), (None, 0), big.clone());
+        assert!(cache.get(Path::new("/a"), (None, 0)).is_some());
+        cache.insert(PathBuf::from("/c"), (None, 0), big);
+
+        assert!(cache.entries.contains_key(Path::new("/a")));
+        assert!(!cache.entries.contains_key(Path::new("/b")));
+        assert_eq!(cache.bytes, MAX_CACHE_BYTES);
+    }
+}
@@ -0,0 +1,179 @@
+use anyhow::{bail, Context, Result};
+use fs2::FileExt;
+use once_cell::sync::Lazy;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// 等待其他进程释放文件锁的最长时间
+const LOCK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// 本次会话中读取或写入过的文件状态
+static FILE_STATE: Lazy<Mutex<HashMap<PathBuf, FileStamp>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// 文件的修改时间和大小，用于判断是否被外部修改
+#[derive(Debug, Clone, PartialEq)]
+struct FileStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl FileStamp {
+    fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// 统一路径形式，避免同一文件以不同写法记录多次
+fn normalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// 记录文件当前状态（读取后或本工具写入后调用）
+pub fn record(path: &Path) {
+    let key = normalize(path);
+    if let Some(stamp) = FileStamp::of(&key) {
+        FILE_STATE.lock().unwrap().insert(key, stamp);
+    }
+}
+
+/// 文件已存在但本次会话中从未读取（或写入）过时，返回给模型的提示信息
+pub fn unread_before_write(path: &Path) -> Option<String> {
+    if !path.exists() {
+        return None;
+    }
+    let key = normalize(path);
+    if FILE_STATE.lock().unwrap().contains_key(&key) {
+        return None;
+    }
+
+    Some(format!(
+        "{} exists but has not been read in this session. \
+        Read it with read_file first, then retry so existing content is not lost.",
+        path.display()
+    ))
+}
+
+/// 文件自上次读取后是否被其他程序修改；返回给模型的提示信息
+pub fn changed_since_read(path: &Path) -> Option<String> {
+    let key = normalize(path);
+    let recorded = FILE_STATE.lock().unwrap().get(&key).cloned()?;
+    let current = FileStamp::of(&key);
+
+    if current.as_ref() == Some(&recorded) {
+        return None;
+    }
+
+    Some(format!(
+        "File changed on disk since it was last read: {}. The file was not modified. \
+        Read it again with read_file and reapply your edit so newer changes are not overwritten.",
+        path.display()
+    ))
+}
+
+/// 写文件期间持有的咨询锁，drop 时释放
+pub struct FileLock {
+    file: File,
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
+
+/// 对已存在的文件加排他咨询锁，防止多个实例同时写入
+///
+/// 文件不存在时返回 None。锁被占用时异步等待，不阻塞运行时的工作线程
+pub async fn lock_for_write(path: &Path) -> Result<Option<FileLock>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+
+    let start = Instant::now();
+    loop {
+        match file.try_lock_exclusive() {
+            Ok(()) => return Ok(Some(FileLock { file })),
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                if start.elapsed() >= LOCK_TIMEOUT {
+                    bail!(
+                        "File is locked by another process: {} (waited {}s)",
+                        path.display(),
+                        LOCK_TIMEOUT.as_secs()
+                    );
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to lock file: {}", path.display()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detects_external_modification() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("a.txt");
+        fs::write(&path, "one").unwrap();
+
+        // 从未读取过的文件不做检查
+        assert!(changed_since_read(&path).is_none());
+
+        record(&path);
+        assert!(changed_since_read(&path).is_none());
+
+        fs::write(&path, "changed by editor").unwrap();
+        let message = changed_since_read(&path).unwrap();
+        assert!(message.contains("changed on disk since it was last read"));
+
+        record(&path);
+        assert!(changed_since_read(&path).is_none());
+    }
+
+    #[test]
+    fn test_unread_file_must_be_read_first() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("c.txt");
+
+        // 新文件可以直接写入
+        assert!(unread_before_write(&path).is_none());
+
+        fs::write(&path, "existing").unwrap();
+        assert!(unread_before_write(&path)
+            .unwrap()
+            .contains("has not been read"));
+
+        record(&path);
+        assert!(unread_before_write(&path).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_lock_is_exclusive() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("b.txt");
+        assert!(lock_for_write(&path).await.unwrap().is_none());
+
+        fs::write(&path, "x").unwrap();
+        let lock = lock_for_write(&path).await.unwrap().unwrap();
+
+        let other = File::open(&path).unwrap();
+        assert!(other.try_lock_exclusive().is_err());
+
+        drop(lock);
+        assert!(other.try_lock_exclusive().is_ok());
+    }
+}
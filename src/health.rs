@@ -0,0 +1,281 @@
+use console::style;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::clock;
+use crate::config::{mask_secret, Config, ConfigSource};
+use crate::error::ApiClient;
+use crate::i18n::{t, tf, Msg};
+use crate::rate_limit::RateLimits;
+
+/// 可达性检查和版本检查的超时时间，不能明显拖慢启动
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+/// 两次版本检查之间的最短间隔
+const UPDATE_CHECK_INTERVAL_SECS: u64 = 24 * 60 * 60;
+const RELEASES_URL: &str = "https://api.github.com/repos/redoop/rust-claude-code/releases/latest";
+/// 版本检查结果的缓存文件，位于 .claude 目录下
+const UPDATE_CACHE_FILE: &str = "update-check.json";
+
+/// API 端点的检查结果
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reachability {
+    /// 请求到达了 messages 接口，认证通过
+    Ok(Duration),
+    /// 服务器拒绝了 API key
+    AuthRejected(u16),
+    /// 地址上没有 messages 接口，通常是 base URL 写错了
+    NotFound(u16),
+    ServerError(u16),
+    Unexpected(u16),
+    /// 无法建立连接或超时
+    Unreachable(String),
+}
+
+impl Reachability {
+    /// 按空请求体的响应状态分类
+    pub fn from_status(status: u16, latency: Duration) -> Self {
+        match status {
+            // 空请求体得到 400，说明接口存在并且认证已通过；429 只是暂时限流
+            200..=299 | 400 | 413 | 422 | 429 => Reachability::Ok(latency),
+            401 | 403 => Reachability::AuthRejected(status),
+            404 | 405 => Reachability::NotFound(status),
+            500..=599 => Reachability::ServerError(status),
+            _ => Reachability::Unexpected(status),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        matches!(self, Reachability::Ok(_))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Reachability::Ok(latency) => tf(Msg::ApiReachable, &[&latency.as_millis()]),
+            Reachability::AuthRejected(status) => tf(Msg::ApiAuthRejected, &[status]),
+            Reachability::NotFound(status) => tf(Msg::ApiNotFound, &[status]),
+            Reachability::ServerError(status) | Reachability::Unexpected(status) => {
+                tf(Msg::ApiUnexpectedStatus, &[status])
+            }
+            Reachability::Unreachable(reason) => tf(Msg::ApiUnreachable, &[reason]),
+        }
+    }
+}
+
+/// 启动时和 /status 显示的状态
+pub struct Status {
+    pub model: String,
+    pub endpoint: String,
+    pub endpoint_source: ConfigSource,
+    /// 打码后的 API key 及其来源
+    pub auth: Option<(String, ConfigSource)>,
+    pub api: Reachability,
+    /// 比当前版本新的发布版本
+    pub newer_release: Option<String>,
+    /// 最近一次响应报告的限流额度
+    pub rate_limits: Option<RateLimits>,
+}
+
+impl Status {
+    /// 检查 API 端点；`check_updates` 为 true 时同时检查新版本
+    pub async fn check(api_client: &ApiClient, config: &Config, check_updates: bool) -> Self {
+        let (api, newer_release) = tokio::join!(probe(api_client), async {
+            if check_updates {
+                newer_release().await
+            } else {
+                None
+            }
+        });
+        let source = |key| {
+            config
+                .sources
+                .get(key)
+                .cloned()
+                .unwrap_or(ConfigSource::Default)
+        };
+
+        Self {
+            model: api_client.model().to_string(),
+            endpoint: config.api_base_url.clone(),
+            endpoint_source: source("api_base_url"),
+            auth: (!config.api_key.is_empty())
+                .then(|| (mask_secret(&config.api_key), source("api_key"))),
+            api,
+            newer_release,
+            rate_limits: api_client.rate_limits(),
+        }
+    }
+
+    pub fn print(&self) {
+        println!("{}", tf(Msg::StatusModel, &[&self.model]));
+        println!(
+            "{}",
+            tf(
+                Msg::StatusEndpoint,
+                &[
+                    &self.endpoint,
+                    &style(format!("({})", self.endpoint_source)).dim()
+                ]
+            )
+        );
+        match &self.auth {
+            Some((key, source)) => println!(
+                "{}",
+                tf(
+                    Msg::StatusAuth,
+                    &[key, &style(format!("({})", source)).dim()]
+                )
+            ),
+            None => println!("{}", tf(Msg::StatusAuth, &[&t(Msg::NotSet), &""])),
+        }
+        let api = self.api.describe();
+        let api = if self.api.is_ok() {
+            style(api).green()
+        } else {
+            style(api).red()
+        };
+        println!("{}", tf(Msg::StatusApi, &[&api]));
+        if let Some(limits) = &self.rate_limits {
+            println!("{}", tf(Msg::StatusRateLimits, &[&limits.describe()]));
+        }
+        if let Some(version) = &self.newer_release {
+            println!(
+                "{}",
+                style(tf(
+                    Msg::UpdateAvailable,
+                    &[version, &env!("CARGO_PKG_VERSION")]
+                ))
+                .yellow()
+            );
+        }
+    }
+}
+
+async fn probe(api_client: &ApiClient) -> Reachability {
+    match api_client.probe(CHECK_TIMEOUT).await {
+        Ok((status, latency)) => Reachability::from_status(status, latency),
+        Err(e) if e.is_timeout() => {
+            Reachability::Unreachable(format!("timed out after {}s", CHECK_TIMEOUT.as_secs()))
+        }
+        Err(e) => Reachability::Unreachable(root_cause(&e)),
+    }
+}
+
+/// reqwest 的错误信息只说明请求失败，真正的原因（拒绝连接、DNS 失败等）在最内层
+fn root_cause(error: &(dyn std::error::Error + 'static)) -> String {
+    let mut cause = error;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+    cause.to_string()
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct UpdateCache {
+    checked_at: u64,
+    latest: Option<String>,
+}
+
+/// 返回比当前版本新的最新发布版本，每天最多请求一次
+///
+/// 请求失败也会记录检查时间，离线时不会每次启动都等待超时
+async fn newer_release() -> Option<String> {
+    let path = Config::get_claude_dir().ok()?.join(UPDATE_CACHE_FILE);
+    let now = clock::unix_secs();
+
+    let cached = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str::<UpdateCache>(&content).ok())
+        .filter(|cache| now.saturating_sub(cache.checked_at) < UPDATE_CHECK_INTERVAL_SECS);
+    let latest = match cached {
+        Some(cache) => cache.latest,
+        None => {
+            let latest = fetch_latest_release().await;
+            let cache = UpdateCache {
+                checked_at: now,
+                latest: latest.clone(),
+            };
+            if let Err(e) = save_cache(&path, &cache) {
+                warn!("Failed to save update check result: {}", e);
+            }
+            latest
+        }
+    };
+    latest.filter(|version| is_newer(version, env!("CARGO_PKG_VERSION")))
+}
+
+fn save_cache(path: &Path, cache: &UpdateCache) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, serde_json::to_string(cache)?)
+}
+
+async fn fetch_latest_release() -> Option<String> {
+    let response = reqwest::Client::new()
+        .get(RELEASES_URL)
+        .header(
+            "user-agent",
+            concat!("rust-claude-code/", env!("CARGO_PKG_VERSION")),
+        )
+        .header("accept", "application/vnd.github+json")
+        .timeout(CHECK_TIMEOUT)
+        .send()
+        .await
+        .map_err(|e| info!("Update check failed: {}", e))
+        .ok()?;
+    let release: serde_json::Value = response.error_for_status().ok()?.json().await.ok()?;
+    release["tag_name"].as_str().map(str::to_string)
+}
+
+/// 比较 `1.2.3` 形式的版本号，可以带 v 前缀；无法解析时视为不更新
+fn is_newer(candidate: &str, current: &str) -> bool {
+    fn parse(version: &str) -> Option<Vec<u64>> {
+        let version = version.trim().trim_start_matches('v');
+        // 预发布版本（1.2.0-beta.1）不提示
+        if version.contains('-') {
+            return None;
+        }
+        version.split('.').map(|part| part.parse().ok()).collect()
+    }
+    match (parse(candidate), parse(current)) {
+        (Some(candidate), Some(current)) => candidate > current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_probe_status() {
+        let latency = Duration::from_millis(120);
+        assert_eq!(
+            Reachability::from_status(400, latency),
+            Reachability::Ok(latency)
+        );
+        assert_eq!(
+            Reachability::from_status(401, latency),
+            Reachability::AuthRejected(401)
+        );
+        assert_eq!(
+            Reachability::from_status(404, latency),
+            Reachability::NotFound(404)
+        );
+        assert!(!Reachability::from_status(529, latency).is_ok());
+        assert!(Reachability::from_status(429, latency).is_ok());
+    }
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("v0.2.0", "0.1.0"));
+        assert!(is_newer("0.1.10", "0.1.9"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("0.0.9", "0.1.0"));
+        assert!(!is_newer("v0.2.0-beta.1", "0.1.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+    }
+}
@@ -0,0 +1,884 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::changes::SessionChanges;
+use crate::clock;
+
+/// zstd 帧的开头，加载时据此判断文件是否压缩
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+/// zstd 压缩级别，兼顾速度和压缩率
+#[cfg(feature = "zstd")]
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// --tag 指定的标签，写入本次会话保存的每个记录
+static TAGS: Mutex<BTreeMap<String, String>> = Mutex::new(BTreeMap::new());
+
+/// 保存到 .claude/history 的对话记录
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationHistory<'a> {
+    pub metadata: ConversationMetadata,
+    /// 保存时借用会话中的消息，不复制整个列表
+    pub messages: Cow<'a, [serde_json::Value]>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConversationMetadata {
+    pub created_at: u64,
+    pub version: String,
+    pub model: String,
+    /// 会话中改动的文件和执行的命令，会话结束时写入
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub changes: Option<SessionChanges>,
+    /// /compare 得到的各个模型的回答
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub comparisons: Vec<Comparison>,
+    /// --tag 指定的标签，例如 {"team": "payments"}
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// 会话中 API 报告的 token 用量，会话结束时写入
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+/// 同一条提示词在不同模型下的回答，不属于对话本身
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub prompt: String,
+    pub answers: Vec<ModelAnswer>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ModelAnswer {
+    pub model: String,
+    pub text: String,
+}
+
+impl<'a> ConversationHistory<'a> {
+    pub fn new(messages: &'a [serde_json::Value], model: &str) -> Self {
+        Self {
+            metadata: ConversationMetadata {
+                created_at: clock::unix_secs(),
+                version: "0.1.0".to_string(),
+                model: model.to_string(),
+                changes: None,
+                comparisons: Vec::new(),
+                tags: tags(),
+                usage: None,
+            },
+            messages: Cow::Borrowed(messages),
+        }
+    }
+}
+
+/// 设置本次会话的标签
+pub fn set_tags(tags: &[(String, String)]) {
+    *TAGS.lock().unwrap() = tags.iter().cloned().collect();
+}
+
+pub fn tags() -> BTreeMap<String, String> {
+    TAGS.lock().unwrap().clone()
+}
+
+/// 把对话写入 `dir/conversation_<timestamp>.json`，`compress` 为 true 时写成 .json.zst
+///
+/// 直接序列化到文件，不先在内存中生成完整的 JSON 字符串
+pub fn save(
+    dir: &Path,
+    messages: &[serde_json::Value],
+    model: &str,
+    compress: bool,
+    changes: Option<SessionChanges>,
+    usage: Option<TokenUsage>,
+) -> Result<PathBuf> {
+    #[cfg(not(feature = "zstd"))]
+    anyhow::ensure!(
+        !compress,
+        "compress_history needs a build with the `zstd` feature"
+    );
+    fs::create_dir_all(dir).context("Failed to create history directory")?;
+
+    let mut history = ConversationHistory::new(messages, model);
+    history.metadata.changes = changes;
+    history.metadata.usage = usage;
+    write_history(dir, &history, compress)
+}
+
+/// 保存导入的对话，文件名使用原对话的开始时间，已有同名记录时顺延一秒
+pub fn save_imported(
+    dir: &Path,
+    messages: &[serde_json::Value],
+    model: &str,
+    created_at: Option<u64>,
+) -> Result<PathBuf> {
+    fs::create_dir_all(dir).context("Failed to create history directory")?;
+    let mut history = ConversationHistory::new(messages, model);
+    if let Some(created_at) = created_at {
+        history.metadata.created_at = created_at;
+    }
+    while ["json", "json.zst", "jsonl"].iter().any(|extension| {
+        dir.join(format!(
+            "conversation_{}.{}",
+            history.metadata.created_at, extension
+        ))
+        .exists()
+    }) {
+        history.metadata.created_at += 1;
+    }
+    write_history(dir, &history, false)
+}
+
+fn write_history(dir: &Path, history: &ConversationHistory, compress: bool) -> Result<PathBuf> {
+    let extension = if compress { "json.zst" } else { "json" };
+    let path = dir.join(format!(
+        "conversation_{}.{}",
+        history.metadata.created_at, extension
+    ));
+    let file = File::create(&path)
+        .with_context(|| format!("Failed to create history file: {}", path.display()))?;
+
+    #[cfg(feature = "zstd")]
+    let written = if compress {
+        zstd::Encoder::new(file, COMPRESSION_LEVEL)
+            .map_err(anyhow::Error::from)
+            .and_then(|encoder| Ok(write_json(encoder, history)?.finish()?))
+            .map(drop)
+    } else {
+        write_json(file, history).map(drop)
+    };
+    #[cfg(not(feature = "zstd"))]
+    let written = write_json(file, history).map(drop);
+    written.context("Failed to write conversation history")?;
+    Ok(path)
+}
+
+/// 导出到指定文件时使用的格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Json,
+    /// 便于阅读和分享的记录，不能再加载
+    Markdown,
+}
+
+impl Format {
+    /// 按扩展名选择：`.md` 和 `.markdown` 为 Markdown，其他为 JSON
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("md") || ext.eq_ignore_ascii_case("markdown") => {
+                Format::Markdown
+            }
+            _ => Format::Json,
+        }
+    }
+}
+
+/// 把对话写到用户指定的文件，格式由扩展名决定；已有文件会被覆盖
+pub fn save_to(path: &Path, messages: &[serde_json::Value], model: &str) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    let history = ConversationHistory::new(messages, model);
+    let file =
+        File::create(path).with_context(|| format!("Failed to create file: {}", path.display()))?;
+    match Format::from_path(path) {
+        Format::Json => write_json(file, &history).map(drop),
+        Format::Markdown => {
+            let mut writer = BufWriter::new(file);
+            writer.write_all(render_markdown(&history).as_bytes())?;
+            writer.flush().map_err(Into::into)
+        }
+    }
+    .with_context(|| format!("Failed to write conversation to {}", path.display()))
+}
+
+/// 渲染为 Markdown：每条消息一个小节，工具调用和结果放在代码块中
+pub fn render_markdown(history: &ConversationHistory) -> String {
+    let mut out = format!(
+        "# Conversation\n\n- Model: {}\n- Saved at: {} (Unix time)\n",
+        history.metadata.model, history.metadata.created_at
+    );
+    for (key, value) in &history.metadata.tags {
+        out.push_str(&format!("- {}: {}\n", key, value));
+    }
+    if let Some(usage) = history.metadata.usage {
+        out.push_str(&format!(
+            "- Tokens: {} in, {} out\n",
+            usage.input_tokens, usage.output_tokens
+        ));
+    }
+    for message in history.messages.iter() {
+        let heading = match message["role"].as_str() {
+            Some("assistant") => "Assistant",
+            _ => "User",
+        };
+        out.push_str(&format!("\n## {}\n", heading));
+        let blocks = match &message["content"] {
+            serde_json::Value::String(text) => {
+                out.push_str(&format!("\n{}\n", text));
+                continue;
+            }
+            serde_json::Value::Array(blocks) => blocks,
+            _ => continue,
+        };
+        for block in blocks {
+            match block["type"].as_str() {
+                Some("text") => {
+                    out.push_str(&format!("\n{}\n", block["text"].as_str().unwrap_or("")))
+                }
+                Some("tool_use") => out.push_str(&format!(
+                    "\n**Tool call:** `{}`\n\n{}",
+                    block["name"].as_str().unwrap_or("?"),
+                    fenced("json", &block["input"].to_string())
+                )),
+                Some("tool_result") => {
+                    let content = match &block["content"] {
+                        serde_json::Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    out.push_str(&format!("\n**Tool result:**\n\n{}", fenced("", &content)));
+                }
+                Some("document") => out.push_str(&format!(
+                    "\n*Attached document: {}*\n",
+                    block["title"].as_str().unwrap_or("untitled")
+                )),
+                _ => {}
+            }
+        }
+    }
+    out
+}
+
+/// 代码块的围栏比内容中最长的连续反引号更长，避免内容提前结束代码块
+fn fenced(lang: &str, content: &str) -> String {
+    let longest = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}{}\n{}\n{}\n", fence, lang, content.trim_end(), fence)
+}
+
+/// 经缓冲写入 JSON，返回底层的 writer
+fn write_json<W: Write>(writer: W, history: &ConversationHistory) -> Result<W> {
+    let mut writer = BufWriter::new(writer);
+    serde_json::to_writer_pretty(&mut writer, history)
+        .context("Failed to serialize conversation history")?;
+    writer.into_inner().map_err(|e| e.into_error().into())
+}
+
+/// 会话日志中的一行
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JournalEntry<'a> {
+    Metadata(ConversationMetadata),
+    Message(Cow<'a, serde_json::Value>),
+    /// 写入之后消息时的毫秒时间戳，回放时用来显示每轮的时间
+    Time(u64),
+    /// 消息列表被整体改写（压缩、重试、切换分支等），之后的消息从头开始
+    Reset,
+    Comparison(Cow<'a, Comparison>),
+}
+
+/// 逐轮追加写入的会话日志 `conversation_<timestamp>.jsonl`
+///
+/// 每轮结束后写入新消息并落盘，进程崩溃或被杀掉时最多丢失当前这一轮；
+/// 会话进行中也可以用 `tail -f` 查看
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    model: String,
+    created_at: u64,
+    written: usize,
+    /// 最后写入的消息，用来判断消息列表是否只是在末尾追加
+    last: Option<serde_json::Value>,
+    /// 压缩保存时写入 metadata
+    comparisons: Vec<Comparison>,
+}
+
+impl Journal {
+    pub fn create(dir: &Path, model: &str) -> Result<Self> {
+        fs::create_dir_all(dir).context("Failed to create history directory")?;
+        let metadata = ConversationHistory::new(&[], model).metadata;
+        let path = dir.join(format!("conversation_{}.jsonl", metadata.created_at));
+        let file = File::options()
+            .create_new(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to create history file: {}", path.display()))?;
+        let mut journal = Self {
+            path,
+            file,
+            model: model.to_string(),
+            created_at: metadata.created_at,
+            written: 0,
+            last: None,
+            comparisons: Vec::new(),
+        };
+        journal.append(&[JournalEntry::Metadata(metadata)])?;
+        Ok(journal)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 写入上次之后新增的消息；列表被改写时先写一条 reset，再写入全部消息
+    pub fn sync(&mut self, messages: &[serde_json::Value]) -> Result<()> {
+        let appended = messages.len() >= self.written
+            && (self.written == 0 || messages.get(self.written - 1) == self.last.as_ref());
+        let mut entries = Vec::new();
+        let start = if appended {
+            self.written
+        } else {
+            entries.push(JournalEntry::Reset);
+            0
+        };
+        if start == messages.len() && entries.is_empty() {
+            return Ok(());
+        }
+        entries.push(JournalEntry::Time(clock::unix_millis()));
+        entries.extend(
+            messages[start..]
+                .iter()
+                .map(|message| JournalEntry::Message(Cow::Borrowed(message))),
+        );
+        self.append(&entries)?;
+        self.written = messages.len();
+        self.last = messages.last().cloned();
+        Ok(())
+    }
+
+    /// 立即写入一次 /compare 的结果
+    pub fn record_comparison(&mut self, comparison: Comparison) -> Result<()> {
+        self.append(&[JournalEntry::Comparison(Cow::Borrowed(&comparison))])?;
+        self.comparisons.push(comparison);
+        Ok(())
+    }
+
+    /// 会话结束时写入剩余的消息、改动汇总和用量；`compress` 为 true 时改存为 .json.zst 并删除日志
+    ///
+    /// 改动汇总和用量写在一条新的 metadata 中，加载时以最后一条为准
+    pub fn finish(
+        mut self,
+        messages: &[serde_json::Value],
+        compress: bool,
+        changes: Option<SessionChanges>,
+        usage: Option<TokenUsage>,
+    ) -> Result<PathBuf> {
+        self.sync(messages)?;
+        if !compress {
+            if changes.is_some() || usage.is_some() {
+                let metadata = ConversationMetadata {
+                    created_at: self.created_at,
+                    changes,
+                    usage,
+                    ..ConversationHistory::new(&[], &self.model).metadata
+                };
+                self.append(&[JournalEntry::Metadata(metadata)])?;
+            }
+            return Ok(self.path);
+        }
+        let dir = self.path.parent().unwrap_or(Path::new("."));
+        let mut history = ConversationHistory::new(messages, &self.model);
+        history.metadata.created_at = self.created_at;
+        history.metadata.changes = changes;
+        history.metadata.usage = usage;
+        history.metadata.comparisons = std::mem::take(&mut self.comparisons);
+        let compressed = write_history(dir, &history, true)?;
+        fs::remove_file(&self.path)
+            .with_context(|| format!("Failed to remove {}", self.path.display()))?;
+        Ok(compressed)
+    }
+
+    /// 一次写入整批记录并同步到磁盘
+    fn append(&mut self, entries: &[JournalEntry]) -> Result<()> {
+        let mut buffer = Vec::new();
+        for entry in entries {
+            serde_json::to_writer(&mut buffer, entry)?;
+            buffer.push(b'\n');
+        }
+        self.file
+            .write_all(&buffer)
+            .and_then(|()| self.file.sync_data())
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+/// 增量读取进行中的会话日志，`follow` 用它跟随另一个终端中的会话
+pub struct JournalTail {
+    path: PathBuf,
+    /// 已读取的字节数
+    offset: u64,
+    /// 还没有写完的最后一行
+    partial: String,
+    at: Option<u64>,
+    pub metadata: Option<ConversationMetadata>,
+    pub messages: Vec<TimedMessage>,
+    /// 会话已结束：写入了结束时的 metadata，或日志已被压缩保存并删除
+    pub finished: bool,
+}
+
+impl JournalTail {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            offset: 0,
+            partial: String::new(),
+            at: None,
+            metadata: None,
+            messages: Vec::new(),
+            finished: false,
+        }
+    }
+
+    /// 读取上次之后写入的完整行，返回消息列表是否有变化
+    pub fn poll(&mut self) -> Result<bool> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && self.metadata.is_some() => {
+                self.finished = true;
+                return Ok(false);
+            }
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to open {}", self.path.display()))
+            }
+        };
+        file.seek(SeekFrom::Start(self.offset))?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        self.offset += bytes.len() as u64;
+        self.partial.push_str(&String::from_utf8_lossy(&bytes));
+
+        let Some(end) = self.partial.rfind('\n') else {
+            return Ok(false);
+        };
+        let complete: String = self.partial.drain(..=end).collect();
+        let mut changed = false;
+        for line in complete.lines().filter(|line| !line.trim().is_empty()) {
+            let entry = serde_json::from_str::<JournalEntry>(line).with_context(|| {
+                format!(
+                    "Failed to parse conversation history: {}",
+                    self.path.display()
+                )
+            })?;
+            match entry {
+                // 第一条是会话开始时的 metadata，之后的一条在会话结束时写入
+                JournalEntry::Metadata(metadata) => {
+                    self.finished = self.metadata.is_some();
+                    self.metadata = Some(metadata);
+                }
+                JournalEntry::Message(message) => {
+                    self.messages.push(TimedMessage {
+                        at: self.at,
+                        message: message.into_owned(),
+                    });
+                    changed = true;
+                }
+                JournalEntry::Time(millis) => self.at = Some(millis),
+                JournalEntry::Reset => {
+                    self.messages.clear();
+                    changed = true;
+                }
+                JournalEntry::Comparison(_) => {}
+            }
+        }
+        Ok(changed)
+    }
+}
+
+/// 带写入时间的消息；只有会话日志记录了时间
+#[derive(Debug)]
+pub struct TimedMessage {
+    /// 写入这条消息时的毫秒时间戳
+    pub at: Option<u64>,
+    pub message: serde_json::Value,
+}
+
+/// 读取保存的对话及每条消息的写入时间
+pub fn load_timed(path: &Path) -> Result<(ConversationMetadata, Vec<TimedMessage>)> {
+    if path.extension().is_some_and(|ext| ext == "jsonl") {
+        let file = File::open(path)
+            .with_context(|| format!("Failed to open conversation history: {}", path.display()))?;
+        return read_journal(BufReader::new(file), path);
+    }
+    let history = load(path)?;
+    let messages = history
+        .messages
+        .into_owned()
+        .into_iter()
+        .map(|message| TimedMessage { at: None, message })
+        .collect();
+    Ok((history.metadata, messages))
+}
+
+/// 回放会话日志
+fn load_journal(reader: impl BufRead, path: &Path) -> Result<ConversationHistory<'static>> {
+    let (metadata, messages) = read_journal(reader, path)?;
+    Ok(ConversationHistory {
+        metadata,
+        messages: Cow::Owned(messages.into_iter().map(|m| m.message).collect()),
+    })
+}
+
+/// 崩溃时写了一半的最后一行会被忽略
+fn read_journal(
+    reader: impl BufRead,
+    path: &Path,
+) -> Result<(ConversationMetadata, Vec<TimedMessage>)> {
+    let mut metadata = None;
+    let mut messages = Vec::new();
+    let mut comparisons = Vec::new();
+    let mut at = None;
+    let mut lines = reader.lines().peekable();
+    while let Some(line) = lines.next() {
+        let line = line?;
+        let entry = match serde_json::from_str::<JournalEntry>(&line) {
+            Ok(entry) => entry,
+            Err(_) if lines.peek().is_none() => break,
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to parse conversation history: {}", path.display())
+                })
+            }
+        };
+        match entry {
+            JournalEntry::Metadata(m) => metadata = Some(m),
+            JournalEntry::Message(message) => messages.push(TimedMessage {
+                at,
+                message: message.into_owned(),
+            }),
+            JournalEntry::Time(millis) => at = Some(millis),
+            JournalEntry::Reset => messages.clear(),
+            JournalEntry::Comparison(comparison) => comparisons.push(comparison.into_owned()),
+        }
+    }
+    let mut metadata: ConversationMetadata = metadata.with_context(|| {
+        format!(
+            "Missing metadata in conversation history: {}",
+            path.display()
+        )
+    })?;
+    metadata.comparisons.extend(comparisons);
+    Ok((metadata, messages))
+}
+
+/// 按标签汇总的用量中的一行
+#[derive(Debug, Clone, PartialEq)]
+pub struct UsageGroup {
+    /// 标签的值；按全部标签分组时为 `key=value, ...`；没有标签时为 None
+    pub tag: Option<String>,
+    pub sessions: usize,
+    pub usage: TokenUsage,
+}
+
+/// 汇总 `dir` 中所有对话记录的 token 用量；`by` 为标签名时按该标签的值分组，否则按全部标签分组
+///
+/// 无法读取的记录计入 `skipped`，不中断汇总
+pub fn usage_by_tag(dir: &Path, by: Option<&str>) -> Result<(Vec<UsageGroup>, usize)> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read history directory: {}", dir.display()))?;
+    let mut groups: BTreeMap<Option<String>, UsageGroup> = BTreeMap::new();
+    let mut skipped = 0;
+    for path in entries.filter_map(|entry| entry.ok().map(|entry| entry.path())) {
+        let is_history = path.file_name().is_some_and(|name| {
+            let name = name.to_string_lossy();
+            name.starts_with("conversation_")
+                && [".json", ".json.zst", ".jsonl"]
+                    .iter()
+                    .any(|ext| name.ends_with(ext))
+        });
+        if !is_history {
+            continue;
+        }
+        let Ok(history) = load(&path) else {
+            skipped += 1;
+            continue;
+        };
+        let tags = &history.metadata.tags;
+        let tag = match by {
+            Some(key) => tags.get(key).cloned(),
+            None if tags.is_empty() => None,
+            None => Some(
+                tags.iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ),
+        };
+        let group = groups.entry(tag.clone()).or_insert(UsageGroup {
+            tag,
+            sessions: 0,
+            usage: TokenUsage::default(),
+        });
+        let usage = history.metadata.usage.unwrap_or_default();
+        group.sessions += 1;
+        group.usage.input_tokens += usage.input_tokens;
+        group.usage.output_tokens += usage.output_tokens;
+    }
+    Ok((groups.into_values().collect(), skipped))
+}
+
+/// 读取保存的对话，支持会话日志（.jsonl），并自动识别是否经过 zstd 压缩
+pub fn load(path: &Path) -> Result<ConversationHistory<'static>> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open conversation history: {}", path.display()))?;
+    let mut reader = BufReader::new(file);
+    if path.extension().is_some_and(|ext| ext == "jsonl") {
+        return load_journal(reader, path);
+    }
+    let compressed = reader.fill_buf()?.starts_with(&ZSTD_MAGIC);
+
+    #[cfg(not(feature = "zstd"))]
+    anyhow::ensure!(
+        !compressed,
+        "{} is compressed; loading it needs a build with the `zstd` feature",
+        path.display()
+    );
+    #[cfg(feature = "zstd")]
+    let history = if compressed {
+        serde_json::from_reader(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        serde_json::from_reader(reader)
+    };
+    #[cfg(not(feature = "zstd"))]
+    let history = serde_json::from_reader(reader);
+    history.with_context(|| format!("Failed to parse conversation history: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn messages() -> Vec<serde_json::Value> {
+        vec![
+            json!({"role": "user", "content": "hello"}),
+            json!({"role": "assistant", "content": [{"type": "text", "text": "x".repeat(10_000)}]}),
+        ]
+    }
+
+    #[test]
+    fn test_save_and_load_plain_history() {
+        let dir = TempDir::new().unwrap();
+        let path = save(dir.path(), &messages(), "claude-test", false, None, None).unwrap();
+        assert_eq!(path.extension().unwrap(), "json");
+
+        let history = load(&path).unwrap();
+        assert_eq!(history.metadata.model, "claude-test");
+        assert_eq!(history.messages.as_ref(), messages().as_slice());
+    }
+
+    #[test]
+    fn test_save_to_chooses_format_by_extension() {
+        let dir = TempDir::new().unwrap();
+        let mut messages = messages();
+        messages.push(json!({"role": "assistant", "content": [
+            {"type": "tool_use", "id": "t1", "name": "read_file", "input": {"path": "a.md"}}
+        ]}));
+        messages.push(json!({"role": "user", "content": [
+            {"type": "tool_result", "tool_use_id": "t1", "content": "```rust\nfn main() {}\n```"}
+        ]}));
+
+        let json_path = dir.path().join("out/chat.json");
+        save_to(&json_path, &messages, "claude-test").unwrap();
+        assert_eq!(load(&json_path).unwrap().messages.len(), 4);
+
+        let md_path = dir.path().join("chat.MD");
+        save_to(&md_path, &messages, "claude-test").unwrap();
+        let markdown = fs::read_to_string(&md_path).unwrap();
+        assert!(markdown.starts_with("# Conversation\n\n- Model: claude-test"));
+        assert!(markdown.contains("## User\n\nhello\n"));
+        assert!(
+            markdown.contains("**Tool call:** `read_file`\n\n```json\n{\"path\":\"a.md\"}\n```")
+        );
+        // 内容中的代码块不会截断外层围栏
+        assert!(markdown.contains("````\n```rust\nfn main() {}\n```\n````"));
+    }
+
+    #[test]
+    fn test_journal_appends_and_replays_resets() {
+        let dir = TempDir::new().unwrap();
+        let mut journal = Journal::create(dir.path(), "claude-test").unwrap();
+        let mut messages = messages();
+        journal.sync(&messages[..1]).unwrap();
+        journal.sync(&messages).unwrap();
+        journal.sync(&messages).unwrap();
+        let content = fs::read_to_string(journal.path()).unwrap();
+        // 元数据、两次写入各一条时间和一条消息；没有变化时不写入
+        assert_eq!(content.lines().count(), 5);
+        let (_, timed) = load_timed(journal.path()).unwrap();
+        assert!(timed.iter().all(|message| message.at.is_some()));
+
+        // 重试时丢弃最后的回答，再追加新的回答
+        messages.pop();
+        messages.push(json!({"role": "assistant", "content": "again"}));
+        journal.sync(&messages).unwrap();
+        let path = journal.path().to_path_buf();
+        assert_eq!(load(&path).unwrap().messages.as_ref(), messages.as_slice());
+
+        // 结束时的改动汇总写在最后一条 metadata 中
+        let changes = SessionChanges {
+            files: Vec::new(),
+            commands: vec!["cargo test".to_string()],
+        };
+        journal
+            .finish(&messages, false, Some(changes.clone()), None)
+            .unwrap();
+        assert_eq!(load(&path).unwrap().metadata.changes, Some(changes));
+
+        // 被杀掉时写了一半的行不影响加载
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"{\"message\": {\"role\": \"us").unwrap();
+        let history = load(&path).unwrap();
+        assert_eq!(history.metadata.model, "claude-test");
+        assert_eq!(history.messages.as_ref(), messages.as_slice());
+    }
+
+    #[test]
+    fn test_tail_follows_a_live_journal() {
+        let dir = TempDir::new().unwrap();
+        let mut journal = Journal::create(dir.path(), "claude-test").unwrap();
+        let mut tail = JournalTail::new(journal.path().to_path_buf());
+        assert!(!tail.poll().unwrap());
+        assert_eq!(tail.metadata.as_ref().unwrap().model, "claude-test");
+
+        let mut messages = messages();
+        journal.sync(&messages).unwrap();
+        // 只写了一半的行等下一次读取
+        let mut file = File::options().append(true).open(journal.path()).unwrap();
+        file.write_all(b"{\"message\": {\"role\": \"us").unwrap();
+        assert!(tail.poll().unwrap());
+        assert_eq!(tail.messages.len(), 2);
+        assert!(tail.messages.iter().all(|message| message.at.is_some()));
+        file.write_all(b"er\", \"content\": \"more\"}}\n").unwrap();
+        assert!(tail.poll().unwrap());
+        assert_eq!(tail.messages[2].message["content"], "more");
+        assert!(!tail.poll().unwrap());
+
+        messages.truncate(1);
+        journal.sync(&messages).unwrap();
+        assert!(tail.poll().unwrap());
+        assert_eq!(tail.messages.len(), 1);
+        assert!(!tail.finished);
+
+        journal
+            .finish(&messages, false, None, Some(TokenUsage::default()))
+            .unwrap();
+        tail.poll().unwrap();
+        assert!(tail.finished);
+    }
+
+    #[test]
+    fn test_fixed_clock_makes_journal_deterministic() {
+        let at = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+        let _clock = clock::set_for_thread(std::sync::Arc::new(clock::FixedClock(at)));
+        let dir = TempDir::new().unwrap();
+        let mut journal = Journal::create(dir.path(), "claude-test").unwrap();
+        journal.sync(&messages()).unwrap();
+        assert!(journal.path().ends_with("conversation_1700000000.jsonl"));
+
+        let (metadata, timed) = load_timed(journal.path()).unwrap();
+        assert_eq!(metadata.created_at, 1_700_000_000);
+        assert!(timed
+            .iter()
+            .all(|message| message.at == Some(1_700_000_000_000)));
+    }
+
+    #[test]
+    fn test_journal_records_comparisons() {
+        let dir = TempDir::new().unwrap();
+        let comparison = Comparison {
+            prompt: "hello".to_string(),
+            answers: vec![
+                ModelAnswer {
+                    model: "claude-a".to_string(),
+                    text: "hi".to_string(),
+                },
+                ModelAnswer {
+                    model: "claude-b".to_string(),
+                    text: "hey".to_string(),
+                },
+            ],
+        };
+        let mut journal = Journal::create(dir.path(), "claude-test").unwrap();
+        journal.sync(&messages()).unwrap();
+        journal.record_comparison(comparison.clone()).unwrap();
+        // 对比的回答不进入对话本身
+        let history = load(journal.path()).unwrap();
+        assert_eq!(history.messages.as_ref(), messages().as_slice());
+        assert_eq!(history.metadata.comparisons, vec![comparison.clone()]);
+
+        // 结束时追加的 metadata 不会覆盖之前的对比
+        let path = journal
+            .finish(&messages(), false, Some(SessionChanges::default()), None)
+            .unwrap();
+        assert_eq!(load(&path).unwrap().metadata.comparisons.len(), 1);
+    }
+
+    #[test]
+    fn test_usage_grouped_by_tag() {
+        let dir = TempDir::new().unwrap();
+        let write = |created_at: u64, tags: &[(&str, &str)], input_tokens: u64| {
+            let mut history = ConversationHistory::new(&[], "claude-test");
+            history.metadata.created_at = created_at;
+            history.metadata.tags = tags
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect();
+            history.metadata.usage = Some(TokenUsage {
+                input_tokens,
+                output_tokens: 10,
+            });
+            write_history(dir.path(), &history, false).unwrap();
+        };
+        write(1, &[("team", "payments"), ("ticket", "PAY-1")], 100);
+        write(2, &[("team", "payments")], 50);
+        write(3, &[], 7);
+        std::fs::write(dir.path().join("conversation_4.json"), "{").unwrap();
+
+        let (groups, skipped) = usage_by_tag(dir.path(), Some("team")).unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].tag, None);
+        assert_eq!(groups[0].usage.input_tokens, 7);
+        assert_eq!(groups[1].tag.as_deref(), Some("payments"));
+        assert_eq!(groups[1].sessions, 2);
+        assert_eq!(groups[1].usage.input_tokens, 150);
+        assert_eq!(groups[1].usage.output_tokens, 20);
+
+        let (groups, _) = usage_by_tag(dir.path(), None).unwrap();
+        assert_eq!(groups.len(), 3);
+        assert_eq!(
+            groups[2].tag.as_deref(),
+            Some("team=payments, ticket=PAY-1")
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_compressed_history_loads_transparently() {
+        let dir = TempDir::new().unwrap();
+        let path = save(dir.path(), &messages(), "claude-test", true, None, None).unwrap();
+        assert!(path.to_string_lossy().ends_with(".json.zst"));
+        // 重复内容压缩后明显变小
+        assert!(fs::metadata(&path).unwrap().len() < 1_000);
+
+        // 即使改了扩展名也按内容识别
+        let renamed = dir.path().join("copy.json");
+        fs::rename(&path, &renamed).unwrap();
+        assert_eq!(
+            load(&renamed).unwrap().messages.as_ref(),
+            messages().as_slice()
+        );
+    }
+}
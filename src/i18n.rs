@@ -0,0 +1,468 @@
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// 界面语言
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En = 0,
+    Zh = 1,
+}
+
+impl Lang {
+    /// 解析语言代码，支持 "zh"、"zh-CN"、"zh_CN.UTF-8" 等写法
+    pub fn parse(code: &str) -> Option<Lang> {
+        let code = code.trim().to_lowercase();
+        let primary = code.split(['-', '_', '.']).next().unwrap_or("");
+        match primary {
+            "en" | "c" | "posix" => Some(Lang::En),
+            "zh" => Some(Lang::Zh),
+            _ => None,
+        }
+    }
+
+    /// 从 LC_ALL / LC_MESSAGES / LANG 环境变量推断语言，无法识别时使用英文
+    pub fn detect() -> Lang {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Lang::parse(&value))
+            .unwrap_or(Lang::En)
+    }
+
+    pub fn code(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Zh => "zh",
+        }
+    }
+}
+
+static CURRENT: AtomicU8 = AtomicU8::new(Lang::En as u8);
+
+/// 设置当前进程的界面语言
+pub fn set_lang(lang: Lang) {
+    CURRENT.store(lang as u8, Ordering::Relaxed);
+}
+
+pub fn lang() -> Lang {
+    match CURRENT.load(Ordering::Relaxed) {
+        1 => Lang::Zh,
+        _ => Lang::En,
+    }
+}
+
+/// 按优先级选择语言（--lang > settings.json > 环境变量），返回语言和无法识别时的警告
+pub fn resolve(requested: Option<&str>) -> (Lang, Option<String>) {
+    match requested {
+        Some(code) => match Lang::parse(code) {
+            Some(lang) => (lang, None),
+            None => {
+                let fallback = Lang::detect();
+                let warning = fill(
+                    Msg::UnknownLanguage.text(fallback),
+                    &[&code, &fallback.code()],
+                );
+                (fallback, Some(warning))
+            }
+        },
+        None => (Lang::detect(), None),
+    }
+}
+
+macro_rules! messages {
+    ($($name:ident => $en:expr, $zh:expr;)*) => {
+        /// 所有面向用户的界面文本
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub enum Msg {
+            $($name,)*
+        }
+
+        impl Msg {
+            #[cfg(test)]
+            const ALL: &'static [Msg] = &[$(Msg::$name,)*];
+
+            fn text(self, lang: Lang) -> &'static str {
+                match (self, lang) {
+                    $(
+                        (Msg::$name, Lang::En) => $en,
+                        (Msg::$name, Lang::Zh) => $zh,
+                    )*
+                }
+            }
+        }
+    };
+}
+
+messages! {
+    Subtitle => "A Rust implementation of Claude Code", "Claude Code 的 Rust 实现";
+    AiFeatures => "AI features: {}", "AI 功能: {}";
+    Enabled => "enabled", "已启用";
+    Disabled => "disabled", "已禁用";
+    ConfigFile => "Config file: {}", "配置文件: {}";
+    YouPrompt => "You", "你";
+    ToolLabel => "Tool:", "工具:";
+    ExecutingLabel => "Executing:", "执行命令:";
+    WarningLabel => "warning:", "警告:";
+    ErrorLabel => "Error:", "错误:";
+    HintLabel => "hint:", "提示:";
+    ResponseTruncated => "Warning: the response was truncated at the max_tokens limit.",
+        "警告：响应在 max_tokens 限制处被截断。";
+    ContextSummarized => "Context is nearly full (~{} tokens); summarized {} earlier messages (~{} tokens now).",
+        "上下文接近上限（约 {} tokens），已总结 {} 条较早的消息（当前约 {} tokens）。";
+    ToolResultsElided => "Context is nearly full (~{} tokens); replaced {} earlier tool outputs with short summaries (~{} tokens now).",
+        "上下文接近上限（约 {} tokens），已将 {} 个较早的工具输出替换为简短摘要（当前约 {} tokens）。";
+    SettingsReloadFailed => "Settings reload failed, keeping previous settings:",
+        "重新加载配置失败，继续使用之前的配置:";
+    SettingsReloaded => "Settings reloaded:", "配置已重新加载:";
+    RestartRequired => "Changes to {} take effect after restart", "{} 的修改需要重启后生效";
+    ExtendTurnsPrompt => "Maximum turns ({}) reached. Extend by how many turns? (0 to stop)",
+        "已达到最大轮数（{}）。要再增加多少轮？（0 表示结束）";
+    MaxTurnsReached => "Maximum turns reached.", "已达到最大轮数。";
+    MaxTurnsBeforeDone => "Maximum turns ({}) reached before the task finished.",
+        "任务完成前已达到最大轮数（{}）。";
+    TurnLimitSet => "Turn limit set to {} ({} used)", "轮数上限已设为 {}（已使用 {}）";
+    TurnStatus => "Turn {}/{}", "第 {}/{} 轮";
+    Attached => "Attached {} ({} pages, {} KB); it will be sent with your next message",
+        "已附加 {}（{} 页，{} KB），将随下一条消息发送";
+    HelpTurns => "Show or change the maximum number of turns", "查看或修改最大对话轮数";
+    HelpStats => "Show API and per-tool statistics", "显示 API 和各工具的调用统计";
+    HelpContext => "Show what the next request contains and how much of the context window it uses",
+        "显示下一个请求包含的内容及其占用的上下文窗口";
+    EstimateHeader => "Estimate for {} (nothing was sent)", "{} 的估算（未发送请求）";
+    EstimateTokens => "Input: ~{} tokens. Output: up to {} tokens.", "输入：约 {} tokens。输出：最多 {} tokens。";
+    EstimateCost => "Cost of the first request (input only – input plus the longest answer):",
+        "第一个请求的花费（只计输入 – 输入加最长的回答）：";
+    EstimateNote => "Token counts are estimates. Each tool call round sends the conversation again, so runs that use tools cost more.",
+        "token 数为估算值。每轮工具调用都会再次发送整个对话，使用工具的运行花费更多。";
+    ContextTotal => "Context: ~{} of {} tokens ({}%), estimated", "上下文：约 {} / {} token（{}%），为估算值";
+    ContextSystem => "System prompt", "系统提示词";
+    ContextPinned => "pinned {}", "固定 {}";
+    ContextTools => "Tools ({})", "工具（{} 个）";
+    ContextMessages => "Messages ({} turns)", "消息（{} 轮）";
+    ContextTurn => "#{} {} ({} messages)", "#{} {}（{} 条消息）";
+    ContextEarlierTurns => "{} earlier turns", "更早的 {} 轮";
+    ContextSummarizeNote => "Older turns are summarized automatically above {}%", "超过 {}% 时会自动总结较早的对话";
+    HelpAttach => "Attach a PDF to your next message", "附加 PDF，随下一条消息发送";
+    HelpEditor => "Compose the next message in $EDITOR (also Ctrl+X Ctrl+E)",
+        "在 $EDITOR 中编写下一条消息（也可按 Ctrl+X Ctrl+E）";
+    HelpExpand => "Show pasted content waiting to be sent", "查看等待发送的粘贴内容";
+    PastedPlaceholder => "[pasted {} lines] It will be sent as a separate block with your next message; /expand to review it.",
+        "[已粘贴 {} 行] 将作为单独的内容块随下一条消息发送；输入 /expand 查看。";
+    NothingPasted => "No pasted content is waiting to be sent", "没有等待发送的粘贴内容";
+    HelpBranch => "Branch the conversation here, or list branches", "在此处创建对话分支，不带名称时列出分支";
+    HelpCheckout => "Switch to another conversation branch", "切换到另一个对话分支";
+    UsageBranch => "Usage: /branch [name]", "用法：/branch [名称]";
+    UsageCheckout => "Usage: /checkout <name>", "用法：/checkout <名称>";
+    BranchCreated => "Created branch '{}' from '{}'", "已创建分支 '{}'（基于 '{}'）";
+    BranchSwitched => "Switched to branch '{}' ({} messages)", "已切换到分支 '{}'（{} 条消息）";
+    BranchEntry => "{} ({} messages)", "{}（{} 条消息）";
+    HelpRetry => "Discard the last answer and ask again (--model NAME, --temperature T)",
+        "丢弃上一个回答并重新请求（可加 --model 名称、--temperature 数值）";
+    HelpEdit => "Edit the last prompt in $EDITOR and resend it", "在 $EDITOR 中修改上一条提示词后重新发送";
+    UsageRetry => "Usage: /retry [--model NAME] [--temperature 0-1]", "用法：/retry [--model 名称] [--temperature 0-1]";
+    NothingToRetry => "There is no previous prompt to resend", "没有可以重新发送的提示词";
+    Retrying => "Resending: {}", "重新发送: {}";
+    HelpCompare => "Ask another model the last prompt and show both answers (read-only tools)",
+        "用另一个模型回答上一条提示词并显示两个回答（只能使用只读工具）";
+    UsageCompare => "Usage: /compare <model>", "用法：/compare <模型>";
+    NothingToCompare => "There is no previous prompt to compare", "没有可以对比的提示词";
+    Comparing => "Asking {}: {}", "正在询问 {}: {}";
+    HelpVariants => "Ask for several answers to the last prompt at different temperatures and pick one",
+        "以不同的温度为上一条提示词请求多个回答，从中选择一个";
+    UsageVariants => "Usage: /variants [n], with n from 2 to {}", "用法：/variants [n]，n 为 2 到 {}";
+    VariantLabel => "Variant {} (temperature {})", "候选 {}（温度 {}）";
+    VariantCallsTool => "[then calls {}]", "[随后调用 {}]";
+    ChooseVariant => "Which answer should replace the last one?", "用哪个回答替换上一个回答？";
+    KeepCurrentAnswer => "Keep the current answer", "保留当前的回答";
+    NoVariants => "None of the requests succeeded", "所有请求都失败了";
+    CompareNotRecorded => "auto_save is off, so this comparison is not saved", "auto_save 已关闭，这次对比不会保存";
+    HelpPlan => "Toggle plan mode: read-only tools, then approve the plan", "切换计划模式：只用只读工具，批准计划后再执行";
+    PlanModeOn => "Plan mode on: Claude can only read files and will propose a plan for approval",
+        "计划模式已开启：Claude 只能读取文件，并会给出计划等待批准";
+    PlanModeOff => "Plan mode off: all tools are available", "计划模式已关闭：所有工具均可使用";
+    ApprovePlan => "Approve this plan and carry it out?", "批准并执行这个计划？";
+    HelpMode => "Switch permission mode (default, acceptEdits, plan); also Shift+Tab",
+        "切换权限模式（default、acceptEdits、plan），也可按 Shift+Tab";
+    UsageMode => "Usage: /mode [default|acceptEdits|plan]", "用法：/mode [default|acceptEdits|plan]";
+    HelpAddDir => "Let Claude edit another directory without asking; lists the directories without a path",
+        "允许 Claude 不经询问修改另一个目录；不带路径时列出这些目录";
+    DirAdded => "Added {} to the workspace", "已将 {} 加入工作区";
+    HelpCd => "Change the directory Claude's commands run in; shows it without a path",
+        "改变 Claude 执行命令的目录；不带路径时显示当前目录";
+    WorkingDirSet => "Commands now run in {}", "命令现在在 {} 中执行";
+    CommandDir => "  in {}", "  目录：{}";
+    HelpPin => "Include a file's latest contents in every turn", "每轮对话都附带文件的最新内容";
+    HelpPins => "List pinned files and choose which to unpin", "列出固定的文件并选择取消固定";
+    UsagePin => "Usage: /pin <file>", "用法：/pin <文件>";
+    FilePinned => "Pinned {}; its latest contents are sent with every turn", "已固定 {}，每轮对话都会附带它的最新内容";
+    NoPinnedFiles => "No files are pinned", "没有固定的文件";
+    KeepPinned => "Pinned files (uncheck to unpin)", "固定的文件（取消勾选即取消固定）";
+    FilesUnpinned => "Unpinned {} file(s)", "已取消固定 {} 个文件";
+    HelpOutputStyle => "Set the answer style (default, concise, explanatory, teaching) and save it",
+        "设置回答风格（default、concise、explanatory、teaching）并保存";
+    UsageOutputStyle => "Usage: /output-style [default|concise|explanatory|teaching]",
+        "用法：/output-style [default|concise|explanatory|teaching]";
+    OutputStyleSet => "Output style: {}", "回答风格：{}";
+    HelpTasks => "List background tasks started by Claude", "列出 Claude 启动的后台任务";
+    NoTasks => "No background tasks", "没有后台任务";
+    TaskRunning => "running", "运行中";
+    TaskExited => "exit {}", "已退出 {}";
+    TaskKilled => "killed", "已终止";
+    HelpServe => "Run a dev server alongside the session, show its output, or stop it",
+        "在会话旁运行开发服务器，查看其输出或停止它";
+    DevServerStarted => "Dev server started: {}", "开发服务器已启动：{}";
+    DevServerRunning => "Dev server running: {}", "开发服务器运行中：{}";
+    DevServerExited => "Dev server exited: {}", "开发服务器已退出：{}";
+    DevServerStopped => "Dev server stopped", "开发服务器已停止";
+    NoDevServer => "No dev server is running; start one with /serve <command>",
+        "没有运行中的开发服务器，可以用 /serve <command> 启动";
+    HelpOpen => "Open a URL in the browser, by default the last local address seen in command output",
+        "在浏览器中打开 URL，默认打开命令输出中最近出现的本地地址";
+    UsageOpen => "Usage: /open [url]", "用法：/open [url]";
+    OpenUrl => "Open {} in your browser?", "在浏览器中打开 {}？";
+    NoUrl => "No local URL has appeared in command output yet", "命令输出中还没有出现本地地址";
+    ConversationResumed => "Resumed {} messages from {}", "已恢复 {} 条消息（来自 {}）";
+    HelpTest => "Run the project's tests (cargo test, pytest or jest) and show a summary",
+        "运行项目的测试（cargo test、pytest 或 jest）并显示结果摘要";
+    RunningTests => "Running {}...", "正在运行 {}...";
+    HelpBuild => "Build the project with its usual command (cargo build, go build, npm run build)",
+        "用项目惯用的命令构建（cargo build、go build、npm run build）";
+    HelpLint => "Lint the project with its usual command (cargo clippy, go vet, npm run lint, ruff)",
+        "用项目惯用的命令检查代码（cargo clippy、go vet、npm run lint、ruff）";
+    NoProjectCommand => "No {} command is known for this project", "不知道这个项目的 {} 命令";
+    ProjectCommandSucceeded => "{} succeeded", "{} 成功";
+    ProjectCommandFailed => "{} failed ({})", "{} 失败（{}）";
+    HelpStatus => "Show the model, endpoint, auth and API reachability, and check for a newer release",
+        "显示模型、API 地址、认证方式和 API 连通性，并检查是否有新版本";
+    ServeListening => "Listening on {}. Send \"Authorization: Bearer {}\" with every request.",
+        "正在监听 {}，每个请求都需要带上 \"Authorization: Bearer {}\"。";
+    HelpSave => "Save the conversation now, even with auto_save off; a .md path saves Markdown",
+        "立即保存对话（即使关闭了 auto_save），路径以 .md 结尾时保存为 Markdown";
+    ConversationSaved => "Conversation saved to {}", "对话已保存到 {}";
+    HelpEnv => "List the variables loaded from .env and .claude/.env into the commands Claude runs",
+        "列出从 .env 和 .claude/.env 加入 Claude 执行的命令的环境变量";
+    NoEnvVars => "No variables loaded from .env or .claude/.env", "没有从 .env 或 .claude/.env 加载变量";
+    EnvShadowed => "(already set in your environment, which wins)", "（环境中已有同名变量，以环境中的值为准）";
+    HelpShare => "Share the session with secrets redacted, as a secret gist or an HTML file",
+        "分享去除了密钥的会话记录，上传为私密 gist 或写成 HTML 文件";
+    UsageShare => "Usage: /share [gist|html]", "用法：/share [gist|html]";
+    Sharing => "Uploading a secret gist...", "正在上传私密 gist...";
+    ConversationShared => "Shared: {}", "已分享：{}";
+    StatusModel => "Model: {}", "模型: {}";
+    StatusEndpoint => "Endpoint: {} {}", "API 地址: {} {}";
+    StatusAuth => "API key: {} {}", "API key: {} {}";
+    StatusApi => "API: {}", "API: {}";
+    StatusRateLimits => "Rate limits left: {}", "剩余额度: {}";
+    ApiReachable => "reachable ({} ms)", "可以访问（{} 毫秒）";
+    ApiAuthRejected => "the API key was rejected (HTTP {})", "API key 被拒绝（HTTP {}）";
+    ApiNotFound => "no messages endpoint at this URL (HTTP {}); api_base_url should be the full URL ending in /v1/messages",
+        "该地址没有 messages 接口（HTTP {}），api_base_url 应是以 /v1/messages 结尾的完整地址";
+    ApiUnexpectedStatus => "unexpected response (HTTP {})", "意外的响应（HTTP {}）";
+    ApiUnreachable => "unreachable: {}", "无法访问: {}";
+    TelemetryConsent => "Send anonymous usage statistics (tool, command and error counts; no code, paths or prompts) to {}?",
+        "是否向 {} 发送匿名使用统计（工具、命令和错误的次数，不含代码、路径或提示词）？";
+    TelemetryChoiceSaved => "Saved to .claude/settings.json; change \"telemetry\" there at any time",
+        "已保存到 .claude/settings.json，可以随时修改其中的 \"telemetry\"";
+    CrashReportSaved => "rust-claude-code crashed. A report was saved to {} (panic message, backtrace, version and OS; no conversation content).",
+        "rust-claude-code 崩溃了。报告已保存到 {}（panic 信息、调用栈、版本和系统，不含对话内容）。";
+    CrashOpenIssue => "Open a GitHub issue prefilled with this report?", "打开一个预填了该报告的 GitHub issue？";
+    CrashIssueLink => "You can report it at {}", "可以在 {} 报告这个问题";
+    UpdateAvailable => "A newer release is available: {} (current {})", "有新版本可用: {}（当前 {}）";
+    ModeAcceptEdits => "accept edits", "自动接受修改";
+    ModePlan => "plan", "计划";
+    ModeNormal => "normal", "普通";
+    ModeNotAllowed => "{} mode is disabled by managed-settings.json", "{} 模式已被 managed-settings.json 禁用";
+    StatusLineDirty => "{} ({} changed)", "{}（{} 个改动）";
+    StatusLineProfile => "profile {}", "配置档 {}";
+    StatusLineTokens => "{}/{} tokens ({}%)", "{}/{} tokens（{}%）";
+    DefaultModeOn => "Normal mode: all tools are available", "普通模式：所有工具均可使用";
+    AcceptEditsModeOn => "Accept-edits mode: workspace file edits run without asking, commands ask first",
+        "自动接受修改模式：工作区内的文件修改直接执行，执行命令前会询问";
+    ApproveCommand => "Run `{}`?", "执行 `{}`？";
+    CommandMayPrompt => "If the command asks for a password, type it here",
+        "如果命令要求输入密码，请直接在这里输入";
+    ContentFlagged => "Warning: the output of {} contains text that looks like instructions to Claude: {}",
+        "警告：{} 的输出中有像是写给 Claude 的指令：{}";
+    ApproveSuspiciousContent => "Add the output of {} to the conversation anyway?",
+        "仍然把 {} 的输出加入对话？";
+    ApproveCommandReason => "Run `{}`? ({})", "执行 `{}`？（{}）";
+    ApproveOutsideWorkspace => "Allow {} to access {} outside the workspace?",
+        "允许 {} 访问工作区外的 {}？";
+    PatchOutWorktree => "Working in a temporary copy of the repository; changes will be written to {}",
+        "在仓库的临时副本中工作，改动将写入 {}";
+    PatchOutWritten => "Wrote a patch changing {} file(s) to {}. Review it, then apply it in {} with `git apply`",
+        "改动 {} 个文件的补丁已写入 {}。检查后在 {} 中用 `git apply` 应用";
+    PatchOutEmpty => "No files were changed; {} is empty", "没有文件被改动，{} 为空";
+    TaskWorktreeCreated => "Working on branch {} in {}", "在分支 {} 上工作（{}）";
+    TaskWorktreeDone => "The work is on branch {} in {}; merge it when ready, and remove the worktree with `worktrees cleanup`",
+        "改动在分支 {} 上（{}），准备好后合并；用 `worktrees cleanup` 删除 worktree";
+    NoTaskWorktrees => "No task worktrees in {}", "{} 中没有任务 worktree";
+    WorktreeRunning => "running (pid {})", "运行中（pid {}）";
+    WorktreeClean => "clean", "无改动";
+    WorktreeDirty => "{} uncommitted change(s)", "{} 处未提交的改动";
+    WorktreeDetached => "(detached)", "（分离）";
+    WorktreeSkippedRunning => "Skipped {}: a session is running (pid {})", "已跳过 {}：有会话正在运行（pid {}）";
+    WorktreeSkippedDirty => "Skipped {}: {} uncommitted change(s); use --force to remove it anyway",
+        "已跳过 {}：有 {} 处未提交的改动；用 --force 仍然删除";
+    WorktreeRemoved => "Removed {}", "已删除 {}";
+    WorktreeRemovedKeptBranch => "Removed {} (kept unmerged branch {})", "已删除 {}（保留未合并的分支 {}）";
+    ScratchWorktree => "Working in a temporary copy of the project; nothing here changes unless you apply the result",
+        "在项目的临时副本中工作；除非应用结果，这里的文件不会改变";
+    ScratchNoChanges => "No files were changed in the temporary copy", "临时副本中没有文件被改动";
+    ScratchChanges => "Changes to {} file(s) in the temporary copy:", "临时副本中 {} 个文件的改动:";
+    ScratchApply => "Apply these changes to {}?", "把这些改动应用到 {}？";
+    ScratchApplied => "Applied changes to {} file(s)", "已应用 {} 个文件的改动";
+    ScratchDiscarded => "Changes discarded", "已丢弃改动";
+    ScratchNotApplied => "The changes were not applied because there is no terminal to ask; use --patch-out to keep them",
+        "没有终端可以询问，改动未应用；可以用 --patch-out 保留改动";
+    SessionChanges => "Session Changes:", "本次会话的改动:";
+    SessionCommands => "{} command(s) run:", "执行了 {} 条命令:";
+    ApproveProtectedFile => "Allow {} to modify {}?", "允许 {} 修改 {}？";
+    EditorEmpty => "Editor closed with an empty message; nothing was sent",
+        "编辑器中的内容为空，未发送任何消息";
+    UnknownEditMode => "Unknown edit_mode '{}' in .claude/settings.json, using emacs",
+        ".claude/settings.json 中的 edit_mode '{}' 无法识别，使用 emacs";
+    HelpHelp => "Show available commands", "显示可用命令";
+    UsageTurns => "Usage: /turns <positive number>", "用法：/turns <正整数>";
+    UsageAttach => "Usage: /attach <file.pdf>", "用法：/attach <文件.pdf>";
+    UnknownCommand => "Unknown command: /{} (try /help)", "未知命令：/{}（输入 /help 查看可用命令）";
+    PerformanceStats => "Performance Statistics:", "性能统计:";
+    TotalRequests => "Total requests: {}", "请求总数: {}";
+    Successful => "Successful: {}", "成功: {}";
+    Failed => "Failed: {}", "失败: {}";
+    SuccessRate => "Success rate: {}%", "成功率: {}%";
+    AverageResponse => "Average response time: {} ms", "平均响应时间: {} ms";
+    ToolStatistics => "Tool Statistics:", "工具统计:";
+    ConfigDirectory => "Config directory:", "配置目录:";
+    NotSet => "(not set)", "（未设置）";
+    ApiKeyMissing => "API key not found. Please set ANTHROPIC_API_KEY environment variable \
+        or configure it in .claude/settings.json",
+        "未找到 API 密钥。请设置 ANTHROPIC_API_KEY 环境变量，或在 .claude/settings.json 中配置";
+    HintAuth => "check the API key (ANTHROPIC_API_KEY or anthropic_api_key in .claude/settings.json)",
+        "请检查 API 密钥（ANTHROPIC_API_KEY 或 .claude/settings.json 中的 anthropic_api_key）";
+    HintRateLimited => "still rate limited after retries; wait a moment or raise retry.max_retries in .claude/settings.json",
+        "重试后仍被限流；请稍后再试，或调大 .claude/settings.json 中的 retry.max_retries";
+    HintTimedOut => "the request timed out; raise --timeout or API_TIMEOUT_MS",
+        "请求超时；请调大 --timeout 或 API_TIMEOUT_MS";
+    CachedResponse => "Using a cached response saved {} ago (--no-cache to send the request)",
+        "使用 {} 前缓存的回答（--no-cache 重新发送请求）";
+    RateLimitWait => "Waiting {}s for rate limit", "等待 {} 秒以免超出速率限制";
+    FallbackModel => "{} is still overloaded after retries; using {} for the rest of this turn",
+        "{} 重试后仍然过载，本轮剩余的请求改用 {}";
+    StatusWaiting => "waiting for Claude", "等待 Claude 回复";
+    StatusResponse => "response received", "已收到回复";
+    StatusToolRunning => "running tool {}", "正在运行工具 {}";
+    StatusToolDone => "tool {} finished", "工具 {} 已完成";
+    StatusToolFailed => "tool {} failed", "工具 {} 执行失败";
+    StatusReady => "ready for input", "等待输入";
+    StatusSessionEnded => "session ended", "会话已结束";
+    UnknownLanguage => "Unknown language '{}', using '{}'", "无法识别的语言 '{}'，使用 '{}'";
+    ReplayHeader => "Replaying {} ({}, {} messages)", "回放 {}（{}，{} 条消息）";
+    ReplayTurn => "Turn {}", "第 {} 轮";
+    ReplayNext => "Enter: next turn, q: quit", "回车：下一轮，q：退出";
+    ReplayUser => "You:", "你:";
+    ReplayToolResult => "Result:", "结果:";
+    ReplayToolError => "Error:", "错误:";
+    ReplayMoreLines => "... {} more lines", "……还有 {} 行";
+    FollowHeader => "Following {} ({}), read-only; Ctrl-C to stop",
+        "正在跟随 {}（{}），只读；Ctrl-C 停止";
+    FollowRewritten => "The session rewrote earlier messages (compaction, /retry or a branch switch)",
+        "会话改写了之前的消息（压缩、/retry 或切换分支）";
+    FollowEnded => "The session has ended", "会话已结束";
+    ConversationImported => "Imported \"{}\" ({} messages) to {}", "已导入“{}”（{} 条消息）到 {}";
+    ProfileExported => "Wrote {} settings to {}", "已将 {} 项设置写入 {}";
+    ProfileImported => "Saved profile '{}'. Use it with --profile {}.", "已保存配置档“{}”，用 --profile {} 使用它。";
+    NoProfiles => "No profiles in ~/.claude/profiles. Save one with `config import <FILE>`.",
+        "~/.claude/profiles 中没有配置档，可以用 `config import <FILE>` 保存。";
+    ProfileActive => "(active)", "（当前）";
+    UsageTags => "tags", "标签";
+    UsageUntagged => "(untagged)", "（无标签）";
+    UsageSessions => "sessions", "会话数";
+    UsageInputTokens => "input tokens", "输入 tokens";
+    UsageOutputTokens => "output tokens", "输出 tokens";
+    UsageSkipped => "{} conversations could not be read and were skipped", "{} 个对话记录无法读取，已跳过";
+    ImportResumeHint => "Continue a conversation with --resume <FILE>.", "用 --resume <FILE> 继续对话。";
+    EvalWorkspace => "workspace: {}", "工作区：{}";
+    EvalPass => "PASS", "通过";
+    EvalFail => "FAIL", "失败";
+    EvalTokens => "{} in {} out", "输入 {} 输出 {}";
+    EvalRegressed => "regressed", "退步";
+    EvalFixed => "fixed", "修复";
+    EvalSummary => "{} passed, {} failed, ${}", "{} 个通过，{} 个失败，${}";
+    EvalSaved => "Saved results to {}", "结果已保存到 {}";
+    EvalRegressions => "Regressed: {}", "退步的任务：{}";
+    BenchLegend => "time: [min median max] ± std dev per iteration", "时间：每次迭代的 [最小 中位数 最大] ± 标准差";
+    BenchTime => "time", "时间";
+    BenchThroughput => "thrpt", "吞吐量";
+    BenchChange => "change", "变化";
+    BenchRegressed => "(regressed)", "（变慢）";
+    BenchImproved => "(improved)", "（变快）";
+    BenchSaved => "Saved baseline '{}' to {}", "基线“{}”已保存到 {}";
+}
+
+/// 当前语言下的文本
+pub fn t(msg: Msg) -> &'static str {
+    msg.text(lang())
+}
+
+/// 当前语言下的文本，依次替换其中的 `{}` 占位符
+pub fn tf(msg: Msg, args: &[&dyn Display]) -> String {
+    fill(t(msg), args)
+}
+
+fn fill(template: &str, args: &[&dyn Display]) -> String {
+    let mut parts = template.split("{}");
+    let mut output = parts.next().unwrap_or("").to_string();
+    for (index, part) in parts.enumerate() {
+        if let Some(arg) = args.get(index) {
+            output.push_str(&arg.to_string());
+        }
+        output.push_str(part);
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_language_codes() {
+        assert_eq!(Lang::parse("zh_CN.UTF-8"), Some(Lang::Zh));
+        assert_eq!(Lang::parse("zh-TW"), Some(Lang::Zh));
+        assert_eq!(Lang::parse("en_US.UTF-8"), Some(Lang::En));
+        assert_eq!(Lang::parse("C"), Some(Lang::En));
+        assert_eq!(Lang::parse("fr"), None);
+
+        let (_, warning) = resolve(Some("klingon"));
+        assert!(warning.unwrap().contains("klingon"));
+    }
+
+    #[test]
+    fn test_catalogs_have_matching_placeholders() {
+        for msg in Msg::ALL {
+            let en = msg.text(Lang::En);
+            let zh = msg.text(Lang::Zh);
+            assert_eq!(
+                en.matches("{}").count(),
+                zh.matches("{}").count(),
+                "{:?}",
+                msg
+            );
+            assert!(!zh.is_empty(), "{:?}", msg);
+        }
+    }
+
+    #[test]
+    fn test_fill_placeholders() {
+        assert_eq!(
+            fill(Msg::TurnStatus.text(Lang::En), &[&3, &10]),
+            "Turn 3/10"
+        );
+        assert_eq!(
+            fill(Msg::TurnStatus.text(Lang::Zh), &[&3, &10]),
+            "第 3/10 轮"
+        );
+    }
+}
@@ -0,0 +1,322 @@
+//! history import：把 Claude Code 的会话记录和 claude.ai 的导出转换为本工具的对话记录，之后用 --resume 继续
+
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// 转换后的一段对话
+#[derive(Debug)]
+pub struct ImportedConversation {
+    pub title: Option<String>,
+    /// 原对话开始的 Unix 秒数，无法得知时为 None
+    pub created_at: Option<u64>,
+    pub model: String,
+    pub messages: Vec<Value>,
+}
+
+/// 读取导出文件，按内容识别格式；claude.ai 的导出可以包含多段对话
+pub fn read(path: &Path) -> Result<Vec<ImportedConversation>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let conversations = match serde_json::from_str::<Value>(&text) {
+        Ok(Value::Array(conversations)) => conversations.iter().map(from_claude_ai).collect(),
+        Ok(conversation) if conversation.get("chat_messages").is_some() => {
+            vec![from_claude_ai(&conversation)]
+        }
+        // Claude Code 的会话记录每行一个 JSON 对象
+        _ => vec![from_claude_code(&text)?],
+    };
+    let conversations: Vec<_> = conversations
+        .into_iter()
+        .filter(|conversation| !conversation.messages.is_empty())
+        .collect();
+    if conversations.is_empty() {
+        bail!(
+            "{} contains no messages in a Claude Code transcript or claude.ai export format",
+            path.display()
+        );
+    }
+    Ok(conversations)
+}
+
+/// Claude Code 的 `~/.claude/projects/<project>/<session>.jsonl`
+///
+/// 记录中有重试和编辑留下的分支，沿 parentUuid 从最后一条消息回溯得到当前分支；
+/// 子代理（isSidechain）和内部消息（isMeta）不导入
+fn from_claude_code(text: &str) -> Result<ImportedConversation> {
+    let mut entries = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: Value = serde_json::from_str(line)
+            .with_context(|| format!("Line {} is not valid JSON", index + 1))?;
+        entries.push(entry);
+    }
+    let is_message = |entry: &Value| {
+        matches!(entry["type"].as_str(), Some("user" | "assistant"))
+            && entry["isSidechain"] != true
+            && entry["isMeta"] != true
+    };
+
+    let by_uuid: HashMap<&str, &Value> = entries
+        .iter()
+        .filter_map(|entry| Some((entry["uuid"].as_str()?, entry)))
+        .collect();
+    let mut chain = Vec::new();
+    let mut current = entries.iter().rev().find(|entry| is_message(entry));
+    while let Some(entry) = current {
+        chain.push(entry);
+        current = entry["parentUuid"]
+            .as_str()
+            .and_then(|parent| by_uuid.get(parent).copied());
+        // 防止损坏的记录中出现环
+        if chain.len() > entries.len() {
+            bail!("The transcript's parentUuid links form a cycle");
+        }
+    }
+    chain.reverse();
+    // 没有 uuid 的旧记录按文件顺序
+    if chain.len() <= 1 {
+        chain = entries.iter().collect();
+    }
+
+    let model = chain
+        .iter()
+        .rev()
+        .find_map(|entry| entry["message"]["model"].as_str())
+        .unwrap_or("unknown")
+        .to_string();
+    let created_at = chain
+        .iter()
+        .find_map(|entry| entry["timestamp"].as_str().and_then(parse_timestamp));
+    let title = entries
+        .iter()
+        .find(|entry| entry["type"] == "summary")
+        .and_then(|entry| entry["summary"].as_str())
+        .map(str::to_string);
+    let messages = chain
+        .into_iter()
+        .filter(|entry| is_message(entry))
+        .map(|entry| {
+            let message = &entry["message"];
+            let role = message["role"].as_str().unwrap_or("user").to_string();
+            (role, message["content"].clone())
+        })
+        .collect();
+    Ok(ImportedConversation {
+        title,
+        created_at,
+        model,
+        messages: convert(messages),
+    })
+}
+
+/// claude.ai 导出（conversations.json）中的一段对话
+fn from_claude_ai(conversation: &Value) -> ImportedConversation {
+    let messages = conversation["chat_messages"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(|message| {
+            let role = match message["sender"].as_str() {
+                Some("assistant") => "assistant",
+                _ => "user",
+            };
+            let mut blocks = match &message["content"] {
+                Value::Array(blocks) if !blocks.is_empty() => blocks.clone(),
+                _ => vec![json!({ "type": "text", "text": message["text"] })],
+            };
+            // 附件只导出了提取出的文本
+            for attachment in message["attachments"].as_array().into_iter().flatten() {
+                if let Some(content) = attachment["extracted_content"].as_str() {
+                    blocks.push(json!({
+                        "type": "text",
+                        "text": format!(
+                            "<attachment name=\"{}\">\n{}\n</attachment>",
+                            attachment["file_name"].as_str().unwrap_or("attachment"),
+                            content
+                        )
+                    }));
+                }
+            }
+            (role.to_string(), Value::Array(blocks))
+        })
+        .collect();
+    ImportedConversation {
+        title: conversation["name"]
+            .as_str()
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+        created_at: conversation["created_at"]
+            .as_str()
+            .and_then(parse_timestamp),
+        model: "claude.ai".to_string(),
+        messages: convert(messages),
+    }
+}
+
+fn parse_timestamp(timestamp: &str) -> Option<u64> {
+    let time = humantime::parse_rfc3339_weak(timestamp.trim_end_matches('Z')).ok()?;
+    time.duration_since(std::time::UNIX_EPOCH)
+        .ok()
+        .map(|duration| duration.as_secs())
+}
+
+/// 转换为本工具的消息格式
+///
+/// 原来的工具在这里不存在，工具调用和结果改写为文本，模型不会以为还能调用它们；
+/// thinking 块的签名无法在另一段对话中使用，直接去掉。相邻的同角色消息合并，
+/// 对话总是从用户消息开始
+fn convert(messages: Vec<(String, Value)>) -> Vec<Value> {
+    let mut tool_names: HashMap<String, String> = HashMap::new();
+    let mut merged: Vec<(String, Vec<Value>)> = Vec::new();
+    for (role, content) in messages {
+        let blocks = match content {
+            Value::String(text) => vec![json!({ "type": "text", "text": text })],
+            Value::Array(blocks) => blocks,
+            _ => continue,
+        };
+        let blocks: Vec<Value> = blocks
+            .into_iter()
+            .filter_map(|block| convert_block(block, &mut tool_names))
+            .collect();
+        if blocks.is_empty() || (merged.is_empty() && role != "user") {
+            continue;
+        }
+        match merged.last_mut() {
+            Some((last_role, last_blocks)) if *last_role == role => last_blocks.extend(blocks),
+            _ => merged.push((role, blocks)),
+        }
+    }
+
+    merged
+        .into_iter()
+        .map(|(role, blocks)| {
+            let content = if blocks.iter().all(|block| block["type"] == "text") {
+                let texts: Vec<&str> = blocks
+                    .iter()
+                    .filter_map(|block| block["text"].as_str())
+                    .collect();
+                json!(texts.join("\n\n"))
+            } else {
+                Value::Array(blocks)
+            };
+            json!({ "role": role, "content": content })
+        })
+        .collect()
+}
+
+fn convert_block(block: Value, tool_names: &mut HashMap<String, String>) -> Option<Value> {
+    let text = match block["type"].as_str()? {
+        "text" => block["text"]
+            .as_str()
+            .filter(|text| !text.trim().is_empty())?
+            .to_string(),
+        "tool_use" => {
+            let name = block["name"].as_str().unwrap_or("tool").to_string();
+            if let Some(id) = block["id"].as_str() {
+                tool_names.insert(id.to_string(), name.clone());
+            }
+            format!("[Called {} with {}]", name, block["input"])
+        }
+        "tool_result" => {
+            let name = block["tool_use_id"]
+                .as_str()
+                .and_then(|id| tool_names.get(id))
+                .map_or("tool", String::as_str);
+            let content = match &block["content"] {
+                Value::String(text) => text.clone(),
+                Value::Array(blocks) => blocks
+                    .iter()
+                    .filter_map(|block| block["text"].as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => String::new(),
+            };
+            let label = if block["is_error"] == true {
+                "Error from"
+            } else {
+                "Result of"
+            };
+            format!("[{} {}]\n{}", label, name, content)
+        }
+        "image" if block["source"]["type"] == "base64" => return Some(block),
+        _ => return None,
+    };
+    Some(json!({ "type": "text", "text": text }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_claude_code_transcript_follows_current_branch() {
+        let lines = [
+            json!({"type": "summary", "summary": "Fix the parser"}),
+            json!({"type": "user", "uuid": "u1", "parentUuid": null, "timestamp": "2025-06-01T10:00:00.000Z",
+                   "message": {"role": "user", "content": "fix the parser"}}),
+            json!({"type": "assistant", "uuid": "a1", "parentUuid": "u1",
+                   "message": {"role": "assistant", "model": "claude-sonnet-4-5", "content": [
+                       {"type": "thinking", "thinking": "...", "signature": "sig"},
+                       {"type": "tool_use", "id": "toolu_1", "name": "Read", "input": {"file_path": "src/parser.rs"}}]}}),
+            json!({"type": "user", "uuid": "r1", "parentUuid": "a1",
+                   "message": {"role": "user", "content": [
+                       {"type": "tool_result", "tool_use_id": "toolu_1", "content": "fn parse() {}"}]}}),
+            // 被重试替换掉的回答
+            json!({"type": "assistant", "uuid": "a2", "parentUuid": "r1",
+                   "message": {"role": "assistant", "content": [{"type": "text", "text": "old answer"}]}}),
+            json!({"type": "assistant", "uuid": "a3", "parentUuid": "r1",
+                   "message": {"role": "assistant", "content": [{"type": "text", "text": "Fixed."}]}}),
+            json!({"type": "user", "uuid": "s1", "parentUuid": "a3", "isSidechain": true,
+                   "message": {"role": "user", "content": "subagent prompt"}}),
+        ];
+        let text: Vec<String> = lines.iter().map(Value::to_string).collect();
+        let conversation = from_claude_code(&text.join("\n")).unwrap();
+
+        assert_eq!(conversation.title.as_deref(), Some("Fix the parser"));
+        assert_eq!(conversation.model, "claude-sonnet-4-5");
+        assert_eq!(conversation.created_at, Some(1_748_772_000));
+        let messages = conversation.messages;
+        assert_eq!(messages.len(), 4, "{:#?}", messages);
+        assert_eq!(messages[0]["content"], "fix the parser");
+        assert_eq!(
+            messages[1]["content"],
+            "[Called Read with {\"file_path\":\"src/parser.rs\"}]"
+        );
+        assert_eq!(messages[2]["content"], "[Result of Read]\nfn parse() {}");
+        assert_eq!(messages[3]["content"], "Fixed.");
+    }
+
+    #[test]
+    fn test_claude_ai_export_with_several_conversations() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("conversations.json");
+        let export = json!([
+            {"uuid": "c1", "name": "Haiku", "created_at": "2024-03-01T08:00:00.123456Z", "chat_messages": [
+                {"sender": "human", "text": "write a haiku", "content": [], "attachments": [
+                    {"file_name": "notes.txt", "extracted_content": "autumn"}]},
+                {"sender": "assistant", "text": "", "content": [{"type": "text", "text": "Leaves fall"}]}
+            ]},
+            {"uuid": "c2", "name": "", "chat_messages": []}
+        ]);
+        std::fs::write(&path, export.to_string()).unwrap();
+
+        let conversations = read(&path).unwrap();
+        assert_eq!(conversations.len(), 1);
+        let conversation = &conversations[0];
+        assert_eq!(conversation.title.as_deref(), Some("Haiku"));
+        assert_eq!(conversation.created_at, Some(1_709_280_000));
+        assert_eq!(
+            conversation.messages[0]["content"],
+            "write a haiku\n\n<attachment name=\"notes.txt\">\nautumn\n</attachment>"
+        );
+        assert_eq!(conversation.messages[1]["role"], "assistant");
+
+        std::fs::write(&path, "not json\n").unwrap();
+        assert!(read(&path).is_err());
+    }
+}
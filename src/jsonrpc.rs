@@ -0,0 +1,229 @@
+//! 标准输入输出上逐行 JSON-RPC 2.0 的公共部分，acp 和 mcp serve 共用
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::future::Future;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// JSON-RPC 错误响应
+pub struct RpcError {
+    code: i64,
+    message: String,
+    data: Option<Value>,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// 附加的结构化信息，放在错误的 data 字段
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+}
+
+/// 与对端之间的连接：发出的消息进入队列，由写入任务逐行写出
+pub struct Connection {
+    outgoing: mpsc::UnboundedSender<Value>,
+    /// 本端发出、等待对端响应的请求
+    pending: Mutex<HashMap<u64, oneshot::Sender<Value>>>,
+    next_id: AtomicU64,
+}
+
+impl Connection {
+    /// 新连接及其发出消息的队列
+    pub fn channel() -> (Self, mpsc::UnboundedReceiver<Value>) {
+        let (outgoing, queue) = mpsc::unbounded_channel();
+        let connection = Self {
+            outgoing,
+            pending: Mutex::default(),
+            next_id: AtomicU64::new(1),
+        };
+        (connection, queue)
+    }
+
+    fn send(&self, message: Value) {
+        // 写入任务结束说明对端已经断开
+        let _ = self.outgoing.send(message);
+    }
+
+    pub fn respond(&self, id: Value, result: Result<Value, RpcError>) {
+        self.send(match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(e) => {
+                let mut error = json!({ "code": e.code, "message": e.message });
+                if let Some(data) = e.data {
+                    error["data"] = data;
+                }
+                json!({ "jsonrpc": "2.0", "id": id, "error": error })
+            }
+        });
+    }
+
+    pub fn notify(&self, method: &str, params: Value) {
+        self.send(json!({ "jsonrpc": "2.0", "method": method, "params": params }));
+    }
+
+    /// 向对端发出请求并等待结果；对端返回错误或断开时为 None
+    pub async fn request(&self, method: &str, params: Value) -> Option<Value> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        self.send(json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params }));
+        let result = receiver.await.ok();
+        self.pending.lock().unwrap().remove(&id);
+        result.filter(|result| !result.is_null())
+    }
+
+    /// 对端对本端请求的响应
+    fn resolve(&self, message: &Value) {
+        let Some(id) = message["id"].as_u64() else {
+            return;
+        };
+        if let Some(sender) = self.pending.lock().unwrap().remove(&id) {
+            let _ = sender.send(message.get("result").cloned().unwrap_or(Value::Null));
+        }
+    }
+}
+
+/// 处理对端发来的请求和通知
+pub trait Handler: Send + Sync + 'static {
+    fn connection(&self) -> &Connection;
+
+    /// 每个请求在单独的任务中处理，较长的请求不会阻塞其他消息
+    fn request(
+        self: Arc<Self>,
+        method: String,
+        params: Value,
+    ) -> impl Future<Output = Result<Value, RpcError>> + Send;
+
+    fn notification(&self, method: &str, params: &Value);
+}
+
+/// 协议消息独占标准输出，工具和日志原本写到标准输出的内容改写到标准错误
+///
+/// 必须在输出任何内容之前调用
+pub fn take_stdout() -> Result<Box<dyn Write + Send>> {
+    #[cfg(unix)]
+    {
+        use std::os::fd::FromRawFd;
+        // SAFETY: 只复制和替换本进程的标准文件描述符，dup 得到的描述符只交给返回的 File
+        unsafe {
+            let protocol = libc::dup(libc::STDOUT_FILENO);
+            if protocol < 0 || libc::dup2(libc::STDERR_FILENO, libc::STDOUT_FILENO) < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to redirect stdout");
+            }
+            Ok(Box::new(std::fs::File::from_raw_fd(protocol)))
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        Ok(Box::new(std::io::stdout()))
+    }
+}
+
+/// 在标准输入输出上运行 `handler`，直到对端关闭输入
+pub async fn run<H: Handler>(
+    mut output: Box<dyn Write + Send>,
+    handler: impl FnOnce(Connection) -> H,
+) -> Result<()> {
+    let (connection, mut queue) = Connection::channel();
+    let writer = tokio::spawn(async move {
+        while let Some(message) = queue.recv().await {
+            let written = writeln!(output, "{}", message).and_then(|_| output.flush());
+            if let Err(e) = written {
+                warn!("Failed to write to the client: {}", e);
+                break;
+            }
+        }
+    });
+
+    let handler = Arc::new(handler(connection));
+    serve(tokio::io::BufReader::new(tokio::io::stdin()), handler).await?;
+    writer.abort();
+    Ok(())
+}
+
+pub async fn serve<H: Handler>(input: impl AsyncBufRead + Unpin, handler: Arc<H>) -> Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await.context("Failed to read stdin")? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let connection = handler.connection();
+        let message: Value = match serde_json::from_str(&line) {
+            Ok(message) => message,
+            Err(e) => {
+                connection.respond(Value::Null, Err(RpcError::new(PARSE_ERROR, e.to_string())));
+                continue;
+            }
+        };
+
+        let method = message["method"].as_str().map(str::to_string);
+        let id = message.get("id").cloned();
+        match (method, id) {
+            (Some(method), Some(id)) => {
+                let handler = Arc::clone(&handler);
+                let params = message["params"].clone();
+                tokio::spawn(async move {
+                    let result = Arc::clone(&handler).request(method, params).await;
+                    handler.connection().respond(id, result);
+                });
+            }
+            (Some(method), None) => handler.notification(&method, &message["params"]),
+            (None, Some(_)) => connection.resolve(&message),
+            (None, None) => connection.respond(
+                Value::Null,
+                Err(RpcError::new(INVALID_REQUEST, "not a JSON-RPC message")),
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_waits_for_response() {
+        let (connection, mut queue) = Connection::channel();
+        let connection = Arc::new(connection);
+        let asking = {
+            let connection = Arc::clone(&connection);
+            tokio::spawn(async move { connection.request("ping", json!({})).await })
+        };
+
+        let request = queue.recv().await.unwrap();
+        assert_eq!(request["method"], "ping");
+        connection.resolve(&json!({ "jsonrpc": "2.0", "id": request["id"], "result": "pong" }));
+        assert_eq!(asking.await.unwrap(), Some(json!("pong")));
+
+        // 对端返回错误时没有结果
+        let asking = {
+            let connection = Arc::clone(&connection);
+            tokio::spawn(async move { connection.request("ping", json!({})).await })
+        };
+        let request = queue.recv().await.unwrap();
+        connection.resolve(&json!({ "jsonrpc": "2.0", "id": request["id"], "error": {} }));
+        assert_eq!(asking.await.unwrap(), None);
+    }
+}
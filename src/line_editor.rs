@@ -0,0 +1,213 @@
+use anyhow::{bail, Context, Result};
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::{
+    Cmd, ConditionalEventHandler, EditMode, Editor, Event, EventContext, EventHandler, KeyCode,
+    KeyEvent, Modifiers, RepeatCount,
+};
+use std::fs;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// 一次读取的结果
+#[derive(Debug, PartialEq)]
+pub enum LineInput {
+    Line(String),
+    /// 按下 Ctrl+X Ctrl+E，携带当前已输入的内容
+    Editor(String),
+    /// 按下 Shift+Tab：切换权限模式，已输入的内容会在下次读取时恢复
+    CycleMode,
+    /// Ctrl+C：放弃当前行
+    Interrupted,
+    /// Ctrl+D：结束会话
+    Eof,
+}
+
+/// 解析 edit_mode 设置
+pub fn parse_edit_mode(mode: &str) -> Option<EditMode> {
+    match mode.trim().to_lowercase().as_str() {
+        "emacs" => Some(EditMode::Emacs),
+        "vi" | "vim" => Some(EditMode::Vi),
+        _ => None,
+    }
+}
+
+/// 交互模式的行编辑器，支持 emacs/vi 键位和输入历史
+pub struct LineEditor {
+    editor: Editor<(), DefaultHistory>,
+    chord: Arc<ChordState>,
+    /// 按下 Shift+Tab 时的输入内容
+    mode_draft: Arc<Mutex<Option<String>>>,
+    /// 下次读取时预先填入的内容
+    initial: String,
+}
+
+/// Ctrl+X Ctrl+E 组合键的状态；rustyline 的自定义绑定只支持单个按键
+#[derive(Default)]
+struct ChordState {
+    /// 上一个按键是 Ctrl+X
+    prefix: AtomicBool,
+    /// 组合键触发时的输入内容
+    draft: Mutex<Option<String>>,
+}
+
+struct CtrlXHandler(Arc<ChordState>);
+
+impl ConditionalEventHandler for CtrlXHandler {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, _: &EventContext) -> Option<Cmd> {
+        self.0.prefix.store(true, Ordering::Relaxed);
+        Some(Cmd::Noop)
+    }
+}
+
+struct CtrlEHandler(Arc<ChordState>);
+
+impl ConditionalEventHandler for CtrlEHandler {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        if !self.0.prefix.swap(false, Ordering::Relaxed) {
+            // 单独的 Ctrl+E 保持默认行为（emacs 模式下移动到行尾）
+            return None;
+        }
+        *self.0.draft.lock().unwrap() = Some(ctx.line().to_string());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+struct BackTabHandler(Arc<Mutex<Option<String>>>);
+
+impl ConditionalEventHandler for BackTabHandler {
+    fn handle(&self, _: &Event, _: RepeatCount, _: bool, ctx: &EventContext) -> Option<Cmd> {
+        *self.0.lock().unwrap() = Some(ctx.line().to_string());
+        Some(Cmd::AcceptLine)
+    }
+}
+
+impl LineEditor {
+    pub fn new(mode: EditMode) -> Result<Self> {
+        let config = rustyline::Config::builder().edit_mode(mode).build();
+        let mut editor: Editor<(), DefaultHistory> =
+            Editor::with_config(config).context("Failed to initialize line editor")?;
+
+        let chord = Arc::new(ChordState::default());
+        editor.bind_sequence(
+            KeyEvent::ctrl('X'),
+            EventHandler::Conditional(Box::new(CtrlXHandler(Arc::clone(&chord)))),
+        );
+        editor.bind_sequence(
+            KeyEvent::ctrl('E'),
+            EventHandler::Conditional(Box::new(CtrlEHandler(Arc::clone(&chord)))),
+        );
+
+        let mode_draft = Arc::new(Mutex::new(None));
+        editor.bind_sequence(
+            KeyEvent(KeyCode::BackTab, Modifiers::NONE),
+            EventHandler::Conditional(Box::new(BackTabHandler(Arc::clone(&mode_draft)))),
+        );
+
+        Ok(Self {
+            editor,
+            chord,
+            mode_draft,
+            initial: String::new(),
+        })
+    }
+
+    pub fn read(&mut self, prompt: &str) -> Result<LineInput> {
+        self.chord.prefix.store(false, Ordering::Relaxed);
+        let initial = std::mem::take(&mut self.initial);
+        let line = match self
+            .editor
+            .readline_with_initial(&format!("{} > ", prompt), (&initial, ""))
+        {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => return Ok(LineInput::Interrupted),
+            Err(ReadlineError::Eof) => return Ok(LineInput::Eof),
+            Err(e) => return Err(e).context("Failed to read input"),
+        };
+
+        // 切换模式时提交的半行内容不计入历史，下次读取时恢复
+        if let Some(draft) = self.mode_draft.lock().unwrap().take() {
+            self.initial = draft;
+            return Ok(LineInput::CycleMode);
+        }
+        let _ = self.editor.add_history_entry(line.as_str());
+
+        match self.chord.draft.lock().unwrap().take() {
+            Some(draft) => Ok(LineInput::Editor(draft)),
+            None => Ok(LineInput::Line(line)),
+        }
+    }
+}
+
+/// 在 $VISUAL / $EDITOR 中编辑 `draft`，返回保存后的内容；内容为空时返回 None
+pub fn compose_in_editor(draft: &str) -> Result<Option<String>> {
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| {
+            if cfg!(target_os = "windows") {
+                "notepad".to_string()
+            } else {
+                "vi".to_string()
+            }
+        });
+    compose_with(&editor, draft)
+}
+
+fn compose_with(editor: &str, draft: &str) -> Result<Option<String>> {
+    let path = std::env::temp_dir().join(format!("rust-claude-code-prompt-{}.md", Uuid::new_v4()));
+    fs::write(&path, draft)
+        .with_context(|| format!("Failed to create temp file: {}", path.display()))?;
+
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().unwrap_or_default();
+    let status = Command::new(program).args(parts).arg(&path).status();
+
+    let content = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+
+    let status = status.with_context(|| format!("Failed to start editor '{}'", editor))?;
+    if !status.success() {
+        bail!("Editor '{}' exited with {}", editor, status);
+    }
+
+    let content = content.context("Failed to read the edited prompt")?;
+    let content = content.trim_end();
+    Ok((!content.trim().is_empty()).then(|| content.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_edit_mode() {
+        assert_eq!(parse_edit_mode("vi"), Some(EditMode::Vi));
+        assert_eq!(parse_edit_mode(" Emacs "), Some(EditMode::Emacs));
+        assert_eq!(parse_edit_mode("nano"), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_compose_with_editor() {
+        // 用 sh 脚本模拟编辑器：在文件末尾追加内容
+        let script = std::env::temp_dir().join(format!("fake-editor-{}.sh", Uuid::new_v4()));
+        fs::write(&script, "printf ' world\\n\\n' >> \"$1\"\n").unwrap();
+
+        let result = compose_with(&format!("sh {}", script.display()), "hello").unwrap();
+        assert_eq!(result.as_deref(), Some("hello world"));
+
+        fs::write(&script, ": > \"$1\"\n").unwrap();
+        assert_eq!(
+            compose_with(&format!("sh {}", script.display()), "draft").unwrap(),
+            None
+        );
+
+        assert!(compose_with("false", "x").is_err());
+        let _ = fs::remove_file(&script);
+    }
+}
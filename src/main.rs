@@ -1,79 +1,140 @@
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use console::style;
-use dialoguer::{theme::ColorfulTheme, Input};
-use serde::{Deserialize, Serialize};
+use dialoguer::{theme::Theme, Confirm, Input, MultiSelect, Select};
 use serde_json::json;
-use std::fs;
-use std::path::PathBuf;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::time::timeout;
-use tracing::info;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::ExitCode;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
 use tracing_subscriber::{fmt, EnvFilter};
 
+mod acp;
+// 供嵌入使用的接口，拆分出库之后才有外部调用方
+#[allow(dead_code)]
+mod agent;
+mod attachments;
+mod audit;
+mod bench;
+mod branches;
+mod cargo_tools;
+mod changes;
+mod citations;
+mod clock;
+mod commands;
 mod config;
+mod context;
+mod coverage;
+mod crash;
+mod dedup;
+mod dev_server;
+mod dotenv;
+mod engine;
 mod error;
-
+mod eval;
+mod exit_code;
+mod file_cache;
+mod file_state;
+mod health;
+mod history;
+mod i18n;
+mod import;
+mod injection;
+mod jsonrpc;
+mod line_editor;
+mod managed;
+mod mcp;
+mod middleware;
+mod output_style;
+mod patch;
+mod permissions;
+mod pins;
+// 工具写文件使用其中的 FileProcessor（原子写入、.bak 备份）和按行读取，bench 也用到它；
+// 其余的异步读写和缓存还没有接入
+#[allow(dead_code)]
+mod performance;
+mod policy;
+mod pricing;
+mod profiles;
+mod project;
+mod pty;
+mod rate_limit;
+mod replay;
+mod response_cache;
+mod sanitize;
+mod search;
+// bench 使用其中的 InputValidator；SafeToolExecutor 还没有接入工具执行链
+#[allow(dead_code)]
+mod security;
+#[cfg(feature = "serve")]
+mod server;
+mod share;
+mod shell;
+mod status_line;
+mod stream;
+mod syntax;
+mod tasks;
+mod telemetry;
+mod templates;
+mod test_runner;
+mod tool_output;
+mod tool_stats;
+mod tools;
+mod ui;
+mod urls;
+mod watcher;
+mod worktree;
+
+use attachments::{Attachment, PastedText};
+use branches::BranchStore;
+use commands::{RetryOptions, SlashCommand};
 use config::Config;
-use error::ApiClient;
-
-const MODEL: &str = "claude-3-haiku-20240307";
-
-const MAX_CONVERSATION_HISTORY: usize = 50;
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ConversationHistory {
-    metadata: ConversationMetadata,
-    messages: Vec<serde_json::Value>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-struct ConversationMetadata {
-    created_at: u64,
-    version: String,
-    model: String,
-}
-
-fn create_conversation_history(messages: &[serde_json::Value]) -> ConversationHistory {
-    let now = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-
-    ConversationHistory {
-        metadata: ConversationMetadata {
-            created_at: now,
-            version: "0.1.0".to_string(),
-            model: MODEL.to_string(),
-        },
-        messages: messages.to_vec(),
-    }
-}
-
+use context::ContextUsage;
+use engine::{ConversationOutcome, Engine, Event, EventSink};
+use error::{ApiClient, ClaudeCodeError};
+use exit_code::ExitStatus;
+use health::Status;
+use history::{Comparison, Journal, ModelAnswer, TokenUsage};
+use i18n::{t, tf, Msg};
+use line_editor::{LineEditor, LineInput};
+use middleware::ToolCall;
+use output_style::OutputStyle;
+use performance::{FileProcessingConfig, FileProcessor};
+use permissions::{ApprovalRequest, PermissionMode};
+use response_cache::ResponseCache;
+use status_line::{GitStatus, StatusLine};
+use tasks::TaskStatus;
+use tool_stats::ToolStats;
+use tools::ToolFilter;
+use watcher::SettingsWatcher;
+
+/// 保存对话，不受 auto_save 影响；指定 `path` 时按扩展名写成 JSON 或 Markdown，否则写入 .claude/history
 async fn save_conversation_history(
     messages: &[serde_json::Value],
     config: &Config,
+    path: Option<&Path>,
+    usage: Option<TokenUsage>,
 ) -> Result<PathBuf> {
-    if !config.user_settings.auto_save {
-        return Ok(PathBuf::new());
-    }
-
-    let history = create_conversation_history(messages);
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap_or_default()
-        .as_secs();
-    let filename = format!("conversation_{}.json", timestamp);
-    let claude_dir = std::env::current_dir()?.join(".claude");
-    let history_file = claude_dir.join("history").join(filename);
-
-    fs::create_dir_all(history_file.parent().unwrap())
-        .context("Failed to create history directory")?;
-
-    let content = serde_json::to_string_pretty(&history)
-        .context("Failed to serialize conversation history")?;
-
-    fs::write(&history_file, content).context("Failed to write conversation history")?;
+    let history_file = match path {
+        Some(path) => {
+            history::save_to(path, messages, config.model())?;
+            path.to_path_buf()
+        }
+        None => {
+            let history_dir = std::env::current_dir()?.join(".claude").join("history");
+            history::save(
+                &history_dir,
+                messages,
+                config.model(),
+                config.user_settings.compress_history,
+                Some(changes::collect()),
+                usage,
+            )?
+        }
+    };
 
     info!("Conversation history saved to: {}", history_file.display());
     Ok(history_file)
@@ -88,103 +149,388 @@ struct Args {
     #[arg(short, long)]
     api_key: Option<String>,
 
-    /// Maximum number of turns in conversation
+    /// Maximum number of turns in conversation (model round-trips in --prompt mode)
     #[arg(short, long, default_value = "10")]
     max_turns: usize,
 
     /// Non-interactive mode: process a single prompt and exit
-    #[arg(short, long)]
+    #[arg(short, long, num_args = 0..=1, default_missing_value = "")]
     prompt: Option<String>,
 
+    /// Render a stored prompt template (.claude/templates/NAME.md or ~/.claude/templates/NAME.md) and send it; implies --prompt
+    #[arg(long, value_name = "NAME")]
+    template: Option<String>,
+
+    /// Template variable; repeatable
+    #[arg(long = "var", value_name = "KEY=VALUE", value_parser = templates::parse_var, requires = "template")]
+    vars: Vec<(String, String)>,
+
     /// API base URL (overrides config)
     #[arg(short = 'u', long)]
     api_url: Option<String>,
 
-    /// Timeout in seconds (overrides config)
+    /// Per-request timeout in seconds, applied to each retry attempt (overrides config)
     #[arg(short = 't', long)]
     timeout: Option<u64>,
 
-    /// Show configuration file path
+    /// anthropic-version header sent with each request (overrides config)
+    #[arg(long, value_name = "VERSION")]
+    anthropic_version: Option<String>,
+
+    /// Enable a beta feature via the anthropic-beta header; repeatable or comma-separated
+    #[arg(long = "beta", value_name = "FEATURE", value_delimiter = ',')]
+    betas: Vec<String>,
+
+    /// Attach a PDF to the first message; repeatable
+    #[arg(long = "attach", value_name = "FILE")]
+    attachments: Vec<PathBuf>,
+
+    /// Interface language: en or zh (overrides config; defaults to LANG)
+    #[arg(long, value_name = "LANG")]
+    lang: Option<String>,
+
+    /// Screen-reader friendly output: no color or emoji, plain labeled lines and status announcements
+    #[arg(long)]
+    accessible: bool,
+
+    /// Permission mode: default, plan (read-only tools until you approve the plan),
+    /// or acceptEdits (edit workspace files without asking, ask before running commands)
+    #[arg(long, value_enum, value_name = "MODE", default_value_t = PermissionMode::Default)]
+    permission_mode: PermissionMode,
+
+    /// Keep a development server (e.g. "npm run dev") running alongside the session
+    #[arg(long, value_name = "COMMAND")]
+    serve: Option<String>,
+
+    /// Extra directory Claude may edit without asking in acceptEdits mode (repeatable)
+    #[arg(long = "add-dir", value_name = "PATH")]
+    add_dirs: Vec<PathBuf>,
+
+    /// Continue a conversation saved in .claude/history (.jsonl, .json or .json.zst)
+    #[arg(long, value_name = "FILE")]
+    resume: Option<PathBuf>,
+
+    /// Save the conversation when the session ends even if auto_save is off; with a path, write it there (.md for Markdown, otherwise JSON)
+    #[arg(long, value_name = "PATH")]
+    save: Option<Option<PathBuf>>,
+
+    /// Leave the working tree untouched: run the session in a temporary git worktree and
+    /// write its changes to FILE as a patch for `git apply`
+    #[arg(long, value_name = "FILE")]
+    patch_out: Option<PathBuf>,
+
+    /// Work in a temporary copy of the project, then show the changes and offer to apply them here
+    #[arg(long)]
+    scratch: bool,
+
+    /// Same as --permission-mode acceptEdits
+    #[arg(short = 'y', long, conflicts_with = "permission_mode")]
+    yes: bool,
+
+    /// Seed for randomized behavior such as retry jitter, to make runs reproducible
+    #[arg(long, value_name = "N")]
+    seed: Option<u64>,
+
+    /// Sampling temperature for requests, 0.0 to 1.0 (API default when unset)
+    #[arg(long, value_name = "T", value_parser = parse_temperature)]
+    temperature: Option<f64>,
+
+    /// Send --prompt requests even if response_cache has a saved answer
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Count the tokens of the --prompt request and estimate its cost, then exit without sending it
+    #[arg(long)]
+    estimate: bool,
+
+    /// Tag the session for usage reports, e.g. team=payments; repeatable
+    #[arg(long = "tag", value_name = "KEY=VALUE", value_parser = templates::parse_var)]
+    tags: Vec<(String, String)>,
+
+    /// Use the settings profile saved in ~/.claude/profiles/<NAME>.json on top of settings.json
+    #[arg(long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Show the effective configuration and where each value came from
     #[arg(long)]
     show_config: bool,
-}
 
-// Claude API 响应结构
-#[derive(serde::Deserialize)]
-struct ClaudeResponse {
-    content: Vec<ContentBlock>,
+    /// Print --show-config output as JSON
+    #[arg(long, requires = "show_config")]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Option<CliCommand>,
+
+    /// 交互会话开始时自动发送的第一条消息
+    #[arg(skip)]
+    first_message: Option<String>,
 }
 
-#[derive(serde::Deserialize)]
-struct ContentBlock {
-    #[serde(rename = "type")]
-    content_type: String,
-    text: Option<String>,
-    name: Option<String>,
-    id: Option<String>,
-    input: Option<serde_json::Value>,
+#[derive(clap::Subcommand, Debug)]
+enum CliCommand {
+    /// Speak the Agent Client Protocol (JSON-RPC over stdio) so editor plugins can embed the agent
+    Acp,
+    /// Benchmark file I/O, token estimation and validation paths of this build
+    Bench(bench::BenchArgs),
+    /// Run a directory of agent tasks in temporary workspaces and compare with a baseline
+    Eval(eval::EvalArgs),
+    /// Export or import settings profiles for --profile
+    Config(profiles::ConfigArgs),
+    /// Inspect stored conversations
+    History(replay::HistoryArgs),
+    /// Watch a live session from another terminal, read-only, as its turns are saved
+    Follow(replay::FollowArgs),
+    /// Start a session on a new branch in its own git worktree, so several can run at once
+    Worktree(worktree::WorktreeArgs),
+    /// List or clean up the worktrees created by `worktree`
+    Worktrees(worktree::WorktreesArgs),
+    /// Run as a Model Context Protocol server that provides the built-in tools
+    Mcp(mcp::McpArgs),
+    /// Expose the agent over a localhost HTTP/WebSocket API for editors and web UIs
+    #[cfg(feature = "serve")]
+    Serve(server::ServeArgs),
 }
 
-// 限制对话历史长度以防止内存泄漏
-fn trim_conversation_history(messages: &mut Vec<serde_json::Value>) {
-    if messages.len() > MAX_CONVERSATION_HISTORY {
-        // 保留前几条重要的系统消息，删除中间的消息
-        let system_messages_count = messages
+// 写入源文件后检查语法，把错误附加到工具结果中交给模型修复
+fn append_syntax_errors(result: String, file_path: &str) -> String {
+    let Ok(content) = std::fs::read_to_string(file_path) else {
+        return result;
+    };
+    let errors = syntax::check_syntax(Path::new(file_path), &content);
+    if errors.is_empty() {
+        return result;
+    }
+
+    warn!("Syntax errors after writing {}: {:?}", file_path, errors);
+    format!(
+        "{}\n\nWarning: the file was saved but has syntax errors:\n{}\nPlease fix them.",
+        result,
+        errors
             .iter()
-            .take_while(|msg| msg["role"] == "system")
-            .count();
+            .map(|e| format!("  {}", e))
+            .collect::<Vec<_>>()
+            .join("\n")
+    )
+}
 
-        if system_messages_count < messages.len() {
-            // 删除中间的消息，保留系统消息和最近的消息
-            let keep_start = system_messages_count;
-            let remove_end = messages
-                .len()
-                .saturating_sub(MAX_CONVERSATION_HISTORY - system_messages_count);
+// 工具写文件使用的处理器（原子写入，按配置保留备份）
+fn file_processor(config: &Config) -> FileProcessor {
+    FileProcessor::with_config(FileProcessingConfig {
+        keep_backup: config.user_settings.backup_on_write,
+        ..Default::default()
+    })
+}
 
-            if remove_end > keep_start {
-                messages.drain(keep_start..remove_end);
-            }
-        }
+// 覆盖已有文件前要求模型先读取它
+//
+// 返回 Err 时拒绝写入并把提示交给模型；未开启强制检查时返回警告信息
+fn check_read_before_write(
+    path: &Path,
+    config: &Config,
+) -> std::result::Result<Option<String>, String> {
+    let Some(message) = file_state::unread_before_write(path) else {
+        return Ok(None);
+    };
+
+    if config.user_settings.require_read_before_write {
+        warn!("Refused blind write to {}", path.display());
+        Err(format!("Write refused, file unchanged: {}", message))
+    } else {
+        Ok(Some(message))
     }
 }
 
-// 执行工具调用
-async fn execute_tool(name: &str, input: &serde_json::Value) -> Result<String> {
+/// 执行一次工具调用，先经过 middleware 中的校验、权限、审计和脱敏
+async fn execute_tool(
+    name: &str,
+    input: &serde_json::Value,
+    config: &Config,
+    events: &dyn EventSink,
+    cancel: &CancellationToken,
+    read_only: bool,
+) -> std::result::Result<String, ClaudeCodeError> {
+    let chain = middleware::chain();
+    let call = ToolCall {
+        name,
+        input,
+        config,
+        events,
+        cancel,
+        read_only,
+    };
+    middleware::run(&chain, call, |call| {
+        Box::pin(run_tool(call.name, call.input, call.config, call.cancel))
+    })
+    .await
+}
+
+// 执行链的末端：真正执行工具，失败时是 ClaudeCodeError::ToolFailed
+async fn run_tool(
+    name: &str,
+    input: &serde_json::Value,
+    config: &Config,
+    cancel: &CancellationToken,
+) -> std::result::Result<String, ClaudeCodeError> {
+    dispatch_tool(name, input, config, cancel)
+        .await
+        .map_err(|e| ClaudeCodeError::tool_failed(name, e))
+}
+
+async fn dispatch_tool(
+    name: &str,
+    input: &serde_json::Value,
+    config: &Config,
+    cancel: &CancellationToken,
+) -> Result<String> {
+    if let Some(command) = permissions::command_line(name, input) {
+        changes::record_command(&command);
+    }
+    if let Some(path) = permissions::written_path(name, input) {
+        changes::before_write(&path);
+    }
     match name {
         "read_file" => {
             let file_path = input["file_path"].as_str().context("Missing file_path")?;
-            let content = std::fs::read_to_string(file_path)
-                .with_context(|| format!("Failed to read file: {}", file_path))?;
+            let offset = input["offset"].as_u64().map(|n| n as usize);
+            let limit = input["limit"].as_u64().map(|n| n as usize);
+            // 按行读取时大文件只解码请求的部分
+            let content = if offset.is_none() && limit.is_none() {
+                file_cache::read(Path::new(file_path))
+                    .with_context(|| format!("Failed to read file: {}", file_path))?
+            } else {
+                file_processor(config).read_lines(Path::new(file_path), offset, limit)?
+            };
+            file_state::record(Path::new(file_path));
             Ok(content)
         }
         "write_file" => {
             let file_path = input["file_path"].as_str().context("Missing file_path")?;
             let content = input["content"].as_str().context("Missing content")?;
 
-            if let Some(parent) = std::path::Path::new(file_path).parent() {
+            // 持有锁期间检查文件是否在上次读取后被外部修改
+            let _lock = file_state::lock_for_write(Path::new(file_path)).await?;
+            let unread_warning = match check_read_before_write(Path::new(file_path), config) {
+                Ok(warning) => warning,
+                Err(message) => return Ok(message),
+            };
+            if let Some(message) = file_state::changed_since_read(Path::new(file_path)) {
+                return Ok(message);
+            }
+
+            file_processor(config)
+                .write_file_sync(Path::new(file_path), content)
+                .with_context(|| format!("Failed to write file: {}", file_path))?;
+            file_state::record(Path::new(file_path));
+            file_cache::invalidate(Path::new(file_path));
+
+            let mut result = format!("Successfully wrote to file: {}", file_path);
+            if let Some(warning) = unread_warning {
+                result.push_str(&format!("\n\nWarning: {}", warning));
+            }
+            Ok(append_syntax_errors(result, file_path))
+        }
+        "append_file" => {
+            let file_path = input["file_path"].as_str().context("Missing file_path")?;
+            let content = input["content"].as_str().context("Missing content")?;
+
+            if let Some(parent) = Path::new(file_path).parent() {
                 std::fs::create_dir_all(parent)
                     .with_context(|| format!("Failed to create directory: {:?}", parent))?;
             }
 
-            std::fs::write(file_path, content)
-                .with_context(|| format!("Failed to write file: {}", file_path))?;
+            // 持有锁期间检查文件是否在上次读取后被外部修改
+            let _lock = file_state::lock_for_write(Path::new(file_path)).await?;
+            if let Some(message) = file_state::changed_since_read(Path::new(file_path)) {
+                return Ok(message);
+            }
 
-            Ok(format!("Successfully wrote to file: {}", file_path))
+            use std::io::Write;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(file_path)
+                .with_context(|| format!("Failed to open file: {}", file_path))?;
+            file.write_all(content.as_bytes())
+                .with_context(|| format!("Failed to append to file: {}", file_path))?;
+            file_state::record(Path::new(file_path));
+            file_cache::invalidate(Path::new(file_path));
+
+            Ok(append_syntax_errors(
+                format!(
+                    "Successfully appended {} bytes to file: {}",
+                    content.len(),
+                    file_path
+                ),
+                file_path,
+            ))
+        }
+        "apply_patch" => {
+            let file_path = input["file_path"].as_str().context("Missing file_path")?;
+            let diff = input["patch"].as_str().context("Missing patch")?;
+
+            // 持有锁期间检查文件是否在上次读取后被外部修改
+            let _lock = file_state::lock_for_write(Path::new(file_path)).await?;
+            let unread_warning = match check_read_before_write(Path::new(file_path), config) {
+                Ok(warning) => warning,
+                Err(message) => return Ok(message),
+            };
+            if let Some(message) = file_state::changed_since_read(Path::new(file_path)) {
+                return Ok(message);
+            }
+
+            let original = file_cache::read(Path::new(file_path))
+                .with_context(|| format!("Failed to read file: {}", file_path))?;
+            let outcome = match patch::apply_patch(&original, diff) {
+                Ok(outcome) => outcome,
+                // 上下文不匹配时把原因返回给模型，而不是中断对话
+                Err(e) => return Ok(format!("Patch rejected, file unchanged: {:#}", e)),
+            };
+
+            file_processor(config)
+                .write_file_sync(Path::new(file_path), &outcome.content)
+                .with_context(|| format!("Failed to write file: {}", file_path))?;
+            file_state::record(Path::new(file_path));
+            file_cache::invalidate(Path::new(file_path));
+
+            let mut result = format!(
+                "Successfully applied {} hunk(s) to file: {}",
+                outcome.hunks, file_path
+            );
+            for note in &outcome.notes {
+                result.push_str(&format!("\n  note: {}", note));
+            }
+            if let Some(warning) = unread_warning {
+                result.push_str(&format!("\n\nWarning: {}", warning));
+            }
+            Ok(append_syntax_errors(result, file_path))
         }
         "execute_command" => {
             let command = input["command"].as_str().context("Missing command")?;
+            let dir = permissions::command_cwd(input)?;
 
-            println!("\n{}", style("Executing:").cyan());
-            println!("  {}", style(command).yellow());
+            ui::print_command(command);
+            if std::env::current_dir()?.canonicalize().ok().as_ref() != Some(&dir) {
+                println!("{}", style(tf(Msg::CommandDir, &[&dir.display()])).dim());
+            }
 
-            let output = if cfg!(target_os = "windows") {
-                std::process::Command::new("cmd")
-                    .args(["/C", command])
-                    .output()?
+            if input["run_in_background"].as_bool().unwrap_or(false) {
+                let id = tasks::spawn(command, &dir)?;
+                return Ok(format!(
+                    "Started background task {}. Use check_task_output with task_id {} to read its output, or kill_task to stop it.",
+                    id, id
+                ));
+            }
+
+            if std::io::IsTerminal::is_terminal(&std::io::stdin()) && tasks::may_prompt(command) {
+                println!("{}", style(t(Msg::CommandMayPrompt)).dim());
+            }
+            let mut cmd = tasks::shell(command);
+            cmd.current_dir(&dir);
+            let output = if config.user_settings.pty {
+                tasks::output_in_terminal(cmd, cancel).await?
             } else {
-                std::process::Command::new("sh")
-                    .args(["-c", command])
-                    .output()?
+                tasks::output(cmd, cancel).await?
             };
 
             let stdout = String::from_utf8_lossy(&output.stdout);
@@ -204,287 +550,1352 @@ async fn execute_tool(name: &str, input: &serde_json::Value) -> Result<String> {
             if result.is_empty() {
                 result = "(command produced no output)".to_string();
             }
+            if !output.status.success() {
+                if let Some(prompt) = tasks::credential_prompt(&result) {
+                    result.push_str(&format!(
+                        "\n\nThe command failed at a prompt for credentials (`{}`). It cannot read a password here, \
+                         so do not retry it as is. Use a non-interactive form instead (for example `sudo -n`, \
+                         `ssh -o BatchMode=yes`, or a token in an environment variable), or ask the user to run it themselves.",
+                        prompt
+                    ));
+                }
+            }
+            urls::record(&result);
 
             Ok(result)
         }
+        "check_task_output" => {
+            let id = input["task_id"].as_u64().context("Missing task_id")? as usize;
+            let (status, output) = tasks::check_output(id)?;
+            let status = match status {
+                TaskStatus::Running => "still running".to_string(),
+                TaskStatus::Exited(Some(code)) => format!("exited with code {}", code),
+                TaskStatus::Exited(None) => "terminated by a signal".to_string(),
+                TaskStatus::Killed => "killed".to_string(),
+            };
+            if output.is_empty() {
+                return Ok(format!("Task {} is {} (no new output).", id, status));
+            }
+            Ok(format!(
+                "Task {} is {}. New output:\n{}",
+                id, status, output
+            ))
+        }
+        "dev_server_output" => {
+            let lines = input["lines"]
+                .as_u64()
+                .map_or(dev_server::DEFAULT_TAIL_LINES, |n| n as usize);
+            let Some(output) = dev_server::recent_output(lines) else {
+                return Ok("No development server is running.".to_string());
+            };
+            let (status, output) = output?;
+            let status = match status {
+                TaskStatus::Running => "running".to_string(),
+                TaskStatus::Exited(Some(code)) => format!("exited with code {}", code),
+                TaskStatus::Exited(None) | TaskStatus::Killed => "stopped".to_string(),
+            };
+            Ok(format!(
+                "Development server is {}. Recent output:\n{}",
+                status, output
+            ))
+        }
+        "run_tests" => {
+            let framework = test_runner::resolve(input["framework"].as_str())?;
+            Ok(
+                test_runner::run(framework, input["filter"].as_str(), cancel)
+                    .await?
+                    .render(),
+            )
+        }
+        "cargo_check" => cargo_tools::check(input["package"].as_str(), cancel).await,
+        "cargo_add" => {
+            let args = cargo_tools::AddArgs::from_input(input)?;
+            cargo_tools::add(&args, cancel).await
+        }
+        "cargo_metadata" => cargo_tools::metadata(cancel).await,
+        "audit_dependencies" => {
+            let ecosystems = audit::resolve(input["ecosystem"].as_str())?;
+            audit::run_all(&ecosystems, cancel).await
+        }
+        "coverage" => Ok(coverage::render(
+            &coverage::run(cancel).await?,
+            input["path"].as_str(),
+        )),
+        "kill_task" => {
+            let id = input["task_id"].as_u64().context("Missing task_id")? as usize;
+            match tasks::kill(id)? {
+                TaskStatus::Killed => Ok(format!("Killed background task {}.", id)),
+                _ => Ok(format!("Task {} had already finished.", id)),
+            }
+        }
         "list_files" => {
             let pattern = input["pattern"].as_str().context("Missing pattern")?;
-            let base_path = input["path"].as_str().unwrap_or(".");
+            let limit = search::DEFAULT_MAX_FILES;
+            let (files, truncated) =
+                search::across(&search_bases(input)?, limit, |base, limit| {
+                    search::list_files(base, pattern, limit)
+                })?;
+            Ok(search::render_files(&files, truncated, limit))
+        }
+        "search_files" => {
+            let pattern = input["pattern"].as_str().context("Missing pattern")?;
+            let limit = input["max_results"]
+                .as_u64()
+                .map_or(search::DEFAULT_MAX_MATCHES, |n| n.clamp(1, 1000) as usize);
+            let (matches, truncated) =
+                search::across(&search_bases(input)?, limit, |base, limit| {
+                    search::search_files(
+                        base,
+                        pattern,
+                        input["glob"].as_str(),
+                        input["case_insensitive"].as_bool().unwrap_or(false),
+                        limit,
+                    )
+                })?;
+            Ok(search::render_matches(&matches, truncated, limit))
+        }
+        _ => Ok(format!("Unknown tool: {}", name)),
+    }
+}
 
-            use glob::glob;
+/// list_files 和 search_files 的起始目录：指定了 path 时只用它，否则覆盖所有工作区根目录
+fn search_bases(input: &serde_json::Value) -> Result<Vec<String>> {
+    match input["path"].as_str() {
+        Some(path) => Ok(vec![path.to_string()]),
+        None => Ok(search::default_bases(&permissions::workspace_roots()?)),
+    }
+}
 
-            let full_pattern = if pattern.starts_with('/') {
-                pattern.to_string()
-            } else {
-                format!("{}/{}", base_path, pattern)
-            };
+// 检查配置文件是否被修改，并将变化应用到当前会话
+fn apply_settings_changes(config: &mut Config, api_client: &mut ApiClient) {
+    let changed = match config.reload_user_settings() {
+        Ok(changed) => changed,
+        Err(e) => {
+            println!("\n{} {:#}", style(t(Msg::SettingsReloadFailed)).yellow(), e);
+            return;
+        }
+    };
 
-            let mut files = Vec::new();
+    for warning in &config.warnings {
+        println!("{} {}", style(t(Msg::WarningLabel)).yellow(), warning);
+    }
 
-            for entry in glob(&full_pattern)
-                .with_context(|| format!("Failed to read glob pattern: {}", full_pattern))?
-            {
-                match entry {
-                    Ok(path) => {
-                        if let Some(path_str) = path.to_str() {
-                            files.push(path_str.to_string());
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Error reading entry: {:?}", e);
+    if changed.is_empty() {
+        return;
+    }
+
+    info!("Settings reloaded, changed keys: {:?}", changed);
+    api_client.set_model(config.model().to_string());
+    api_client.set_routing(config.user_settings.model_routing.clone());
+    api_client.set_fallback_model(config.user_settings.fallback_model.clone());
+    set_workspaces(config);
+    apply_request_settings(api_client, config);
+    ui::set_pager_enabled(config.user_settings.pager);
+    telemetry::init(&config.user_settings);
+
+    println!(
+        "\n{} {}",
+        style(t(Msg::SettingsReloaded)).cyan(),
+        changed.join(", ")
+    );
+    let restart_required: Vec<&str> = changed
+        .iter()
+        .copied()
+        .filter(|key| config::RESTART_REQUIRED_KEYS.contains(key))
+        .collect();
+    if !restart_required.is_empty() {
+        println!(
+            "  {}",
+            style(tf(Msg::RestartRequired, &[&restart_required.join(", ")])).dim()
+        );
+    }
+}
+
+// 在外部编辑器中编写消息，失败或内容为空时返回 None
+fn compose_prompt(draft: &str) -> Option<String> {
+    match line_editor::compose_in_editor(draft) {
+        Ok(Some(text)) => {
+            println!("{}", text);
+            Some(text)
+        }
+        Ok(None) => {
+            println!("{}", style(t(Msg::EditorEmpty)).dim());
+            None
+        }
+        Err(e) => {
+            println!("{}", style(format!("{:#}", e)).red());
+            None
+        }
+    }
+}
+
+/// 在终端中输出对话引擎的事件
+struct Terminal;
+
+impl EventSink for Terminal {
+    fn emit(&self, event: Event) {
+        match event {
+            Event::Waiting => ui::announce(t(Msg::StatusWaiting)),
+            Event::Responded => ui::announce(t(Msg::StatusResponse)),
+            Event::Text { text, footnotes } => {
+                let mut output = sanitize::sanitize(&text).into_owned();
+                if !footnotes.is_empty() {
+                    output.push('\n');
+                    for (index, footnote) in footnotes.iter().enumerate() {
+                        output.push_str(&format!(
+                            "\n{}",
+                            style(format!("[{}] {}", index + 1, sanitize::sanitize(footnote)))
+                                .dim()
+                        ));
                     }
                 }
+                ui::print_claude_heading();
+                ui::print_paged(&output);
             }
-
-            if files.is_empty() {
-                Ok("(no files found)".to_string())
-            } else {
-                Ok(files.join("\n"))
+            Event::ToolUse { name, .. } => ui::print_tool_heading(&name),
+            Event::ToolStarted { name, .. } => ui::announce(tf(Msg::StatusToolRunning, &[&name])),
+            Event::ToolFinished { name, ok, .. } => {
+                let status = if ok {
+                    Msg::StatusToolDone
+                } else {
+                    Msg::StatusToolFailed
+                };
+                ui::announce(tf(status, &[&name]));
             }
+            Event::ContextSummarized {
+                summarized_messages,
+                tokens_before,
+                tokens_after,
+            } => println!(
+                "\n{}",
+                style(tf(
+                    Msg::ContextSummarized,
+                    &[&tokens_before, &summarized_messages, &tokens_after]
+                ))
+                .yellow()
+            ),
+            Event::ToolResultsElided {
+                count,
+                tokens_before,
+                tokens_after,
+            } => println!(
+                "\n{}",
+                style(tf(
+                    Msg::ToolResultsElided,
+                    &[&tokens_before, &count, &tokens_after]
+                ))
+                .yellow()
+            ),
+            Event::ResponseTruncated => println!("\n{}", style(t(Msg::ResponseTruncated)).yellow()),
+            Event::ContentFlagged { tool, findings } => println!(
+                "\n{}",
+                style(tf(Msg::ContentFlagged, &[&tool, &findings])).yellow()
+            ),
+            Event::PermissionRequest { .. } | Event::TurnComplete { .. } | Event::Error { .. } => {}
         }
-        _ => Ok(format!("Unknown tool: {}", name)),
+    }
+
+    // acceptEdits 模式下执行命令或访问工作区外的路径前询问用户；无法交互时视为拒绝
+    fn approve<'a>(
+        &'a self,
+        tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        let prompt = match request {
+            ApprovalRequest::Command {
+                command,
+                reason: None,
+            } => tf(Msg::ApproveCommand, &[command]),
+            ApprovalRequest::Command {
+                command,
+                reason: Some(reason),
+            } => tf(Msg::ApproveCommandReason, &[command, reason]),
+            ApprovalRequest::OutsideWorkspace(path) => {
+                tf(Msg::ApproveOutsideWorkspace, &[&tool, &path.display()])
+            }
+            ApprovalRequest::ProtectedFile { path, reason } => {
+                let prompt = tf(Msg::ApproveProtectedFile, &[&tool, &path.display()]);
+                match reason {
+                    Some(reason) => format!("{} ({})", prompt, reason),
+                    None => prompt,
+                }
+            }
+            ApprovalRequest::SuspiciousContent(findings) => {
+                format!(
+                    "{} ({})",
+                    tf(Msg::ApproveSuspiciousContent, &[&tool]),
+                    findings
+                )
+            }
+        };
+        let approved = Confirm::with_theme(ui::dialog_theme().as_ref())
+            .with_prompt(prompt)
+            .default(false)
+            .interact()
+            .unwrap_or(false);
+        Box::pin(std::future::ready(approved))
     }
 }
 
-async fn call_claude(
-    api_client: &ApiClient,
-    messages: &serde_json::Value,
-    tools: bool,
-) -> Result<ClaudeResponse> {
-    let response_json = api_client.call_claude_with_retry(messages, tools).await?;
+// 切换权限模式并提示；进入计划模式时记下之前的模式，供退出计划模式时恢复
+fn switch_mode(
+    mode: PermissionMode,
+    mode_before_plan: &mut PermissionMode,
+    api_client: &mut ApiClient,
+    config: &Config,
+) {
+    if !config.managed.allows_mode(mode) {
+        let name = mode.label().unwrap_or(t(Msg::ModeNormal));
+        println!("{}", style(tf(Msg::ModeNotAllowed, &[&name])).yellow());
+        return;
+    }
+    let previous = permissions::mode();
+    if mode == PermissionMode::Plan && previous != PermissionMode::Plan {
+        *mode_before_plan = previous;
+    }
+    permissions::set_mode(mode);
+    apply_request_settings(api_client, config);
+
+    let notice = match mode {
+        PermissionMode::Plan => Msg::PlanModeOn,
+        PermissionMode::AcceptEdits => Msg::AcceptEditsModeOn,
+        PermissionMode::Default if previous == PermissionMode::Plan => Msg::PlanModeOff,
+        PermissionMode::Default => Msg::DefaultModeOn,
+    };
+    println!("{}", style(t(notice)).dim());
+}
 
-    let claude_response: ClaudeResponse = serde_json::from_value(response_json)?;
-    Ok(claude_response)
+/// 按配置设置工作区的根目录、固定的文件和命令的 .env 变量，无法使用的只给出警告
+fn set_workspaces(config: &Config) {
+    let current_dir = std::env::current_dir().unwrap_or_default();
+    let errors = permissions::set_workspaces(&config.user_settings.workspaces)
+        .into_iter()
+        .chain(pins::set_configured(&config.user_settings.pinned_files))
+        .chain(dotenv::load(
+            &current_dir,
+            config.user_settings.load_env_files,
+        ));
+    for error in errors {
+        println!("{} {:#}", style(t(Msg::WarningLabel)).yellow(), error);
+    }
 }
 
-// 工具使用任务结构
-struct ToolUseTask {
-    tool_use_id: String,
-    tool_name: String,
-    tool_input: serde_json::Value,
+// 按权限模式和回答风格设置发送的工具和系统提示词
+fn apply_request_settings(api_client: &mut ApiClient, config: &Config) {
+    let mode = permissions::mode();
+    api_client.set_tool_filter(mode.tool_filter(config.tool_filter()));
+    api_client.set_system_prompt(output_style::build_system_prompt(&[
+        config.user_settings.output_style.system_prompt(),
+        mode.system_prompt(),
+        project::current()
+            .map(|project| project.system_prompt())
+            .as_deref(),
+        dev_server::system_prompt().as_deref(),
+        permissions::workspace_roots()
+            .ok()
+            .and_then(|roots| permissions::workspace_prompt(&roots))
+            .as_deref(),
+        permissions::working_dir_prompt().as_deref(),
+        pins::system_prompt().as_deref(),
+    ]));
 }
 
-async fn process_tool_use(
-    api_client: &ApiClient,
-    messages: &mut Vec<serde_json::Value>,
-    initial_task: ToolUseTask,
-) -> Result<()> {
-    let mut task_stack = vec![initial_task];
+/// 在对话副本中用 `model` 回答从 `start` 开始的最后一轮，依次显示两个模型的回答
+///
+/// 副本只能使用只读工具，避免重复修改文件或执行命令；当前对话不变
+async fn compare_models(
+    api_client: &mut ApiClient,
+    config: &Config,
+    tool_stats: &ToolStats,
+    messages: &[serde_json::Value],
+    start: usize,
+    model: String,
+) -> Result<Comparison> {
+    let content = messages[start]["content"].clone();
+    let prompt = context::prompt_text(&content);
+    let first_line = prompt.lines().next().unwrap_or_default();
+    println!(
+        "{}",
+        style(tf(Msg::Comparing, &[&model, &first_line])).dim()
+    );
 
-    while let Some(task) = task_stack.pop() {
-        let tool_result = execute_tool(&task.tool_name, &task.tool_input).await?;
+    let original = ModelAnswer {
+        model: api_client.model().to_string(),
+        text: context::answer_text(&messages[start..]),
+    };
+    ui::print_section_heading(&original.model);
+    ui::print_paged(&sanitize::sanitize(&original.text));
+
+    ui::print_section_heading(&model);
+    let previous = api_client.model().to_string();
+    api_client.set_model(model.clone());
+    api_client.set_tool_filter(ToolFilter {
+        read_only: true,
+        ..config.tool_filter()
+    });
+    api_client.reset_fallback();
+    let mut fork = messages[..start].to_vec();
+    let result = Engine {
+        api_client,
+        config,
+        tool_stats,
+        events: &Terminal,
+        cancel: &CancellationToken::new(),
+        read_only: true,
+        fail_on_denial: false,
+    }
+    .run_turn(&mut fork, content, None)
+    .await;
+    api_client.set_model(previous);
+    apply_request_settings(api_client, config);
+    result?;
+
+    Ok(Comparison {
+        prompt,
+        answers: vec![
+            original,
+            ModelAnswer {
+                text: context::answer_text(&fork[start..]),
+                model,
+            },
+        ],
+    })
+}
 
-        messages.push(json!({
-            "role": "user",
-            "content": [
-                {
-                    "type": "tool_result",
-                    "tool_use_id": task.tool_use_id,
-                    "content": tool_result
+/// 以不同的温度同时请求 `n` 个回答供用户挑选，选中的回答替换从 `start` 开始的最后一轮
+///
+/// 选中回答中的工具调用照常执行；返回是否替换了最后一轮
+async fn choose_variant(
+    api_client: &ApiClient,
+    config: &Config,
+    tool_stats: &ToolStats,
+    messages: &mut Vec<serde_json::Value>,
+    start: usize,
+    n: usize,
+) -> Result<bool> {
+    let content = messages[start]["content"].clone();
+    let temperatures = engine::variant_temperatures(n);
+    api_client.reset_fallback();
+    let cancel = CancellationToken::new();
+    let engine = Engine {
+        api_client,
+        config,
+        tool_stats,
+        events: &Terminal,
+        cancel: &cancel,
+        read_only: false,
+        fail_on_denial: false,
+    };
+    let results = engine
+        .variants(&messages[..start], &content, &temperatures)
+        .await;
+
+    let mut variants = Vec::new();
+    for (index, (temperature, result)) in temperatures.iter().zip(results).enumerate() {
+        let label = tf(
+            Msg::VariantLabel,
+            &[&(index + 1), &format!("{:.2}", temperature)],
+        );
+        ui::print_section_heading(&label);
+        match result {
+            Ok(response) => {
+                let mut text = sanitize::sanitize(&response.text()).into_owned();
+                for name in response.tool_names() {
+                    let note = tf(Msg::VariantCallsTool, &[&name]);
+                    text.push_str(&format!("\n{}", style(note).dim()));
                 }
-            ]
-        }));
-
-        trim_conversation_history(messages);
-
-        let response = call_claude(api_client, &json!(messages), true).await?;
+                ui::print_paged(&text);
+                variants.push((label, response));
+            }
+            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+        }
+    }
+    anyhow::ensure!(!variants.is_empty(), t(Msg::NoVariants));
+
+    let mut items: Vec<&str> = variants.iter().map(|(label, _)| label.as_str()).collect();
+    items.push(t(Msg::KeepCurrentAnswer));
+    let keep = items.len() - 1;
+    let selection = Select::with_theme(ui::dialog_theme().as_ref())
+        .with_prompt(t(Msg::ChooseVariant))
+        .items(&items)
+        .default(keep)
+        .interact_opt()?;
+    let Some(index) = selection.filter(|index| *index < keep) else {
+        return Ok(false);
+    };
 
-        // 收集新的工具使用任务
-        let mut new_tool_tasks = Vec::new();
+    let (_, response) = variants.swap_remove(index);
+    messages.truncate(start);
+    engine
+        .accept_response(messages, content, response, None)
+        .await?;
+    Ok(true)
+}
 
-        // 处理响应中的所有内容块
-        for block in &response.content {
-            match block.content_type.as_str() {
-                "text" => {
-                    if let Some(text) = &block.text {
-                        println!("\n{}", style("Claude:").green());
-                        println!("{}", text);
-                    }
-                }
-                "tool_use" => {
-                    let name = block.name.as_ref().context("Missing tool name")?.clone();
-                    let id = block.id.as_ref().context("Missing tool id")?.clone();
-                    let input = block.input.as_ref().context("Missing tool input")?.clone();
-
-                    println!("\n{} {}", style("Tool:").cyan(), style(&name).yellow());
-
-                    // 将新任务添加到临时列表
-                    new_tool_tasks.push(ToolUseTask {
-                        tool_use_id: id,
-                        tool_name: name,
-                        tool_input: input,
-                    });
-                }
-                _ => {}
+/// 运行 /build、/lint 对应的项目命令并显示输出；`action` 用于没有命令时的提示
+async fn run_project_command(command: Option<String>, action: &str) {
+    let Some(command) = command else {
+        println!("{}", style(tf(Msg::NoProjectCommand, &[&action])).dim());
+        return;
+    };
+    println!("{}", style(tf(Msg::RunningTests, &[&command])).dim());
+    match tasks::output(tasks::shell(&command), &CancellationToken::new()).await {
+        Ok(output) => {
+            print!("{}", String::from_utf8_lossy(&output.stdout));
+            eprint!("{}", String::from_utf8_lossy(&output.stderr));
+            if output.status.success() {
+                println!(
+                    "{}",
+                    style(tf(Msg::ProjectCommandSucceeded, &[&command])).green()
+                );
+            } else {
+                println!(
+                    "{}",
+                    style(tf(Msg::ProjectCommandFailed, &[&command, &output.status])).red()
+                );
             }
         }
+        Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+    }
+}
 
-        // 添加助手响应到历史
-        let assistant_content: Vec<serde_json::Value> = response
-            .content
-            .iter()
-            .map(|block| {
-                json!({
-                    "type": block.content_type,
-                    "text": block.text,
-                    "name": block.name,
-                    "id": block.id,
-                    "input": block.input
-                })
-            })
-            .collect();
+// 达到最大轮数时询问用户是否继续，返回新增的轮数（0 表示结束）
+fn prompt_turn_extension(theme: &dyn Theme, max_turns: usize, default: usize) -> Result<usize> {
+    let extension = Input::with_theme(theme)
+        .with_prompt(tf(Msg::ExtendTurnsPrompt, &[&max_turns]))
+        .default(default)
+        .interact_text()?;
+    Ok(extension)
+}
 
-        messages.push(json!({
-            "role": "assistant",
-            "content": assistant_content
-        }));
+/// 解析 `--temperature`，与 /retry --temperature 的范围相同
+fn parse_temperature(raw: &str) -> Result<f64, String> {
+    raw.parse::<f64>()
+        .ok()
+        .filter(|temperature| (0.0..=1.0).contains(temperature))
+        .ok_or_else(|| format!("expected a number from 0.0 to 1.0, got '{}'", raw))
+}
 
-        // 限制对话历史长度
-        trim_conversation_history(messages);
+/// 按配置创建 API 客户端
+fn api_client(config: &Config) -> ApiClient {
+    let mut api_client = ApiClient::new(config.api_key.clone(), config.api_base_url.clone())
+        .with_retry_config(config.retry.clone())
+        .with_timeout(Duration::from_millis(config.api_timeout_ms))
+        .with_api_version(
+            config.anthropic_version.clone(),
+            config.anthropic_beta.clone(),
+        )
+        .with_compact_tools(config.user_settings.token_efficient_tools)
+        .with_rate_limit_pacing(config.user_settings.rate_limit_pacing);
+    api_client.set_model(config.model().to_string());
+    api_client.set_routing(config.user_settings.model_routing.clone());
+    api_client.set_fallback_model(config.user_settings.fallback_model.clone());
+    // Messages API 的 metadata 只支持 user_id，其他标签只记录在本地
+    api_client.set_user_id(history::tags().get("user_id").cloned());
+    api_client
+}
 
-        // 将新工具任务添加到栈中（逆序添加以保持执行顺序）
-        for task in new_tool_tasks.into_iter().rev() {
-            task_stack.push(task);
-        }
+/// 按命令行参数和配置设置权限模式与工作区
+fn set_permissions(args: &Args, config: &Config) -> Result<()> {
+    let mode = if args.yes {
+        PermissionMode::AcceptEdits
+    } else {
+        args.permission_mode
+    };
+    // 默认模式不被允许时改用托管配置允许的第一个模式；明确要求的模式不被允许时报错
+    let mode = match config.managed.first_allowed_mode() {
+        _ if config.managed.allows_mode(mode) => mode,
+        Some(allowed) if mode == PermissionMode::Default => allowed,
+        _ => anyhow::bail!(
+            "Permission mode {} is not allowed by {}",
+            mode.label().unwrap_or(t(Msg::ModeNormal)),
+            managed::MANAGED_SETTINGS_FILE
+        ),
+    };
+    permissions::set_mode(mode);
+    set_workspaces(config);
+    for dir in &args.add_dirs {
+        permissions::add_dir(dir)?;
     }
-
     Ok(())
 }
 
-async fn run_conversation(args: Args, config: &Config) -> Result<()> {
+async fn run_conversation(mut args: Args, mut config: Config) -> Result<ConversationOutcome> {
     info!("Starting conversation");
     info!("API base URL: {}", config.api_base_url);
-    info!(
-        "Timeout: {} seconds",
-        args.timeout.unwrap_or(config.api_timeout_ms / 1000)
+    info!("Request timeout: {} ms", config.api_timeout_ms);
+    if !config.anthropic_beta.is_empty() {
+        info!("Beta features: {}", config.anthropic_beta.join(","));
+    }
+    if let Some(project) = project::current() {
+        info!("Detected {} project ({})", project.kind, project.marker);
+    }
+
+    let mut api_client = api_client(&config);
+    set_permissions(&args, &config)?;
+    if let Some(command) = &args.serve {
+        dev_server::start(command)?;
+        println!("{}", style(tf(Msg::DevServerStarted, &[command])).dim());
+    }
+    apply_request_settings(&mut api_client, &config);
+    api_client.set_temperature(args.temperature);
+    if args.prompt.is_some() && config.user_settings.response_cache && !args.no_cache {
+        let ttl = Duration::from_secs(config.user_settings.response_cache_ttl_secs);
+        let cache = ResponseCache::in_project(ttl)?;
+        info!("Response cache: {}", cache.dir().display());
+        api_client.set_response_cache(Some(cache));
+    }
+
+    // 交互模式下监听配置文件，修改后无需重启即可生效
+    let settings_watcher = if args.prompt.is_none() {
+        Config::settings_path()
+            .and_then(|path| SettingsWatcher::start(&path))
+            .map_err(|e| warn!("Settings hot-reload disabled: {:#}", e))
+            .ok()
+    } else {
+        None
+    };
+
+    // 交互模式使用支持 emacs/vi 键位的行编辑器
+    let mut line_editor = if args.prompt.is_none() {
+        let mode =
+            line_editor::parse_edit_mode(&config.user_settings.edit_mode).unwrap_or_else(|| {
+                println!(
+                    "{} {}",
+                    style(t(Msg::WarningLabel)).yellow(),
+                    tf(Msg::UnknownEditMode, &[&config.user_settings.edit_mode])
+                );
+                rustyline::EditMode::Emacs
+            });
+        Some(LineEditor::new(mode)?)
+    } else {
+        None
+    };
+
+    // 交互模式启动时检查 API 地址和认证，配置错误不会等到第一次请求才发现
+    if args.prompt.is_none() {
+        Status::check(&api_client, &config, config.user_settings.update_check)
+            .await
+            .print();
+        println!();
+        telemetry::ask_consent(&mut config);
+    }
+    telemetry::init(&config.user_settings);
+    telemetry::record(
+        "session",
+        if args.prompt.is_some() {
+            "prompt"
+        } else {
+            "interactive"
+        },
     );
 
-    let api_client = ApiClient::new(config.api_key.clone(), config.api_base_url.clone());
+    // 单次提示模式的输出常被管道处理，不使用分页器
+    ui::set_pager_enabled(args.prompt.is_none() && config.user_settings.pager);
+
     let stats = api_client.get_stats();
+    let tool_stats = ToolStats::default();
     let mut messages: Vec<serde_json::Value> = Vec::new();
+    if let Some(path) = &args.resume {
+        messages = history::load(path)?.messages.into_owned();
+        println!(
+            "{}",
+            style(tf(
+                Msg::ConversationResumed,
+                &[&messages.len(), &path.display()]
+            ))
+            .dim()
+        );
+    }
+    let mut pending_attachments = args
+        .attachments
+        .iter()
+        .map(|path| Attachment::load_pdf(path))
+        .collect::<Result<Vec<_>>>()?;
+    let mut pending_pastes: Vec<PastedText> = Vec::new();
+    let session_started = clock::unix_secs();
+    changes::start();
+    let mut branches = BranchStore::new(BranchStore::session_path(
+        &Config::get_claude_dir()?,
+        session_started,
+    ));
+    // auto_save 开启时每轮结束后追加写入会话日志，中途退出也不会丢失之前的对话
+    let mut journal = if config.user_settings.auto_save {
+        std::env::current_dir()
+            .map_err(anyhow::Error::from)
+            .and_then(|dir| Journal::create(&dir.join(".claude").join("history"), config.model()))
+            .map_err(|e| warn!("Conversation history disabled: {:#}", e))
+            .ok()
+    } else {
+        None
+    };
     let mut turn_count = 0;
+    let mut outcome = ConversationOutcome::Completed;
 
-    let timeout_secs = args.timeout.unwrap_or(config.api_timeout_ms / 1000);
-    let max_turns = args.max_turns;
+    let mut max_turns = args.max_turns;
 
-    let theme = ColorfulTheme::default();
+    let theme = ui::dialog_theme();
+    // 批准计划后自动发送的下一条消息
+    let mut follow_up: Option<String> = args.first_message.take();
+    // 退出计划模式时恢复的权限模式
+    let mut mode_before_plan = match permissions::mode() {
+        PermissionMode::Plan => PermissionMode::Default,
+        mode => mode,
+    };
 
     loop {
-        let user_input = if let Some(prompt) = &args.prompt {
+        if settings_watcher.as_ref().is_some_and(|w| w.take_change()) {
+            apply_settings_changes(&mut config, &mut api_client);
+        }
+
+        if args.prompt.is_none() && turn_count >= max_turns {
+            info!("Maximum turns ({}) reached", max_turns);
+            let extension = prompt_turn_extension(theme.as_ref(), max_turns, args.max_turns)?;
+            if extension == 0 {
+                println!("\n{}", style(t(Msg::MaxTurnsReached)).dim());
+                break;
+            }
+            max_turns = turn_count + extension;
+            info!("Turn limit extended to {}", max_turns);
+        }
+
+        let user_input = if let Some(message) = follow_up.take() {
+            message
+        } else if let Some(prompt) = &args.prompt {
             info!("Using single prompt mode");
             prompt.clone()
         } else {
-            Input::with_theme(&theme)
-                .with_prompt("You")
-                .allow_empty(false)
-                .interact()
-                .unwrap()
+            let Some(editor) = line_editor.as_mut() else {
+                break;
+            };
+            ui::announce(t(Msg::StatusReady));
+            if config.user_settings.status_line {
+                let mode = permissions::mode();
+                StatusLine {
+                    model: api_client.model(),
+                    profile: profiles::active(),
+                    git: GitStatus::current(),
+                    tokens: context::estimate_tokens(&messages),
+                    context_window: context::CONTEXT_WINDOW_TOKENS,
+                    mode: mode.label().unwrap_or(t(Msg::ModeNormal)),
+                    url: urls::latest(),
+                }
+                .print();
+            }
+            // 非普通模式时在提示符中显示当前模式
+            let prompt = match permissions::mode().label() {
+                Some(label) => format!("{} [{}]", t(Msg::YouPrompt), label),
+                None => t(Msg::YouPrompt).to_string(),
+            };
+            let line = match editor.read(&prompt)? {
+                LineInput::Line(line)
+                    if matches!(SlashCommand::parse(&line), Some(Ok(SlashCommand::Editor))) =>
+                {
+                    compose_prompt("")
+                }
+                // 大段粘贴先保存起来，随用户的下一条消息作为单独的内容块发送
+                LineInput::Line(line) => match PastedText::detect(&line) {
+                    Some(paste) => {
+                        println!(
+                            "{}",
+                            style(tf(Msg::PastedPlaceholder, &[&paste.lines()])).dim()
+                        );
+                        pending_pastes.push(paste);
+                        None
+                    }
+                    None => Some(line),
+                },
+                LineInput::Editor(draft) => compose_prompt(&draft),
+                LineInput::CycleMode => {
+                    let mode = config.managed.next_mode(permissions::mode());
+                    switch_mode(mode, &mut mode_before_plan, &mut api_client, &config);
+                    None
+                }
+                LineInput::Interrupted => None,
+                LineInput::Eof => break,
+            };
+            match line.filter(|line| !line.trim().is_empty()) {
+                Some(line) => line,
+                None => continue,
+            }
         };
 
+        // /retry 和 /edit 会移除最后一轮对话，改为重新发送其中的用户消息
+        let mut resend: Option<(serde_json::Value, RetryOptions)> = None;
+        if args.prompt.is_none() {
+            if let Some(command) = SlashCommand::parse(&user_input) {
+                if command.is_ok() {
+                    let name = user_input.split_whitespace().next().unwrap_or_default();
+                    telemetry::record("command", name);
+                }
+                match command {
+                    Ok(SlashCommand::Turns(Some(limit))) => {
+                        max_turns = limit;
+                        println!(
+                            "{}",
+                            style(tf(Msg::TurnLimitSet, &[&max_turns, &turn_count])).dim()
+                        );
+                    }
+                    Ok(SlashCommand::Turns(None)) => {
+                        println!("{}", tf(Msg::TurnStatus, &[&turn_count, &max_turns]));
+                    }
+                    Ok(SlashCommand::Stats) => {
+                        print_performance_stats(&stats);
+                        print_tool_stats(&tool_stats);
+                    }
+                    Ok(SlashCommand::Context) => {
+                        let pinned = pins::sizes()
+                            .into_iter()
+                            .map(|(path, bytes)| (pins::display(&path), bytes.div_ceil(4)))
+                            .collect();
+                        print_context_usage(&ContextUsage::measure(
+                            api_client.system_prompt(),
+                            pinned,
+                            &api_client.tool_definitions(),
+                            &messages,
+                        ));
+                    }
+                    Ok(SlashCommand::Attach(path)) => match Attachment::load_pdf(&path) {
+                        Ok(attachment) => {
+                            println!(
+                                "{}",
+                                style(tf(
+                                    Msg::Attached,
+                                    &[
+                                        &attachment.title,
+                                        &attachment.pages,
+                                        &format!("{:.1}", attachment.size as f64 / 1024.0)
+                                    ]
+                                ))
+                                .dim()
+                            );
+                            pending_attachments.push(attachment);
+                        }
+                        Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                    },
+                    // 读取输入时已打开编辑器
+                    Ok(SlashCommand::Editor) => {}
+                    Ok(SlashCommand::Expand) => {
+                        if pending_pastes.is_empty() {
+                            println!("{}", style(t(Msg::NothingPasted)).dim());
+                        }
+                        for paste in &pending_pastes {
+                            ui::print_paged(&paste.text);
+                        }
+                    }
+                    Ok(SlashCommand::Branch(None)) => {
+                        for (name, len, is_current) in branches.list(&messages) {
+                            let marker = if is_current { "*" } else { " " };
+                            println!("{} {}", marker, tf(Msg::BranchEntry, &[&name, &len]));
+                        }
+                    }
+                    Ok(SlashCommand::Branch(Some(name))) => {
+                        let from = branches.current().to_string();
+                        match branches.branch(&name, &messages) {
+                            Ok(()) => {
+                                println!("{}", style(tf(Msg::BranchCreated, &[&name, &from])).dim())
+                            }
+                            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                        }
+                    }
+                    Ok(SlashCommand::Checkout(name)) => {
+                        match branches.checkout(&name, &mut messages) {
+                            Ok(()) => println!(
+                                "{}",
+                                style(tf(Msg::BranchSwitched, &[&name, &messages.len()])).dim()
+                            ),
+                            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                        }
+                    }
+                    Ok(SlashCommand::AddDir(Some(dir))) => match permissions::add_dir(&dir) {
+                        Ok(dir) => {
+                            apply_request_settings(&mut api_client, &config);
+                            println!("{}", style(tf(Msg::DirAdded, &[&dir.display()])).dim())
+                        }
+                        Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                    },
+                    Ok(SlashCommand::Pin(path)) => match pins::pin(&path) {
+                        Ok(path) => {
+                            apply_request_settings(&mut api_client, &config);
+                            println!(
+                                "{}",
+                                style(tf(Msg::FilePinned, &[&pins::display(&path)])).dim()
+                            )
+                        }
+                        Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                    },
+                    Ok(SlashCommand::Pins) => {
+                        let pinned = pins::list();
+                        if pinned.is_empty() {
+                            println!("{}", style(t(Msg::NoPinnedFiles)).dim());
+                        } else if std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+                            let names: Vec<String> =
+                                pinned.iter().map(|path| pins::display(path)).collect();
+                            let kept = MultiSelect::with_theme(ui::dialog_theme().as_ref())
+                                .with_prompt(t(Msg::KeepPinned))
+                                .items(&names)
+                                .defaults(&vec![true; names.len()])
+                                .interact_opt()
+                                .ok()
+                                .flatten();
+                            if let Some(kept) = kept {
+                                let removed: Vec<PathBuf> = pinned
+                                    .into_iter()
+                                    .enumerate()
+                                    .filter(|(index, _)| !kept.contains(index))
+                                    .map(|(_, path)| path)
+                                    .collect();
+                                if !removed.is_empty() {
+                                    pins::unpin(&removed);
+                                    apply_request_settings(&mut api_client, &config);
+                                    println!(
+                                        "{}",
+                                        style(tf(Msg::FilesUnpinned, &[&removed.len()])).dim()
+                                    );
+                                }
+                            }
+                        } else {
+                            for path in &pinned {
+                                println!("  {}", pins::display(path));
+                            }
+                        }
+                    }
+                    Ok(SlashCommand::Env) => {
+                        let vars = dotenv::vars();
+                        if vars.is_empty() {
+                            println!("{}", style(t(Msg::NoEnvVars)).dim());
+                        }
+                        // 值可能是密钥，只显示遮盖后的形式
+                        for var in &vars {
+                            let mut line = format!(
+                                "  {}={}  {}",
+                                var.key,
+                                config::mask_secret(&var.value),
+                                style(var.source.display()).dim()
+                            );
+                            if var.shadowed() {
+                                line.push_str(&format!(" {}", style(t(Msg::EnvShadowed)).yellow()));
+                            }
+                            println!("{}", line);
+                        }
+                    }
+                    Ok(SlashCommand::Cd(Some(dir))) => match permissions::set_working_dir(&dir) {
+                        Ok(dir) => {
+                            apply_request_settings(&mut api_client, &config);
+                            println!("{}", style(tf(Msg::WorkingDirSet, &[&dir.display()])).dim())
+                        }
+                        Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                    },
+                    Ok(SlashCommand::Cd(None)) => {
+                        println!("  {}", permissions::working_dir()?.display());
+                    }
+                    Ok(SlashCommand::AddDir(None)) => {
+                        for root in permissions::workspace_roots()? {
+                            println!("  {}", root.display());
+                        }
+                    }
+                    Ok(SlashCommand::OutputStyle(Some(selected))) => {
+                        config.user_settings.output_style = selected;
+                        apply_request_settings(&mut api_client, &config);
+                        println!(
+                            "{}",
+                            style(tf(Msg::OutputStyleSet, &[&selected.name()])).dim()
+                        );
+                        if let Err(e) = config.save_setting("output_style", selected.name().into())
+                        {
+                            println!("{}", style(format!("{:#}", e)).red());
+                        }
+                    }
+                    Ok(SlashCommand::OutputStyle(None)) => {
+                        let current = config.user_settings.output_style;
+                        for option in OutputStyle::value_variants() {
+                            let marker = if *option == current { "*" } else { " " };
+                            println!("{} {}", marker, option.name());
+                        }
+                    }
+                    Ok(SlashCommand::Serve(Some(command))) => {
+                        match dev_server::start(&command) {
+                            Ok(_) => {
+                                println!("{}", style(tf(Msg::DevServerStarted, &[&command])).dim())
+                            }
+                            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                        }
+                        apply_request_settings(&mut api_client, &config);
+                    }
+                    Ok(SlashCommand::Serve(None)) => match dev_server::recent_output(20) {
+                        Some(Ok((status, output))) => {
+                            let command = dev_server::command().unwrap_or_default();
+                            let notice = if status == TaskStatus::Running {
+                                Msg::DevServerRunning
+                            } else {
+                                Msg::DevServerExited
+                            };
+                            println!("{}", style(tf(notice, &[&command])).dim());
+                            print!("{}", output);
+                        }
+                        Some(Err(e)) => println!("{}", style(format!("{:#}", e)).red()),
+                        None => println!("{}", style(t(Msg::NoDevServer)).dim()),
+                    },
+                    Ok(SlashCommand::ServeStop) => {
+                        match dev_server::stop() {
+                            Ok(true) => println!("{}", style(t(Msg::DevServerStopped)).dim()),
+                            Ok(false) => println!("{}", style(t(Msg::NoDevServer)).dim()),
+                            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                        }
+                        apply_request_settings(&mut api_client, &config);
+                    }
+                    Ok(SlashCommand::Open(url)) => match url.or_else(urls::latest) {
+                        Some(url) => {
+                            println!("{}", urls::hyperlink(&url));
+                            if let Err(e) = urls::open_in_browser(&url) {
+                                println!("{}", style(format!("{:#}", e)).red());
+                            }
+                        }
+                        None => println!("{}", style(t(Msg::NoUrl)).dim()),
+                    },
+                    Ok(SlashCommand::Save(path)) => {
+                        match save_conversation_history(
+                            &messages,
+                            &config,
+                            path.as_deref(),
+                            Some(stats.usage()),
+                        )
+                        .await
+                        {
+                            Ok(path) => println!(
+                                "{}",
+                                style(tf(Msg::ConversationSaved, &[&path.display()])).dim()
+                            ),
+                            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                        }
+                    }
+                    Ok(SlashCommand::Share(target)) => {
+                        match share::share(
+                            target.unwrap_or_else(share::Target::default_for_env),
+                            &messages,
+                            api_client.model(),
+                            stats.usage(),
+                            &config.api_key,
+                        )
+                        .await
+                        {
+                            Ok(link) => println!("{}", tf(Msg::ConversationShared, &[&link])),
+                            Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                        }
+                    }
+                    Ok(SlashCommand::Status) => {
+                        Status::check(&api_client, &config, config.user_settings.update_check)
+                            .await
+                            .print();
+                    }
+                    Ok(SlashCommand::Test(filter)) => match test_runner::resolve(None) {
+                        Ok(framework) => {
+                            let command = test_runner::command_line(framework, filter.as_deref());
+                            println!("{}", style(tf(Msg::RunningTests, &[&command])).dim());
+                            match test_runner::run(
+                                framework,
+                                filter.as_deref(),
+                                &CancellationToken::new(),
+                            )
+                            .await
+                            {
+                                Ok(summary) if summary.success() => {
+                                    println!("{}", style(summary.render()).green())
+                                }
+                                Ok(summary) => println!("{}", style(summary.render()).red()),
+                                Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                            }
+                        }
+                        Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                    },
+                    Ok(SlashCommand::Build) => {
+                        run_project_command(project::current().and_then(|p| p.build), "build").await
+                    }
+                    Ok(SlashCommand::Lint) => {
+                        run_project_command(project::current().and_then(|p| p.lint), "lint").await
+                    }
+                    Ok(SlashCommand::Tasks) => {
+                        let tasks = tasks::list();
+                        if tasks.is_empty() {
+                            println!("{}", style(t(Msg::NoTasks)).dim());
+                        }
+                        for task in tasks {
+                            let status = match task.status {
+                                TaskStatus::Running => t(Msg::TaskRunning).to_string(),
+                                TaskStatus::Exited(Some(code)) => tf(Msg::TaskExited, &[&code]),
+                                TaskStatus::Exited(None) | TaskStatus::Killed => {
+                                    t(Msg::TaskKilled).to_string()
+                                }
+                            };
+                            println!(
+                                "  #{} {:<12} {:>6}s  {}",
+                                task.id,
+                                status,
+                                task.elapsed.as_secs(),
+                                task.command
+                            );
+                        }
+                    }
+                    Ok(SlashCommand::Plan) => {
+                        let mode = match permissions::mode() {
+                            PermissionMode::Plan => mode_before_plan,
+                            _ => PermissionMode::Plan,
+                        };
+                        switch_mode(mode, &mut mode_before_plan, &mut api_client, &config);
+                    }
+                    Ok(SlashCommand::Mode(mode)) => {
+                        let mode =
+                            mode.unwrap_or_else(|| config.managed.next_mode(permissions::mode()));
+                        switch_mode(mode, &mut mode_before_plan, &mut api_client, &config);
+                    }
+                    Ok(SlashCommand::Help) => {
+                        for (usage, description) in SlashCommand::help() {
+                            println!("  {:16} {}", style(usage).cyan(), description);
+                        }
+                    }
+                    Ok(SlashCommand::Retry(options)) => match context::last_turn_start(&messages) {
+                        Some(start) => {
+                            let content = messages[start]["content"].clone();
+                            messages.truncate(start);
+                            resend = Some((content, options));
+                        }
+                        None => println!("{}", style(t(Msg::NothingToRetry)).dim()),
+                    },
+                    Ok(SlashCommand::Edit) => match context::last_turn_start(&messages) {
+                        Some(start) => {
+                            let content = messages[start]["content"].clone();
+                            if let Some(text) = compose_prompt(&context::prompt_text(&content)) {
+                                messages.truncate(start);
+                                resend = Some((
+                                    context::replace_prompt_text(&content, &text),
+                                    RetryOptions::default(),
+                                ));
+                            }
+                        }
+                        None => println!("{}", style(t(Msg::NothingToRetry)).dim()),
+                    },
+                    Ok(SlashCommand::Compare(model)) => match context::last_turn_start(&messages) {
+                        Some(start) => {
+                            match compare_models(
+                                &mut api_client,
+                                &config,
+                                &tool_stats,
+                                &messages,
+                                start,
+                                model,
+                            )
+                            .await
+                            {
+                                Ok(comparison) => match journal.as_mut() {
+                                    Some(journal) => {
+                                        if let Err(e) = journal.record_comparison(comparison) {
+                                            warn!(
+                                                "Failed to append to {}: {:#}",
+                                                journal.path().display(),
+                                                e
+                                            );
+                                        }
+                                    }
+                                    None => {
+                                        println!("{}", style(t(Msg::CompareNotRecorded)).dim())
+                                    }
+                                },
+                                Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                            }
+                        }
+                        None => println!("{}", style(t(Msg::NothingToCompare)).dim()),
+                    },
+                    Ok(SlashCommand::Variants(n)) => match context::last_turn_start(&messages) {
+                        Some(start) => {
+                            match choose_variant(
+                                &api_client,
+                                &config,
+                                &tool_stats,
+                                &mut messages,
+                                start,
+                                n,
+                            )
+                            .await
+                            {
+                                Ok(true) => {
+                                    if let Some(journal) = journal.as_mut() {
+                                        if let Err(e) = journal.sync(&messages) {
+                                            warn!(
+                                                "Failed to append to {}: {:#}",
+                                                journal.path().display(),
+                                                e
+                                            );
+                                        }
+                                    }
+                                }
+                                Ok(false) => {}
+                                Err(e) => println!("{}", style(format!("{:#}", e)).red()),
+                            }
+                        }
+                        None => println!("{}", style(t(Msg::NothingToRetry)).dim()),
+                    },
+                    Err(e) => println!("{}", style(e).red()),
+                }
+                if resend.is_none() {
+                    continue;
+                }
+            }
+        }
+
         info!(
             "User input received (turn {}/{})",
             turn_count + 1,
             max_turns
         );
 
-        messages.push(json!({
-            "role": "user",
-            "content": user_input
-        }));
+        api_client.reset_fallback();
+        // 固定的文件可能已经改动，每轮重新读取
+        if !pins::list().is_empty() {
+            apply_request_settings(&mut api_client, &config);
+        }
 
-        let response = timeout(
-            Duration::from_secs(timeout_secs),
-            call_claude(&api_client, &json!(messages), true),
-        )
-        .await
-        .context("Request timed out")?
-        .context("API call failed")?;
-
-        // 处理响应
-        for block in &response.content {
-            match block.content_type.as_str() {
-                "text" => {
-                    if let Some(text) = &block.text {
-                        println!("\n{}", style("Claude:").green());
-                        println!("{}", text);
+        // 重试时临时替换的模型和温度，本轮结束后恢复
+        let mut restore = None;
+        let content = match resend {
+            Some((content, options)) => {
+                let prompt = context::prompt_text(&content);
+                let first_line = prompt.lines().next().unwrap_or_default();
+                println!("{}", style(tf(Msg::Retrying, &[&first_line])).dim());
+                if options.model.is_some() || options.temperature.is_some() {
+                    restore = Some((api_client.model().to_string(), api_client.temperature()));
+                    if let Some(model) = options.model {
+                        api_client.set_model(model);
+                    }
+                    if options.temperature.is_some() {
+                        api_client.set_temperature(options.temperature);
                     }
                 }
-                "tool_use" => {
-                    let name = block.name.as_ref().context("Missing tool name")?;
-                    let id = block.id.as_ref().context("Missing tool id")?;
-                    let input = block.input.as_ref().context("Missing tool input")?;
-
-                    info!("Tool execution requested: {}", name);
-                    println!("\n{} {}", style("Tool:").cyan(), style(name).yellow());
-
-                    let assistant_content = json!([{
-                        "type": "tool_use",
-                        "id": id,
-                        "name": name,
-                        "input": input
-                    }]);
-
-                    messages.push(json!({
-                        "role": "assistant",
-                        "content": assistant_content
-                    }));
-
-                    process_tool_use(
-                        &api_client,
-                        &mut messages,
-                        ToolUseTask {
-                            tool_use_id: id.clone(),
-                            tool_name: name.clone(),
-                            tool_input: input.clone(),
-                        },
-                    )
-                    .await?;
+                content
+            }
+            None => {
+                if !pending_attachments.is_empty() {
+                    info!("Sending {} attachment(s)", pending_attachments.len());
                 }
-                _ => {}
+                attachments::user_content(
+                    &user_input,
+                    &std::mem::take(&mut pending_attachments),
+                    &std::mem::take(&mut pending_pastes),
+                )
+            }
+        };
+        let engine = Engine {
+            api_client: &api_client,
+            config: &config,
+            tool_stats: &tool_stats,
+            events: &Terminal,
+            cancel: &CancellationToken::new(),
+            read_only: false,
+            fail_on_denial: args.prompt.is_some(),
+        };
+        // 单次提示模式下，首个请求也计为一轮
+        let max_rounds = args.prompt.as_ref().map(|_| max_turns.saturating_sub(1));
+        outcome = engine.run_turn(&mut messages, content, max_rounds).await?;
+
+        turn_count += 1;
+
+        if let Some(journal) = journal.as_mut() {
+            if let Err(e) = journal.sync(&messages) {
+                warn!("Failed to append to {}: {:#}", journal.path().display(), e);
             }
         }
 
-        // 如果没有工具使用，添加助手响应到历史
-        if !response
-            .content
-            .iter()
-            .any(|b| b.content_type == "tool_use")
-        {
-            let assistant_content: Vec<serde_json::Value> = response
-                .content
-                .iter()
-                .map(|block| {
-                    json!({
-                        "type": block.content_type,
-                        "text": block.text
-                    })
-                })
-                .collect();
-
-            messages.push(json!({
-                "role": "assistant",
-                "content": assistant_content
-            }));
+        if let Some((model, temperature)) = restore {
+            api_client.set_model(model);
+            api_client.set_temperature(temperature);
         }
 
-        turn_count += 1;
+        // 命令输出中出现了新的本地地址（例如刚启动的开发服务器）时询问是否在浏览器中打开
+        if args.prompt.is_none() {
+            if let Some(url) = urls::take_unoffered().pop() {
+                let open = Confirm::with_theme(theme.as_ref())
+                    .with_prompt(tf(Msg::OpenUrl, &[&url]))
+                    .default(false)
+                    .interact()
+                    .unwrap_or(false);
+                if open {
+                    if let Err(e) = urls::open_in_browser(&url) {
+                        println!("{}", style(format!("{:#}", e)).red());
+                    }
+                }
+            }
+        }
 
-        if args.prompt.is_some() {
-            info!("Single prompt mode completed");
-            break;
+        // 计划模式下每轮回答后询问是否批准，批准后开放全部工具执行计划
+        if args.prompt.is_none()
+            && permissions::mode() == PermissionMode::Plan
+            && outcome == ConversationOutcome::Completed
+        {
+            // 无法交互（例如输入来自管道）时视为未批准
+            let approved = Confirm::with_theme(theme.as_ref())
+                .with_prompt(t(Msg::ApprovePlan))
+                .default(false)
+                .interact()
+                .unwrap_or(false);
+            if approved {
+                switch_mode(
+                    mode_before_plan,
+                    &mut mode_before_plan,
+                    &mut api_client,
+                    &config,
+                );
+                follow_up = Some(permissions::PLAN_APPROVED_PROMPT.to_string());
+            }
         }
 
-        if turn_count >= args.max_turns {
-            info!("Maximum turns ({}) reached", args.max_turns);
-            println!("\n{}", style("Maximum turns reached.").dim());
+        if args.prompt.is_some() {
+            if outcome == ConversationOutcome::TurnLimitReached {
+                warn!(
+                    "Maximum turns ({}) reached in single prompt mode",
+                    max_turns
+                );
+                eprintln!(
+                    "\n{}",
+                    style(tf(Msg::MaxTurnsBeforeDone, &[&max_turns])).yellow()
+                );
+            } else {
+                info!("Single prompt mode completed");
+            }
             break;
         }
     }
 
     info!("Conversation completed ({} turns)", turn_count);
+    ui::announce(t(Msg::StatusSessionEnded));
+
+    let session_changes = changes::collect();
+    if let Some(journal) = journal {
+        let path = journal.finish(
+            &messages,
+            config.user_settings.compress_history,
+            Some(session_changes.clone()),
+            Some(stats.usage()),
+        )?;
+        info!("Conversation history saved to: {}", path.display());
+    } else if args.save == Some(None) {
+        save_conversation_history(&messages, &config, None, Some(stats.usage())).await?;
+    }
+    if let Some(Some(path)) = &args.save {
+        let path = save_conversation_history(&messages, &config, Some(path), None).await?;
+        eprintln!(
+            "{}",
+            style(tf(Msg::ConversationSaved, &[&path.display()])).dim()
+        );
+    }
 
-    save_conversation_history(&messages, config).await?;
+    print_session_changes(&session_changes);
+    print_performance_stats(&stats);
+    print_tool_stats(&tool_stats);
+
+    Ok(outcome)
+}
 
+// 打印 API 请求统计
+fn print_performance_stats(stats: &error::PerformanceStats) {
     let total_requests = stats
         .total_requests
         .load(std::sync::atomic::Ordering::SeqCst);
@@ -504,23 +1915,236 @@ async fn run_conversation(args: Args, config: &Config) -> Result<()> {
     info!("  Success rate: {:.2}%", success_rate);
     info!("  Average response time: {:.2} ms", avg_duration);
 
-    println!("\n{}", style("Performance Statistics:").cyan());
-    println!("  Total requests: {}", total_requests);
+    println!("\n{}", style(t(Msg::PerformanceStats)).cyan());
+    println!("  {}", tf(Msg::TotalRequests, &[&total_requests]));
     println!(
-        "  Successful: {}",
-        style(format!("{}", successful_requests)).green()
+        "  {}",
+        tf(Msg::Successful, &[&style(successful_requests).green()])
     );
     if failed_requests > 0 {
-        println!("  Failed: {}", style(format!("{}", failed_requests)).red());
+        println!("  {}", tf(Msg::Failed, &[&style(failed_requests).red()]));
+    } else {
+        println!("  {}", tf(Msg::Failed, &[&failed_requests]));
+    }
+    println!(
+        "  {}",
+        tf(Msg::SuccessRate, &[&format!("{:.2}", success_rate)])
+    );
+    println!(
+        "  {}",
+        tf(Msg::AverageResponse, &[&format!("{:.2}", avg_duration)])
+    );
+}
+
+/// 导出 worktree 中的改动并删除 worktree：`patch_out` 时写成补丁，`scratch` 时显示改动并询问是否应用
+fn finish_worktree(
+    worktree: worktree::Worktree,
+    patch_out: Option<&Path>,
+    scratch: bool,
+) -> Result<()> {
+    let patch = worktree.diff();
+    let repo = worktree.repo().to_path_buf();
+    worktree.leave()?;
+    let patch = patch?;
+    let files = worktree::changed_files(&patch);
+
+    if let Some(path) = patch_out {
+        std::fs::write(path, &patch)
+            .with_context(|| format!("Failed to write patch: {}", path.display()))?;
+        let message = if files == 0 {
+            tf(Msg::PatchOutEmpty, &[&path.display()])
+        } else {
+            tf(
+                Msg::PatchOutWritten,
+                &[&files, &path.display(), &repo.display()],
+            )
+        };
+        println!("\n{}", style(message).cyan());
+    }
+    if !scratch {
+        return Ok(());
+    }
+    if files == 0 {
+        println!("\n{}", style(t(Msg::ScratchNoChanges)).cyan());
+        return Ok(());
+    }
+    println!("\n{}", style(tf(Msg::ScratchChanges, &[&files])).cyan());
+    ui::print_diff(&patch);
+    if !std::io::IsTerminal::is_terminal(&std::io::stdin()) {
+        println!("{}", style(t(Msg::ScratchNotApplied)).yellow());
+        return Ok(());
+    }
+    let apply = Confirm::with_theme(ui::dialog_theme().as_ref())
+        .with_prompt(tf(Msg::ScratchApply, &[&repo.display()]))
+        .default(false)
+        .interact()
+        .unwrap_or(false);
+    if apply {
+        worktree::apply(&repo, &patch)?;
+        println!("{}", style(tf(Msg::ScratchApplied, &[&files])).green());
     } else {
-        println!("  Failed: {}", failed_requests);
+        println!("{}", style(t(Msg::ScratchDiscarded)).dim());
+    }
+    Ok(())
+}
+
+// 打印本次会话改动的文件和执行的命令
+fn print_session_changes(changes: &changes::SessionChanges) {
+    if changes.is_empty() {
+        return;
+    }
+    println!("\n{}", style(t(Msg::SessionChanges)).cyan());
+    for line in changes.file_lines() {
+        println!("  {}", line);
     }
-    println!("  Success rate: {:.2}%", success_rate);
-    println!("  Average response time: {:.2} ms", avg_duration);
+    if !changes.commands.is_empty() {
+        println!(
+            "  {}",
+            style(tf(Msg::SessionCommands, &[&changes.commands.len()])).dim()
+        );
+        for command in &changes.commands {
+            println!("    {}", sanitize::sanitize(command));
+        }
+    }
+}
+
+/// /context 最多逐轮列出的对话轮数，更早的合并为一行
+const CONTEXT_TURNS_SHOWN: usize = 15;
+
+/// --estimate：按实际会发送的系统提示词、工具和消息估算第一个请求，不需要 API 密钥
+fn estimate_prompt(args: &Args, config: &Config) -> Result<()> {
+    let Some(prompt) = &args.prompt else {
+        anyhow::bail!("--estimate needs --prompt or --template");
+    };
+    let mut api_client = api_client(config);
+    set_permissions(args, config)?;
+    apply_request_settings(&mut api_client, config);
+
+    let mut messages = match &args.resume {
+        Some(path) => history::load(path)?.messages.into_owned(),
+        None => Vec::new(),
+    };
+    let attachments = args
+        .attachments
+        .iter()
+        .map(|path| Attachment::load_pdf(path))
+        .collect::<Result<Vec<_>>>()?;
+    messages.push(json!({
+        "role": "user",
+        "content": attachments::user_content(prompt, &attachments, &[]),
+    }));
+    let usage = ContextUsage::measure(
+        api_client.system_prompt(),
+        Vec::new(),
+        &api_client.tool_definitions(),
+        &messages,
+    );
+    let input = usage.total() as u64;
+
+    println!(
+        "{}",
+        style(tf(Msg::EstimateHeader, &[&api_client.model()])).bold()
+    );
+    let row = |label: &str, tokens: usize| println!("  {:30} {:>8}", label, tokens);
+    row(t(Msg::ContextSystem), usage.system);
+    row(&tf(Msg::ContextTools, &[&usage.tool_count]), usage.tools);
+    row(
+        &tf(Msg::ContextMessages, &[&usage.turns.len()]),
+        usage.messages(),
+    );
+    println!(
+        "{}",
+        tf(Msg::EstimateTokens, &[&input, &error::MAX_OUTPUT_TOKENS])
+    );
 
+    // 最少只付输入，最多再加上 max_tokens 的输出
+    let mut models = vec![api_client.model()];
+    models.extend(
+        pricing::REFERENCE_MODELS.iter().filter(|model| {
+            pricing::per_million(model) != pricing::per_million(api_client.model())
+        }),
+    );
+    println!("\n{}", t(Msg::EstimateCost));
+    for model in models {
+        let low = pricing::cost_usd(model, input, 0);
+        let high = pricing::cost_usd(model, input, error::MAX_OUTPUT_TOKENS);
+        let line = format!("  {:30} ${:.4} – ${:.4}", model, low, high);
+        if model == api_client.model() {
+            println!("{}", style(line).cyan());
+        } else {
+            println!("{}", line);
+        }
+    }
+    println!("\n{}", style(t(Msg::EstimateNote)).dim());
     Ok(())
 }
 
+fn print_context_usage(usage: &ContextUsage) {
+    let percent = style(format!("{}", usage.percent()));
+    let percent = match usage.percent() {
+        p if p as f64 >= context::SUMMARIZE_THRESHOLD * 100.0 => percent.red(),
+        p if p >= 50 => percent.yellow(),
+        _ => percent.green(),
+    };
+    println!(
+        "{}",
+        tf(
+            Msg::ContextTotal,
+            &[&usage.total(), &context::CONTEXT_WINDOW_TOKENS, &percent]
+        )
+    );
+    let row = |indent: usize, label: &str, tokens: usize| {
+        let width = 40usize.saturating_sub(indent);
+        println!("{:indent$}{:width$} {:>8}", "", label, tokens);
+    };
+    row(2, t(Msg::ContextSystem), usage.system);
+    for (path, tokens) in &usage.pinned {
+        row(4, &tf(Msg::ContextPinned, &[path]), *tokens);
+    }
+    row(2, &tf(Msg::ContextTools, &[&usage.tool_count]), usage.tools);
+    row(
+        2,
+        &tf(Msg::ContextMessages, &[&usage.turns.len()]),
+        usage.messages(),
+    );
+    let hidden = usage.turns.len().saturating_sub(CONTEXT_TURNS_SHOWN);
+    if hidden > 0 {
+        let tokens = usage.turns[..hidden].iter().map(|turn| turn.tokens).sum();
+        row(4, &tf(Msg::ContextEarlierTurns, &[&hidden]), tokens);
+    }
+    for (index, turn) in usage.turns.iter().enumerate().skip(hidden) {
+        let prompt: String = if turn.prompt.chars().count() > 20 {
+            format!("{}…", turn.prompt.chars().take(19).collect::<String>())
+        } else {
+            turn.prompt.clone()
+        };
+        let label = tf(Msg::ContextTurn, &[&(index + 1), &prompt, &turn.messages]);
+        row(4, &label, turn.tokens);
+    }
+    println!(
+        "{}",
+        style(tf(
+            Msg::ContextSummarizeNote,
+            &[&(context::SUMMARIZE_THRESHOLD * 100.0)]
+        ))
+        .dim()
+    );
+}
+
+// 打印各工具的调用次数和耗时
+fn print_tool_stats(tool_stats: &ToolStats) {
+    let lines = tool_stats.report_lines();
+    if lines.is_empty() {
+        return;
+    }
+
+    println!("\n{}", style(t(Msg::ToolStatistics)).cyan());
+    println!("  {}", style(&lines[0]).dim());
+    for line in &lines[1..] {
+        println!("  {}", line);
+    }
+}
+
 fn init_logging() -> Result<()> {
     let filter = EnvFilter::try_from_default_env()
         .or_else(|_| EnvFilter::try_new("info"))?
@@ -530,52 +2154,287 @@ fn init_logging() -> Result<()> {
         .with_env_filter(filter)
         .with_target(false)
         .with_thread_ids(false)
+        .with_ansi(!ui::accessible())
         .try_init()
         .map_err(|e| anyhow::anyhow!("Failed to initialize logging: {}", e))?;
 
     Ok(())
 }
 
+// 打印合并后的生效配置
+fn print_effective_config(config: &Config, as_json: bool) -> Result<()> {
+    let claude_dir = Config::get_claude_dir()?;
+    let entries = config.effective_entries();
+
+    if as_json {
+        let settings: serde_json::Map<String, serde_json::Value> = entries
+            .iter()
+            .map(|entry| {
+                (
+                    entry.key.to_string(),
+                    json!({ "value": entry.value, "source": entry.source }),
+                )
+            })
+            .collect();
+        let output = json!({
+            "config_dir": claude_dir.display().to_string(),
+            "settings": settings,
+        });
+        println!("{}", serde_json::to_string_pretty(&output)?);
+        return Ok(());
+    }
+
+    println!(
+        "{} {}",
+        style(t(Msg::ConfigDirectory)).cyan(),
+        claude_dir.display()
+    );
+    println!();
+
+    let key_width = entries.iter().map(|e| e.key.len()).max().unwrap_or(0);
+    for entry in &entries {
+        let value = match &entry.value {
+            serde_json::Value::Null => t(Msg::NotSet).to_string(),
+            serde_json::Value::String(s) => s.clone(),
+            other => other.to_string(),
+        };
+        println!(
+            "  {:width$}  {}  {}",
+            entry.key,
+            value,
+            style(format!("({})", entry.source)).dim(),
+            width = key_width
+        );
+    }
+
+    Ok(())
+}
+
 #[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+async fn main() -> ExitCode {
+    crash::install_hook();
+    let args = match Args::try_parse() {
+        Ok(args) => args,
+        Err(e) => {
+            let _ = e.print();
+            // --help / --version 也会走到这里，它们不算错误
+            return if e.use_stderr() {
+                ExitStatus::Usage.into()
+            } else {
+                ExitStatus::Success.into()
+            };
+        }
+    };
+
+    let result = run(args).await;
+    if let Err(e) = &result {
+        telemetry::record_error(e);
+    }
+    telemetry::flush().await;
+
+    match result {
+        Ok(status) => status.into(),
+        Err(e) => {
+            let status = ExitStatus::from_error(&e);
+            eprintln!("{} {:?}", t(Msg::ErrorLabel), e);
+            if let Some(hint) = status.hint() {
+                eprintln!("{} {}", style(t(Msg::HintLabel)).cyan(), t(hint));
+            }
+            status.into()
+        }
+    }
+}
+
+async fn run(mut args: Args) -> Result<ExitStatus> {
+    let command = args.command.take();
+    clock::init_from_env();
+    if let Some(seed) = args.seed {
+        clock::set_seed(seed);
+    }
+    if let Some(CliCommand::Bench(bench_args)) = command {
+        // 基准测试不读取配置，界面语言只看 --lang 和环境变量
+        i18n::set_lang(i18n::resolve(args.lang.as_deref()).0);
+        bench::run(bench_args).await?;
+        return Ok(ExitStatus::Success);
+    }
+    // acp 和 mcp 模式下标准输出只用于协议消息
+    let protocol_output = matches!(command, Some(CliCommand::Acp | CliCommand::Mcp(_)))
+        .then(jsonrpc::take_stdout)
+        .transpose()?;
+
+    profiles::select(args.profile.clone())?;
+    history::set_tags(&args.tags);
+    let mut final_config = Config::load()?;
+    // 命令行参数优先级最高
+    final_config.apply_cli_overrides(args.api_key.clone(), args.api_url.clone(), args.timeout);
+    final_config.apply_api_header_overrides(args.anthropic_version.clone(), args.betas.clone());
+    final_config.check_managed()?;
+    final_config.apply_language(args.lang.clone());
+    if final_config.apply_accessible(args.accessible) {
+        ui::enable_accessible();
+    }
+    for warning in &final_config.warnings {
+        eprintln!("{} {}", style(t(Msg::WarningLabel)).yellow(), warning);
+    }
 
     if args.show_config {
-        let claude_dir = std::env::current_dir()?.join(".claude");
-        println!("{}", claude_dir.display());
-        return Ok(());
+        print_effective_config(&final_config, args.json)?;
+        return Ok(ExitStatus::Success);
+    }
+    if let Some(CliCommand::Config(config_args)) = command {
+        profiles::run(config_args, &final_config)?;
+        return Ok(ExitStatus::Success);
+    }
+    if let Some(CliCommand::History(history_args)) = command {
+        replay::run(history_args)?;
+        return Ok(ExitStatus::Success);
+    }
+    if let Some(CliCommand::Follow(follow_args)) = command {
+        replay::follow(follow_args)?;
+        return Ok(ExitStatus::Success);
+    }
+    if let Some(CliCommand::Worktrees(worktrees_args)) = command {
+        worktree::run(worktrees_args)?;
+        return Ok(ExitStatus::Success);
+    }
+
+    // 模板渲染结果作为单次提示词，-p 的内容追加在后面
+    if let Some(name) = &args.template {
+        let rendered = templates::load_and_render(name, &args.vars)?;
+        args.prompt = Some(match args.prompt.as_deref() {
+            Some(extra) if !extra.trim().is_empty() => format!("{}\n\n{}", rendered, extra),
+            _ => rendered,
+        });
+    }
+    if args.prompt.as_deref().is_some_and(|p| p.trim().is_empty()) {
+        anyhow::bail!("--prompt needs a prompt unless --template is given");
     }
 
     init_logging()?;
     info!("Initializing Rust Claude Code");
-
-    let config = Config::load()?;
     info!("Configuration loaded successfully");
 
-    // 如果命令行提供了 API key，覆盖配置
-    let api_key = if let Some(ref key) = args.api_key {
-        key.clone()
-    } else {
-        config.api_key.clone()
-    };
+    if let Some(output) = protocol_output {
+        set_permissions(&args, &final_config)?;
+        if let Some(CliCommand::Mcp(mcp_args)) = command {
+            // 只执行工具，不需要 API key
+            mcp::run(mcp_args, output, final_config).await?;
+        } else {
+            final_config.require_api_key()?;
+            let mut api_client = api_client(&final_config);
+            apply_request_settings(&mut api_client, &final_config);
+            acp::run(output, api_client, final_config, args.max_turns).await?;
+        }
+        tasks::kill_all();
+        return Ok(ExitStatus::Success);
+    }
 
-    // 更新配置中的 API key（如果命令行提供了）
-    let final_config = Config { api_key, ..config };
+    if args.estimate {
+        estimate_prompt(&args, &final_config)?;
+        return Ok(ExitStatus::Success);
+    }
+
+    final_config.require_api_key()?;
+
+    if let Some(CliCommand::Eval(eval_args)) = command {
+        set_permissions(&args, &final_config)?;
+        let status = eval::run(eval_args, &final_config, args.max_turns).await?;
+        tasks::kill_all();
+        return Ok(status);
+    }
 
-    println!("\n{}", style("🦀 Rust Claude Code").blue().bold());
-    println!("{}", style("A Rust implementation of Claude Code").dim());
+    #[cfg(feature = "serve")]
+    if let Some(CliCommand::Serve(serve_args)) = command {
+        let mut api_client = api_client(&final_config);
+        set_permissions(&args, &final_config)?;
+        apply_request_settings(&mut api_client, &final_config);
+        server::run(serve_args, api_client, final_config, args.max_turns).await?;
+        tasks::kill_all();
+        return Ok(ExitStatus::Success);
+    }
+
+    ui::print_banner();
     println!();
 
     if final_config.user_settings.ai_enabled {
-        println!("AI 功能: {}", style("已启用").green());
+        println!(
+            "{}",
+            tf(Msg::AiFeatures, &[&style(t(Msg::Enabled)).green()])
+        );
     } else {
-        println!("AI 功能: {}", style("已禁用").yellow());
+        println!(
+            "{}",
+            tf(Msg::AiFeatures, &[&style(t(Msg::Disabled)).yellow()])
+        );
     }
-    println!("配置文件: {}", style(".claude/settings.json").dim());
+    println!(
+        "{}",
+        tf(Msg::ConfigFile, &[&style(".claude/settings.json").dim()])
+    );
     println!();
 
-    run_conversation(args, &final_config).await?;
+    // --patch-out 和 --scratch 的会话在临时 worktree 中进行，结束后导出改动
+    let patch_out = match &args.patch_out {
+        Some(path) => Some(std::env::current_dir()?.join(path)),
+        None => None,
+    };
+    let scratch = args.scratch;
+    // worktree 子命令的会话在新建的 worktree 中进行，任务是第一条消息
+    let task_worktree = match &command {
+        Some(CliCommand::Worktree(worktree_args)) => {
+            let task_worktree = worktree::TaskWorktree::create(worktree_args)?;
+            println!(
+                "{}",
+                style(tf(
+                    Msg::TaskWorktreeCreated,
+                    &[&task_worktree.branch(), &task_worktree.dir().display()]
+                ))
+                .dim()
+            );
+            let task = worktree_args.task().to_string();
+            if worktree_args.print {
+                args.prompt = Some(task);
+            } else {
+                args.first_message = Some(task);
+            }
+            Some(task_worktree)
+        }
+        _ => None,
+    };
+    let worktree = if patch_out.is_some() || scratch {
+        let worktree = worktree::Worktree::enter()?;
+        let message = match &patch_out {
+            Some(path) => tf(Msg::PatchOutWorktree, &[&path.display()]),
+            None => t(Msg::ScratchWorktree).to_string(),
+        };
+        println!("{}", style(message).dim());
+        Some(worktree)
+    } else {
+        None
+    };
 
+    let outcome = run_conversation(args, final_config).await;
+
+    tasks::kill_all();
+    if let Some(worktree) = worktree {
+        finish_worktree(worktree, patch_out.as_deref(), scratch)?;
+    }
+    if let Some(task_worktree) = task_worktree {
+        let (branch, dir) = (
+            task_worktree.branch().to_string(),
+            task_worktree.dir().to_path_buf(),
+        );
+        task_worktree.release()?;
+        println!(
+            "\n{}",
+            style(tf(Msg::TaskWorktreeDone, &[&branch, &dir.display()])).cyan()
+        );
+    }
+    let outcome = outcome?;
     info!("Application shutting down");
-    Ok(())
+    match outcome {
+        ConversationOutcome::Completed => Ok(ExitStatus::Success),
+        ConversationOutcome::TurnLimitReached => Ok(ExitStatus::TurnLimit),
+        ConversationOutcome::Cancelled => Ok(ExitStatus::Failure),
+    }
 }
@@ -0,0 +1,262 @@
+//! 管理员统一下发的 managed-settings.json，放在普通用户不能修改的系统目录中
+//!
+//! 文件中的键与 settings.json 相同，覆盖用户和项目的设置，环境变量和命令行也不能绕过；
+//! 另外可以限制允许的 API 地址和权限模式。
+
+use anyhow::{bail, Context, Result};
+use reqwest::Url;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::UserSettings;
+use crate::permissions::PermissionMode;
+
+pub const MANAGED_SETTINGS_FILE: &str = "managed-settings.json";
+
+/// 托管配置文件的位置
+pub fn path() -> PathBuf {
+    let dir = if cfg!(target_os = "windows") {
+        PathBuf::from(r"C:\ProgramData\rust-claude-code")
+    } else if cfg!(target_os = "macos") {
+        PathBuf::from("/Library/Application Support/rust-claude-code")
+    } else {
+        PathBuf::from("/etc/rust-claude-code")
+    };
+    dir.join(MANAGED_SETTINGS_FILE)
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ManagedSettings {
+    /// 允许使用的 API 地址前缀，为空时不限制
+    #[serde(default)]
+    pub allowed_api_urls: Vec<String>,
+
+    /// 允许使用的权限模式，为空时不限制
+    #[serde(default)]
+    allowed_permission_modes: Vec<String>,
+
+    /// 其余的键与 settings.json 相同
+    #[serde(flatten)]
+    settings: Map<String, Value>,
+}
+
+impl ManagedSettings {
+    /// 读取系统目录中的托管配置；文件不存在时不做任何限制
+    pub fn load() -> Result<Self> {
+        Self::load_from(&path())
+    }
+
+    /// 文件存在但无法解析时报错而不是忽略，避免管理员的限制悄悄失效
+    fn load_from(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read managed settings: {}", path.display()))?;
+        let managed: Self = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse managed settings: {}", path.display()))?;
+
+        if let Some(key) = managed
+            .settings
+            .keys()
+            .find(|key| !UserSettings::KEYS.contains(&key.as_str()))
+        {
+            bail!("Unknown setting '{}' in {}", key, path.display());
+        }
+        if let Some(mode) = managed
+            .allowed_permission_modes
+            .iter()
+            .find(|mode| PermissionMode::parse(mode).is_none())
+        {
+            bail!("Unknown permission mode '{}' in {}", mode, path.display());
+        }
+        Ok(managed)
+    }
+
+    /// 键 `key` 是否由托管配置决定
+    pub fn overrides(&self, key: &str) -> bool {
+        self.settings.contains_key(key)
+    }
+
+    /// 托管的设置覆盖用户设置；disabled_tools 与用户的列表合并，用户只能禁用更多工具；
+    /// command_rules 和 write_rules 追加在用户的规则之后，deny 优先，用户的规则无法放开托管的拒绝
+    pub fn apply(&self, settings: &mut UserSettings) -> Result<()> {
+        if self.settings.is_empty() {
+            return Ok(());
+        }
+        let mut merged = serde_json::to_value(&*settings)?;
+        for (key, value) in &self.settings {
+            match (key.as_str(), value) {
+                ("disabled_tools", Value::Array(tools)) => {
+                    let disabled = merged[key.as_str()].as_array_mut();
+                    if let Some(disabled) = disabled {
+                        for tool in tools {
+                            if !disabled.contains(tool) {
+                                disabled.push(tool.clone());
+                            }
+                        }
+                    }
+                }
+                ("command_rules" | "write_rules", Value::Array(rules)) => {
+                    if let Some(user_rules) = merged[key.as_str()].as_array_mut() {
+                        user_rules.extend(rules.iter().cloned());
+                    }
+                }
+                _ => merged[key.as_str()] = value.clone(),
+            }
+        }
+        *settings = serde_json::from_value(merged).context("Invalid value in managed settings")?;
+        Ok(())
+    }
+
+    /// 检查 API 地址是否在允许的范围内
+    pub fn check_api_url(&self, url: &str) -> Result<()> {
+        if self.allowed_api_urls.is_empty()
+            || self
+                .allowed_api_urls
+                .iter()
+                .any(|allowed| url_allowed(url, allowed))
+        {
+            return Ok(());
+        }
+        bail!(
+            "API URL {} is not allowed by {} (allowed: {})",
+            url,
+            MANAGED_SETTINGS_FILE,
+            self.allowed_api_urls.join(", ")
+        )
+    }
+
+    pub fn allows_mode(&self, mode: PermissionMode) -> bool {
+        self.allowed_permission_modes.is_empty()
+            || self
+                .allowed_permission_modes
+                .iter()
+                .any(|name| PermissionMode::parse(name) == Some(mode))
+    }
+
+    /// 允许的模式中的第一个，用于替换不允许的默认模式
+    pub fn first_allowed_mode(&self) -> Option<PermissionMode> {
+        self.allowed_permission_modes
+            .iter()
+            .find_map(|name| PermissionMode::parse(name))
+    }
+
+    /// Shift+Tab 切换到的下一个允许的模式
+    pub fn next_mode(&self, from: PermissionMode) -> PermissionMode {
+        let mut mode = from.next();
+        while mode != from && !self.allows_mode(mode) {
+            mode = mode.next();
+        }
+        mode
+    }
+}
+
+/// 协议、主机和端口必须相同，路径按 `/` 分隔的段匹配前缀
+fn url_allowed(url: &str, allowed: &str) -> bool {
+    let (Ok(url), Ok(allowed)) = (Url::parse(url), Url::parse(allowed)) else {
+        return false;
+    };
+    if url.scheme() != allowed.scheme()
+        || url.host_str() != allowed.host_str()
+        || url.port_or_known_default() != allowed.port_or_known_default()
+    {
+        return false;
+    }
+    let prefix = allowed.path().trim_end_matches('/');
+    url.path() == prefix
+        || url
+            .path()
+            .strip_prefix(prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn load(content: &str) -> Result<ManagedSettings> {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join(MANAGED_SETTINGS_FILE);
+        fs::write(&path, content).unwrap();
+        ManagedSettings::load_from(&path)
+    }
+
+    #[test]
+    fn test_managed_settings_override_user_settings() {
+        let managed = load(
+            r#"{"disabled_tools": ["execute_command"], "telemetry": false,
+                "allowed_api_urls": ["https://llm.example.com/"]}"#,
+        )
+        .unwrap();
+        let mut settings = UserSettings {
+            disabled_tools: vec!["web_fetch".to_string()],
+            telemetry: Some(true),
+            ..UserSettings::default()
+        };
+        managed.apply(&mut settings).unwrap();
+        assert_eq!(settings.disabled_tools, ["web_fetch", "execute_command"]);
+        assert_eq!(settings.telemetry, Some(false));
+        assert!(managed.overrides("telemetry"));
+        assert!(!managed.overrides("model"));
+
+        assert!(managed
+            .check_api_url("https://llm.example.com/v1/messages")
+            .is_ok());
+        assert!(managed
+            .check_api_url("https://api.anthropic.com/v1/messages")
+            .is_err());
+        assert!(ManagedSettings::load_from(Path::new("/nonexistent/managed.json")).is_ok());
+    }
+
+    #[test]
+    fn test_api_url_must_match_host_and_path_segments() {
+        let managed = load(
+            r#"{"allowed_api_urls": ["https://api.anthropic.com",
+                                     "https://proxy.example.com/llm"]}"#,
+        )
+        .unwrap();
+        assert!(managed
+            .check_api_url("https://api.anthropic.com/v1/messages")
+            .is_ok());
+        assert!(managed
+            .check_api_url("https://api.anthropic.com:443/v1/messages")
+            .is_ok());
+        assert!(managed
+            .check_api_url("https://api.anthropic.com.evil.example/v1/messages")
+            .is_err());
+        assert!(managed
+            .check_api_url("https://api.anthropic.com@evil.example/v1/messages")
+            .is_err());
+        assert!(managed
+            .check_api_url("http://api.anthropic.com/v1/messages")
+            .is_err());
+        assert!(managed
+            .check_api_url("https://proxy.example.com/llm/v1/messages")
+            .is_ok());
+        assert!(managed
+            .check_api_url("https://proxy.example.com/llm-evil/v1/messages")
+            .is_err());
+    }
+
+    #[test]
+    fn test_permission_modes_and_invalid_files() {
+        let managed = load(r#"{"allowed_permission_modes": ["default", "plan"]}"#).unwrap();
+        assert!(!managed.allows_mode(PermissionMode::AcceptEdits));
+        assert_eq!(
+            managed.next_mode(PermissionMode::Default),
+            PermissionMode::Plan
+        );
+        assert_eq!(
+            managed.next_mode(PermissionMode::Plan),
+            PermissionMode::Default
+        );
+
+        assert!(load(r#"{"allowed_permission_modes": ["yolo"]}"#).is_err());
+        assert!(load(r#"{"disabled_tool": []}"#).is_err());
+        assert!(load("not json").is_err());
+    }
+}
@@ -0,0 +1,322 @@
+//! mcp serve：作为 MCP stdio 服务器提供内置工具，其他 MCP 宿主可以复用同样的权限检查和沙箱
+//!
+//! 工具调用经过与对话中相同的 `execute_tool`：计划模式、禁用的工具、工作区限制和
+//! 需要批准的操作都照常生效。需要批准时通过 elicitation 询问宿主，宿主不支持时视为拒绝。
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::engine::{Event, EventSink};
+use crate::error::ClaudeCodeError;
+use crate::i18n::{tf, Msg};
+use crate::jsonrpc::{self, Connection, Handler, RpcError, INVALID_PARAMS, METHOD_NOT_FOUND};
+use crate::permissions::{self, ApprovalRequest};
+use crate::tool_output;
+use crate::tools::ToolRegistry;
+
+/// 支持的协议版本，新的在前；宿主请求其他版本时回复最新的
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+
+#[derive(clap::Args, Debug)]
+pub struct McpArgs {
+    #[command(subcommand)]
+    command: McpCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum McpCommand {
+    /// Serve the built-in tools to other MCP hosts over stdio
+    Serve,
+}
+
+struct ToolServer {
+    config: Config,
+    registry: ToolRegistry,
+    connection: Connection,
+    /// 宿主在 initialize 中声明了 elicitation 能力
+    elicitation: AtomicBool,
+}
+
+/// 处理标准输入上的消息，直到宿主关闭输入
+pub async fn run(args: McpArgs, output: Box<dyn Write + Send>, config: Config) -> Result<()> {
+    let McpCommand::Serve = args.command;
+    info!("MCP server ready on stdio");
+    jsonrpc::run(output, |connection| ToolServer {
+        config,
+        registry: ToolRegistry::builtin(),
+        connection,
+        elicitation: AtomicBool::new(false),
+    })
+    .await
+}
+
+impl ToolServer {
+    /// 按权限模式和配置可用的工具，字段名使用 MCP 的 inputSchema
+    fn tools(&self) -> Vec<Value> {
+        let filter = permissions::mode().tool_filter(self.config.tool_filter());
+        let definitions = self.registry.definitions(&filter);
+        definitions
+            .as_array()
+            .into_iter()
+            .flatten()
+            .map(|tool| {
+                json!({
+                    "name": tool["name"],
+                    "description": tool["description"],
+                    "inputSchema": tool["input_schema"]
+                })
+            })
+            .collect()
+    }
+
+    async fn call_tool(&self, params: &Value) -> Result<Value, RpcError> {
+        let name = params["name"]
+            .as_str()
+            .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing tool name"))?;
+        if !self.tools().iter().any(|tool| tool["name"] == name) {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("unknown or unavailable tool {}", name),
+            ));
+        }
+        let input = match &params["arguments"] {
+            Value::Null => json!({}),
+            arguments => arguments.clone(),
+        };
+
+        info!("MCP tool call: {}", name);
+        let result = crate::execute_tool(
+            name,
+            &input,
+            &self.config,
+            self,
+            &CancellationToken::new(),
+            false,
+        )
+        .await;
+        // 被拒绝的调用把原因作为结果返回
+        let result = match result {
+            Err(ClaudeCodeError::ToolDenied { reason, .. }) => Ok(reason),
+            result => result.map_err(anyhow::Error::from),
+        };
+        let (text, is_error) = match result.and_then(|output| {
+            tool_output::limit_tool_output(
+                &Config::get_claude_dir()?,
+                &format!("mcp_{}", Uuid::new_v4().simple()),
+                output,
+                self.config.user_settings.tool_output_limit,
+            )
+        }) {
+            Ok(output) => (output, false),
+            // 工具失败是调用结果的一部分，不是协议错误
+            Err(e) => (format!("{:#}", e), true),
+        };
+        Ok(json!({
+            "content": [{ "type": "text", "text": text }],
+            "isError": is_error
+        }))
+    }
+}
+
+impl Handler for ToolServer {
+    fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    async fn request(self: Arc<Self>, method: String, params: Value) -> Result<Value, RpcError> {
+        match method.as_str() {
+            "initialize" => {
+                let requested = params["protocolVersion"].as_str().unwrap_or_default();
+                let version = PROTOCOL_VERSIONS
+                    .iter()
+                    .find(|version| **version == requested)
+                    .unwrap_or(&PROTOCOL_VERSIONS[0]);
+                self.elicitation.store(
+                    params["capabilities"].get("elicitation").is_some(),
+                    Ordering::Relaxed,
+                );
+                Ok(json!({
+                    "protocolVersion": version,
+                    "capabilities": { "tools": { "listChanged": false } },
+                    "serverInfo": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION")
+                    }
+                }))
+            }
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.tools() })),
+            "tools/call" => self.call_tool(&params).await,
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            )),
+        }
+    }
+
+    fn notification(&self, method: &str, _params: &Value) {
+        info!("Ignoring notification {}", method);
+    }
+}
+
+impl EventSink for ToolServer {
+    // 单独执行工具时没有对话事件
+    fn emit(&self, _event: Event) {}
+
+    fn approve<'a>(
+        &'a self,
+        tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        let message = match request {
+            ApprovalRequest::Command {
+                command,
+                reason: None,
+            } => tf(Msg::ApproveCommand, &[command]),
+            ApprovalRequest::Command {
+                command,
+                reason: Some(reason),
+            } => tf(Msg::ApproveCommandReason, &[command, reason]),
+            ApprovalRequest::OutsideWorkspace(path) => {
+                tf(Msg::ApproveOutsideWorkspace, &[&tool, &path.display()])
+            }
+            ApprovalRequest::ProtectedFile { path, reason } => {
+                let prompt = tf(Msg::ApproveProtectedFile, &[&tool, &path.display()]);
+                match reason {
+                    Some(reason) => format!("{} ({})", prompt, reason),
+                    None => prompt,
+                }
+            }
+            ApprovalRequest::SuspiciousContent(findings) => {
+                format!(
+                    "{} ({})",
+                    tf(Msg::ApproveSuspiciousContent, &[&tool]),
+                    findings
+                )
+            }
+        };
+        Box::pin(async move {
+            if !self.elicitation.load(Ordering::Relaxed) {
+                info!("Denied {}: the MCP host cannot ask for approval", tool);
+                return false;
+            }
+            let params = json!({
+                "message": message,
+                "requestedSchema": { "type": "object", "properties": {} }
+            });
+            let result = self.connection.request("elicitation/create", params).await;
+            result.is_some_and(|result| result["action"] == "accept")
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserSettings;
+    use crate::error::RetryConfig;
+    use crate::jsonrpc::serve;
+    use std::collections::HashMap;
+
+    fn server() -> (Arc<ToolServer>, tokio::sync::mpsc::UnboundedReceiver<Value>) {
+        let config = Config {
+            user_settings: UserSettings {
+                disabled_tools: vec!["execute_command".to_string()],
+                ..UserSettings::default()
+            },
+            api_key: String::new(),
+            api_base_url: String::new(),
+            api_timeout_ms: 5_000,
+            retry: RetryConfig::default(),
+            anthropic_version: "2023-06-01".to_string(),
+            anthropic_beta: Vec::new(),
+            sources: HashMap::new(),
+            warnings: Vec::new(),
+            managed: Default::default(),
+        };
+        let (connection, queue) = Connection::channel();
+        let server = ToolServer {
+            config,
+            registry: ToolRegistry::builtin(),
+            connection,
+            elicitation: AtomicBool::new(false),
+        };
+        (Arc::new(server), queue)
+    }
+
+    async fn call(
+        server: &Arc<ToolServer>,
+        queue: &mut tokio::sync::mpsc::UnboundedReceiver<Value>,
+        request: Value,
+    ) -> Value {
+        serve(format!("{}\n", request).as_bytes(), Arc::clone(server))
+            .await
+            .unwrap();
+        queue.recv().await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_lists_allowed_tools() {
+        let (server, mut queue) = server();
+        let response = call(
+            &server,
+            &mut queue,
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize", "params": { "protocolVersion": "2024-11-05" } }),
+        )
+        .await;
+        assert_eq!(response["result"]["protocolVersion"], "2024-11-05");
+
+        let response = call(
+            &server,
+            &mut queue,
+            json!({ "jsonrpc": "2.0", "id": 2, "method": "tools/list" }),
+        )
+        .await;
+        let tools = response["result"]["tools"].as_array().unwrap();
+        let read_file = tools
+            .iter()
+            .find(|tool| tool["name"] == "read_file")
+            .unwrap();
+        assert_eq!(read_file["inputSchema"]["required"], json!(["file_path"]));
+        assert!(!tools.iter().any(|tool| tool["name"] == "execute_command"));
+    }
+
+    #[tokio::test]
+    async fn test_call_reports_tool_errors() {
+        let (server, mut queue) = server();
+        let response = call(
+            &server,
+            &mut queue,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "tools/call",
+                "params": { "name": "read_file", "arguments": { "file_path": "/nonexistent/file.txt" } }
+            }),
+        )
+        .await;
+        assert_eq!(response["result"]["isError"], true);
+
+        let response = call(
+            &server,
+            &mut queue,
+            json!({
+                "jsonrpc": "2.0",
+                "id": 2,
+                "method": "tools/call",
+                "params": { "name": "execute_command", "arguments": { "command": "true" } }
+            }),
+        )
+        .await;
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,93 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+/// 回答风格，对应系统提示词中的一段说明
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStyle {
+    /// 不附加说明
+    #[default]
+    Default,
+    /// 简短直接
+    Concise,
+    /// 解释做法和取舍
+    Explanatory,
+    /// 边做边教，把适合用户自己完成的部分留作 TODO
+    Teaching,
+}
+
+const CONCISE_PROMPT: &str = "Keep answers short and direct. Skip preambles, summaries and \
+restating the question. Show code or commands without commentary unless something is \
+surprising.";
+
+const EXPLANATORY_PROMPT: &str = "Explain your reasoning as you work. When you make a change, \
+say why you chose this approach, which alternatives you considered and what trade-offs they \
+have, and point out anything about the codebase that would help the user understand it.";
+
+const TEACHING_PROMPT: &str = "The user wants to learn while the work gets done. Explain the \
+concepts involved as you go. Leave small, well-defined pieces for the user to write themselves: \
+add a `TODO(you):` comment in the code describing what to implement and why, instead of \
+implementing it, and mention each TODO at the end of your answer.";
+
+impl OutputStyle {
+    /// 按名称解析，忽略大小写
+    pub fn parse(name: &str) -> Option<Self> {
+        <Self as ValueEnum>::from_str(name, true).ok()
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            OutputStyle::Default => "default",
+            OutputStyle::Concise => "concise",
+            OutputStyle::Explanatory => "explanatory",
+            OutputStyle::Teaching => "teaching",
+        }
+    }
+
+    /// 附加到系统提示词的说明
+    pub fn system_prompt(self) -> Option<&'static str> {
+        match self {
+            OutputStyle::Default => None,
+            OutputStyle::Concise => Some(CONCISE_PROMPT),
+            OutputStyle::Explanatory => Some(EXPLANATORY_PROMPT),
+            OutputStyle::Teaching => Some(TEACHING_PROMPT),
+        }
+    }
+}
+
+/// 把各部分说明组合成系统提示词；没有任何说明时返回 None
+pub fn build_system_prompt(fragments: &[Option<&str>]) -> Option<String> {
+    let fragments: Vec<&str> = fragments.iter().flatten().copied().collect();
+    (!fragments.is_empty()).then(|| fragments.join("\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_serialize() {
+        assert_eq!(OutputStyle::parse("Teaching"), Some(OutputStyle::Teaching));
+        assert_eq!(OutputStyle::parse("verbose"), None);
+        assert_eq!(
+            serde_json::to_value(OutputStyle::Explanatory).unwrap(),
+            "explanatory"
+        );
+        for style in OutputStyle::value_variants() {
+            assert_eq!(OutputStyle::parse(style.name()), Some(*style));
+        }
+    }
+
+    #[test]
+    fn test_build_system_prompt() {
+        assert_eq!(build_system_prompt(&[None, None]), None);
+        assert_eq!(
+            build_system_prompt(&[Some("style"), None, Some("plan")]).as_deref(),
+            Some("style\n\nplan")
+        );
+        assert!(OutputStyle::Teaching
+            .system_prompt()
+            .unwrap()
+            .contains("TODO"));
+    }
+}
@@ -0,0 +1,275 @@
+use anyhow::{bail, Context, Result};
+
+/// 统一 diff 中的一个 hunk
+#[derive(Debug, Default)]
+struct Hunk {
+    /// 原文件中的起始行（从 1 开始），hunk 头中没有行号时为 None
+    old_start: Option<usize>,
+    /// 需要匹配的原始行（上下文行和删除行）
+    old_lines: Vec<String>,
+    /// 替换后的行（上下文行和新增行）
+    new_lines: Vec<String>,
+    /// 按顺序记录每一行的类型：' '、'-' 或 '+'
+    kinds: Vec<char>,
+}
+
+/// 生成替换内容；上下文行沿用文件中的原始行，避免引入补丁里多余的空白
+fn hunk_replacement(hunk: &Hunk, matched: &[String]) -> Vec<String> {
+    let mut old = matched.iter();
+    let mut added = hunk.new_lines.iter();
+    let mut replacement = Vec::with_capacity(hunk.new_lines.len());
+
+    for kind in &hunk.kinds {
+        match kind {
+            ' ' => {
+                replacement.extend(old.next().cloned());
+                added.next();
+            }
+            '-' => {
+                old.next();
+            }
+            _ => replacement.extend(added.next().cloned()),
+        }
+    }
+
+    replacement
+}
+
+/// 补丁应用结果
+#[derive(Debug)]
+pub struct PatchOutcome {
+    pub content: String,
+    pub hunks: usize,
+    /// 非精确匹配的说明（位置偏移、忽略行尾空白）
+    pub notes: Vec<String>,
+}
+
+/// 将统一 diff 应用到文件内容上
+///
+/// 允许 hunk 位置偏移以及行尾空白不同；任何一个 hunk 的上下文不匹配时整个补丁被拒绝
+pub fn apply_patch(original: &str, patch: &str) -> Result<PatchOutcome> {
+    let hunks = parse_hunks(patch)?;
+    if hunks.is_empty() {
+        bail!("Patch contains no hunks (expected lines starting with '@@')");
+    }
+
+    let crlf = original.contains("\r\n");
+    let trailing_newline = original.ends_with('\n');
+    let mut lines: Vec<String> = original
+        .lines()
+        .map(|line| line.trim_end_matches('\r').to_string())
+        .collect();
+
+    let mut notes = Vec::new();
+    let mut offset: isize = 0;
+    let mut min_pos = 0;
+
+    for (index, hunk) in hunks.iter().enumerate() {
+        let expected = hunk
+            .old_start
+            .map(|start| {
+                // 纯新增的 hunk 中 old_start 指向插入位置之前的一行
+                let start = if hunk.old_lines.is_empty() {
+                    start
+                } else {
+                    start.saturating_sub(1)
+                };
+                (start as isize + offset).max(0) as usize
+            })
+            .unwrap_or(min_pos);
+
+        let (pos, exact) = if hunk.old_lines.is_empty() {
+            (expected.clamp(min_pos, lines.len()), true)
+        } else {
+            find_hunk(&lines, &hunk.old_lines, expected, min_pos).with_context(|| {
+                format!(
+                    "Hunk {} does not match the file (expected near line {}):\n{}",
+                    index + 1,
+                    expected + 1,
+                    hunk.old_lines.join("\n")
+                )
+            })?
+        };
+
+        if pos != expected && hunk.old_start.is_some() {
+            notes.push(format!(
+                "hunk {} applied at line {} (offset {:+})",
+                index + 1,
+                pos + 1,
+                pos as isize - expected as isize
+            ));
+        }
+        if !exact {
+            notes.push(format!(
+                "hunk {} matched ignoring trailing whitespace",
+                index + 1
+            ));
+        }
+
+        let replacement = hunk_replacement(hunk, &lines[pos..pos + hunk.old_lines.len()]);
+        lines.splice(pos..pos + hunk.old_lines.len(), replacement);
+        offset += hunk.new_lines.len() as isize - hunk.old_lines.len() as isize;
+        min_pos = pos + hunk.new_lines.len();
+    }
+
+    let separator = if crlf { "\r\n" } else { "\n" };
+    let mut content = lines.join(separator);
+    if trailing_newline && !content.is_empty() {
+        content.push_str(separator);
+    }
+
+    Ok(PatchOutcome {
+        content,
+        hunks: hunks.len(),
+        notes,
+    })
+}
+
+/// 从 `expected` 开始向两侧查找 hunk 的位置，先精确匹配，再忽略行尾空白匹配
+fn find_hunk(
+    lines: &[String],
+    old_lines: &[String],
+    expected: usize,
+    min_pos: usize,
+) -> Option<(usize, bool)> {
+    if old_lines.len() > lines.len() {
+        return None;
+    }
+    let last = lines.len() - old_lines.len();
+    let expected = expected.clamp(min_pos, last.max(min_pos));
+
+    let matches_at = |pos: usize, exact: bool| {
+        lines[pos..pos + old_lines.len()]
+            .iter()
+            .zip(old_lines)
+            .all(|(line, old)| {
+                if exact {
+                    line == old
+                } else {
+                    line.trim_end() == old.trim_end()
+                }
+            })
+    };
+
+    for exact in [true, false] {
+        for distance in 0..=lines.len() {
+            let candidates = [
+                expected.checked_add(distance),
+                expected.checked_sub(distance).filter(|_| distance > 0),
+            ];
+            for pos in candidates.into_iter().flatten() {
+                if pos >= min_pos && pos <= last && matches_at(pos, exact) {
+                    return Some((pos, exact));
+                }
+            }
+            if expected + distance > last && expected.saturating_sub(distance) <= min_pos {
+                break;
+            }
+        }
+    }
+
+    None
+}
+
+/// 解析统一 diff，忽略文件头（---/+++/diff/index）
+fn parse_hunks(patch: &str) -> Result<Vec<Hunk>> {
+    let mut hunks = Vec::new();
+    let mut current: Option<Hunk> = None;
+
+    for line in patch.lines() {
+        let line = line.trim_end_matches('\r');
+        if let Some(header) = line.strip_prefix("@@") {
+            hunks.extend(current.take());
+            current = Some(Hunk {
+                old_start: parse_old_start(header),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let Some(hunk) = current.as_mut() else {
+            continue;
+        };
+
+        if line.starts_with("--- ") || line.starts_with("+++ ") || line.starts_with("diff ") {
+            // 多文件补丁的下一个文件头
+            hunks.extend(current.take());
+            continue;
+        }
+
+        match line.chars().next() {
+            Some(' ') => {
+                hunk.old_lines.push(line[1..].to_string());
+                hunk.new_lines.push(line[1..].to_string());
+                hunk.kinds.push(' ');
+            }
+            Some('-') => {
+                hunk.old_lines.push(line[1..].to_string());
+                hunk.kinds.push('-');
+            }
+            Some('+') => {
+                hunk.new_lines.push(line[1..].to_string());
+                hunk.kinds.push('+');
+            }
+            Some('\\') => {} // "\ No newline at end of file"
+            // 模型生成的补丁常把空的上下文行写成空行
+            None => {
+                hunk.old_lines.push(String::new());
+                hunk.new_lines.push(String::new());
+                hunk.kinds.push(' ');
+            }
+            Some(_) => bail!("Invalid patch line: {}", line),
+        }
+    }
+    hunks.extend(current);
+
+    Ok(hunks)
+}
+
+/// 从 "@@ -12,5 +12,7 @@" 中取出 12
+fn parse_old_start(header: &str) -> Option<usize> {
+    let range = header
+        .split_whitespace()
+        .find(|part| part.starts_with('-'))?;
+    range[1..].split(',').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORIGINAL: &str =
+        "fn main() {\n    let a = 1;\n    let b = 2;\n    println!(\"{}\", a + b);\n}\n";
+
+    #[test]
+    fn test_apply_simple_patch() {
+        let patch = "--- a/src/main.rs\n+++ b/src/main.rs\n@@ -2,3 +2,3 @@\n     let a = 1;\n-    let b = 2;\n+    let b = 3;\n     println!(\"{}\", a + b);\n";
+        let outcome = apply_patch(ORIGINAL, patch).unwrap();
+        assert_eq!(outcome.hunks, 1);
+        assert!(outcome.notes.is_empty());
+        assert_eq!(
+            outcome.content,
+            "fn main() {\n    let a = 1;\n    let b = 3;\n    println!(\"{}\", a + b);\n}\n"
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_with_offset_and_whitespace() {
+        // 行号偏移了 2 行，且上下文行带有行尾空白
+        let patch = "@@ -4,2 +4,3 @@\n     let a = 1;   \n+    let c = 0;\n     let b = 2;\n";
+        let outcome = apply_patch(ORIGINAL, patch).unwrap();
+        assert!(outcome
+            .content
+            .contains("let a = 1;\n    let c = 0;\n    let b = 2;"));
+        assert_eq!(outcome.notes.len(), 2);
+    }
+
+    #[test]
+    fn test_reject_context_mismatch() {
+        let patch = "@@ -2,2 +2,2 @@\n     let a = 10;\n-    let b = 2;\n+    let b = 3;\n";
+        let err = apply_patch(ORIGINAL, patch).unwrap_err();
+        assert!(err.to_string().contains("Hunk 1 does not match"));
+
+        assert!(apply_patch(ORIGINAL, "no hunks here").is_err());
+    }
+}
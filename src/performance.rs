@@ -1,12 +1,19 @@
 use anyhow::{Context, Result};
-use std::fs::File;
-use std::io::{self, BufRead, BufReader, Read, Write};
-use std::path::Path;
+use memmap2::Mmap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use tokio::fs as async_fs;
 use tokio::io::AsyncBufReadExt;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 use tracing::{info, warn};
 
+use crate::{file_cache, tool_output};
+
+/// 超过 50MB 的文件最多读取的字节数
+const MAX_LARGE_FILE_CONTENT: usize = 10 * 1024 * 1024;
+
 /// 大文件处理配置
 #[derive(Debug, Clone)]
 pub struct FileProcessingConfig {
@@ -18,6 +25,8 @@ pub struct FileProcessingConfig {
     pub chunk_size: usize,
     /// 最大读取时间 (秒)
     pub max_read_time: u64,
+    /// 覆盖文件时保留原文件的 .bak 备份
+    pub keep_backup: bool,
 }
 
 impl Default for FileProcessingConfig {
@@ -27,6 +36,7 @@ impl Default for FileProcessingConfig {
             buffer_size: 64 * 1024,            // 64KB
             chunk_size: 8192,                  // 8KB
             max_read_time: 30,                 // 30秒
+            keep_backup: false,
         }
     }
 }
@@ -36,6 +46,12 @@ pub struct FileProcessor {
     config: FileProcessingConfig,
 }
 
+impl Default for FileProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FileProcessor {
     pub fn new() -> Self {
         Self {
@@ -63,14 +79,14 @@ impl FileProcessor {
             }
             size if size <= 50 * 1024 * 1024 => {
                 // 50MB
-                self.read_medium_file(file_path).await
+                self.read_mapped_file(file_path, usize::MAX)
             }
             _ => {
                 warn!(
-                    "File is very large ({} bytes), reading in chunks",
-                    file_size
+                    "File is very large ({} bytes), reading the first {} bytes",
+                    file_size, MAX_LARGE_FILE_CONTENT
                 );
-                self.read_large_file(file_path).await
+                self.read_mapped_file(file_path, MAX_LARGE_FILE_CONTENT)
             }
         }
     }
@@ -84,74 +100,60 @@ impl FileProcessor {
         Ok(content)
     }
 
-    /// 读取中等大小文件 (使用缓冲读取)
-    async fn read_medium_file(&self, file_path: &Path) -> Result<String> {
-        info!(
-            "Reading medium file with buffering: {}",
-            file_path.display()
-        );
-
-        let mut file = async_fs::File::open(file_path)
-            .await
-            .with_context(|| format!("Failed to open medium file: {}", file_path.display()))?;
-
-        let mut buffer = Vec::with_capacity(self.config.buffer_size);
-        file.read_to_end(&mut buffer)
-            .await
-            .with_context(|| format!("Failed to read medium file: {}", file_path.display()))?;
-
-        let content = String::from_utf8(buffer)
-            .with_context(|| format!("File contains invalid UTF-8: {}", file_path.display()))?;
-
-        Ok(content)
-    }
-
-    /// 读取大文件 (使用分块读取，并限制读取量)
-    async fn read_large_file(&self, file_path: &Path) -> Result<String> {
-        info!("Reading large file in chunks: {}", file_path.display());
-
-        let mut file = async_fs::File::open(file_path)
-            .await
-            .with_context(|| format!("Failed to open large file: {}", file_path.display()))?;
-
-        let mut buffer = Vec::new();
-        let mut chunk = vec![0u8; self.config.chunk_size];
-        let mut total_read = 0;
-        let max_content = 10 * 1024 * 1024; // 10MB 最大内容
-
-        loop {
-            let bytes_read = file
-                .read(&mut chunk)
-                .await
-                .with_context(|| format!("Failed to read chunk from: {}", file_path.display()))?;
-
-            if bytes_read == 0 {
-                break;
-            }
-
-            buffer.extend_from_slice(&chunk[..bytes_read]);
-            total_read += bytes_read;
-
-            // 限制读取的总量
-            if total_read >= max_content {
-                warn!(
-                    "File truncated at {} bytes (original size: {})",
-                    max_content, total_read
-                );
-                break;
-            }
-
-            // 添加超时保护
-            if total_read % (1024 * 1024) == 0 {
-                // 每读取 1MB 检查一次
-                info!("Read {} MB so far", total_read / (1024 * 1024));
+    /// 通过内存映射读取大文件，最多返回 `max_bytes` 字节，不经过分块缓冲区复制
+    fn read_mapped_file(&self, file_path: &Path, max_bytes: usize) -> Result<String> {
+        info!("Reading large file via mmap: {}", file_path.display());
+        let mapped = MappedFile::open(file_path)?;
+        let bytes = mapped.bytes();
+        let mut end = bytes.len().min(max_bytes);
+        if end < bytes.len() {
+            warn!(
+                "File truncated at {} bytes (original size: {})",
+                max_bytes,
+                bytes.len()
+            );
+            // 截断位置落在多字节字符中间时退回到字符边界
+            if let Err(e) = std::str::from_utf8(&bytes[..end]) {
+                if e.error_len().is_none() {
+                    end = e.valid_up_to();
+                }
             }
         }
+        mapped.text(0..end).map(str::to_string)
+    }
 
-        let content = String::from_utf8(buffer)
-            .with_context(|| format!("File contains invalid UTF-8: {}", file_path.display()))?;
+    /// 按行读取文件，`offset` 从 1 开始；输出格式与 `tool_output::slice_lines` 相同
+    ///
+    /// 超过大文件阈值时使用内存映射，只解码请求的行
+    pub fn read_lines(
+        &self,
+        file_path: &Path,
+        offset: Option<usize>,
+        limit: Option<usize>,
+    ) -> Result<String> {
+        let metadata = fs::metadata(file_path)
+            .with_context(|| format!("Failed to get metadata for: {}", file_path.display()))?;
+        if metadata.len() as usize <= self.config.large_file_threshold {
+            let content = file_cache::read(file_path)
+                .with_context(|| format!("Failed to read file: {}", file_path.display()))?;
+            return Ok(tool_output::slice_lines(&content, offset, limit));
+        }
 
-        Ok(content)
+        let mapped = MappedFile::open(file_path)?;
+        let start = offset.unwrap_or(1).max(1);
+        let range = mapped.line_range(start, limit.unwrap_or(usize::MAX));
+        let total = mapped.line_count();
+        if range.is_empty() {
+            return Ok(format!("[no lines in range; file has {} lines]", total));
+        }
+        let selected: Vec<&str> = mapped.text(range)?.lines().collect();
+        Ok(format!(
+            "[lines {}-{} of {}]\n{}",
+            start,
+            start + selected.len() - 1,
+            total,
+            selected.join("\n")
+        ))
     }
 
     /// 高效写入文件
@@ -170,10 +172,8 @@ impl FileProcessor {
 
     /// 写入小文件
     async fn write_small_file(&self, file_path: &Path, content: &str) -> Result<()> {
-        async_fs::write(file_path, content)
-            .await
-            .with_context(|| format!("Failed to write small file: {}", file_path.display()))?;
-        Ok(())
+        write_atomic(file_path, content.as_bytes(), self.config.keep_backup)
+            .with_context(|| format!("Failed to write small file: {}", file_path.display()))
     }
 
     /// 写入大文件 (使用缓冲写入)
@@ -185,31 +185,46 @@ impl FileProcessor {
             })?;
         }
 
-        let mut file = async_fs::File::create(file_path)
-            .await
-            .with_context(|| format!("Failed to create file: {}", file_path.display()))?;
-
-        // 分块写入
-        let mut bytes_written = 0;
-        let chunks = content.as_bytes().chunks(self.config.chunk_size);
+        // 先写入同目录下的临时文件，完成后再替换目标文件
+        let target = resolve_write_target(file_path);
+        let temp_path = temp_path_for(&target);
 
-        for chunk in chunks {
-            file.write_all(chunk)
+        let written: Result<()> = async {
+            let mut file = async_fs::File::create(&temp_path)
                 .await
-                .with_context(|| format!("Failed to write chunk to: {}", file_path.display()))?;
-            bytes_written += chunk.len();
-
-            // 添加进度日志
-            if bytes_written % (1024 * 1024) == 0 {
-                info!("Written {} MB so far", bytes_written / (1024 * 1024));
+                .with_context(|| format!("Failed to create file: {}", temp_path.display()))?;
+
+            // 分块写入
+            let mut bytes_written = 0;
+            let chunks = content.as_bytes().chunks(self.config.chunk_size);
+
+            for chunk in chunks {
+                file.write_all(chunk).await.with_context(|| {
+                    format!("Failed to write chunk to: {}", file_path.display())
+                })?;
+                bytes_written += chunk.len();
+
+                // 添加进度日志
+                if bytes_written % (1024 * 1024) == 0 {
+                    info!("Written {} MB so far", bytes_written / (1024 * 1024));
+                }
             }
-        }
 
-        file.flush()
-            .await
-            .with_context(|| format!("Failed to flush file: {}", file_path.display()))?;
+            file.flush()
+                .await
+                .with_context(|| format!("Failed to flush file: {}", file_path.display()))?;
+            file.sync_all()
+                .await
+                .with_context(|| format!("Failed to sync file: {}", file_path.display()))?;
+            Ok(())
+        }
+        .await;
 
-        Ok(())
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        commit_temp_file(&temp_path, &target, self.config.keep_backup)
     }
 
     /// 流式处理文件行
@@ -284,20 +299,34 @@ impl FileProcessor {
             })?;
         }
 
-        let file = File::create(file_path)
-            .with_context(|| format!("Failed to create file: {}", file_path.display()))?;
+        let target = resolve_write_target(file_path);
+        let temp_path = temp_path_for(&target);
 
-        let mut writer = io::BufWriter::with_capacity(self.config.buffer_size, file);
+        let written = (|| -> Result<()> {
+            let file = File::create(&temp_path)
+                .with_context(|| format!("Failed to create file: {}", temp_path.display()))?;
 
-        writer
-            .write_all(content.as_bytes())
-            .with_context(|| format!("Failed to write to file: {}", file_path.display()))?;
+            let mut writer = io::BufWriter::with_capacity(self.config.buffer_size, file);
 
-        writer
-            .flush()
-            .with_context(|| format!("Failed to flush file: {}", file_path.display()))?;
+            writer
+                .write_all(content.as_bytes())
+                .with_context(|| format!("Failed to write to file: {}", file_path.display()))?;
 
-        Ok(())
+            writer
+                .flush()
+                .with_context(|| format!("Failed to flush file: {}", file_path.display()))?;
+            writer
+                .get_ref()
+                .sync_all()
+                .with_context(|| format!("Failed to sync file: {}", file_path.display()))?;
+            Ok(())
+        })();
+
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        commit_temp_file(&temp_path, &target, self.config.keep_backup)
     }
 
     /// 获取文件信息
@@ -316,6 +345,170 @@ impl FileProcessor {
     }
 }
 
+/// 内存映射的只读文件，可以只取其中一段而不读入整个文件
+pub struct MappedFile {
+    path: PathBuf,
+    /// 空文件不能映射
+    map: Option<Mmap>,
+}
+
+impl MappedFile {
+    pub fn open(file_path: &Path) -> Result<Self> {
+        let file = File::open(file_path)
+            .with_context(|| format!("Failed to open file: {}", file_path.display()))?;
+        let len = file
+            .metadata()
+            .with_context(|| format!("Failed to get metadata: {}", file_path.display()))?
+            .len();
+        let map = if len == 0 {
+            None
+        } else {
+            // SAFETY: 映射是只读的；文件在读取期间被其他进程截断属于外部修改，与普通读取一样不做保证
+            Some(
+                unsafe { Mmap::map(&file) }
+                    .with_context(|| format!("Failed to map file: {}", file_path.display()))?,
+            )
+        };
+        Ok(Self {
+            path: file_path.to_path_buf(),
+            map,
+        })
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.map.as_deref().unwrap_or_default()
+    }
+
+    /// 行数，与 `str::lines` 的计数方式一致
+    pub fn line_count(&self) -> usize {
+        let bytes = self.bytes();
+        let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+        newlines + usize::from(bytes.last().is_some_and(|&b| b != b'\n'))
+    }
+
+    /// 从第 `start` 行（从 1 开始）起最多 `limit` 行的字节范围
+    pub fn line_range(&self, start: usize, limit: usize) -> Range<usize> {
+        let bytes = self.bytes();
+        let line_end = |from: usize| {
+            bytes[from..]
+                .iter()
+                .position(|&b| b == b'\n')
+                .map_or(bytes.len(), |i| from + i + 1)
+        };
+
+        let mut begin = 0;
+        for _ in 1..start.max(1) {
+            if begin >= bytes.len() {
+                break;
+            }
+            begin = line_end(begin);
+        }
+        let mut end = begin;
+        for _ in 0..limit {
+            if end >= bytes.len() {
+                break;
+            }
+            end = line_end(end);
+        }
+        begin..end
+    }
+
+    pub fn text(&self, range: Range<usize>) -> Result<&str> {
+        std::str::from_utf8(&self.bytes()[range])
+            .with_context(|| format!("File contains invalid UTF-8: {}", self.path.display()))
+    }
+}
+
+/// 原子写入：先写入同目录下的临时文件并 fsync，再重命名覆盖目标文件
+///
+/// 写入过程中崩溃时原文件保持不变；`keep_backup` 为 true 时保留 `<文件名>.bak`
+pub fn write_atomic(file_path: &Path, contents: &[u8], keep_backup: bool) -> Result<()> {
+    let target = resolve_write_target(file_path);
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    let temp_path = temp_path_for(&target);
+    let written = (|| -> Result<()> {
+        let mut file = File::create(&temp_path)
+            .with_context(|| format!("Failed to create file: {}", temp_path.display()))?;
+        file.write_all(contents)
+            .with_context(|| format!("Failed to write file: {}", temp_path.display()))?;
+        file.sync_all()
+            .with_context(|| format!("Failed to sync file: {}", temp_path.display()))?;
+        Ok(())
+    })();
+
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        return Err(e);
+    }
+    commit_temp_file(&temp_path, &target, keep_backup)
+}
+
+/// 目标是符号链接时写入链接指向的文件，而不是用普通文件替换链接
+fn resolve_write_target(file_path: &Path) -> PathBuf {
+    match fs::symlink_metadata(file_path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(file_path).unwrap_or_else(|_| file_path.to_path_buf())
+        }
+        _ => file_path.to_path_buf(),
+    }
+}
+
+/// 与目标文件同目录的临时文件路径，保证 rename 不跨文件系统
+fn temp_path_for(target: &Path) -> PathBuf {
+    let file_name = target
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    target.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name,
+        uuid::Uuid::new_v4().simple()
+    ))
+}
+
+/// 备份文件路径：在完整文件名后追加 .bak
+pub fn backup_path(target: &Path) -> PathBuf {
+    let mut name = target.as_os_str().to_owned();
+    name.push(".bak");
+    PathBuf::from(name)
+}
+
+/// 用已经 fsync 的临时文件替换目标文件
+fn commit_temp_file(temp_path: &Path, target: &Path, keep_backup: bool) -> Result<()> {
+    if let Ok(metadata) = fs::metadata(target) {
+        // 保留原文件权限（例如可执行脚本）
+        let _ = fs::set_permissions(temp_path, metadata.permissions());
+
+        if keep_backup {
+            let backup = backup_path(target);
+            if let Err(e) = fs::copy(target, &backup) {
+                let _ = fs::remove_file(temp_path);
+                return Err(e)
+                    .with_context(|| format!("Failed to create backup: {}", backup.display()));
+            }
+        }
+    }
+
+    if let Err(e) = fs::rename(temp_path, target) {
+        let _ = fs::remove_file(temp_path);
+        return Err(e).with_context(|| format!("Failed to replace file: {}", target.display()));
+    }
+
+    // 同步目录项，确保重命名在断电后依然生效
+    #[cfg(unix)]
+    if let Some(parent) = target.parent().filter(|p| !p.as_os_str().is_empty()) {
+        if let Ok(dir) = File::open(parent) {
+            let _ = dir.sync_all();
+        }
+    }
+
+    Ok(())
+}
+
 /// 文件信息结构
 #[derive(Debug)]
 pub struct FileInfo {
@@ -349,7 +542,6 @@ impl FileInfo {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
     use tempfile::NamedTempFile;
 
     #[tokio::test]
@@ -387,6 +579,37 @@ mod tests {
         assert_eq!(read_content.len(), 100);
     }
 
+    #[tokio::test]
+    async fn test_ranged_read_of_mapped_file() {
+        let processor = FileProcessor::with_config(FileProcessingConfig {
+            large_file_threshold: 10,
+            ..Default::default()
+        });
+        let temp_file = NamedTempFile::new().unwrap();
+        let content: String = (1..=300).map(|i| format!("line {}\n", i)).collect();
+        std::fs::write(temp_file.path(), &content).unwrap();
+
+        // 大文件走内存映射，结果与小文件的按行截取一致
+        for (offset, limit) in [(Some(100), Some(101)), (Some(299), None), (None, Some(2))] {
+            assert_eq!(
+                processor
+                    .read_lines(temp_file.path(), offset, limit)
+                    .unwrap(),
+                tool_output::slice_lines(&content, offset, limit)
+            );
+        }
+        assert_eq!(
+            processor
+                .read_lines(temp_file.path(), Some(400), None)
+                .unwrap(),
+            "[no lines in range; file has 300 lines]"
+        );
+
+        let mapped = MappedFile::open(temp_file.path()).unwrap();
+        assert_eq!(mapped.line_count(), 300);
+        assert_eq!(mapped.text(mapped.line_range(2, 1)).unwrap(), "line 2\n");
+    }
+
     #[tokio::test]
     async fn test_file_info() {
         let processor = FileProcessor::new();
@@ -404,6 +627,28 @@ mod tests {
         assert!(formatted.ends_with("B"));
     }
 
+    #[test]
+    fn test_atomic_write_keeps_backup() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("main.rs");
+        std::fs::write(&file_path, "old").unwrap();
+
+        write_atomic(&file_path, b"new", true).unwrap();
+        assert_eq!(std::fs::read_to_string(&file_path).unwrap(), "new");
+        assert_eq!(
+            std::fs::read_to_string(backup_path(&file_path)).unwrap(),
+            "old"
+        );
+
+        // 不应残留临时文件
+        let leftovers: Vec<_> = std::fs::read_dir(temp_dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .collect();
+        assert!(leftovers.is_empty());
+    }
+
     #[test]
     fn test_sync_operations() {
         let processor = FileProcessor::new();
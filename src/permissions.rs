@@ -0,0 +1,527 @@
+use anyhow::{bail, Context, Result};
+use clap::ValueEnum;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Mutex;
+
+use crate::error::ClaudeCodeError;
+use crate::i18n::{t, Msg};
+use crate::tools::{self, ToolCategory, ToolFilter};
+use crate::{audit, cargo_tools, coverage, test_runner};
+
+/// 计划模式下附加的系统提示词
+const PLAN_MODE_PROMPT: &str = "You are in plan mode. Only read-only tools are available: \
+explore the code and gather what you need, but do not try to modify files or run commands. \
+Finish your answer with a concise, numbered plan of the changes you would make. \
+The user will review the plan and approve it before anything is executed.";
+
+/// 用户批准计划后发送的消息
+pub const PLAN_APPROVED_PROMPT: &str = "The plan above is approved. Carry it out now.";
+
+/// 工具调用的权限模式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum PermissionMode {
+    /// 所有工具可用
+    #[default]
+    #[value(alias = "normal")]
+    Default,
+    /// 只能使用只读工具并给出计划，用户批准后再执行
+    Plan,
+    /// 工作区内的文件修改自动执行，执行命令和访问工作区外的路径前询问
+    #[value(name = "acceptEdits", alias = "accept-edits")]
+    AcceptEdits,
+}
+
+/// 执行前需要用户确认的工具调用
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApprovalRequest {
+    /// 执行命令，reason 是匹配的命令规则给出的说明
+    Command {
+        command: String,
+        reason: Option<String>,
+    },
+    /// 访问工作区外的路径
+    OutsideWorkspace(PathBuf),
+    /// 修改匹配写入规则的文件，reason 是规则给出的说明
+    ProtectedFile {
+        path: PathBuf,
+        reason: Option<String>,
+    },
+    /// 把含有可疑指令的工具输出加入对话，内容是 injection::describe 的摘要
+    SuspiciousContent(String),
+}
+
+static MODE: AtomicU8 = AtomicU8::new(PermissionMode::Default as u8);
+
+pub fn set_mode(mode: PermissionMode) {
+    MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// 通过 --add-dir 或 /add-dir 加入工作区的目录（已展开为绝对路径）
+static ADDED_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// 配置中 workspaces 列出的目录，配置热更新时整体替换
+static CONFIGURED_DIRS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// /cd 设置的命令工作目录（已展开为绝对路径），None 时为当前目录
+static WORKING_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+/// 把目录加入工作区，返回展开后的路径
+pub fn add_dir(dir: &Path) -> Result<PathBuf> {
+    let dir = resolve_dir(dir)?;
+    let mut dirs = ADDED_DIRS.lock().unwrap();
+    if !dirs.contains(&dir) {
+        dirs.push(dir.clone());
+    }
+    Ok(dir)
+}
+
+/// 按配置的 workspaces 设置工作区目录（相对路径基于当前目录），返回无法使用的目录的错误
+pub fn set_workspaces(dirs: &[String]) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    let resolved = dirs
+        .iter()
+        .filter_map(|dir| {
+            resolve_dir(Path::new(dir))
+                .map_err(|e| errors.push(e.context("Ignoring entry in workspaces")))
+                .ok()
+        })
+        .collect();
+    *CONFIGURED_DIRS.lock().unwrap() = resolved;
+    errors
+}
+
+/// 工作区的所有根目录：当前目录在前，其后是配置的目录和添加的目录
+pub fn workspace_roots() -> Result<Vec<PathBuf>> {
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    let mut roots = vec![current_dir.canonicalize().unwrap_or(current_dir)];
+    let configured = CONFIGURED_DIRS.lock().unwrap();
+    let added = ADDED_DIRS.lock().unwrap();
+    for dir in configured.iter().chain(added.iter()) {
+        if !roots.contains(dir) {
+            roots.push(dir.clone());
+        }
+    }
+    Ok(roots)
+}
+
+/// 工作区有多个根目录时附加的系统提示词，告诉模型各个根目录的位置
+pub fn workspace_prompt(roots: &[PathBuf]) -> Option<String> {
+    let (current, others) = roots.split_first()?;
+    if others.is_empty() {
+        return None;
+    }
+    let mut prompt = format!(
+        "The workspace has {} root directories. Relative paths resolve against the first one, \
+         the current directory; use absolute paths for files under the others. \
+         list_files and search_files cover every root when no path is given.\n- {} (current directory)",
+        roots.len(),
+        current.display()
+    );
+    for root in others {
+        prompt.push_str(&format!("\n- {}", root.display()));
+    }
+    Some(prompt)
+}
+
+/// execute_command 默认的工作目录：/cd 设置的目录，未设置时为当前目录
+pub fn working_dir() -> Result<PathBuf> {
+    if let Some(dir) = WORKING_DIR.lock().unwrap().clone() {
+        return Ok(dir);
+    }
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    Ok(current_dir.canonicalize().unwrap_or(current_dir))
+}
+
+/// 命令实际执行的目录：execute_command 传入的 cwd，否则为 working_dir()
+pub fn command_cwd(input: &serde_json::Value) -> Result<PathBuf> {
+    match input["cwd"].as_str() {
+        Some(cwd) => command_dir(Path::new(cwd)),
+        None => working_dir(),
+    }
+}
+
+/// 解析命令的工作目录：相对路径基于 working_dir()，必须是工作区内已存在的目录
+pub fn command_dir(dir: &Path) -> Result<PathBuf> {
+    let resolved = working_dir()?
+        .join(dir)
+        .canonicalize()
+        .with_context(|| format!("Cannot run commands in {}", dir.display()))?;
+    if !resolved.is_dir() {
+        bail!("Cannot run commands in {}: not a directory", dir.display());
+    }
+    if !within_roots(&resolved, &workspace_roots()?) {
+        return Err(ClaudeCodeError::Sandbox(format!(
+            "Cannot run commands in {}: it is outside the workspace (add it with /add-dir first)",
+            resolved.display()
+        ))
+        .into());
+    }
+    Ok(resolved)
+}
+
+/// /cd：改变之后的命令默认的工作目录，返回展开后的路径
+pub fn set_working_dir(dir: &Path) -> Result<PathBuf> {
+    let dir = command_dir(dir)?;
+    let current_dir = std::env::current_dir().context("Failed to get current directory")?;
+    // 回到当前目录时清除设置，系统提示词也不再提及
+    let is_current = current_dir
+        .canonicalize()
+        .is_ok_and(|current| current == dir);
+    *WORKING_DIR.lock().unwrap() = Some(dir.clone()).filter(|_| !is_current);
+    Ok(dir)
+}
+
+/// 命令不在当前目录执行时附加的系统提示词
+pub fn working_dir_prompt() -> Option<String> {
+    let dir = WORKING_DIR.lock().unwrap().clone()?;
+    Some(format!(
+        "The user changed the working directory for commands to {} with /cd. execute_command runs there \
+         unless you pass cwd, so do not prefix commands with `cd`. Relative paths for the other tools \
+         still resolve against the current directory.",
+        dir.display()
+    ))
+}
+
+fn resolve_dir(dir: &Path) -> Result<PathBuf> {
+    let resolved = dir
+        .canonicalize()
+        .with_context(|| format!("Cannot add directory {}", dir.display()))?;
+    if !resolved.is_dir() {
+        bail!("Cannot add {}: not a directory", dir.display());
+    }
+    Ok(resolved)
+}
+
+pub fn mode() -> PermissionMode {
+    match MODE.load(Ordering::Relaxed) {
+        1 => PermissionMode::Plan,
+        2 => PermissionMode::AcceptEdits,
+        _ => PermissionMode::Default,
+    }
+}
+
+impl PermissionMode {
+    /// 按名称解析，忽略大小写
+    pub fn parse(name: &str) -> Option<Self> {
+        <Self as ValueEnum>::from_str(name, true).ok()
+    }
+
+    /// Shift+Tab 的切换顺序：普通 → 自动接受修改 → 计划
+    pub fn next(self) -> Self {
+        match self {
+            PermissionMode::Default => PermissionMode::AcceptEdits,
+            PermissionMode::AcceptEdits => PermissionMode::Plan,
+            PermissionMode::Plan => PermissionMode::Default,
+        }
+    }
+
+    /// 输入提示符中显示的模式名；普通模式不显示
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            PermissionMode::Default => None,
+            PermissionMode::AcceptEdits => Some(t(Msg::ModeAcceptEdits)),
+            PermissionMode::Plan => Some(t(Msg::ModePlan)),
+        }
+    }
+
+    /// 在配置的筛选条件上叠加该模式的限制
+    pub fn tool_filter(self, mut filter: ToolFilter) -> ToolFilter {
+        if self == PermissionMode::Plan {
+            filter.read_only = true;
+        }
+        filter
+    }
+
+    pub fn system_prompt(self) -> Option<&'static str> {
+        match self {
+            PermissionMode::Plan => Some(PLAN_MODE_PROMPT),
+            PermissionMode::Default | PermissionMode::AcceptEdits => None,
+        }
+    }
+
+    /// 该模式下是否允许执行工具 `name`
+    pub fn allows_tool(self, name: &str) -> bool {
+        match self {
+            PermissionMode::Plan => tools::is_read_only(name),
+            PermissionMode::Default | PermissionMode::AcceptEdits => true,
+        }
+    }
+
+    /// 执行工具 `name` 前是否需要用户确认；只有 acceptEdits 模式会询问
+    ///
+    /// `roots` 是工作区的根目录，第一个用于解析相对路径
+    pub fn approval_request(
+        self,
+        name: &str,
+        input: &serde_json::Value,
+        roots: &[PathBuf],
+    ) -> Option<ApprovalRequest> {
+        if self != PermissionMode::AcceptEdits {
+            return None;
+        }
+        match tools::category(name)? {
+            ToolCategory::Execute => {
+                command_line(name, input).map(|command| ApprovalRequest::Command {
+                    command,
+                    reason: None,
+                })
+            }
+            ToolCategory::Read | ToolCategory::Write => {
+                let path = tool_path(name, input)?;
+                (!within_roots(&path, roots)).then_some(ApprovalRequest::OutsideWorkspace(path))
+            }
+        }
+    }
+}
+
+/// 命令类工具要执行的命令行；kill_task 只终止已批准启动的任务，没有命令行
+pub fn command_line(name: &str, input: &serde_json::Value) -> Option<String> {
+    match name {
+        "run_tests" => {
+            let framework = test_runner::resolve(input["framework"].as_str()).ok()?;
+            Some(test_runner::command_line(
+                framework,
+                input["filter"].as_str(),
+            ))
+        }
+        "coverage" => Some(coverage::command_line()),
+        "cargo_check" | "cargo_add" => cargo_tools::command_line(name, input),
+        "audit_dependencies" => audit::resolve(input["ecosystem"].as_str())
+            .ok()
+            .map(|ecosystems| audit::command_line(&ecosystems)),
+        _ if tools::category(name) == Some(ToolCategory::Execute) => {
+            input["command"].as_str().map(str::to_string)
+        }
+        _ => None,
+    }
+}
+
+/// 写入类工具要修改的文件
+pub fn written_path(name: &str, input: &serde_json::Value) -> Option<PathBuf> {
+    if tools::category(name) != Some(ToolCategory::Write) {
+        return None;
+    }
+    input["file_path"].as_str().map(PathBuf::from)
+}
+
+/// 文件类工具操作的路径：file_path，list_files 的 path 与 pattern，或 search_files 的 path
+fn tool_path(name: &str, input: &serde_json::Value) -> Option<PathBuf> {
+    if let Some(file_path) = input["file_path"].as_str() {
+        return Some(PathBuf::from(file_path));
+    }
+    // search_files 的 pattern 是正则表达式，不是路径
+    if name == "search_files" {
+        return Some(PathBuf::from(input["path"].as_str().unwrap_or(".")));
+    }
+    let pattern = input["pattern"].as_str()?;
+    let base = input["path"].as_str().unwrap_or(".");
+    Some(Path::new(base).join(pattern))
+}
+
+/// `path`（相对路径按第一个根目录解析）是否位于某个根目录内，符号链接会被展开
+pub fn within_roots(path: &Path, roots: &[PathBuf]) -> bool {
+    let roots: Vec<PathBuf> = roots
+        .iter()
+        .map(|root| root.canonicalize().unwrap_or_else(|_| root.clone()))
+        .collect();
+    let Some(full) = roots
+        .first()
+        .and_then(|workspace| resolve(&workspace.join(path)))
+    else {
+        return false;
+    };
+    roots.iter().any(|root| full.starts_with(root))
+}
+
+/// 展开 `path` 中已存在的最长前缀，其余尚不存在的部分原样拼接（不能含 ..）
+pub fn resolve(path: &Path) -> Option<PathBuf> {
+    let mut base = path;
+    let mut rest = Vec::new();
+    let resolved = loop {
+        if let Ok(resolved) = base.canonicalize() {
+            break resolved;
+        }
+        match (base.parent(), base.file_name()) {
+            (Some(parent), Some(name)) => {
+                rest.push(name);
+                base = parent;
+            }
+            _ => return None,
+        }
+    };
+    Some(
+        rest.iter()
+            .rev()
+            .fold(resolved, |full, name| full.join(name)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_mode_is_read_only() {
+        let plan = PermissionMode::Plan;
+        assert!(plan.allows_tool("read_file"));
+        assert!(!plan.allows_tool("write_file"));
+        assert!(!plan.allows_tool("execute_command"));
+        assert!(plan.tool_filter(ToolFilter::default()).read_only);
+        assert!(plan.system_prompt().is_some());
+
+        let default = PermissionMode::Default;
+        assert!(default.allows_tool("execute_command"));
+        assert_eq!(
+            default.tool_filter(ToolFilter::default()),
+            ToolFilter::default()
+        );
+    }
+
+    #[test]
+    fn test_workspace_prompt_lists_roots() {
+        let roots = vec![PathBuf::from("/repo"), PathBuf::from("/shared-lib")];
+        assert_eq!(workspace_prompt(&roots[..1]), None);
+        let prompt = workspace_prompt(&roots).unwrap();
+        assert!(prompt.contains("\n- /repo (current directory)\n- /shared-lib"));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let errors = set_workspaces(&[
+            dir.path().display().to_string(),
+            "/no/such/workspace".to_string(),
+        ]);
+        assert_eq!(errors.len(), 1);
+        assert!(workspace_roots()
+            .unwrap()
+            .contains(&dir.path().canonicalize().unwrap()));
+        set_workspaces(&[]);
+    }
+
+    #[test]
+    fn test_command_dir_stays_in_workspace() {
+        let root = working_dir().unwrap();
+        assert_eq!(command_dir(Path::new("src")).unwrap(), root.join("src"));
+        assert!(command_dir(Path::new("Cargo.toml")).is_err());
+        let outside = command_dir(Path::new("..")).unwrap_err();
+        assert_eq!(crate::error::kind(&outside), "sandbox");
+        assert!(command_dir(Path::new("no-such-dir")).is_err());
+
+        set_working_dir(Path::new("src")).unwrap();
+        assert_eq!(working_dir().unwrap(), root.join("src"));
+        assert!(working_dir_prompt().unwrap().contains("/src with /cd"));
+        // 相对路径基于 /cd 设置的目录
+        assert_eq!(set_working_dir(Path::new("..")).unwrap(), root);
+        assert_eq!(working_dir_prompt(), None);
+    }
+
+    #[test]
+    fn test_parse_mode_from_cli() {
+        assert_eq!(
+            PermissionMode::from_str("plan", false),
+            Ok(PermissionMode::Plan)
+        );
+        assert_eq!(
+            PermissionMode::from_str("acceptEdits", false),
+            Ok(PermissionMode::AcceptEdits)
+        );
+        assert!(PermissionMode::from_str("yolo", false).is_err());
+        assert_eq!(
+            PermissionMode::parse("ACCEPTEDITS"),
+            Some(PermissionMode::AcceptEdits)
+        );
+
+        let mut mode = PermissionMode::Default;
+        let mut seen = Vec::new();
+        for _ in 0..3 {
+            mode = mode.next();
+            seen.push(mode);
+        }
+        assert_eq!(
+            seen,
+            vec![
+                PermissionMode::AcceptEdits,
+                PermissionMode::Plan,
+                PermissionMode::Default
+            ]
+        );
+    }
+
+    #[test]
+    fn test_accept_edits_scope() {
+        let workspace = tempfile::TempDir::new().unwrap();
+        let root = [workspace.path().to_path_buf()];
+        let mode = PermissionMode::AcceptEdits;
+        let write = |path: &str| serde_json::json!({"file_path": path, "content": ""});
+
+        assert_eq!(
+            mode.approval_request("write_file", &write("src/new/lib.rs"), &root),
+            None
+        );
+        assert_eq!(
+            mode.approval_request("read_file", &write("Cargo.toml"), &root),
+            None
+        );
+        assert_eq!(
+            mode.approval_request("write_file", &write("/etc/hosts"), &root),
+            Some(ApprovalRequest::OutsideWorkspace(PathBuf::from(
+                "/etc/hosts"
+            )))
+        );
+        assert!(mode
+            .approval_request("append_file", &write("src/../../escape.txt"), &root)
+            .is_some());
+        assert!(mode
+            .approval_request(
+                "list_files",
+                &serde_json::json!({"pattern": "**/*.rs", "path": ".."}),
+                &root
+            )
+            .is_some());
+        // 正则里的 ../ 不是路径
+        assert_eq!(
+            mode.approval_request(
+                "search_files",
+                &serde_json::json!({"pattern": "\\.\\./\\.\\./x", "path": "src"}),
+                &root
+            ),
+            None
+        );
+        assert_eq!(
+            mode.approval_request(
+                "execute_command",
+                &serde_json::json!({"command": "cargo test"}),
+                &root
+            ),
+            Some(ApprovalRequest::Command {
+                command: "cargo test".to_string(),
+                reason: None
+            })
+        );
+        assert_eq!(
+            PermissionMode::Default.approval_request("write_file", &write("/etc/hosts"), &root),
+            None
+        );
+    }
+
+    #[test]
+    fn test_added_dirs_extend_workspace() {
+        let parent = tempfile::TempDir::new().unwrap();
+        let app = parent.path().join("app");
+        let shared = parent.path().join("shared");
+        std::fs::create_dir_all(&app).unwrap();
+        std::fs::create_dir_all(&shared).unwrap();
+
+        let sibling = shared.join("src/lib.rs");
+        assert!(!within_roots(&sibling, std::slice::from_ref(&app)));
+        assert!(within_roots(&sibling, &[app.clone(), shared.clone()]));
+        assert!(within_roots(
+            Path::new("../shared/x"),
+            &[app.clone(), shared]
+        ));
+        assert!(!within_roots(Path::new("../other/x"), &[app]));
+
+        assert!(resolve_dir(&parent.path().join("missing")).is_err());
+        let file = parent.path().join("notes.txt");
+        std::fs::write(&file, "").unwrap();
+        assert!(resolve_dir(&file).is_err());
+    }
+}
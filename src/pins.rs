@@ -0,0 +1,168 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 每个固定文件最多放入系统提示词的字节数
+const MAX_PINNED_BYTES: usize = 50_000;
+
+/// settings.json 中 pinned_files 指定的文件
+static CONFIGURED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+/// 会话中用 /pin 固定的文件
+static ADDED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// 固定文件，之后每轮对话都附带它的最新内容
+pub fn pin(path: &Path) -> Result<PathBuf> {
+    let path = resolve(path)?;
+    let configured = CONFIGURED.lock().unwrap().contains(&path);
+    let mut added = ADDED.lock().unwrap();
+    if !configured && !added.contains(&path) {
+        added.push(path.clone());
+    }
+    Ok(path)
+}
+
+/// 取消固定；配置中的文件只在本次会话中取消
+pub fn unpin(paths: &[PathBuf]) {
+    for list in [&CONFIGURED, &ADDED] {
+        list.lock()
+            .unwrap()
+            .retain(|pinned| !paths.contains(pinned));
+    }
+}
+
+/// 按配置的 pinned_files 设置固定的文件（相对路径基于当前目录），返回无法使用的文件的错误
+pub fn set_configured(files: &[String]) -> Vec<anyhow::Error> {
+    let mut errors = Vec::new();
+    let resolved = files
+        .iter()
+        .filter_map(|file| {
+            resolve(Path::new(file))
+                .map_err(|e| errors.push(e.context("Ignoring entry in pinned_files")))
+                .ok()
+        })
+        .collect();
+    *CONFIGURED.lock().unwrap() = resolved;
+    errors
+}
+
+/// 所有固定的文件，配置的在前
+pub fn list() -> Vec<PathBuf> {
+    let mut files = CONFIGURED.lock().unwrap().clone();
+    for file in ADDED.lock().unwrap().iter() {
+        if !files.contains(file) {
+            files.push(file.clone());
+        }
+    }
+    files
+}
+
+/// 附加到系统提示词的固定文件内容，每次调用都重新读取文件
+pub fn system_prompt() -> Option<String> {
+    render(&list())
+}
+
+/// 每个固定文件放入系统提示词的字节数，读取失败的文件为 0
+pub fn sizes() -> Vec<(PathBuf, usize)> {
+    list()
+        .into_iter()
+        .map(|file| {
+            let len = std::fs::metadata(&file).map_or(0, |m| m.len() as usize);
+            (file, len.min(MAX_PINNED_BYTES))
+        })
+        .collect()
+}
+
+/// 相对于当前目录显示路径，不在当前目录下时显示绝对路径
+pub fn display(path: &Path) -> String {
+    std::env::current_dir()
+        .ok()
+        .and_then(|dir| dir.canonicalize().ok())
+        .and_then(|dir| path.strip_prefix(dir).ok().map(Path::to_path_buf))
+        .unwrap_or_else(|| path.to_path_buf())
+        .display()
+        .to_string()
+}
+
+fn render(files: &[PathBuf]) -> Option<String> {
+    if files.is_empty() {
+        return None;
+    }
+    let mut prompt = String::from(
+        "The user pinned these files so their latest contents are always in view. They are \
+         re-read before every turn, so there is no need to read them with read_file.",
+    );
+    for file in files {
+        let content = match std::fs::read(file) {
+            Ok(bytes) if bytes.len() > MAX_PINNED_BYTES => {
+                let text = String::from_utf8_lossy(&bytes[..MAX_PINNED_BYTES]);
+                format!(
+                    "{}\n[Truncated: the file has {} bytes, only the first {} are shown]",
+                    text.trim_end_matches('\u{fffd}'),
+                    bytes.len(),
+                    MAX_PINNED_BYTES
+                )
+            }
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) => format!("[The file could not be read: {}]", e),
+        };
+        prompt.push_str(&format!(
+            "\n\n<pinned_file path=\"{}\">\n{}\n</pinned_file>",
+            display(file),
+            content.trim_end()
+        ));
+    }
+    Some(prompt)
+}
+
+fn resolve(path: &Path) -> Result<PathBuf> {
+    let resolved = path
+        .canonicalize()
+        .with_context(|| format!("Cannot pin {}", path.display()))?;
+    if !resolved.is_file() {
+        bail!("Cannot pin {}: not a file", path.display());
+    }
+    Ok(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_render_rereads_files() {
+        let dir = TempDir::new().unwrap();
+        let types = dir.path().join("types.rs");
+        std::fs::write(&types, "struct User;\n").unwrap();
+        let files = vec![types.clone(), dir.path().join("gone.rs")];
+        assert_eq!(render(&[]), None);
+
+        let prompt = render(&files).unwrap();
+        assert!(prompt.contains("struct User;\n</pinned_file>"));
+        assert!(prompt.contains("[The file could not be read"));
+
+        // 文件改动后下一次渲染即是新内容
+        std::fs::write(&types, "struct User { id: u64 }\n").unwrap();
+        assert!(render(&files).unwrap().contains("struct User { id: u64 }"));
+
+        std::fs::write(&types, "x".repeat(MAX_PINNED_BYTES + 10)).unwrap();
+        assert!(render(&files)
+            .unwrap()
+            .contains("[Truncated: the file has 50010 bytes"));
+    }
+
+    #[test]
+    fn test_pin_and_unpin() {
+        let dir = TempDir::new().unwrap();
+        let file = dir.path().join("api.ts");
+        std::fs::write(&file, "export type Id = string;\n").unwrap();
+        assert!(pin(dir.path()).is_err());
+        assert!(pin(&dir.path().join("missing.ts")).is_err());
+
+        let pinned = pin(&file).unwrap();
+        pin(&file).unwrap();
+        assert_eq!(list().iter().filter(|path| **path == pinned).count(), 1);
+        unpin(std::slice::from_ref(&pinned));
+        assert!(!list().contains(&pinned));
+    }
+}
@@ -0,0 +1,455 @@
+//! 命令审批规则：按 glob 或正则匹配要执行的命令，决定直接执行、拒绝还是先询问用户
+//!
+//! 规则来自 settings.json 和 managed-settings.json 的 command_rules；都没有匹配时
+//! 使用内置的规则。命令行先由 shell.rs 解析，规则分别匹配其中每条简单命令的 argv
+//! （而不是其中任意一段文字），所以 `git commit -m "don't shutdown"` 不会被当成
+//! 关机命令，`echo hi && r\m -rf /` 也不会漏掉后半段。
+//!
+//! write_rules 用同样的动作限制写入哪些文件，例如禁止修改 `*.pem` 或 `.github/workflows/**`。
+
+use anyhow::{bail, Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::shell::{self, SimpleCommand};
+
+/// 规则的动作，同时匹配多条规则时取最严格的：deny > ask > allow
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Allow,
+    Ask,
+    Deny,
+}
+
+/// settings.json 中的一条规则，pattern 和 regex 二选一
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandRule {
+    /// glob 模式，`*` 匹配任意字符，需要匹配整条简单命令，例如 "cargo *" 或 "Bash(cargo *)"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    /// 正则表达式，在每条简单命令中查找
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    pub action: Action,
+    /// 只在这些目录及其子目录中生效，相对路径基于项目根目录（启动时的当前目录）；为空时处处生效
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub directories: Vec<String>,
+    /// 询问或拒绝时显示的说明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// 对一条命令的判定
+#[derive(Debug, Clone, PartialEq)]
+pub struct Decision {
+    pub action: Action,
+    /// 匹配的规则，例如 "rm -rf *"
+    pub rule: String,
+    /// 命令行中匹配规则的那条简单命令
+    pub command: String,
+    pub reason: Option<String>,
+}
+
+struct CompiledRule {
+    source: String,
+    regex: Regex,
+    action: Action,
+    /// 已解析为绝对路径
+    directories: Vec<PathBuf>,
+    reason: Option<String>,
+}
+
+impl CompiledRule {
+    fn new(rule: &CommandRule, root: &Path) -> Result<Self> {
+        let (source, regex) = match (&rule.pattern, &rule.regex) {
+            (Some(pattern), None) => {
+                let glob = pattern
+                    .strip_prefix("Bash(")
+                    .and_then(|glob| glob.strip_suffix(')'))
+                    .unwrap_or(pattern);
+                (pattern.clone(), glob_to_regex(glob))
+            }
+            (None, Some(regex)) => (regex.clone(), regex.clone()),
+            _ => bail!("A command rule needs exactly one of 'pattern' and 'regex'"),
+        };
+        Ok(Self {
+            regex: Regex::new(&regex)
+                .with_context(|| format!("Invalid command rule: {}", source))?,
+            source,
+            action: rule.action,
+            directories: rule
+                .directories
+                .iter()
+                .map(|dir| {
+                    let dir = root.join(dir);
+                    canonical(&dir).unwrap_or(dir)
+                })
+                .collect(),
+            reason: rule.reason.clone(),
+        })
+    }
+
+    fn builtin(regex: &str, action: Action, reason: &str) -> Self {
+        Self {
+            source: regex.to_string(),
+            regex: Regex::new(regex).unwrap(),
+            action,
+            directories: Vec::new(),
+            reason: Some(reason.to_string()),
+        }
+    }
+
+    fn matches(&self, command: &str, cwd: &Path) -> bool {
+        self.regex.is_match(command)
+            && (self.directories.is_empty()
+                || self.directories.iter().any(|dir| cwd.starts_with(dir)))
+    }
+}
+
+/// 没有配置规则匹配时使用，代替原来按子串查找的危险命令列表
+static BUILTIN_RULES: Lazy<Vec<CompiledRule>> = Lazy::new(|| {
+    vec![
+        CompiledRule::builtin(
+            r"^(sudo )?rm (-\S+ )*(/|/\*|~|~/|\$HOME/?)( |$)",
+            Action::Deny,
+            "Deletes the whole file system or home directory",
+        ),
+        CompiledRule::builtin(
+            r"^(sudo )?(mkfs(\.\w+)?|fdisk|sfdisk|parted)( |$)",
+            Action::Deny,
+            "Formats or repartitions a disk",
+        ),
+        CompiledRule::builtin(
+            r"^(sudo )?dd .*\bof=/dev/",
+            Action::Deny,
+            "Writes directly to a device",
+        ),
+        CompiledRule::builtin(r"(?i)^format [a-z]:", Action::Deny, "Formats a drive"),
+        CompiledRule::builtin(
+            r"(?i)^(del|erase) (.* )?/[fsq]\b",
+            Action::Ask,
+            "Force-deletes files",
+        ),
+        CompiledRule::builtin(
+            r"^(sudo )?(shutdown|reboot|halt|poweroff)( |$)",
+            Action::Ask,
+            "Shuts down or restarts the machine",
+        ),
+        CompiledRule::builtin(r"^sudo ", Action::Ask, "Runs with root privileges"),
+    ]
+});
+
+/// 配置的规则；每次判定前从设置中编译，设置修改后立即生效
+pub struct Policy {
+    rules: Vec<CompiledRule>,
+}
+
+impl Policy {
+    /// 规则缺少 pattern/regex 或正则无效时报错；相对目录基于当前目录解析
+    pub fn new(rules: &[CommandRule]) -> Result<Self> {
+        let root = std::env::current_dir().context("Failed to get current directory")?;
+        Self::with_root(rules, &root)
+    }
+
+    fn with_root(rules: &[CommandRule], root: &Path) -> Result<Self> {
+        Ok(Self {
+            rules: rules
+                .iter()
+                .map(|rule| CompiledRule::new(rule, root))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    /// 在 `cwd` 中执行 `command` 的判定，取各条简单命令中最严格的结果；
+    /// 只有每条都被允许时才整体允许。没有规则匹配时返回 None，由权限模式决定
+    pub fn evaluate(&self, command: &str, cwd: &Path) -> Option<Decision> {
+        // 无法解析的命令行（例如引号没有闭合）按空白切分后整体匹配
+        let lines: Vec<String> = match shell::parse(command) {
+            Ok(parsed) => parsed.commands.iter().map(SimpleCommand::line).collect(),
+            Err(_) => vec![normalize(command)],
+        };
+        let cwd = canonical(cwd).unwrap_or_else(|| cwd.to_path_buf());
+        let decisions: Vec<Option<Decision>> = lines
+            .iter()
+            .map(|line| self.evaluate_line(line, &cwd))
+            .collect();
+        let all_matched = decisions.iter().all(Option::is_some);
+        decisions
+            .into_iter()
+            .flatten()
+            .filter(|decision| all_matched || decision.action != Action::Allow)
+            .max_by_key(|decision| decision.action)
+    }
+
+    /// 先看配置的规则，都不匹配时再看内置规则
+    fn evaluate_line(&self, line: &str, cwd: &Path) -> Option<Decision> {
+        strictest(&self.rules, line, cwd)
+            .or_else(|| strictest(&BUILTIN_RULES, line, cwd))
+            .map(|rule| Decision {
+                action: rule.action,
+                rule: rule.source.clone(),
+                command: line.to_string(),
+                reason: rule.reason.clone(),
+            })
+    }
+}
+
+/// 匹配的规则中最严格的一条
+fn strictest<'a>(rules: &'a [CompiledRule], command: &str, cwd: &Path) -> Option<&'a CompiledRule> {
+    rules
+        .iter()
+        .filter(|rule| rule.matches(command, cwd))
+        .max_by_key(|rule| rule.action)
+}
+
+/// 合并连续的空白
+fn normalize(command: &str) -> String {
+    command.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `*` 匹配任意字符，其余字符按字面匹配，整条简单命令都要匹配
+fn glob_to_regex(pattern: &str) -> String {
+    let body = normalize(pattern)
+        .split('*')
+        .map(regex::escape)
+        .collect::<Vec<_>>()
+        .join(".*");
+    format!("(?s)^{}$", body)
+}
+
+fn canonical(path: &Path) -> Option<PathBuf> {
+    path.canonicalize().ok()
+}
+
+/// settings.json 中的一条写入规则
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WriteRule {
+    /// glob 模式：不含 `/` 时匹配文件名，例如 "*.pem"；含 `/` 时匹配相对工作区的路径，
+    /// 例如 ".github/workflows/**"，工作区外的文件用绝对路径匹配
+    pub pattern: String,
+    pub action: Action,
+    /// 询问或拒绝时显示的说明
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+/// 对写入一个文件的判定
+#[derive(Debug, Clone, PartialEq)]
+pub struct WriteDecision {
+    pub action: Action,
+    /// 匹配的规则，例如 "*.pem"
+    pub rule: String,
+    pub reason: Option<String>,
+}
+
+struct CompiledWriteRule {
+    rule: WriteRule,
+    matcher: GlobMatcher,
+    /// 只匹配文件名
+    name_only: bool,
+}
+
+/// 配置的写入规则；没有内置规则
+pub struct WritePolicy {
+    rules: Vec<CompiledWriteRule>,
+}
+
+impl WritePolicy {
+    /// glob 无效时报错
+    pub fn new(rules: &[WriteRule]) -> Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let pattern = rule.pattern.trim_start_matches('/');
+                let matcher = GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .with_context(|| format!("Invalid write rule: {}", rule.pattern))?
+                    .compile_matcher();
+                Ok(CompiledWriteRule {
+                    rule: rule.clone(),
+                    matcher,
+                    name_only: !rule.pattern.contains('/'),
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// 写入 `path` 的判定，取匹配的规则中最严格的；`path` 和 `workspace` 都应已展开符号链接
+    pub fn evaluate(&self, path: &Path, workspace: &Path) -> Option<WriteDecision> {
+        let relative = path.strip_prefix(workspace).unwrap_or(path);
+        let name = path.file_name().map(Path::new);
+        self.rules
+            .iter()
+            .filter(|compiled| match (compiled.name_only, name) {
+                (true, Some(name)) => compiled.matcher.is_match(name),
+                (true, None) => false,
+                (false, _) => {
+                    compiled.matcher.is_match(relative)
+                        || compiled
+                            .matcher
+                            .is_match(path.to_string_lossy().trim_start_matches('/'))
+                }
+            })
+            .max_by_key(|compiled| compiled.rule.action)
+            .map(|compiled| WriteDecision {
+                action: compiled.rule.action,
+                rule: compiled.rule.pattern.clone(),
+                reason: compiled.rule.reason.clone(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(pattern: &str, action: Action) -> CommandRule {
+        CommandRule {
+            pattern: Some(pattern.to_string()),
+            regex: None,
+            action,
+            directories: Vec::new(),
+            reason: None,
+        }
+    }
+
+    fn action(policy: &Policy, command: &str) -> Option<Action> {
+        policy
+            .evaluate(command, &std::env::current_dir().unwrap())
+            .map(|decision| decision.action)
+    }
+
+    #[test]
+    fn test_builtin_rules_match_whole_commands() {
+        let policy = Policy::new(&[]).unwrap();
+        assert_eq!(action(&policy, "git commit -m \"don't shutdown\""), None);
+        assert_eq!(action(&policy, "echo format"), None);
+        assert_eq!(action(&policy, "rm -rf ./target"), None);
+        assert_eq!(action(&policy, "rm  -rf   /"), Some(Action::Deny));
+        assert_eq!(action(&policy, "sudo rm -rf /*"), Some(Action::Deny));
+        assert_eq!(
+            action(&policy, "dd if=x.img of=/dev/sda"),
+            Some(Action::Deny)
+        );
+        assert_eq!(action(&policy, "shutdown -h now"), Some(Action::Ask));
+        assert_eq!(action(&policy, "sudo apt install jq"), Some(Action::Ask));
+    }
+
+    #[test]
+    fn test_configured_rules_take_precedence() {
+        let policy = Policy::new(&[
+            rule("cargo *", Action::Allow),
+            rule("cargo publish*", Action::Deny),
+            rule("sudo apt *", Action::Allow),
+        ])
+        .unwrap();
+        assert_eq!(action(&policy, "cargo test"), Some(Action::Allow));
+        assert_eq!(
+            action(&policy, "cargo publish --dry-run"),
+            Some(Action::Deny)
+        );
+        // 配置的规则匹配时不再使用内置规则
+        assert_eq!(action(&policy, "sudo apt install jq"), Some(Action::Allow));
+        assert_eq!(action(&policy, "ls"), None);
+
+        let decision = policy.evaluate("cargo publish", Path::new(".")).unwrap();
+        assert_eq!(decision.rule, "cargo publish*");
+
+        assert!(Policy::new(&[CommandRule {
+            pattern: None,
+            regex: Some("(".to_string()),
+            ..rule("x", Action::Deny)
+        }])
+        .is_err());
+    }
+
+    #[test]
+    fn test_rules_apply_to_each_chained_command() {
+        let policy = Policy::new(&[rule("Bash(cargo *)", Action::Allow)]).unwrap();
+        assert_eq!(
+            action(&policy, "cargo build && cargo test"),
+            Some(Action::Allow)
+        );
+        // 只有一部分被允许时交给权限模式
+        assert_eq!(action(&policy, "cargo test | tee log.txt"), None);
+
+        let decision = policy
+            .evaluate("cargo build; r\\m -rf \"/\"", Path::new("."))
+            .unwrap();
+        assert_eq!(decision.action, Action::Deny);
+        assert_eq!(decision.command, "rm -rf /");
+        assert_eq!(action(&policy, "echo $(sudo reboot)"), Some(Action::Ask));
+        assert_eq!(action(&policy, "/sbin/shutdown now"), Some(Action::Ask));
+    }
+
+    #[test]
+    fn test_rules_scoped_to_directories() {
+        let parent = tempfile::TempDir::new().unwrap();
+        let app = parent.path().join("app");
+        std::fs::create_dir_all(app.join("src")).unwrap();
+        let policy = Policy::new(&[CommandRule {
+            directories: vec![app.display().to_string()],
+            reason: Some("Deploys from this repository are done by CI".to_string()),
+            ..rule("make deploy*", Action::Deny)
+        }])
+        .unwrap();
+
+        let decision = policy.evaluate("make deploy", &app.join("src")).unwrap();
+        assert_eq!(decision.action, Action::Deny);
+        assert_eq!(
+            decision.reason.as_deref(),
+            Some("Deploys from this repository are done by CI")
+        );
+        assert_eq!(policy.evaluate("make deploy", parent.path()), None);
+
+        // 相对目录基于项目根目录，而不是执行命令的目录
+        let relative = Policy::with_root(
+            &[CommandRule {
+                directories: vec!["./app".to_string()],
+                ..rule("make deploy*", Action::Ask)
+            }],
+            parent.path(),
+        )
+        .unwrap();
+        let action = |cwd: &Path| relative.evaluate("make deploy", cwd).map(|d| d.action);
+        assert_eq!(action(&app.join("src")), Some(Action::Ask));
+        assert_eq!(action(&app), Some(Action::Ask));
+        assert_eq!(action(parent.path()), None);
+    }
+
+    #[test]
+    fn test_write_rules_match_names_and_paths() {
+        let write_rule = |pattern: &str, action| WriteRule {
+            pattern: pattern.to_string(),
+            action,
+            reason: None,
+        };
+        let policy = WritePolicy::new(&[
+            write_rule("*.pem", Action::Deny),
+            write_rule(".github/workflows/**", Action::Ask),
+            write_rule("Cargo.lock", Action::Ask),
+            write_rule("/etc/**", Action::Deny),
+        ])
+        .unwrap();
+        let root = Path::new("/work/app");
+        let action = |path: &str| {
+            policy
+                .evaluate(&root.join(path), root)
+                .map(|decision| decision.action)
+        };
+        assert_eq!(action("certs/server.pem"), Some(Action::Deny));
+        assert_eq!(action(".github/workflows/ci.yml"), Some(Action::Ask));
+        assert_eq!(action("crates/core/Cargo.lock"), Some(Action::Ask));
+        assert_eq!(action("docs/.github/workflows/ci.yml"), None);
+        assert_eq!(action("src/main.rs"), None);
+        assert_eq!(action("/etc/hosts"), Some(Action::Deny));
+
+        assert!(WritePolicy::new(&[write_rule("src/[", Action::Deny)]).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+//! 按公开价格估算的花费（美元），不计提示缓存
+
+/// --estimate 一并列出的各档模型
+pub const REFERENCE_MODELS: &[&str] = &["claude-haiku-4-5", "claude-sonnet-4-5", "claude-opus-4-5"];
+
+/// 每百万 token 的输入、输出价格；未知的模型按 Sonnet 计算
+pub fn per_million(model: &str) -> (f64, f64) {
+    if model.contains("opus-4-5") {
+        (5.0, 25.0)
+    } else if model.contains("opus") {
+        (15.0, 75.0)
+    } else if model.contains("haiku") {
+        (1.0, 5.0)
+    } else {
+        (3.0, 15.0)
+    }
+}
+
+pub fn cost_usd(model: &str, input_tokens: u64, output_tokens: u64) -> f64 {
+    let (input, output) = per_million(model);
+    (input_tokens as f64 * input + output_tokens as f64 * output) / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cost_by_model_family() {
+        assert!((cost_usd("claude-sonnet-4-5", 1_000_000, 100_000) - 4.5).abs() < 1e-9);
+        assert!((cost_usd("claude-opus-4-1-20250805", 0, 1_000_000) - 75.0).abs() < 1e-9);
+        assert_eq!(per_million("claude-opus-4-5"), (5.0, 25.0));
+        assert_eq!(per_million("claude-haiku-4-5"), (1.0, 5.0));
+        assert_eq!(per_million("some-gateway-model"), (3.0, 15.0));
+    }
+}
@@ -0,0 +1,367 @@
+//! 命名的设置配置档：`~/.claude/profiles/<name>.json` 中的设置覆盖在 settings.json 之上，
+//! 用 `--profile <name>` 按次切换模型、API 地址和密钥、权限规则和主题等；
+//! 没有指定时按 `~/.claude/profiles.json` 中的目录规则自动选择
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use globset::GlobBuilder;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::config::{self, Config, UserSettings};
+use crate::i18n::{t, tf, Msg};
+
+/// config export 写出的设置：后端、认证、权限和外观
+const PROFILE_KEYS: &[&str] = &[
+    "model",
+    "api_base_url",
+    "anthropic_api_key",
+    "anthropic_version",
+    "anthropic_beta",
+    "model_routing",
+    "fallback_model",
+    "disabled_tools",
+    "command_rules",
+    "write_rules",
+    "injection_guard",
+    "theme",
+    "output_style",
+];
+
+/// `~/.claude/profiles.json`：按目录选择配置档的规则
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectoryRules {
+    /// 按顺序匹配，第一个匹配当前目录的规则生效
+    #[serde(default)]
+    directories: Vec<DirectoryRule>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DirectoryRule {
+    /// 目录的 glob，`~` 展开为主目录，例如 `~/work/**`
+    path: String,
+    profile: String,
+}
+
+/// 本次运行使用的配置档
+static ACTIVE: Mutex<Option<String>> = Mutex::new(None);
+
+#[derive(clap::Args, Debug)]
+pub struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum ConfigCommand {
+    /// Write the current model, endpoint, auth, permission rules and theme to a profile file
+    Export {
+        file: PathBuf,
+
+        /// Include the API key (left out by default)
+        #[arg(long)]
+        include_secrets: bool,
+    },
+    /// Save a profile file under ~/.claude/profiles so --profile can use it
+    Import {
+        file: PathBuf,
+
+        /// Profile name (defaults to the file name without extension)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// List the saved profiles
+    Profiles,
+}
+
+pub fn run(args: ConfigArgs, config: &Config) -> Result<()> {
+    match args.command {
+        ConfigCommand::Export {
+            file,
+            include_secrets,
+        } => {
+            let profile = export(config, include_secrets)?;
+            std::fs::write(&file, serde_json::to_string_pretty(&profile)? + "\n")
+                .with_context(|| format!("Failed to write {}", file.display()))?;
+            println!(
+                "{}",
+                tf(Msg::ProfileExported, &[&profile.len(), &file.display()])
+            );
+        }
+        ConfigCommand::Import { file, name } => {
+            let dir = dir().context("HOME is not set")?;
+            let name = import(&dir, &file, name.as_deref())?;
+            println!("{}", tf(Msg::ProfileImported, &[&name, &name]));
+        }
+        ConfigCommand::Profiles => {
+            let names = dir().map(|dir| list(&dir)).unwrap_or_default();
+            if names.is_empty() {
+                println!("{}", t(Msg::NoProfiles));
+            }
+            let active = active();
+            for name in names {
+                match &active {
+                    Some(active) if *active == name => {
+                        println!("{} {}", name, style(t(Msg::ProfileActive)).green())
+                    }
+                    _ => println!("{}", name),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 配置档目录 `~/.claude/profiles`
+pub fn dir() -> Option<PathBuf> {
+    home().map(|home| home.join(".claude").join("profiles"))
+}
+
+/// 选择本次运行的配置档，需在 Config::load 之前调用；`name` 为 None 时按当前目录匹配规则
+pub fn select(name: Option<String>) -> Result<()> {
+    let name = match name {
+        Some(name) => Some(name),
+        None => match (home(), std::env::current_dir()) {
+            (Some(home), Ok(dir)) => for_directory(&home, &dir)?,
+            _ => None,
+        },
+    };
+    *ACTIVE.lock().unwrap() = name;
+    Ok(())
+}
+
+fn home() -> Option<PathBuf> {
+    std::env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
+
+/// `home/.claude/profiles.json` 中第一个匹配 `dir` 的规则指定的配置档
+fn for_directory(home: &Path, dir: &Path) -> Result<Option<String>> {
+    let path = home.join(".claude").join("profiles.json");
+    let rules: DirectoryRules = match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?,
+        Err(_) => return Ok(None),
+    };
+    let dir = dir.canonicalize().unwrap_or_else(|_| dir.to_path_buf());
+    let home = home.canonicalize().unwrap_or_else(|_| home.to_path_buf());
+    for rule in rules.directories {
+        let pattern = match rule.path.strip_prefix("~/") {
+            Some(rest) => format!("{}/{}", home.display(), rest),
+            None => rule.path.clone(),
+        };
+        // `~/work/**` 也匹配 ~/work 本身
+        let patterns = [Some(pattern.as_str()), pattern.strip_suffix("/**")];
+        for pattern in patterns.into_iter().flatten() {
+            let matcher = GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .with_context(|| {
+                    format!(
+                        "Invalid directory pattern in {}: {}",
+                        path.display(),
+                        rule.path
+                    )
+                })?
+                .compile_matcher();
+            if matcher.is_match(&dir) {
+                return Ok(Some(rule.profile));
+            }
+        }
+    }
+    Ok(None)
+}
+
+pub fn active() -> Option<String> {
+    ACTIVE.lock().unwrap().clone()
+}
+
+/// 读取选中的配置档；没有选择时为 None
+pub fn load_active() -> Result<Option<Map<String, Value>>> {
+    let Some(name) = active() else {
+        return Ok(None);
+    };
+    let dir = dir().context("--profile needs HOME to locate ~/.claude/profiles")?;
+    let path = dir.join(format!("{}.json", name));
+    if !path.is_file() {
+        let available = list(&dir);
+        if available.is_empty() {
+            bail!(
+                "Profile '{}' not found; save one with `config import <FILE>`",
+                name
+            );
+        }
+        bail!(
+            "Profile '{}' not found (available: {})",
+            name,
+            available.join(", ")
+        );
+    }
+    let content = std::fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read profile {}", path.display()))?;
+    let value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse profile {}", path.display()))?;
+    validate(value)
+        .map(Some)
+        .with_context(|| format!("Invalid profile {}", path.display()))
+}
+
+/// 配置档中只能有 settings.json 的键，且值的类型正确
+fn validate(value: Value) -> Result<Map<String, Value>> {
+    let Value::Object(mut profile) = value else {
+        bail!("A profile must be a JSON object of settings");
+    };
+    profile.remove("schema_version");
+    for key in profile.keys() {
+        if !UserSettings::KEYS.contains(&key.as_str()) {
+            match config::suggest_key(key) {
+                Some(suggestion) => {
+                    bail!("Unknown setting '{}' (did you mean '{}'?)", key, suggestion)
+                }
+                None => bail!("Unknown setting '{}'", key),
+            }
+        }
+    }
+    serde_json::from_value::<UserSettings>(Value::Object(profile.clone()))?;
+    Ok(profile)
+}
+
+/// 当前生效配置中与默认值不同的 PROFILE_KEYS
+fn export(config: &Config, include_secrets: bool) -> Result<Map<String, Value>> {
+    let current = serde_json::to_value(&config.user_settings)?;
+    let defaults = serde_json::to_value(UserSettings::default())?;
+    let mut profile = Map::new();
+    for key in PROFILE_KEYS {
+        let value = match *key {
+            // 来自环境变量或命令行的地址和密钥也一并导出
+            "api_base_url" => Value::String(config.api_base_url.clone()),
+            "anthropic_api_key" if !include_secrets => continue,
+            "anthropic_api_key" => Value::String(config.api_key.clone()),
+            _ => current[*key].clone(),
+        };
+        let default_url = *key == "api_base_url" && value == config::DEFAULT_API_BASE_URL;
+        if value.is_null() || value == "" || value == defaults[*key] || default_url {
+            continue;
+        }
+        profile.insert(key.to_string(), value);
+    }
+    Ok(profile)
+}
+
+/// 校验后复制到 `dir/<name>.json`，返回配置档名称
+fn import(dir: &Path, file: &Path, name: Option<&str>) -> Result<String> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => file
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .context("Cannot derive a profile name from the file name; pass --name")?,
+    };
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!(
+            "Invalid profile name '{}': use letters, digits, '-' and '_'",
+            name
+        );
+    }
+    let content = std::fs::read_to_string(file)
+        .with_context(|| format!("Failed to read {}", file.display()))?;
+    let value = serde_json::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", file.display()))?;
+    let profile = validate(value).with_context(|| format!("Invalid profile {}", file.display()))?;
+
+    std::fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create directory: {}", dir.display()))?;
+    let path = dir.join(format!("{}.json", name));
+    std::fs::write(&path, serde_json::to_string_pretty(&profile)? + "\n")
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(name)
+}
+
+/// 已保存的配置档名称，按名称排序
+fn list(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| Some(path.file_stem()?.to_string_lossy().into_owned()))
+        .collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_validate_rejects_unknown_keys_and_bad_values() {
+        let profile = validate(json!({"schema_version": 1, "model": "claude-opus-4-1"})).unwrap();
+        assert_eq!(profile.len(), 1);
+        let err = validate(json!({"modle": "x"})).unwrap_err().to_string();
+        assert!(err.contains("did you mean 'model'"), "{}", err);
+        assert!(validate(json!({"theme": 3})).is_err());
+        assert!(validate(json!(["model"])).is_err());
+    }
+
+    #[test]
+    fn test_directory_rules_pick_first_match() {
+        let home = TempDir::new().unwrap();
+        let work = home.path().join("work");
+        let repo = work.join("api");
+        std::fs::create_dir_all(&repo).unwrap();
+        assert_eq!(for_directory(home.path(), &repo).unwrap(), None);
+
+        std::fs::create_dir(home.path().join(".claude")).unwrap();
+        std::fs::write(
+            home.path().join(".claude").join("profiles.json"),
+            r#"{"directories": [
+                {"path": "~/work/api/**", "profile": "api"},
+                {"path": "~/work/**", "profile": "work-bedrock"}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            for_directory(home.path(), &repo).unwrap().as_deref(),
+            Some("api")
+        );
+        assert_eq!(
+            for_directory(home.path(), &work).unwrap().as_deref(),
+            Some("work-bedrock")
+        );
+        assert_eq!(for_directory(home.path(), home.path()).unwrap(), None);
+    }
+
+    #[test]
+    fn test_import_and_list() {
+        let dir = TempDir::new().unwrap();
+        let profiles = dir.path().join("profiles");
+        let file = dir.path().join("work-bedrock.json");
+        std::fs::write(
+            &file,
+            r#"{"api_base_url": "https://gateway.example.com/v1/messages", "theme": "dark"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(import(&profiles, &file, None).unwrap(), "work-bedrock");
+        assert_eq!(
+            import(&profiles, &file, Some("personal")).unwrap(),
+            "personal"
+        );
+        assert!(import(&profiles, &file, Some("../escape")).is_err());
+        assert_eq!(list(&profiles), ["personal", "work-bedrock"]);
+        let saved = std::fs::read_to_string(profiles.join("personal.json")).unwrap();
+        assert!(saved.contains("gateway.example.com"));
+    }
+}
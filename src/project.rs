@@ -0,0 +1,195 @@
+use std::fmt;
+use std::path::Path;
+
+/// 根据项目文件识别的项目类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectKind {
+    Rust,
+    Go,
+    Node,
+    Python,
+}
+
+impl fmt::Display for ProjectKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ProjectKind::Rust => "Rust",
+            ProjectKind::Go => "Go",
+            ProjectKind::Node => "Node.js",
+            ProjectKind::Python => "Python",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// 当前项目的类型和构建、测试、检查命令；项目没有对应命令时为 None
+#[derive(Debug, Clone, PartialEq)]
+pub struct Project {
+    pub kind: ProjectKind,
+    /// 识别依据的文件
+    pub marker: &'static str,
+    pub build: Option<String>,
+    pub test: Option<String>,
+    pub lint: Option<String>,
+}
+
+impl Project {
+    /// 按 Cargo.toml、go.mod、package.json、pyproject.toml 的顺序识别，只看 `dir` 本身
+    pub fn detect(dir: &Path) -> Option<Self> {
+        if dir.join("Cargo.toml").is_file() {
+            return Some(Self {
+                kind: ProjectKind::Rust,
+                marker: "Cargo.toml",
+                build: Some("cargo build".to_string()),
+                test: Some("cargo test".to_string()),
+                lint: Some("cargo clippy --all-targets -- -D warnings".to_string()),
+            });
+        }
+        if dir.join("go.mod").is_file() {
+            return Some(Self {
+                kind: ProjectKind::Go,
+                marker: "go.mod",
+                build: Some("go build ./...".to_string()),
+                test: Some("go test ./...".to_string()),
+                lint: Some("go vet ./...".to_string()),
+            });
+        }
+        if let Ok(package) = std::fs::read_to_string(dir.join("package.json")) {
+            let scripts = serde_json::from_str::<serde_json::Value>(&package)
+                .ok()
+                .and_then(|package| package.get("scripts").cloned())
+                .unwrap_or_default();
+            let runner = node_runner(dir);
+            // 只使用 package.json 中定义了的脚本
+            let script = |name: &str| {
+                scripts.get(name).map(|_| match name {
+                    "test" => format!("{} test", runner),
+                    _ => format!("{} run {}", runner, name),
+                })
+            };
+            return Some(Self {
+                kind: ProjectKind::Node,
+                marker: "package.json",
+                build: script("build"),
+                test: script("test"),
+                lint: script("lint"),
+            });
+        }
+        let pyproject = std::fs::read_to_string(dir.join("pyproject.toml")).ok();
+        if let Some(pyproject) = pyproject {
+            let ruff = pyproject.contains("[tool.ruff") || dir.join("ruff.toml").is_file();
+            return Some(Self {
+                kind: ProjectKind::Python,
+                marker: "pyproject.toml",
+                build: None,
+                test: Some("python3 -m pytest".to_string()),
+                lint: ruff.then(|| "ruff check .".to_string()),
+            });
+        }
+        None
+    }
+
+    /// 附加到系统提示词的说明，让模型使用这个项目惯用的命令
+    pub fn system_prompt(&self) -> String {
+        let commands: Vec<String> = [
+            ("build", &self.build),
+            ("run the tests", &self.test),
+            ("lint", &self.lint),
+        ]
+        .into_iter()
+        .filter_map(|(action, command)| {
+            command
+                .as_ref()
+                .map(|command| format!("{} with `{}`", action, command))
+        })
+        .collect();
+        let mut prompt = format!(
+            "This is a {} project ({} in the working directory).",
+            self.kind, self.marker
+        );
+        if !commands.is_empty() {
+            prompt.push_str(&format!(
+                " Use the project's usual commands: {}. Run them to check your changes before \
+                 saying the work is done.",
+                commands.join(", ")
+            ));
+        }
+        prompt
+    }
+}
+
+/// 按锁文件选择包管理器
+fn node_runner(dir: &Path) -> &'static str {
+    if dir.join("pnpm-lock.yaml").is_file() {
+        "pnpm"
+    } else if dir.join("yarn.lock").is_file() {
+        "yarn"
+    } else if dir.join("bun.lockb").is_file() || dir.join("bun.lock").is_file() {
+        "bun"
+    } else {
+        "npm"
+    }
+}
+
+/// 当前目录的项目
+pub fn current() -> Option<Project> {
+    Project::detect(Path::new("."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_rust_and_go() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(Project::detect(dir.path()), None);
+
+        std::fs::write(dir.path().join("go.mod"), "module example.com/app\n").unwrap();
+        let project = Project::detect(dir.path()).unwrap();
+        assert_eq!(project.kind, ProjectKind::Go);
+        assert_eq!(project.lint.as_deref(), Some("go vet ./..."));
+
+        // Cargo.toml 优先
+        std::fs::write(dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+        let project = Project::detect(dir.path()).unwrap();
+        assert_eq!(project.kind, ProjectKind::Rust);
+        assert!(project
+            .system_prompt()
+            .contains("run the tests with `cargo test`"));
+    }
+
+    #[test]
+    fn test_node_commands_follow_scripts_and_lockfile() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("package.json"),
+            r#"{"scripts": {"build": "tsc", "test": "vitest"}}"#,
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("pnpm-lock.yaml"), "").unwrap();
+        let project = Project::detect(dir.path()).unwrap();
+        assert_eq!(project.build.as_deref(), Some("pnpm run build"));
+        assert_eq!(project.test.as_deref(), Some("pnpm test"));
+        assert_eq!(project.lint, None);
+        assert!(!project.system_prompt().contains("lint"));
+    }
+
+    #[test]
+    fn test_python_lint_needs_ruff_configuration() {
+        let dir = TempDir::new().unwrap();
+        std::fs::write(
+            dir.path().join("pyproject.toml"),
+            "[project]\nname = \"app\"\n",
+        )
+        .unwrap();
+        assert_eq!(Project::detect(dir.path()).unwrap().lint, None);
+
+        std::fs::write(dir.path().join("ruff.toml"), "").unwrap();
+        let project = Project::detect(dir.path()).unwrap();
+        assert_eq!(project.kind, ProjectKind::Python);
+        assert_eq!(project.lint.as_deref(), Some("ruff check ."));
+        assert_eq!(project.build, None);
+    }
+}
@@ -0,0 +1,106 @@
+//! 在伪终端中运行命令：程序的标准输出和标准错误是终端，颜色、进度条和 isatty 判断
+//! 与在终端中运行时一致；输出仍然全部收集起来交给模型
+//!
+//! 标准输入不接到伪终端上，读取输入的程序仍然立即读到 EOF，不会卡住。
+
+use std::fs::File;
+use std::io::{self, Read};
+
+/// 伪终端的列数，足够宽以免输出被折行
+#[cfg(unix)]
+const COLUMNS: u16 = 200;
+#[cfg(unix)]
+const ROWS: u16 = 50;
+
+/// 一对伪终端：父进程读 master，子进程写 slave
+pub struct Pty {
+    pub master: File,
+    pub slave: File,
+}
+
+#[cfg(unix)]
+// macOS 上 openpty 的 winsize 参数是 *mut
+#[allow(clippy::unnecessary_mut_passed)]
+pub fn open() -> io::Result<Pty> {
+    use std::os::unix::io::FromRawFd;
+
+    let mut master = 0;
+    let mut slave = 0;
+    let mut size = libc::winsize {
+        ws_row: ROWS,
+        ws_col: COLUMNS,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    // SAFETY: openpty 只写入传入的两个 fd；成功后它们归下面的 File 所有
+    unsafe {
+        if libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            &mut size,
+        ) != 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // master 不能被子进程继承，否则命令退出后读取端收不到结束
+        libc::fcntl(master, libc::F_SETFD, libc::FD_CLOEXEC);
+        libc::fcntl(slave, libc::F_SETFD, libc::FD_CLOEXEC);
+        Ok(Pty {
+            master: File::from_raw_fd(master),
+            slave: File::from_raw_fd(slave),
+        })
+    }
+}
+
+#[cfg(not(unix))]
+pub fn open() -> io::Result<Pty> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "pseudo-terminals are only supported on Unix",
+    ))
+}
+
+/// 读取 master 直到所有 slave 端关闭；Linux 上这时 read 返回 EIO 而不是 0
+pub fn read_all(mut master: File) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut buf = [0u8; 8192];
+    loop {
+        match master.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => output.extend_from_slice(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    output
+}
+
+/// 把终端输出整理成纯文本：去掉 ANSI 转义，`\r\n` 换成 `\n`，
+/// 用 `\r` 重绘的进度条只保留最后一次的内容
+pub fn clean(raw: &[u8]) -> String {
+    let text = String::from_utf8_lossy(raw);
+    console::strip_ansi_codes(&text)
+        .split('\n')
+        .map(|line| {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            line.rsplit('\r').next().unwrap_or(line)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_terminal_output() {
+        assert_eq!(
+            clean(b"\x1b[32mCompiling\x1b[0m foo\r\n 10%\r 50%\r100%\r\ndone\r\n"),
+            "Compiling foo\n100%\ndone\n"
+        );
+        assert_eq!(clean(b"plain"), "plain");
+    }
+}
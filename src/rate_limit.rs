@@ -0,0 +1,191 @@
+//! 读取 anthropic-ratelimit-* 响应头，记录剩余的请求数和 token 数；
+//! 额度快用完时在发送下一个请求前等到重置时间，而不是等 429 之后再花时间重试
+
+use reqwest::header::HeaderMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::clock;
+
+/// token 余量低于上限的这个比例时视为快用完
+const LOW_TOKEN_FRACTION: f64 = 0.05;
+/// 单次等待的上限，重置时间异常时不会一直卡住
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+/// 一类额度：上限、剩余和完全恢复的时间
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bucket {
+    pub limit: u64,
+    pub remaining: u64,
+    pub reset: Option<SystemTime>,
+}
+
+impl Bucket {
+    fn from_headers(headers: &HeaderMap, name: &str) -> Option<Self> {
+        let header = |field: &str| {
+            headers
+                .get(format!("anthropic-ratelimit-{}-{}", name, field))
+                .and_then(|value| value.to_str().ok())
+        };
+        Some(Self {
+            limit: header("limit")?.trim().parse().ok()?,
+            remaining: header("remaining")?.trim().parse().ok()?,
+            reset: header("reset").and_then(|reset| humantime::parse_rfc3339_weak(reset).ok()),
+        })
+    }
+
+    fn exhausted(&self, low: u64) -> bool {
+        self.limit > 0 && self.remaining <= low
+    }
+}
+
+/// 最近一次响应报告的各类额度，服务端没有返回的为 None
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimits {
+    pub requests: Option<Bucket>,
+    pub tokens: Option<Bucket>,
+    pub input_tokens: Option<Bucket>,
+    pub output_tokens: Option<Bucket>,
+}
+
+impl RateLimits {
+    /// 响应中没有任何限流头时返回 None
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let limits = Self {
+            requests: Bucket::from_headers(headers, "requests"),
+            tokens: Bucket::from_headers(headers, "tokens"),
+            input_tokens: Bucket::from_headers(headers, "input-tokens"),
+            output_tokens: Bucket::from_headers(headers, "output-tokens"),
+        };
+        (limits != Self::default()).then_some(limits)
+    }
+
+    /// 下一个请求前应等待的时间：请求数用完，或任一类 token 低于上限的 5% 时等到它重置
+    pub fn delay(&self, now: SystemTime) -> Option<Duration> {
+        let token_buckets = [&self.tokens, &self.input_tokens, &self.output_tokens];
+        let exhausted = self
+            .requests
+            .iter()
+            .filter(|bucket| bucket.exhausted(0))
+            .chain(token_buckets.into_iter().flatten().filter(|bucket| {
+                bucket.exhausted((bucket.limit as f64 * LOW_TOKEN_FRACTION) as u64)
+            }));
+        exhausted
+            .filter_map(|bucket| bucket.reset?.duration_since(now).ok())
+            .max()
+            .map(|wait| wait.min(MAX_WAIT))
+            .filter(|wait| !wait.is_zero())
+    }
+
+    /// 供 /status 显示的摘要，例如 "48/50 requests, 38000/40000 tokens"
+    pub fn describe(&self) -> String {
+        [
+            (&self.requests, "requests"),
+            (&self.tokens, "tokens"),
+            (&self.input_tokens, "input tokens"),
+            (&self.output_tokens, "output tokens"),
+        ]
+        .into_iter()
+        .filter_map(|(bucket, label)| {
+            let bucket = bucket.as_ref()?;
+            Some(format!("{}/{} {}", bucket.remaining, bucket.limit, label))
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
+
+/// 在请求之间共享的限流状态
+#[derive(Default)]
+pub struct Pacer {
+    latest: Mutex<Option<RateLimits>>,
+}
+
+impl Pacer {
+    /// 记录响应（包括 429 响应）中的限流头
+    pub fn update(&self, headers: &HeaderMap) {
+        if let Some(limits) = RateLimits::from_headers(headers) {
+            *self.latest.lock().unwrap() = Some(limits);
+        }
+    }
+
+    pub fn latest(&self) -> Option<RateLimits> {
+        self.latest.lock().unwrap().clone()
+    }
+
+    /// 按最近的额度计算下一个请求前需要等待的时间
+    pub fn delay(&self) -> Option<Duration> {
+        self.latest.lock().unwrap().as_ref()?.delay(clock::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(
+                reqwest::header::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                value.parse().unwrap(),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let limits = RateLimits::from_headers(&headers(&[
+            ("anthropic-ratelimit-requests-limit", "50"),
+            ("anthropic-ratelimit-requests-remaining", "48"),
+            ("anthropic-ratelimit-requests-reset", "2023-11-14T22:13:30Z"),
+            ("anthropic-ratelimit-tokens-limit", "40000"),
+            ("anthropic-ratelimit-tokens-remaining", "38000"),
+        ]))
+        .unwrap();
+        let requests = limits.requests.as_ref().unwrap();
+        assert_eq!(requests.remaining, 48);
+        assert_eq!(
+            requests.reset,
+            Some(UNIX_EPOCH + Duration::from_secs(1_700_000_010))
+        );
+        assert_eq!(limits.tokens.as_ref().unwrap().reset, None);
+        assert_eq!(limits.describe(), "48/50 requests, 38000/40000 tokens");
+        assert!(RateLimits::from_headers(&headers(&[("retry-after", "5")])).is_none());
+    }
+
+    #[test]
+    fn test_delay_when_nearly_exhausted() {
+        let now = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let bucket = |limit, remaining, reset_in: u64| {
+            Some(Bucket {
+                limit,
+                remaining,
+                reset: Some(now + Duration::from_secs(reset_in)),
+            })
+        };
+
+        let plenty = RateLimits {
+            requests: bucket(50, 10, 30),
+            tokens: bucket(40_000, 10_000, 30),
+            ..Default::default()
+        };
+        assert_eq!(plenty.delay(now), None);
+
+        let low_tokens = RateLimits {
+            tokens: bucket(40_000, 1_500, 12),
+            ..plenty.clone()
+        };
+        assert_eq!(low_tokens.delay(now), Some(Duration::from_secs(12)));
+
+        let no_requests = RateLimits {
+            requests: bucket(50, 0, 600),
+            ..plenty
+        };
+        assert_eq!(no_requests.delay(now), Some(MAX_WAIT));
+        // 重置时间已过时不再等待
+        assert_eq!(no_requests.delay(now + Duration::from_secs(601)), None);
+    }
+}
@@ -0,0 +1,500 @@
+//! history replay：在终端重新展示保存的对话，包括每轮的时间和工具的输入输出，用于排查出错的运行
+
+use anyhow::{bail, Context, Result};
+use console::style;
+use serde_json::Value;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::history::{self, JournalTail, TimedMessage};
+use crate::i18n::{t, tf, Msg};
+use crate::import;
+use crate::sanitize::sanitize;
+use crate::ui;
+
+/// 工具结果最多显示的行数
+const RESULT_LINES: usize = 20;
+/// follow 检查会话日志的间隔
+const FOLLOW_INTERVAL: Duration = Duration::from_millis(300);
+
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    #[command(subcommand)]
+    command: HistoryCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum HistoryCommand {
+    /// Re-render a stored conversation with timing and tool inputs/outputs
+    Replay {
+        /// History file path, or part of its name under .claude/history
+        id: String,
+
+        /// Wait for Enter before each turn
+        #[arg(long)]
+        step: bool,
+    },
+    /// Convert a Claude Code transcript or a claude.ai export so it can be continued with --resume
+    Import {
+        /// A Claude Code session (.jsonl) or claude.ai conversations.json
+        file: PathBuf,
+
+        /// Only import conversations whose title contains this text
+        #[arg(long)]
+        title: Option<String>,
+    },
+    /// Sum the token usage of stored conversations by their --tag values
+    Usage {
+        /// Group by this tag (default: by the whole set of tags)
+        #[arg(long, value_name = "KEY")]
+        by: Option<String>,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FollowArgs {
+    /// Session to follow: the id or file name of a .claude/history/conversation_<id>.jsonl
+    /// journal (default: the most recently written one)
+    session: Option<String>,
+}
+
+/// 一轮对话：从用户的一条消息开始，到下一条用户消息之前
+struct Turn<'a> {
+    messages: &'a [TimedMessage],
+}
+
+impl Turn<'_> {
+    fn started_at(&self) -> Option<u64> {
+        self.messages.iter().find_map(|message| message.at)
+    }
+
+    /// 这一轮第一次和最后一次写入之间的毫秒数
+    fn duration(&self) -> Option<u64> {
+        let last = self.messages.iter().rev().find_map(|message| message.at)?;
+        Some(last.saturating_sub(self.started_at()?))
+    }
+}
+
+pub fn run(args: HistoryArgs) -> Result<()> {
+    let dir = std::env::current_dir()?.join(".claude").join("history");
+    match args.command {
+        HistoryCommand::Replay { id, step } => replay(&dir, &id, step),
+        HistoryCommand::Import { file, title } => import(&dir, &file, title.as_deref()),
+        HistoryCommand::Usage { by } => usage(&dir, by.as_deref()),
+    }
+}
+
+/// 只读地跟随另一个终端中正在进行的会话，每轮写入会话日志后显示新的消息，会话结束时退出
+pub fn follow(args: FollowArgs) -> Result<()> {
+    let dir = std::env::current_dir()?.join(".claude").join("history");
+    let path = match &args.session {
+        Some(id) => resolve(&dir, id)?,
+        None => latest_journal(&dir)?,
+    };
+    if path.extension().is_none_or(|ext| ext != "jsonl") {
+        bail!(
+            "{} is a finished conversation, not a live session; use `history replay` to view it",
+            path.display()
+        );
+    }
+
+    let mut tail = JournalTail::new(path.clone());
+    let mut shown: Vec<Value> = Vec::new();
+    let mut turn = 0;
+    tail.poll()?;
+    let (model, created_at) = tail
+        .metadata
+        .as_ref()
+        .map(|metadata| (metadata.model.clone(), metadata.created_at))
+        .with_context(|| format!("{} has no session metadata yet", path.display()))?;
+    println!(
+        "{}",
+        style(tf(Msg::FollowHeader, &[&path.display(), &model])).bold()
+    );
+    loop {
+        // 第一次读取的消息也在这里显示
+        let same = shown
+            .iter()
+            .zip(&tail.messages)
+            .take_while(|(old, new)| *old == &new.message)
+            .count();
+        if same < shown.len() {
+            // 压缩、/retry 或切换分支改写了之前的消息
+            println!("\n{}", style(t(Msg::FollowRewritten)).dim());
+            turn = count_turns(&shown[..same]);
+        }
+        for (index, message) in tail.messages.iter().enumerate().skip(same) {
+            if index == 0 || starts_turn(&message.message) {
+                turn += 1;
+                print_turn_header(turn, message.at, None, created_at * 1000);
+            }
+            print_message(&message.message);
+        }
+        shown = tail
+            .messages
+            .iter()
+            .map(|message| message.message.clone())
+            .collect();
+
+        if tail.finished {
+            println!("\n{}", style(t(Msg::FollowEnded)).dim());
+            return Ok(());
+        }
+        while !tail.poll()? && !tail.finished {
+            std::thread::sleep(FOLLOW_INTERVAL);
+        }
+    }
+}
+
+/// 最近写入的会话日志
+fn latest_journal(dir: &Path) -> Result<PathBuf> {
+    std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read history directory: {}", dir.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "jsonl"))
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path())
+        .with_context(|| {
+            format!(
+                "No session journal in {}; sessions are only journaled with auto_save on",
+                dir.display()
+            )
+        })
+}
+
+fn count_turns(messages: &[Value]) -> usize {
+    messages
+        .iter()
+        .enumerate()
+        .filter(|(index, message)| *index == 0 || starts_turn(message))
+        .count()
+}
+
+fn usage(dir: &Path, by: Option<&str>) -> Result<()> {
+    let (groups, skipped) = history::usage_by_tag(dir, by)?;
+    let untagged = t(Msg::UsageUntagged);
+    let label = |group: &history::UsageGroup| group.tag.clone().unwrap_or(untagged.to_string());
+    let width = groups
+        .iter()
+        .map(|group| console::measure_text_width(&label(group)))
+        .chain([console::measure_text_width(by.unwrap_or(t(Msg::UsageTags)))])
+        .max()
+        .unwrap_or(0);
+    println!(
+        "{}",
+        style(format!(
+            "{} {:>8} {:>14} {:>14}",
+            console::pad_str(
+                by.unwrap_or(t(Msg::UsageTags)),
+                width,
+                console::Alignment::Left,
+                None
+            ),
+            t(Msg::UsageSessions),
+            t(Msg::UsageInputTokens),
+            t(Msg::UsageOutputTokens)
+        ))
+        .bold()
+    );
+    for group in &groups {
+        println!(
+            "{} {:>8} {:>14} {:>14}",
+            console::pad_str(&label(group), width, console::Alignment::Left, None),
+            group.sessions,
+            group.usage.input_tokens,
+            group.usage.output_tokens
+        );
+    }
+    if skipped > 0 {
+        println!("{}", style(tf(Msg::UsageSkipped, &[&skipped])).dim());
+    }
+    Ok(())
+}
+
+fn import(dir: &Path, file: &Path, title: Option<&str>) -> Result<()> {
+    let mut conversations = import::read(file)?;
+    if let Some(title) = title {
+        let wanted = title.to_lowercase();
+        conversations.retain(|conversation| {
+            conversation
+                .title
+                .as_ref()
+                .is_some_and(|name| name.to_lowercase().contains(&wanted))
+        });
+        if conversations.is_empty() {
+            bail!(
+                "No conversation in {} has a title matching '{}'",
+                file.display(),
+                title
+            );
+        }
+    }
+    for conversation in &conversations {
+        let path = history::save_imported(
+            dir,
+            &conversation.messages,
+            &conversation.model,
+            conversation.created_at,
+        )?;
+        println!(
+            "{}",
+            tf(
+                Msg::ConversationImported,
+                &[
+                    &conversation.title.as_deref().unwrap_or("-"),
+                    &conversation.messages.len(),
+                    &path.display()
+                ]
+            )
+        );
+    }
+    println!("{}", style(t(Msg::ImportResumeHint)).dim());
+    Ok(())
+}
+
+fn replay(dir: &Path, id: &str, step: bool) -> Result<()> {
+    let path = resolve(dir, id)?;
+    let (metadata, messages) = history::load_timed(&path)?;
+
+    println!(
+        "{}",
+        style(tf(
+            Msg::ReplayHeader,
+            &[&path.display(), &metadata.model, &messages.len()]
+        ))
+        .bold()
+    );
+    let turns = turns(&messages);
+    let stdin = io::stdin();
+    for (index, turn) in turns.iter().enumerate() {
+        if step && index > 0 {
+            print!("{} ", style(t(Msg::ReplayNext)).dim());
+            io::stdout().flush()?;
+            let mut input = String::new();
+            if stdin.lock().read_line(&mut input)? == 0 || input.trim() == "q" {
+                break;
+            }
+        }
+        print_turn(index + 1, turn, metadata.created_at * 1000);
+    }
+    Ok(())
+}
+
+/// `id` 是现有文件时直接使用，否则在历史目录中查找文件名包含它的唯一一个文件
+fn resolve(dir: &Path, id: &str) -> Result<PathBuf> {
+    let path = Path::new(id);
+    if path.is_file() {
+        return Ok(path.to_path_buf());
+    }
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read history directory: {}", dir.display()))?;
+    let mut matches: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .is_some_and(|name| name.to_string_lossy().contains(id))
+        })
+        .collect();
+    matches.sort();
+    match matches.len() {
+        0 => bail!("No conversation matching '{}' in {}", id, dir.display()),
+        1 => Ok(matches.remove(0)),
+        _ => bail!(
+            "'{}' matches several conversations: {}",
+            id,
+            matches
+                .iter()
+                .filter_map(|path| path.file_name())
+                .map(|name| name.to_string_lossy())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// 只包含工具结果的用户消息属于上一轮
+fn starts_turn(message: &Value) -> bool {
+    message["role"] == "user"
+        && match &message["content"] {
+            Value::Array(blocks) => blocks.iter().any(|block| block["type"] != "tool_result"),
+            _ => true,
+        }
+}
+
+fn turns(messages: &[TimedMessage]) -> Vec<Turn<'_>> {
+    let mut turns = Vec::new();
+    let mut start = 0;
+    for index in 1..=messages.len() {
+        if index == messages.len() || starts_turn(&messages[index].message) {
+            if index > start {
+                turns.push(Turn {
+                    messages: &messages[start..index],
+                });
+            }
+            start = index;
+        }
+    }
+    turns
+}
+
+fn format_millis(millis: u64) -> String {
+    let secs = millis / 1000;
+    match secs {
+        0..=59 => format!("{:.1}s", millis as f64 / 1000.0),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn print_turn(number: usize, turn: &Turn, created_at: u64) {
+    print_turn_header(number, turn.started_at(), turn.duration(), created_at);
+    for message in turn.messages {
+        print_message(&message.message);
+    }
+}
+
+fn print_turn_header(
+    number: usize,
+    started_at: Option<u64>,
+    duration: Option<u64>,
+    created_at: u64,
+) {
+    let mut header = tf(Msg::ReplayTurn, &[&number]);
+    if let Some(started) = started_at {
+        header.push_str(&format!(
+            " +{}",
+            format_millis(started.saturating_sub(created_at))
+        ));
+    }
+    if let Some(duration) = duration.filter(|duration| *duration > 0) {
+        header.push_str(&format!(" ({})", format_millis(duration)));
+    }
+    println!("\n{}", style(format!("── {} ──", header)).cyan().bold());
+}
+
+fn print_message(message: &Value) {
+    let assistant = message["role"] == "assistant";
+    let blocks = match &message["content"] {
+        Value::String(text) => vec![serde_json::json!({ "type": "text", "text": text })],
+        Value::Array(blocks) => blocks.clone(),
+        _ => Vec::new(),
+    };
+    for block in &blocks {
+        match block["type"].as_str().unwrap_or_default() {
+            "text" if assistant => {
+                ui::print_claude_heading();
+                println!("{}", sanitize(block["text"].as_str().unwrap_or_default()));
+            }
+            "text" => println!(
+                "\n{} {}",
+                style(t(Msg::ReplayUser)).yellow(),
+                sanitize(block["text"].as_str().unwrap_or_default())
+            ),
+            "tool_use" => {
+                ui::print_tool_heading(block["name"].as_str().unwrap_or_default());
+                let input = serde_json::to_string_pretty(&block["input"]).unwrap_or_default();
+                println!("{}", style(input).dim());
+            }
+            "tool_result" => {
+                let label = if block["is_error"] == true {
+                    style(t(Msg::ReplayToolError)).red()
+                } else {
+                    style(t(Msg::ReplayToolResult)).cyan()
+                };
+                println!("{}", label);
+                println!(
+                    "{}",
+                    truncate_lines(&sanitize(&result_text(&block["content"])))
+                );
+            }
+            "thinking" | "redacted_thinking" => {}
+            other => println!("{}", style(format!("[{}]", other)).dim()),
+        }
+    }
+}
+
+/// 工具结果可以是字符串或内容块数组
+fn result_text(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .map(|block| match block["text"].as_str() {
+                Some(text) => text.to_string(),
+                None => format!("[{}]", block["type"].as_str().unwrap_or_default()),
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn truncate_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    if lines.len() <= RESULT_LINES {
+        return text.to_string();
+    }
+    format!(
+        "{}\n{}",
+        lines[..RESULT_LINES].join("\n"),
+        tf(Msg::ReplayMoreLines, &[&(lines.len() - RESULT_LINES)])
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    fn timed(at: u64, message: Value) -> TimedMessage {
+        TimedMessage {
+            at: Some(at),
+            message,
+        }
+    }
+
+    #[test]
+    fn test_tool_results_stay_in_their_turn() {
+        let messages = vec![
+            timed(1_000, json!({"role": "user", "content": "list files"})),
+            timed(
+                3_000,
+                json!({"role": "assistant", "content": [{"type": "tool_use", "id": "t1", "name": "list_directory", "input": {}}]}),
+            ),
+            timed(
+                4_000,
+                json!({"role": "user", "content": [{"type": "tool_result", "tool_use_id": "t1", "content": "a.rs"}]}),
+            ),
+            timed(6_500, json!({"role": "assistant", "content": "a.rs"})),
+            timed(9_000, json!({"role": "user", "content": "thanks"})),
+        ];
+        let turns = turns(&messages);
+        assert_eq!(turns.len(), 2);
+        assert_eq!(turns[0].messages.len(), 4);
+        assert_eq!(turns[0].started_at(), Some(1_000));
+        assert_eq!(turns[0].duration(), Some(5_500));
+        assert_eq!(format_millis(5_500), "5.5s");
+        assert_eq!(format_millis(125_000), "2m05s");
+    }
+
+    #[test]
+    fn test_resolve_by_path_or_unique_name() {
+        let dir = TempDir::new().unwrap();
+        for name in [
+            "conversation_1700000001.jsonl",
+            "conversation_1700000002.json",
+        ] {
+            std::fs::write(dir.path().join(name), "").unwrap();
+        }
+        let found = resolve(dir.path(), "0001").unwrap();
+        assert!(found.ends_with("conversation_1700000001.jsonl"));
+        assert_eq!(resolve(dir.path(), found.to_str().unwrap()).unwrap(), found);
+        assert!(resolve(dir.path(), "conversation_17")
+            .unwrap_err()
+            .to_string()
+            .contains("several"));
+        assert!(resolve(dir.path(), "missing").is_err());
+    }
+}
@@ -0,0 +1,184 @@
+//! --prompt 模式的本地响应缓存：温度为 0 的相同请求直接返回保存的回答，不再调用 API
+//!
+//! 缓存键是请求体（模型、系统提示词、消息、工具、温度）的哈希，
+//! 缓存文件保存在 `.claude/cache/responses/<key>.json`
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tracing::warn;
+
+use crate::clock;
+
+/// 一条缓存的回答
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    created_at: u64,
+    model: String,
+    response: Value,
+}
+
+/// 缓存命中的回答
+#[derive(Debug)]
+pub struct Hit {
+    pub response: Value,
+    /// 回答保存了多久
+    pub age: Duration,
+}
+
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// 当前项目的缓存目录 `.claude/cache/responses`
+    pub fn in_project(ttl: Duration) -> std::io::Result<Self> {
+        let dir = std::env::current_dir()?
+            .join(".claude")
+            .join("cache")
+            .join("responses");
+        Ok(Self::new(dir, ttl))
+    }
+
+    /// 只有温度明确为 0 的请求才缓存，其他温度每次的回答本来就不同
+    pub fn accepts(temperature: Option<f64>) -> bool {
+        temperature == Some(0.0)
+    }
+
+    /// 未过期的缓存回答；过期的文件顺便删除
+    pub fn get(&self, key: &str) -> Option<Hit> {
+        let path = self.path(key);
+        let text = std::fs::read_to_string(&path).ok()?;
+        let entry: Entry = serde_json::from_str(&text).ok()?;
+        let age = Duration::from_secs(clock::unix_secs().saturating_sub(entry.created_at));
+        if age > self.ttl {
+            let _ = std::fs::remove_file(&path);
+            return None;
+        }
+        Some(Hit {
+            response: entry.response,
+            age,
+        })
+    }
+
+    /// 保存回答；写入失败只记录警告，不影响本次请求
+    pub fn put(&self, key: &str, model: &str, response: &Value) {
+        let entry = Entry {
+            created_at: clock::unix_secs(),
+            model: model.to_string(),
+            response: response.clone(),
+        };
+        let result = std::fs::create_dir_all(&self.dir).and_then(|_| {
+            // 先写临时文件再改名，并发的调用不会读到写了一半的文件
+            let tmp = self
+                .dir
+                .join(format!("{}.json.{}.tmp", key, std::process::id()));
+            std::fs::write(&tmp, serde_json::to_vec(&entry)?)?;
+            std::fs::rename(&tmp, self.path(key))
+        });
+        if let Err(e) = result {
+            warn!(
+                "Failed to write response cache in {}: {}",
+                self.dir.display(),
+                e
+            );
+        }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+/// 请求体的缓存键
+///
+/// 先递归地按键排序请求体：serde_json 开启 preserve_order 时按插入顺序输出对象，
+/// 规范化后字段顺序不同的相同请求也得到相同的文本；
+/// 使用 FNV-1a 而不是 DefaultHasher，后者的结果在不同 Rust 版本间可能变化
+pub fn key(request: &Value) -> String {
+    let text = canonical(request).to_string();
+    let mut low: u64 = 0xcbf29ce484222325;
+    // 第二个哈希使用不同的初值，128 位的键几乎不会冲突
+    let mut high: u64 = 0x84222325cbf29ce4;
+    for byte in text.bytes() {
+        low = (low ^ byte as u64).wrapping_mul(0x100000001b3);
+        high = (high ^ byte as u64).wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}{:016x}", high, low)
+}
+
+/// 递归地按键排序对象
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by(|a, b| a.0.cmp(b.0));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key.clone(), canonical(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => Value::Array(items.iter().map(canonical).collect()),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_key_depends_on_whole_request() {
+        let request = json!({"model": "claude-sonnet-4-5", "temperature": 0.0, "messages": [
+            {"role": "user", "content": "Document src/lib.rs"}]});
+        let reordered = json!({"messages": [{"content": "Document src/lib.rs", "role": "user"}],
+            "temperature": 0.0, "model": "claude-sonnet-4-5"});
+        assert_eq!(key(&request), key(&reordered));
+        assert_eq!(key(&request).len(), 32);
+
+        let mut other_model = request.clone();
+        other_model["model"] = json!("claude-haiku-4-5");
+        assert_ne!(key(&request), key(&other_model));
+
+        assert!(ResponseCache::accepts(Some(0.0)));
+        assert!(!ResponseCache::accepts(Some(0.2)));
+        assert!(!ResponseCache::accepts(None));
+    }
+
+    #[test]
+    fn test_entries_expire_after_ttl() {
+        let dir = TempDir::new().unwrap();
+        let cache = ResponseCache::new(dir.path().join("responses"), Duration::from_secs(60));
+        let response = json!({"content": [{"type": "text", "text": "Done."}]});
+        assert!(cache.get("abc").is_none());
+
+        let at = std::time::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let _clock = clock::set_for_thread(std::sync::Arc::new(clock::FixedClock(at)));
+        cache.put("abc", "claude-sonnet-4-5", &response);
+
+        let later = at + Duration::from_secs(30);
+        let _clock = clock::set_for_thread(std::sync::Arc::new(clock::FixedClock(later)));
+        let hit = cache.get("abc").unwrap();
+        assert_eq!(hit.response, response);
+        assert_eq!(hit.age, Duration::from_secs(30));
+
+        let expired = at + Duration::from_secs(61);
+        let _clock = clock::set_for_thread(std::sync::Arc::new(clock::FixedClock(expired)));
+        assert!(cache.get("abc").is_none());
+        assert!(!cache.path("abc").exists());
+    }
+}
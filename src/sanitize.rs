@@ -0,0 +1,120 @@
+//! 去掉工具结果和要打印的文本中的终端控制序列
+//!
+//! 文件内容或命令输出中的 ANSI 转义可以改写终端标题、写入剪贴板（OSC 52）、伪造超链接，
+//! 或用回车和光标移动盖住前面的内容。CSI、OSC 等序列整段去掉；其余控制字符换成可见的
+//! 符号（例如 `␇`），模型和用户仍能看出原文中有它们。
+
+use std::borrow::Cow;
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// 会让显示顺序与实际内容不一致的双向文本控制字符
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+fn needs_sanitizing(c: char) -> bool {
+    (c.is_control() && c != '\n' && c != '\t') || is_bidi_control(c)
+}
+
+/// 没有需要处理的字符时原样返回
+pub fn sanitize(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(needs_sanitizing) {
+        return Cow::Borrowed(text);
+    }
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' | '\t' => output.push(c),
+            // 单独的回车会让后面的内容盖住这一行，换成换行
+            '\r' => {
+                if chars.peek() != Some(&'\n') {
+                    output.push('\n');
+                }
+            }
+            '\x1b' => skip_escape(&mut chars),
+            '\u{9b}' => skip_csi(&mut chars),
+            // 8 位的 DCS、SOS、OSC、PM、APC
+            '\u{90}' | '\u{98}' | '\u{9d}' | '\u{9e}' | '\u{9f}' => skip_string(&mut chars),
+            '\0'..='\x1f' => output.push(char::from_u32(0x2400 + c as u32).unwrap()),
+            '\x7f' => output.push('\u{2421}'),
+            c if c.is_control() => output.push('\u{FFFD}'),
+            c if is_bidi_control(c) => output.push_str(&format!("<U+{:04X}>", c as u32)),
+            c => output.push(c),
+        }
+    }
+    Cow::Owned(output)
+}
+
+/// ESC 之后的部分
+fn skip_escape(chars: &mut Peekable<Chars>) {
+    match chars.peek() {
+        Some('[') => {
+            chars.next();
+            skip_csi(chars);
+        }
+        Some(']' | 'P' | 'X' | '^' | '_') => {
+            chars.next();
+            skip_string(chars);
+        }
+        Some(' '..='/') => {
+            while chars.next_if(|c| (' '..='/').contains(c)).is_some() {}
+            chars.next_if(|c| !c.is_control());
+        }
+        Some(c) if !c.is_control() => {
+            chars.next();
+        }
+        _ => {}
+    }
+}
+
+/// 参数和中间字节，然后是一个结束字节
+fn skip_csi(chars: &mut Peekable<Chars>) {
+    while chars.next_if(|c| (' '..='?').contains(c)).is_some() {}
+    chars.next_if(|c| ('@'..='~').contains(c));
+}
+
+/// 到 BEL 或 ST 为止的字符串；没有结束符时到行尾为止，不吞掉后面的内容
+fn skip_string(chars: &mut Peekable<Chars>) {
+    while let Some(c) = chars.next_if(|&c| c != '\n') {
+        match c {
+            '\x07' | '\u{9c}' => break,
+            '\x1b' if chars.peek() == Some(&'\\') => {
+                chars.next();
+                break;
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_escape_sequences() {
+        assert!(matches!(sanitize("plain\ttext\n"), Cow::Borrowed(_)));
+        assert_eq!(sanitize("\x1b[1;31merror\x1b[0m: x"), "error: x");
+        // 修改标题、写剪贴板和超链接
+        assert_eq!(sanitize("a\x1b]0;pwned\x07b"), "ab");
+        assert_eq!(sanitize("a\x1b]52;c;Y3VybCBldmls\x1b\\b"), "ab");
+        assert_eq!(
+            sanitize("\x1b]8;;https://evil.example\x1b\\click\x1b]8;;\x1b\\"),
+            "click"
+        );
+        assert_eq!(sanitize("a\u{9b}2Jb"), "ab");
+        // 没有结束符的 OSC 不会吞掉下一行
+        assert_eq!(sanitize("a\x1b]0;title\nnext"), "a\nnext");
+    }
+
+    #[test]
+    fn test_neutralize_control_characters() {
+        assert_eq!(sanitize("line\r\nnext\r\n"), "line\nnext\n");
+        assert_eq!(sanitize("safe\rrm -rf ~"), "safe\nrm -rf ~");
+        assert_eq!(sanitize("bell\x07 back\x08 nul\0"), "bell␇ back␈ nul␀");
+        assert_eq!(sanitize("x\x1b"), "x");
+        assert_eq!(sanitize("if admin \u{202E} {"), "if admin <U+202E> {");
+    }
+}
@@ -0,0 +1,403 @@
+use anyhow::{Context, Result};
+use globset::{GlobBuilder, GlobMatcher};
+use ignore::{WalkBuilder, WalkState};
+use regex::{Regex, RegexBuilder};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::file_cache;
+
+/// list_files 默认最多返回的路径数
+pub const DEFAULT_MAX_FILES: usize = 1000;
+/// search_files 默认最多返回的匹配行数
+pub const DEFAULT_MAX_MATCHES: usize = 200;
+/// 遍历超过这个时间后停止，返回已找到的结果
+const WALK_TIMEOUT: Duration = Duration::from_secs(10);
+/// 匹配行超过这个长度时截断，避免压缩文件等超长行
+const MAX_LINE_CHARS: usize = 200;
+
+/// 并行遍历的结果
+struct Walk<T> {
+    items: Vec<T>,
+    /// 因达到数量上限或超时而提前停止
+    truncated: bool,
+}
+
+/// 在 `root` 下并行遍历，遵循 .gitignore，跳过 .git 目录
+///
+/// `visit` 对每个条目返回要收集的结果；收集满 `limit` 个或超时后所有线程停止
+fn walk<T, F>(root: &Path, max_depth: Option<usize>, limit: usize, visit: F) -> Walk<T>
+where
+    T: Send,
+    F: Fn(&ignore::DirEntry) -> Vec<T> + Sync,
+{
+    let items = Mutex::new(Vec::new());
+    let count = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    let deadline = Instant::now() + WALK_TIMEOUT;
+
+    WalkBuilder::new(root)
+        .hidden(false)
+        .max_depth(max_depth)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build_parallel()
+        .run(|| {
+            Box::new(|entry| {
+                if cancelled.load(Ordering::Relaxed) {
+                    return WalkState::Quit;
+                }
+                if Instant::now() > deadline {
+                    cancelled.store(true, Ordering::Relaxed);
+                    return WalkState::Quit;
+                }
+                let Ok(entry) = entry else {
+                    return WalkState::Continue;
+                };
+                if entry.depth() == 0 {
+                    return WalkState::Continue;
+                }
+
+                let found = visit(&entry);
+                if found.is_empty() {
+                    return WalkState::Continue;
+                }
+                let total = count.fetch_add(found.len(), Ordering::Relaxed) + found.len();
+                items.lock().unwrap().extend(found);
+                if total > limit {
+                    cancelled.store(true, Ordering::Relaxed);
+                    return WalkState::Quit;
+                }
+                WalkState::Continue
+            })
+        });
+
+    let mut items = items.into_inner().unwrap();
+    let truncated = cancelled.load(Ordering::Relaxed) || items.len() > limit;
+    items.truncate(limit);
+    Walk { items, truncated }
+}
+
+/// 把 `base` 与 `pattern` 拆成不含通配符的起始目录和相对它的 glob
+fn split_pattern(base: &str, pattern: &str) -> (PathBuf, String) {
+    let full = Path::new(base).join(pattern);
+    let mut root = PathBuf::new();
+    let mut rest = Vec::new();
+    for component in full.components() {
+        let text = component.as_os_str().to_string_lossy();
+        if rest.is_empty() && !text.contains(['*', '?', '[', '{']) {
+            root.push(component);
+        } else if !matches!(component, Component::CurDir) {
+            rest.push(text.into_owned());
+        }
+    }
+    if root.as_os_str().is_empty() {
+        root.push(".");
+    }
+    (root, rest.join("/"))
+}
+
+/// 展示给模型的路径：相对路径去掉开头的 ./，可以直接传给 read_file
+fn display_path(path: &Path) -> String {
+    path.strip_prefix(".")
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn glob_matcher(glob: &str) -> Result<GlobMatcher> {
+    Ok(GlobBuilder::new(glob)
+        .literal_separator(true)
+        .build()
+        .with_context(|| format!("Invalid glob pattern: {}", glob))?
+        .compile_matcher())
+}
+
+/// 未指定目录时搜索的起点：当前目录，加上位于当前目录之外的其他工作区根目录
+///
+/// `roots` 的第一个是当前目录；位于其中的根目录已经包含在当前目录的搜索里
+pub fn default_bases(roots: &[PathBuf]) -> Vec<String> {
+    let mut bases = vec![".".to_string()];
+    if let Some((current, others)) = roots.split_first() {
+        bases.extend(
+            others
+                .iter()
+                .filter(|root| !root.starts_with(current))
+                .map(|root| root.to_string_lossy().into_owned()),
+        );
+    }
+    bases
+}
+
+/// 依次在多个起点执行 `search`，合并结果并共用数量上限
+pub fn across<T>(
+    bases: &[String],
+    limit: usize,
+    search: impl Fn(&str, usize) -> Result<(Vec<T>, bool)>,
+) -> Result<(Vec<T>, bool)> {
+    let mut items = Vec::new();
+    for base in bases {
+        if items.len() >= limit {
+            return Ok((items, true));
+        }
+        let (found, truncated) = search(base, limit - items.len())?;
+        items.extend(found);
+        if truncated {
+            return Ok((items, true));
+        }
+    }
+    Ok((items, false))
+}
+
+/// 列出 `base` 下与 `pattern` 匹配的文件和目录，按路径排序
+pub fn list_files(base: &str, pattern: &str, limit: usize) -> Result<(Vec<String>, bool)> {
+    let (root, glob) = split_pattern(base, pattern);
+    if glob.is_empty() {
+        // 不含通配符，只检查路径是否存在
+        let found = root.exists().then(|| display_path(&root));
+        return Ok((found.into_iter().collect(), false));
+    }
+    let matcher = glob_matcher(&glob)?;
+    // 不含 ** 时不需要比 glob 更深地遍历
+    let max_depth = (!glob.contains("**")).then(|| glob.split('/').count());
+
+    let walk = walk(&root, max_depth, limit, |entry| {
+        let path = entry.path();
+        match path.strip_prefix(&root) {
+            Ok(relative) if matcher.is_match(relative) => vec![display_path(path)],
+            _ => Vec::new(),
+        }
+    });
+    let mut paths = walk.items;
+    paths.sort();
+    Ok((paths, walk.truncated))
+}
+
+/// 一行匹配的内容
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchMatch {
+    pub path: String,
+    pub line: usize,
+    pub text: String,
+}
+
+/// 在 `base` 下的文本文件中搜索与正则匹配的行
+///
+/// `glob` 不含 / 时匹配文件名，否则匹配相对 `base` 的路径；二进制和无法解码的文件会被跳过
+pub fn search_files(
+    base: &str,
+    pattern: &str,
+    glob: Option<&str>,
+    case_insensitive: bool,
+    limit: usize,
+) -> Result<(Vec<SearchMatch>, bool)> {
+    let regex = RegexBuilder::new(pattern)
+        .case_insensitive(case_insensitive)
+        .build()
+        .with_context(|| format!("Invalid regex: {}", pattern))?;
+    let filter = glob
+        .map(|glob| Ok::<_, anyhow::Error>((glob_matcher(glob)?, glob.contains('/'))))
+        .transpose()?;
+    let root = Path::new(base);
+
+    let walk = walk(root, None, limit, |entry| {
+        if !entry.file_type().is_some_and(|t| t.is_file()) {
+            return Vec::new();
+        }
+        let path = entry.path();
+        if let Some((matcher, by_path)) = &filter {
+            let target = if *by_path {
+                path.strip_prefix(root).unwrap_or(path)
+            } else {
+                Path::new(entry.file_name())
+            };
+            if !matcher.is_match(target) {
+                return Vec::new();
+            }
+        }
+        search_file(path, &regex)
+    });
+
+    let mut matches = walk.items;
+    matches.sort();
+    Ok((matches, walk.truncated))
+}
+
+fn search_file(path: &Path, regex: &Regex) -> Vec<SearchMatch> {
+    let Ok(content) = file_cache::read(path) else {
+        return Vec::new();
+    };
+    if content.contains('\0') {
+        return Vec::new();
+    }
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| regex.is_match(line))
+        .map(|(index, line)| SearchMatch {
+            path: display_path(path),
+            line: index + 1,
+            text: truncate_line(line.trim_end()),
+        })
+        .collect()
+}
+
+fn truncate_line(line: &str) -> String {
+    match line.char_indices().nth(MAX_LINE_CHARS) {
+        Some((end, _)) => format!("{}...", &line[..end]),
+        None => line.to_string(),
+    }
+}
+
+/// list_files 工具的输出：每行一个路径
+pub fn render_files(paths: &[String], truncated: bool, limit: usize) -> String {
+    if paths.is_empty() {
+        return "(no files found)".to_string();
+    }
+    let mut lines = paths.to_vec();
+    if truncated {
+        lines.push(format!(
+            "[stopped after {} results; use a narrower pattern or path]",
+            limit
+        ));
+    }
+    lines.join("\n")
+}
+
+/// search_files 工具的输出：每行 `path:line: text`
+pub fn render_matches(matches: &[SearchMatch], truncated: bool, limit: usize) -> String {
+    if matches.is_empty() {
+        return "(no matches found)".to_string();
+    }
+    let mut lines: Vec<String> = matches
+        .iter()
+        .map(|m| format!("{}:{}: {}", m.path, m.line, m.text))
+        .collect();
+    if truncated {
+        lines.push(format!(
+            "[stopped after {} matches; use a narrower pattern, path or glob]",
+            limit
+        ));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn tree() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir_all(dir.path().join("src/nested")).unwrap();
+        fs::create_dir_all(dir.path().join("target")).unwrap();
+        fs::write(dir.path().join(".gitignore"), "target/\n").unwrap();
+        fs::write(
+            dir.path().join("src/main.rs"),
+            "fn main() {\n    run();\n}\n",
+        )
+        .unwrap();
+        fs::write(dir.path().join("src/nested/lib.rs"), "pub fn run() {}\n").unwrap();
+        fs::write(dir.path().join("README.md"), "call run() first\n").unwrap();
+        fs::write(dir.path().join("target/out.rs"), "fn run() {}\n").unwrap();
+        // 忽略规则只在 git 仓库中生效
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_list_files_sorted_and_ignored() {
+        let dir = tree();
+        let base = dir.path().to_str().unwrap();
+        let display = |relative: &str| dir.path().join(relative).to_string_lossy().into_owned();
+
+        let files = |pattern: &str| list_files(base, pattern, 100).unwrap().0;
+        assert_eq!(
+            files("**/*.rs"),
+            [display("src/main.rs"), display("src/nested/lib.rs")]
+        );
+        // 单个 * 不跨目录
+        assert_eq!(files("src/*.rs"), [display("src/main.rs")]);
+        assert_eq!(files("README.md"), [display("README.md")]);
+
+        let (limited, truncated) = list_files(base, "**/*", 2).unwrap();
+        assert_eq!(limited.len(), 2);
+        assert!(truncated);
+        assert!(render_files(&limited, truncated, 2)
+            .ends_with("[stopped after 2 results; use a narrower pattern or path]"));
+    }
+
+    #[test]
+    fn test_search_files() {
+        let dir = tree();
+        let base = dir.path().to_str().unwrap();
+
+        let (matches, truncated) = search_files(base, r"\brun\(", None, false, 100).unwrap();
+        assert!(!truncated);
+        let found: Vec<(String, usize)> =
+            matches.iter().map(|m| (m.path.clone(), m.line)).collect();
+        assert_eq!(
+            found,
+            [
+                (dir.path().join("README.md").to_string_lossy().into(), 1),
+                (dir.path().join("src/main.rs").to_string_lossy().into(), 2),
+                (
+                    dir.path()
+                        .join("src/nested/lib.rs")
+                        .to_string_lossy()
+                        .into(),
+                    1
+                ),
+            ]
+        );
+        assert_eq!(matches[1].text, "    run();");
+
+        let (matches, _) = search_files(base, "RUN", Some("*.md"), true, 100).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(search_files(base, "(", None, false, 100).is_err());
+    }
+
+    #[test]
+    fn test_search_across_workspace_roots() {
+        let roots = [
+            PathBuf::from("/repo"),
+            PathBuf::from("/repo/backend"),
+            PathBuf::from("/shared-lib"),
+        ];
+        assert_eq!(default_bases(&roots), [".", "/shared-lib"]);
+
+        let (first, second) = (tree(), tree());
+        let bases = [
+            first.path().to_string_lossy().into_owned(),
+            second.path().to_string_lossy().into_owned(),
+        ];
+        let list = |limit| {
+            across(&bases, limit, |base, limit| {
+                list_files(base, "**/*.rs", limit)
+            })
+        };
+        let (files, truncated) = list(100).unwrap();
+        assert_eq!(files.len(), 4);
+        assert!(!truncated);
+        assert!(files[3].starts_with(&bases[1]));
+
+        let (files, truncated) = list(3).unwrap();
+        assert_eq!(files.len(), 3);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_split_pattern() {
+        assert_eq!(
+            split_pattern(".", "src/**/*.rs"),
+            (PathBuf::from("./src"), "**/*.rs".to_string())
+        );
+        assert_eq!(
+            split_pattern(".", "/tmp/a/*.txt"),
+            (PathBuf::from("/tmp/a"), "*.txt".to_string())
+        );
+        assert_eq!(display_path(Path::new("./src/main.rs")), "src/main.rs");
+    }
+}
@@ -1,18 +1,13 @@
 use anyhow::{anyhow, Context, Result};
-use once_cell::sync::Lazy;
-use std::collections::HashSet;
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
 
-/// 危险命令集合 - 使用 HashSet 进行 O(1) 查找
-static DANGEROUS_COMMANDS: Lazy<HashSet<&'static str>> = Lazy::new(|| {
-    HashSet::from([
-        "rm -rf /", "sudo rm", "format", "del /f", "shutdown", "reboot", "halt", "poweroff",
-        "mkfs", "fdisk", "dd if=",
-    ])
-});
+use crate::performance::write_atomic;
+use crate::policy::{Action, Policy};
+use crate::search;
+use crate::shell;
 
 /// 输入验证器
 pub struct InputValidator;
@@ -60,23 +55,28 @@ impl InputValidator {
             return Err(anyhow!("Command cannot be empty"));
         }
 
-        // 检查危险命令 - 使用 HashSet 进行 O(1) 查找
-        let lower_command = command.to_lowercase();
-        for dangerous in DANGEROUS_COMMANDS.iter() {
-            if lower_command.contains(dangerous) {
-                return Err(anyhow!("Dangerous command detected: {}", command));
+        // 按实际的 argv 而不是子串检查；引号没有闭合等无法解析的命令直接拒绝
+        let parsed = shell::parse(command)
+            .with_context(|| format!("Could not parse command: {}", command))?;
+
+        // 按内置的命令规则逐条检查，需要询问的命令在这里同样拒绝
+        let cwd = env::current_dir().unwrap_or_default();
+        if let Some(decision) = Policy::new(&[])?.evaluate(command, &cwd) {
+            if decision.action != Action::Allow {
+                return Err(anyhow!(
+                    "Dangerous command detected: {} ({})",
+                    command,
+                    decision.reason.unwrap_or(decision.rule)
+                ));
             }
         }
 
-        // 检查管道和重定向（限制使用）
-        let pipe_count = command.matches('|').count();
-        let redirect_count = command.matches('>').count() + command.matches('<').count();
-
-        if pipe_count > 2 {
+        // 检查管道和重定向（限制使用），引号中的 | 和 > 不算
+        if parsed.pipes() > 2 {
             return Err(anyhow!("Too many pipes in command: {}", command));
         }
 
-        if redirect_count > 2 {
+        if parsed.redirects() > 2 {
             return Err(anyhow!("Too many redirects in command: {}", command));
         }
 
@@ -193,10 +193,8 @@ impl InputValidator {
         }
 
         // 检查文件大小（限制为 100MB）
-        if metadata.is_file() {
-            if metadata.len() > 100 * 1024 * 1024 {
-                return Err(anyhow!("File too large: {} bytes", metadata.len()));
-            }
+        if metadata.is_file() && metadata.len() > 100 * 1024 * 1024 {
+            return Err(anyhow!("File too large: {} bytes", metadata.len()));
         }
 
         Ok(())
@@ -271,8 +269,8 @@ impl SafeToolExecutor {
                 .with_context(|| format!("Failed to create directory: {:?}", parent))?;
         }
 
-        // 写入文件
-        fs::write(&validated_path, content)
+        // 原子写入，避免中途失败截断原文件
+        write_atomic(&validated_path, content.as_bytes(), false)
             .with_context(|| format!("Failed to write file: {}", validated_path.display()))?;
 
         Ok(format!(
@@ -311,7 +309,7 @@ impl SafeToolExecutor {
         }
         if !stderr.is_empty() {
             if !result.is_empty() {
-                result.push_str("\n");
+                result.push('\n');
             }
             result.push_str(&stderr);
         }
@@ -339,44 +337,22 @@ impl SafeToolExecutor {
         // 验证基础路径
         let validated_base = InputValidator::validate_file_path(base_path)?;
 
-        use glob::glob;
-
-        let full_pattern = if safe_pattern.starts_with('/') {
-            safe_pattern.clone()
-        } else {
-            format!("{}/{}", validated_base.display(), safe_pattern)
-        };
-
-        let mut files = Vec::new();
-        let mut file_count = 0;
-
-        for entry in glob(&full_pattern)
-            .with_context(|| format!("Failed to read glob pattern: {}", full_pattern))?
-        {
-            match entry {
-                Ok(path) => {
-                    // 限制结果数量
-                    if file_count >= 1000 {
-                        warn!("Too many files found, limiting to 1000");
-                        break;
-                    }
-
-                    if let Some(path_str) = path.to_str() {
-                        files.push(path_str.to_string());
-                        file_count += 1;
-                    }
-                }
-                Err(e) => {
-                    warn!("Error reading entry: {:?}", e);
-                }
-            }
-        }
-
-        if files.is_empty() {
-            Ok("(no files found)".to_string())
-        } else {
-            Ok(files.join("\n"))
+        let (files, truncated) = search::list_files(
+            &validated_base.to_string_lossy(),
+            &safe_pattern,
+            search::DEFAULT_MAX_FILES,
+        )?;
+        if truncated {
+            warn!(
+                "Too many files found, limiting to {}",
+                search::DEFAULT_MAX_FILES
+            );
         }
+        Ok(search::render_files(
+            &files,
+            truncated,
+            search::DEFAULT_MAX_FILES,
+        ))
     }
 }
 
@@ -396,11 +372,16 @@ mod tests {
         assert!(InputValidator::validate_command("ls -la").is_ok());
         assert!(InputValidator::validate_command("rm -rf /").is_err());
         assert!(InputValidator::validate_command("").is_err());
+        assert!(InputValidator::validate_command("git commit -m \"don't shutdown\"").is_ok());
+        assert!(InputValidator::validate_command("echo 'a | b | c | d'").is_ok());
+        assert!(InputValidator::validate_command("ls; sudo rm -rf /").is_err());
+        assert!(InputValidator::validate_command("echo $(reboot)").is_err());
+        assert!(InputValidator::validate_command("echo 'oops").is_err());
     }
 
     #[test]
     fn test_validate_api_key() {
-        assert!(InputValidator::validate_api_key("sk-ant-test123").is_ok());
+        assert!(InputValidator::validate_api_key("sk-ant-REDACTED").is_ok());
         assert!(InputValidator::validate_api_key("invalid-key").is_err());
         assert!(InputValidator::validate_api_key("").is_err());
     }
@@ -0,0 +1,472 @@
+//! serve 模式：在本机 HTTP 接口上提供对话引擎，供编辑器和网页界面驱动
+
+use anyhow::{Context, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::oneshot;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::engine::{Engine, Event, EventSink};
+use crate::error::{self, ApiClient};
+use crate::i18n::{tf, Msg};
+use crate::permissions::ApprovalRequest;
+use crate::tool_stats::ToolStats;
+
+/// 等待客户端批准工具调用的最长时间，超时视为拒绝
+const APPROVAL_TIMEOUT: Duration = Duration::from_secs(600);
+/// 每个会话缓冲的事件数，客户端读取过慢时丢弃最早的事件
+const EVENT_BUFFER: usize = 256;
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+    /// Port to listen on; the API is only reachable from this machine
+    #[arg(long, default_value_t = 8080)]
+    port: u16,
+}
+
+struct Server {
+    api_client: ApiClient,
+    config: Config,
+    tool_stats: ToolStats,
+    /// 每条消息最多的工具调用轮数
+    max_rounds: usize,
+    /// 每个请求都要带上的访问令牌，启动时随机生成
+    token: String,
+    sessions: Mutex<HashMap<String, Arc<Session>>>,
+}
+
+impl Server {
+    fn session(&self, id: &str) -> Option<Arc<Session>> {
+        self.sessions.lock().unwrap().get(id).cloned()
+    }
+}
+
+struct Session {
+    /// 进行中的一轮对话持有这把锁
+    messages: Arc<tokio::sync::Mutex<Vec<serde_json::Value>>>,
+    events: broadcast::Sender<Event>,
+    /// 等待客户端回复的批准请求
+    approvals: Mutex<HashMap<u64, oneshot::Sender<bool>>>,
+    next_approval: AtomicU64,
+    /// 取消进行中的一轮对话
+    cancel: Mutex<Option<CancellationToken>>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            messages: Arc::default(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            approvals: Mutex::default(),
+            next_approval: AtomicU64::new(1),
+            cancel: Mutex::default(),
+        }
+    }
+}
+
+impl EventSink for Session {
+    fn emit(&self, event: Event) {
+        // 没有客户端订阅时事件直接丢弃
+        let _ = self.events.send(event);
+    }
+
+    fn approve<'a>(
+        &'a self,
+        tool: &'a str,
+        request: &'a ApprovalRequest,
+    ) -> Pin<Box<dyn Future<Output = bool> + Send + 'a>> {
+        Box::pin(async move {
+            let id = self.next_approval.fetch_add(1, Ordering::Relaxed);
+            let (sender, receiver) = oneshot::channel();
+            self.approvals.lock().unwrap().insert(id, sender);
+            let (command, reason, path) = match request {
+                ApprovalRequest::Command { command, reason } => {
+                    (Some(command.clone()), reason.clone(), None)
+                }
+                ApprovalRequest::OutsideWorkspace(path) => (None, None, Some(path.clone())),
+                ApprovalRequest::ProtectedFile { path, reason } => {
+                    (None, reason.clone(), Some(path.clone()))
+                }
+                ApprovalRequest::SuspiciousContent(findings) => {
+                    (None, Some(findings.clone()), None)
+                }
+            };
+            self.emit(Event::PermissionRequest {
+                id,
+                tool: tool.to_string(),
+                command,
+                reason,
+                path,
+            });
+
+            let approved = tokio::time::timeout(APPROVAL_TIMEOUT, receiver)
+                .await
+                .is_ok_and(|reply| reply.unwrap_or(false));
+            self.approvals.lock().unwrap().remove(&id);
+            approved
+        })
+    }
+}
+
+/// 启动服务，直到进程被终止
+pub async fn run(
+    args: ServeArgs,
+    api_client: ApiClient,
+    config: Config,
+    max_turns: usize,
+) -> Result<()> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, args.port));
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen on {}", addr))?;
+    let server = Arc::new(Server {
+        api_client,
+        config,
+        tool_stats: ToolStats::default(),
+        max_rounds: max_turns.saturating_sub(1),
+        token: Uuid::new_v4().simple().to_string(),
+        sessions: Mutex::default(),
+    });
+    println!(
+        "{}",
+        tf(
+            Msg::ServeListening,
+            &[&format!("http://{}", listener.local_addr()?), &server.token]
+        )
+    );
+    axum::serve(listener, router(server))
+        .await
+        .context("Server failed")
+}
+
+fn router(server: Arc<Server>) -> Router {
+    Router::new()
+        .route("/sessions", post(create_session))
+        .route("/sessions/{id}", get(get_session).delete(delete_session))
+        .route("/sessions/{id}/messages", post(send_message))
+        .route("/sessions/{id}/events", get(stream_events))
+        .route("/sessions/{id}/cancel", post(cancel_turn))
+        .route("/sessions/{id}/approvals/{approval}", post(reply_approval))
+        .layer(middleware::from_fn_with_state(
+            Arc::clone(&server),
+            authorize,
+        ))
+        .with_state(server)
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (status, Json(json!({ "error": message }))).into_response()
+}
+
+fn session_not_found() -> Response {
+    error(StatusCode::NOT_FOUND, "no session with this id")
+}
+
+/// 要求 `Authorization: Bearer <token>`；浏览器的 WebSocket 无法设置请求头，因此也接受 `?token=`
+async fn authorize(State(server): State<Arc<Server>>, request: Request, next: Next) -> Response {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    let query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("token="))
+    });
+    if bearer.or(query) == Some(server.token.as_str()) {
+        next.run(request).await
+    } else {
+        error(StatusCode::UNAUTHORIZED, "missing or wrong token")
+    }
+}
+
+async fn create_session(State(server): State<Arc<Server>>) -> Response {
+    let id = Uuid::new_v4().to_string();
+    server
+        .sessions
+        .lock()
+        .unwrap()
+        .insert(id.clone(), Arc::new(Session::new()));
+    info!("Created session {}", id);
+    (StatusCode::CREATED, Json(json!({ "id": id }))).into_response()
+}
+
+/// 会话的消息列表；一轮对话进行中时只返回 busy
+async fn get_session(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Response {
+    let Some(session) = server.session(&id) else {
+        return session_not_found();
+    };
+    let body = match session.messages.try_lock() {
+        Ok(messages) => json!({ "id": id, "busy": false, "messages": *messages }),
+        Err(_) => json!({ "id": id, "busy": true }),
+    };
+    Json(body).into_response()
+}
+
+async fn delete_session(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Response {
+    match server.sessions.lock().unwrap().remove(&id) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => session_not_found(),
+    }
+}
+
+#[derive(Deserialize)]
+struct SendMessage {
+    text: String,
+}
+
+/// 在后台开始一轮对话，过程和结果通过事件流返回
+async fn send_message(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    Json(body): Json<SendMessage>,
+) -> Response {
+    let Some(session) = server.session(&id) else {
+        return session_not_found();
+    };
+    let Ok(mut messages) = Arc::clone(&session.messages).try_lock_owned() else {
+        return error(
+            StatusCode::CONFLICT,
+            "a turn is already running in this session",
+        );
+    };
+
+    let cancel = CancellationToken::new();
+    *session.cancel.lock().unwrap() = Some(cancel.clone());
+
+    tokio::spawn(async move {
+        let engine = Engine {
+            api_client: &server.api_client,
+            config: &server.config,
+            tool_stats: &server.tool_stats,
+            events: session.as_ref(),
+            cancel: &cancel,
+            read_only: false,
+            fail_on_denial: false,
+        };
+        let before = messages.len();
+        let result = engine
+            .run_turn(&mut messages, json!(body.text), Some(server.max_rounds))
+            .await;
+        session.cancel.lock().unwrap().take();
+        let event = match result {
+            Ok(outcome) => Event::TurnComplete { outcome },
+            Err(e) => {
+                // 失败的一轮不留在会话中，客户端可以直接重新发送
+                messages.truncate(before);
+                warn!("Turn failed in session {}: {:#}", id, e);
+                Event::Error {
+                    message: format!("{:#}", e),
+                    kind: error::kind(&e),
+                }
+            }
+        };
+        session.emit(event);
+    });
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// 取消进行中的一轮对话；这一轮以 cancelled 结束
+async fn cancel_turn(State(server): State<Arc<Server>>, Path(id): Path<String>) -> Response {
+    let Some(session) = server.session(&id) else {
+        return session_not_found();
+    };
+    let cancel = session.cancel.lock().unwrap().take();
+    match cancel {
+        Some(cancel) => {
+            cancel.cancel();
+            StatusCode::ACCEPTED.into_response()
+        }
+        None => error(StatusCode::CONFLICT, "no turn is running in this session"),
+    }
+}
+
+/// WebSocket 事件流：每个事件是一条 JSON 文本消息
+async fn stream_events(
+    State(server): State<Arc<Server>>,
+    Path(id): Path<String>,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let Some(session) = server.session(&id) else {
+        return session_not_found();
+    };
+    let receiver = session.events.subscribe();
+    upgrade.on_upgrade(move |socket| forward_events(socket, receiver))
+}
+
+async fn forward_events(mut socket: WebSocket, mut receiver: broadcast::Receiver<Event>) {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(RecvError::Lagged(skipped)) => {
+                warn!("Event stream fell behind, dropped {} events", skipped);
+                continue;
+            }
+            Err(RecvError::Closed) => break,
+        };
+        let Ok(text) = serde_json::to_string(&event) else {
+            continue;
+        };
+        if socket.send(Message::Text(text.into())).await.is_err() {
+            break;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ApprovalReply {
+    approved: bool,
+}
+
+async fn reply_approval(
+    State(server): State<Arc<Server>>,
+    Path((id, approval)): Path<(String, u64)>,
+    Json(reply): Json<ApprovalReply>,
+) -> Response {
+    let Some(session) = server.session(&id) else {
+        return session_not_found();
+    };
+    let sender = session.approvals.lock().unwrap().remove(&approval);
+    match sender {
+        Some(sender) => {
+            let _ = sender.send(reply.approved);
+            StatusCode::NO_CONTENT.into_response()
+        }
+        None => error(StatusCode::NOT_FOUND, "no pending approval with this id"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserSettings;
+    use crate::error::RetryConfig;
+
+    /// 在随机端口启动服务，模型请求发往 `api_url`
+    async fn start(api_url: String) -> (String, String) {
+        let config = Config {
+            user_settings: UserSettings::default(),
+            api_key: "test-key".to_string(),
+            api_base_url: api_url.clone(),
+            api_timeout_ms: 5_000,
+            retry: RetryConfig::default(),
+            anthropic_version: "2023-06-01".to_string(),
+            anthropic_beta: Vec::new(),
+            sources: HashMap::new(),
+            warnings: Vec::new(),
+            managed: Default::default(),
+        };
+        let server = Arc::new(Server {
+            api_client: ApiClient::new("test-key".to_string(), api_url),
+            config,
+            tool_stats: ToolStats::default(),
+            max_rounds: 3,
+            token: "secret".to_string(),
+            sessions: Mutex::default(),
+        });
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, router(server)).await });
+        (base, "secret".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_requires_token() {
+        let (base, token) = start("http://127.0.0.1:9/v1/messages".to_string()).await;
+        let client = reqwest::Client::new();
+        let response = client
+            .post(format!("{}/sessions", base))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+
+        let response = client
+            .post(format!("{}/sessions?token={}", base, token))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 201);
+        let response = client
+            .post(format!("{}/sessions/nope/approvals/1", base))
+            .bearer_auth(&token)
+            .json(&json!({ "approved": true }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_message_runs_a_turn() {
+        let mut api = mockito::Server::new_async().await;
+        api.mock("POST", "/v1/messages")
+            .with_header("content-type", "application/json")
+            .with_body(
+                r#"{"content": [{"type": "text", "text": "pong"}], "stop_reason": "end_turn"}"#,
+            )
+            .create_async()
+            .await;
+        let (base, token) = start(format!("{}/v1/messages", api.url())).await;
+        let client = reqwest::Client::new();
+
+        let created: serde_json::Value = client
+            .post(format!("{}/sessions", base))
+            .bearer_auth(&token)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let session = format!("{}/sessions/{}", base, created["id"].as_str().unwrap());
+        let response = client
+            .post(format!("{}/messages", session))
+            .bearer_auth(&token)
+            .json(&json!({ "text": "ping" }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+
+        // 等待后台的一轮对话结束
+        let mut state = serde_json::Value::Null;
+        for _ in 0..100 {
+            state = client
+                .get(&session)
+                .bearer_auth(&token)
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            if state["busy"] == false {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(state["messages"][0]["content"], "ping");
+        assert_eq!(state["messages"][1]["content"][0]["text"], "pong");
+    }
+}
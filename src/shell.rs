@@ -0,0 +1,407 @@
+//! 把 shell 命令行解析成一条条简单命令及其 argv
+//!
+//! 只做判断命令需要的部分：引号和转义、`;` `&&` `||` `|` `&` 和换行连接的命令、
+//! `$()` 和反引号中的命令、子 shell、重定向和 here document。不展开变量和通配符。
+
+use anyhow::{bail, Result};
+
+/// 不是命令本身、后面才是命令的关键字
+const KEYWORDS: &[&str] = &[
+    "if", "then", "else", "elif", "fi", "do", "done", "while", "until", "!", "{", "}", "time",
+];
+
+/// 一条简单命令
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SimpleCommand {
+    /// 命令前的环境变量赋值，例如 `RUST_LOG=debug`
+    pub env: Vec<String>,
+    /// 去掉引号和转义后的参数，第一个是程序
+    pub argv: Vec<String>,
+    /// 重定向的目标，例如 `> out.txt` 中的 `out.txt`
+    pub redirects: Vec<String>,
+}
+
+impl SimpleCommand {
+    /// 程序名，去掉路径，`/bin/rm` 和 `rm` 相同
+    pub fn program(&self) -> &str {
+        let first = self.argv.first().map(String::as_str).unwrap_or_default();
+        first.rsplit(['/', '\\']).next().unwrap_or(first)
+    }
+
+    /// 用于匹配规则的形式：程序名和参数以单个空格连接，不含环境变量和重定向
+    pub fn line(&self) -> String {
+        std::iter::once(self.program())
+            .chain(self.argv.iter().skip(1).map(String::as_str))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// 解析后的命令行
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ParsedCommand {
+    /// 按结束顺序排列的所有简单命令，包括 `$()` 和反引号中的命令
+    pub commands: Vec<SimpleCommand>,
+    /// 连接命令的操作符，例如 `&&`、`|`；换行记为 `;`
+    pub operators: Vec<String>,
+    /// `$()` 和反引号命令替换的个数
+    pub substitutions: usize,
+}
+
+impl ParsedCommand {
+    pub fn pipes(&self) -> usize {
+        self.operators
+            .iter()
+            .filter(|op| op.starts_with('|') && *op != "||")
+            .count()
+    }
+
+    pub fn redirects(&self) -> usize {
+        self.commands
+            .iter()
+            .map(|command| command.redirects.len())
+            .sum()
+    }
+}
+
+/// 解析命令行；引号、括号或反引号没有闭合时报错
+pub fn parse(command: &str) -> Result<ParsedCommand> {
+    let mut parser = Parser {
+        chars: command.chars().collect(),
+        pos: 0,
+        heredocs: Vec::new(),
+        parsed: ParsedCommand::default(),
+    };
+    parser.list(None)?;
+    Ok(parser.parsed)
+}
+
+/// 正在读取的命令和单词
+#[derive(Default)]
+struct Builder {
+    command: SimpleCommand,
+    word: Option<String>,
+    /// 下一个单词是重定向的目标
+    redirect: bool,
+    /// 重定向是 here document，目标是结束标记
+    heredoc: bool,
+}
+
+impl Builder {
+    fn push(&mut self, c: char) {
+        self.word.get_or_insert_with(String::new).push(c);
+    }
+
+    fn push_str(&mut self, s: &str) {
+        self.word.get_or_insert_with(String::new).push_str(s);
+    }
+
+    fn end_word(&mut self, heredocs: &mut Vec<String>) {
+        let Some(word) = self.word.take() else {
+            return;
+        };
+        let command = &mut self.command;
+        if self.redirect {
+            if self.heredoc {
+                heredocs.push(word.clone());
+            }
+            command.redirects.push(word);
+            self.redirect = false;
+            self.heredoc = false;
+        } else if command.argv.is_empty() && is_assignment(&word) {
+            command.env.push(word);
+        } else if !command.argv.is_empty() || !KEYWORDS.contains(&word.as_str()) {
+            command.argv.push(word);
+        }
+    }
+
+    fn end_command(&mut self, parsed: &mut ParsedCommand, heredocs: &mut Vec<String>) {
+        self.end_word(heredocs);
+        let command = std::mem::take(&mut self.command);
+        if !command.argv.is_empty() {
+            parsed.commands.push(command);
+        }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// 等待跳过正文的 here document 结束标记
+    heredocs: Vec<String>,
+    parsed: ParsedCommand,
+}
+
+impl Parser {
+    fn peek(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    /// 读取命令列表，直到结尾或 `close`（子 shell 和 `$()` 的 `)`，或反引号）
+    fn list(&mut self, close: Option<char>) -> Result<()> {
+        let mut builder = Builder::default();
+        loop {
+            let Some(c) = self.peek(0) else {
+                if let Some(close) = close {
+                    bail!("Unterminated command substitution: missing {}", close);
+                }
+                break;
+            };
+            match c {
+                _ if Some(c) == close => {
+                    self.pos += 1;
+                    break;
+                }
+                ' ' | '\t' => {
+                    self.pos += 1;
+                    builder.end_word(&mut self.heredocs);
+                }
+                '\'' => {
+                    self.pos += 1;
+                    builder.push_str("");
+                    loop {
+                        match self.peek(0) {
+                            Some('\'') => break,
+                            Some(c) => builder.push(c),
+                            None => bail!("Unterminated single quote"),
+                        }
+                        self.pos += 1;
+                    }
+                    self.pos += 1;
+                }
+                '"' => {
+                    self.pos += 1;
+                    self.double_quoted(&mut builder)?;
+                }
+                '\\' => {
+                    match self.peek(1) {
+                        Some('\n') => {}
+                        Some(c) => builder.push(c),
+                        None => builder.push('\\'),
+                    }
+                    self.pos += 2;
+                }
+                '$' if self.peek(1) == Some('(') => self.substitution(&mut builder, 2, ')')?,
+                '`' => self.substitution(&mut builder, 1, '`')?,
+                '#' if builder.word.is_none() => {
+                    while self.peek(0).is_some_and(|c| c != '\n') {
+                        self.pos += 1;
+                    }
+                }
+                '(' if builder.word.is_none() && builder.command.argv.is_empty() => {
+                    builder.end_command(&mut self.parsed, &mut self.heredocs);
+                    self.pos += 1;
+                    self.list(Some(')'))?;
+                }
+                '&' if self.peek(1) == Some('>') => self.redirect(&mut builder),
+                ';' | '\n' | '&' | '|' => {
+                    builder.end_command(&mut self.parsed, &mut self.heredocs);
+                    let doubled = matches!(
+                        (c, self.peek(1)),
+                        ('&', Some('&')) | ('|', Some('|') | Some('&')) | (';', Some(';'))
+                    );
+                    let operator: String = if c == '\n' {
+                        ";".to_string()
+                    } else {
+                        self.chars[self.pos..self.pos + 1 + doubled as usize]
+                            .iter()
+                            .collect()
+                    };
+                    self.pos += 1 + doubled as usize;
+                    self.parsed.operators.push(operator);
+                    if c == '\n' {
+                        self.skip_heredocs();
+                    }
+                }
+                '<' | '>' => self.redirect(&mut builder),
+                _ => {
+                    builder.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+        builder.end_command(&mut self.parsed, &mut self.heredocs);
+        Ok(())
+    }
+
+    /// 双引号中只有 `\`、`$()` 和反引号有特殊含义
+    fn double_quoted(&mut self, builder: &mut Builder) -> Result<()> {
+        builder.push_str("");
+        loop {
+            match self.peek(0) {
+                Some('"') => break,
+                Some('\\') => {
+                    match self.peek(1) {
+                        Some(c @ ('$' | '`' | '"' | '\\')) => builder.push(c),
+                        Some('\n') => {}
+                        _ => builder.push('\\'),
+                    }
+                    self.pos += if matches!(self.peek(1), Some('$' | '`' | '"' | '\\' | '\n')) {
+                        2
+                    } else {
+                        1
+                    };
+                    continue;
+                }
+                Some('$') if self.peek(1) == Some('(') => {
+                    self.substitution(builder, 2, ')')?;
+                    continue;
+                }
+                Some('`') => {
+                    self.substitution(builder, 1, '`')?;
+                    continue;
+                }
+                Some(c) => builder.push(c),
+                None => bail!("Unterminated double quote"),
+            }
+            self.pos += 1;
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    /// 替换中的命令单独记录，外层单词保留原文
+    fn substitution(&mut self, builder: &mut Builder, open: usize, close: char) -> Result<()> {
+        let start = self.pos;
+        self.pos += open;
+        self.parsed.substitutions += 1;
+        self.list(Some(close))?;
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        builder.push_str(&raw);
+        Ok(())
+    }
+
+    /// `>`、`>>`、`2>&1`、`&>`、`<`、`<<EOF` 等；紧挨着的数字是文件描述符，不是参数
+    fn redirect(&mut self, builder: &mut Builder) {
+        if builder
+            .word
+            .as_ref()
+            .is_some_and(|word| !word.is_empty() && word.chars().all(|c| c.is_ascii_digit()))
+        {
+            builder.word = None;
+        }
+        builder.end_word(&mut self.heredocs);
+        let start = self.pos;
+        while self
+            .peek(0)
+            .is_some_and(|c| matches!(c, '<' | '>' | '&' | '|'))
+        {
+            self.pos += 1;
+        }
+        let operator: String = self.chars[start..self.pos].iter().collect();
+        if operator == "<<" && self.peek(0) == Some('-') {
+            self.pos += 1;
+        }
+        builder.redirect = true;
+        builder.heredoc = operator == "<<";
+    }
+
+    /// 换行后跳过 here document 的正文，直到只有结束标记的一行
+    fn skip_heredocs(&mut self) {
+        for delimiter in std::mem::take(&mut self.heredocs) {
+            while self.pos < self.chars.len() {
+                let end = self.chars[self.pos..]
+                    .iter()
+                    .position(|&c| c == '\n')
+                    .map_or(self.chars.len(), |i| self.pos + i);
+                let line: String = self.chars[self.pos..end].iter().collect();
+                self.pos = (end + 1).min(self.chars.len());
+                if line.trim_start_matches('\t') == delimiter {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// `NAME=value` 形式的环境变量赋值
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty()
+            && !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(command: &str) -> Vec<String> {
+        parse(command)
+            .unwrap()
+            .commands
+            .iter()
+            .map(SimpleCommand::line)
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_quotes_and_escapes() {
+        let parsed = parse(r#"RUST_LOG=debug git commit -m "don't shutdown" 'a  b' c\ d"#).unwrap();
+        assert_eq!(parsed.commands.len(), 1);
+        let command = &parsed.commands[0];
+        assert_eq!(command.env, ["RUST_LOG=debug"]);
+        assert_eq!(
+            command.argv,
+            ["git", "commit", "-m", "don't shutdown", "a  b", "c d"]
+        );
+
+        assert_eq!(lines(r#"r\m -rf "/""#), ["rm -rf /"]);
+        assert_eq!(lines("/usr/bin/env FOO=1 ls"), ["env FOO=1 ls"]);
+        assert!(parse("echo 'oops").is_err());
+        assert!(parse("echo $(date").is_err());
+    }
+
+    #[test]
+    fn test_parse_chains_and_substitutions() {
+        let parsed = parse("cargo build && cargo test 2>&1 | tee log.txt; echo done").unwrap();
+        assert_eq!(
+            parsed
+                .commands
+                .iter()
+                .map(SimpleCommand::line)
+                .collect::<Vec<_>>(),
+            ["cargo build", "cargo test", "tee log.txt", "echo done"]
+        );
+        assert_eq!(parsed.operators, ["&&", "|", ";"]);
+        assert_eq!(parsed.pipes(), 1);
+        assert_eq!(parsed.commands[1].redirects, ["1"]);
+
+        let parsed = parse(r#"echo "today is $(date +%F)" `whoami` && (cd x; make)"#).unwrap();
+        assert_eq!(parsed.substitutions, 2);
+        assert_eq!(
+            parsed
+                .commands
+                .iter()
+                .map(SimpleCommand::line)
+                .collect::<Vec<_>>(),
+            [
+                "date +%F",
+                "whoami",
+                "echo today is $(date +%F) `whoami`",
+                "cd x",
+                "make"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_redirects_and_heredocs() {
+        let parsed = parse(
+            "cat > out.txt <<'EOF'\nsudo reboot\nEOF\nif true; then ls >>log 2>/dev/null; fi",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed
+                .commands
+                .iter()
+                .map(SimpleCommand::line)
+                .collect::<Vec<_>>(),
+            ["cat", "true", "ls"]
+        );
+        assert_eq!(parsed.commands[0].redirects, ["out.txt", "EOF"]);
+        assert_eq!(parsed.commands[2].redirects, ["log", "/dev/null"]);
+        assert_eq!(parsed.redirects(), 4);
+    }
+}
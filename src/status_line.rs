@@ -0,0 +1,185 @@
+use console::{style, truncate_str, Term};
+use std::process::Command;
+
+use crate::i18n::{tf, Msg};
+use crate::ui;
+use crate::urls;
+
+/// 当前目录所在 git 仓库的状态
+#[derive(Debug, Clone, PartialEq)]
+pub struct GitStatus {
+    pub branch: String,
+    /// 有改动（含未跟踪）的文件数
+    pub dirty: usize,
+}
+
+impl GitStatus {
+    /// 运行 git status；不在 git 仓库中或没有安装 git 时返回 None
+    pub fn current() -> Option<Self> {
+        let output = Command::new("git")
+            .args(["status", "--porcelain=v1", "--branch"])
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        parse_git_status(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+/// 解析 `git status --porcelain=v1 --branch` 的输出
+fn parse_git_status(output: &str) -> Option<GitStatus> {
+    let mut lines = output.lines();
+    let header = lines.next()?.strip_prefix("## ")?;
+
+    let branch = if let Some(rest) = header.strip_prefix("No commits yet on ") {
+        rest.to_string()
+    } else if header.starts_with("HEAD (no branch)") {
+        "HEAD".to_string()
+    } else {
+        // "main...origin/main [ahead 1]"
+        let end = header.find("...").or_else(|| header.find(' '));
+        header[..end.unwrap_or(header.len())].to_string()
+    };
+
+    Some(GitStatus {
+        branch,
+        dirty: lines.filter(|line| !line.trim().is_empty()).count(),
+    })
+}
+
+/// 输入提示符上方的状态栏
+#[derive(Debug, Clone)]
+pub struct StatusLine<'a> {
+    pub model: &'a str,
+    /// 使用的配置档
+    pub profile: Option<String>,
+    pub git: Option<GitStatus>,
+    pub tokens: usize,
+    pub context_window: usize,
+    pub mode: &'a str,
+    /// 命令输出中最近出现的本地地址
+    pub url: Option<String>,
+}
+
+impl StatusLine<'_> {
+    /// 拼接各项，超出 `width` 时截断
+    pub fn render(&self, width: usize) -> String {
+        let mut parts = vec![self.model.to_string()];
+        if let Some(profile) = &self.profile {
+            parts.push(tf(Msg::StatusLineProfile, &[profile]));
+        }
+        if let Some(git) = &self.git {
+            if git.dirty > 0 {
+                parts.push(tf(Msg::StatusLineDirty, &[&git.branch, &git.dirty]));
+            } else {
+                parts.push(git.branch.clone());
+            }
+        }
+        let percent = self.tokens * 100 / self.context_window.max(1);
+        parts.push(tf(
+            Msg::StatusLineTokens,
+            &[
+                &format_tokens(self.tokens),
+                &format_tokens(self.context_window),
+                &percent,
+            ],
+        ));
+        parts.push(self.mode.to_string());
+        if let Some(url) = &self.url {
+            parts.push(url.clone());
+        }
+
+        truncate_str(&parts.join(" · "), width, "…").into_owned()
+    }
+
+    /// 在提示符上方输出一行状态；输出不是终端时不显示
+    pub fn print(&self) {
+        let term = Term::stdout();
+        if !term.is_term() {
+            return;
+        }
+        let line = self.render(usize::from(term.size().1));
+        if ui::accessible() {
+            println!("STATUS LINE: {}", line);
+            return;
+        }
+        // 未被截断时把地址显示为可点击的链接
+        match self.url.as_deref().filter(|url| line.ends_with(url)) {
+            Some(url) => {
+                let head = &line[..line.len() - url.len()];
+                println!("{}{}", style(head).dim(), urls::hyperlink(url));
+            }
+            None => println!("{}", style(line).dim()),
+        }
+    }
+}
+
+/// 12345 -> "12.3k"
+fn format_tokens(tokens: usize) -> String {
+    if tokens < 1_000 {
+        tokens.to_string()
+    } else if tokens < 10_000 {
+        format!("{:.1}k", tokens as f64 / 1_000.0)
+    } else {
+        format!("{}k", tokens / 1_000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_git_status() {
+        let status =
+            parse_git_status("## main...origin/main [ahead 1]\n M src/main.rs\n?? new.rs\n");
+        assert_eq!(
+            status,
+            Some(GitStatus {
+                branch: "main".to_string(),
+                dirty: 2
+            })
+        );
+        assert_eq!(
+            parse_git_status("## No commits yet on master\n")
+                .unwrap()
+                .branch,
+            "master"
+        );
+        assert_eq!(
+            parse_git_status("## feature/x\n").unwrap(),
+            GitStatus {
+                branch: "feature/x".to_string(),
+                dirty: 0
+            }
+        );
+        assert_eq!(parse_git_status(""), None);
+    }
+
+    #[test]
+    fn test_render_status_line() {
+        let line = StatusLine {
+            model: "claude-sonnet-4-5",
+            profile: Some("work".to_string()),
+            git: Some(GitStatus {
+                branch: "main".to_string(),
+                dirty: 3,
+            }),
+            tokens: 12_345,
+            context_window: 200_000,
+            mode: "plan",
+            url: None,
+        };
+        let rendered = line.render(200);
+        assert!(
+            rendered.starts_with("claude-sonnet-4-5 · profile work · main"),
+            "{}",
+            rendered
+        );
+        assert!(rendered.contains("12k/200k"), "{}", rendered);
+        assert!(rendered.ends_with("plan"), "{}", rendered);
+        assert_eq!(console::measure_text_width(&line.render(20)), 20);
+        assert_eq!(format_tokens(1_500), "1.5k");
+    }
+}
@@ -0,0 +1,207 @@
+//! 流式响应中 tool_use 输入 (input_json_delta) 的增量解析
+//!
+//! 目前 ApiClient 仍一次性读取完整响应；流式传输接入后，
+//! 每个 content_block_delta 事件的 partial_json 交给 `PartialToolInput::push`，
+//! 即可在 JSON 还未传完时显示目标文件并提前开始权限检查。
+#![allow(dead_code)]
+
+use anyhow::{Context, Result};
+
+/// 细粒度流式传输下，不完整的工具输入以该键包装后原样回传给 API
+pub const INVALID_JSON_KEY: &str = "INVALID_JSON";
+
+/// 被包装为 INVALID_JSON 的原始工具输入
+pub fn invalid_json(input: &serde_json::Value) -> Option<&str> {
+    input.get(INVALID_JSON_KEY).and_then(|raw| raw.as_str())
+}
+
+/// 正在流式接收的工具输入
+#[derive(Debug, Default)]
+pub struct PartialToolInput {
+    tool_name: String,
+    buffer: String,
+}
+
+impl PartialToolInput {
+    pub fn new(tool_name: impl Into<String>) -> Self {
+        Self {
+            tool_name: tool_name.into(),
+            buffer: String::new(),
+        }
+    }
+
+    /// 追加一段 partial_json
+    pub fn push(&mut self, partial_json: &str) {
+        self.buffer.push_str(partial_json);
+    }
+
+    /// 提取已经完整接收的顶层字符串字段
+    pub fn string_field(&self, key: &str) -> Option<String> {
+        scan_top_level_string(&self.buffer, key)
+    }
+
+    /// 生成用于提前展示的摘要，例如 "write_file → /tmp/a.rs"
+    pub fn preview(&self) -> Option<String> {
+        ["file_path", "command", "pattern"]
+            .iter()
+            .find_map(|key| self.string_field(key))
+            .map(|target| format!("{} → {}", self.tool_name, target))
+    }
+
+    /// content_block_stop 之后解析完整输入
+    pub fn finish(self) -> Result<serde_json::Value> {
+        if self.buffer.trim().is_empty() {
+            return Ok(serde_json::json!({}));
+        }
+        serde_json::from_str(&self.buffer)
+            .with_context(|| format!("Incomplete tool input for {}", self.tool_name))
+    }
+
+    /// 细粒度流式传输不保证输入是合法 JSON（例如在 max_tokens 处被截断），
+    /// 无法解析时包装为 {"INVALID_JSON": 原始文本}
+    pub fn finish_lenient(self) -> serde_json::Value {
+        let raw = self.buffer.clone();
+        self.finish()
+            .unwrap_or_else(|_| serde_json::json!({ INVALID_JSON_KEY: raw }))
+    }
+}
+
+/// 在可能不完整的 JSON 对象中查找顶层字符串字段
+///
+/// 只有字段值的结束引号已经到达时才返回，嵌套的值会被跳过
+fn scan_top_level_string(buffer: &str, target: &str) -> Option<String> {
+    let bytes = buffer.as_bytes();
+    let mut pos = skip_whitespace(bytes, 0);
+    if bytes.get(pos) != Some(&b'{') {
+        return None;
+    }
+    pos += 1;
+
+    loop {
+        pos = skip_whitespace(bytes, pos);
+        match bytes.get(pos)? {
+            b'}' => return None,
+            b',' => {
+                pos += 1;
+                continue;
+            }
+            b'"' => {}
+            _ => return None,
+        }
+
+        let key_end = string_end(bytes, pos)?;
+        let key: String = serde_json::from_str(&buffer[pos..key_end]).ok()?;
+        pos = skip_whitespace(bytes, key_end);
+        if bytes.get(pos)? != &b':' {
+            return None;
+        }
+        pos = skip_whitespace(bytes, pos + 1);
+
+        if bytes.get(pos)? == &b'"' {
+            let value_end = string_end(bytes, pos)?;
+            if key == target {
+                return serde_json::from_str(&buffer[pos..value_end]).ok();
+            }
+            pos = value_end;
+        } else {
+            pos = skip_value(bytes, pos)?;
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut pos: usize) -> usize {
+    while bytes.get(pos).is_some_and(|b| b.is_ascii_whitespace()) {
+        pos += 1;
+    }
+    pos
+}
+
+/// 返回从 `start`（开引号）开始的字符串结束后的位置；字符串尚未结束时返回 None
+fn string_end(bytes: &[u8], start: usize) -> Option<usize> {
+    let mut pos = start + 1;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'\\' => pos += 2,
+            b'"' => return Some(pos + 1),
+            _ => pos += 1,
+        }
+    }
+    None
+}
+
+/// 跳过一个非字符串的值（数字、布尔、数组、对象）
+fn skip_value(bytes: &[u8], mut pos: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                pos = string_end(bytes, pos)?;
+                continue;
+            }
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth > 0 => depth -= 1,
+            b',' | b'}' if depth == 0 => return Some(pos),
+            _ => {}
+        }
+        pos += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_path_available_before_content_finishes() {
+        let mut input = PartialToolInput::new("write_file");
+        input.push(r#"{"file_pa"#);
+        assert_eq!(input.string_field("file_path"), None);
+
+        input.push(r#"th": "/tmp/src/ma"#);
+        assert_eq!(input.string_field("file_path"), None);
+
+        input.push(r#"in.rs", "content": "fn main() {\n    let s = \"{\";"#);
+        assert_eq!(
+            input.string_field("file_path").as_deref(),
+            Some("/tmp/src/main.rs")
+        );
+        assert_eq!(
+            input.preview().as_deref(),
+            Some("write_file → /tmp/src/main.rs")
+        );
+        assert_eq!(input.string_field("content"), None);
+
+        input.push(r#"\n}"}"#);
+        let value = input.finish().unwrap();
+        assert_eq!(value["content"], "fn main() {\n    let s = \"{\";\n}");
+    }
+
+    #[test]
+    fn test_truncated_input_wrapped_as_invalid_json() {
+        let mut input = PartialToolInput::new("write_file");
+        input.push(r#"{"file_path": "/tmp/a.rs", "content": "fn ma"#);
+        let value = input.finish_lenient();
+        assert_eq!(
+            invalid_json(&value),
+            Some(r#"{"file_path": "/tmp/a.rs", "content": "fn ma"#)
+        );
+
+        let mut input = PartialToolInput::new("list_files");
+        input.push(r#"{"pattern": "*.rs"}"#);
+        let value = input.finish_lenient();
+        assert_eq!(value["pattern"], "*.rs");
+        assert_eq!(invalid_json(&value), None);
+    }
+
+    #[test]
+    fn test_skips_nested_values() {
+        let mut input = PartialToolInput::new("execute_command");
+        input.push(r#"{"env": {"A": "}", "B": [1, 2]}, "count": 3, "command": "cargo \"test\""}"#);
+        assert_eq!(
+            input.string_field("command").as_deref(),
+            Some("cargo \"test\"")
+        );
+        assert_eq!(input.string_field("env"), None);
+    }
+}
@@ -0,0 +1,155 @@
+use std::path::Path;
+
+/// 单个文件最多报告的语法错误数
+const MAX_ERRORS: usize = 5;
+
+/// 写入后对可识别的源文件做快速语法检查
+///
+/// 返回错误描述列表；语法正确或文件类型不支持时返回空列表
+pub fn check_syntax(path: &Path, content: &str) -> Vec<String> {
+    let extension = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+
+    match extension.as_str() {
+        "rs" => check_rust(content),
+        "json" => serde_json::from_str::<serde_json::Value>(content)
+            .err()
+            .map(|e| vec![format!("line {}, column {}: {}", e.line(), e.column(), e)])
+            .unwrap_or_default(),
+        "toml" => content
+            .parse::<toml::Table>()
+            .err()
+            .map(|e| vec![e.message().to_string()])
+            .unwrap_or_default(),
+        #[cfg(feature = "tree-sitter")]
+        "py" => check_tree_sitter(content, tree_sitter_python::LANGUAGE.into()),
+        #[cfg(feature = "tree-sitter")]
+        "js" | "mjs" | "cjs" | "jsx" => {
+            check_tree_sitter(content, tree_sitter_javascript::LANGUAGE.into())
+        }
+        #[cfg(feature = "tree-sitter")]
+        "go" => check_tree_sitter(content, tree_sitter_go::LANGUAGE.into()),
+        _ => Vec::new(),
+    }
+}
+
+fn check_rust(content: &str) -> Vec<String> {
+    match syn::parse_file(content) {
+        Ok(_) => Vec::new(),
+        Err(errors) => errors
+            .into_iter()
+            .take(MAX_ERRORS)
+            .map(|e| {
+                let start = e.span().start();
+                format!("line {}, column {}: {}", start.line, start.column + 1, e)
+            })
+            .collect(),
+    }
+}
+
+/// 用 tree-sitter 解析，收集 ERROR 和 MISSING 节点
+#[cfg(feature = "tree-sitter")]
+fn check_tree_sitter(content: &str, language: tree_sitter::Language) -> Vec<String> {
+    let mut parser = tree_sitter::Parser::new();
+    if parser.set_language(&language).is_err() {
+        return Vec::new();
+    }
+    let Some(tree) = parser.parse(content, None) else {
+        return Vec::new();
+    };
+    if !tree.root_node().has_error() {
+        return Vec::new();
+    }
+
+    let mut errors = Vec::new();
+    let mut cursor = tree.walk();
+    let mut visit_children = true;
+
+    loop {
+        let node = cursor.node();
+        if visit_children && (node.is_error() || node.is_missing()) {
+            let position = node.start_position();
+            let message = if node.is_missing() {
+                format!("missing `{}`", node.kind())
+            } else {
+                let snippet: String = content[node.byte_range()]
+                    .lines()
+                    .next()
+                    .unwrap_or("")
+                    .chars()
+                    .take(40)
+                    .collect();
+                format!("syntax error near `{}`", snippet.trim())
+            };
+            errors.push(format!(
+                "line {}, column {}: {}",
+                position.row + 1,
+                position.column + 1,
+                message
+            ));
+            if errors.len() >= MAX_ERRORS {
+                break;
+            }
+            // 不再深入已报告的错误节点
+            visit_children = false;
+        }
+
+        if visit_children && node.has_error() && cursor.goto_first_child() {
+            continue;
+        }
+        visit_children = true;
+        if cursor.goto_next_sibling() {
+            continue;
+        }
+        loop {
+            if !cursor.goto_parent() {
+                return errors;
+            }
+            if cursor.goto_next_sibling() {
+                break;
+            }
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rust_syntax_errors_reported() {
+        let path = Path::new("src/lib.rs");
+        assert!(check_syntax(path, "fn main() { let a = 1; }").is_empty());
+
+        let errors = check_syntax(path, "fn main() {\n    let a = 1\n    let b = 2;\n}");
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("line 3"), "{}", errors[0]);
+    }
+
+    #[test]
+    fn test_config_formats() {
+        assert!(check_syntax(Path::new("a.json"), r#"{"a": 1}"#).is_empty());
+        assert!(!check_syntax(Path::new("a.json"), r#"{"a": 1,}"#).is_empty());
+        assert!(check_syntax(Path::new("Cargo.toml"), "[package]\nname = \"x\"").is_empty());
+        assert!(!check_syntax(Path::new("Cargo.toml"), "[package\nname = ").is_empty());
+        assert!(check_syntax(Path::new("notes.txt"), "{{{").is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "tree-sitter")]
+    fn test_tree_sitter_languages() {
+        assert!(check_syntax(Path::new("a.py"), "def f(x):\n    return x\n").is_empty());
+        let errors = check_syntax(Path::new("a.py"), "def f(x)\n    return x\n");
+        assert!(!errors.is_empty());
+        assert!(errors[0].starts_with("line 1"), "{}", errors[0]);
+
+        assert!(check_syntax(Path::new("a.js"), "const a = [1, 2];").is_empty());
+        assert!(!check_syntax(Path::new("a.js"), "const a = [1, 2;").is_empty());
+        assert!(!check_syntax(Path::new("main.go"), "package main\nfunc main( {}").is_empty());
+    }
+}
@@ -0,0 +1,535 @@
+use anyhow::{bail, Context, Result};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::error::ClaudeCodeError;
+use crate::{dotenv, pty, shell as shell_words, urls};
+
+/// 每个后台任务最多保留的输出字节数，超出时丢弃最早的部分
+const MAX_OUTPUT_BYTES: usize = 1024 * 1024;
+
+/// 命令停在输入密码或凭据处的迹象：提示行，或没有终端可读时 sudo、git、ssh 的报错
+static CREDENTIAL_PROMPT: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(
+        r"(?im)^.*(password|passphrase|username|one-time code|otp)[^\n]{0,60}:[ \t]*$|^.*(a terminal is required|no tty present|terminal prompts disabled|could not read (username|password)|askpass).*$",
+    )
+    .unwrap()
+});
+
+/// 本次会话启动的后台任务
+static TASKS: Lazy<Mutex<Vec<Task>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// 后台任务的状态
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TaskStatus {
+    Running,
+    /// 进程已退出；被信号终止时没有退出码
+    Exited(Option<i32>),
+    /// 被 kill_task 或 /tasks 终止
+    Killed,
+}
+
+/// 任务输出缓冲区，`start` 是缓冲区第一个字节在全部输出中的位置
+#[derive(Default)]
+struct OutputBuffer {
+    text: String,
+    start: usize,
+}
+
+impl OutputBuffer {
+    fn push(&mut self, chunk: &str) {
+        self.text.push_str(chunk);
+        if self.text.len() > MAX_OUTPUT_BYTES {
+            let mut cut = self.text.len() - MAX_OUTPUT_BYTES;
+            while !self.text.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.text.drain(..cut);
+            self.start += cut;
+        }
+    }
+
+    /// 从位置 `from` 开始的输出，以及被丢弃而读不到的字节数
+    fn since(&self, from: usize) -> (&str, usize) {
+        let skipped = self.start.saturating_sub(from);
+        let offset = from.saturating_sub(self.start).min(self.text.len());
+        (&self.text[offset..], skipped)
+    }
+
+    fn end(&self) -> usize {
+        self.start + self.text.len()
+    }
+}
+
+struct Task {
+    id: usize,
+    command: String,
+    started: Instant,
+    child: Child,
+    killed: bool,
+    output: Arc<Mutex<OutputBuffer>>,
+    /// 模型已经读取到的位置
+    read_to: usize,
+}
+
+impl Task {
+    fn status(&mut self) -> TaskStatus {
+        if self.killed {
+            return TaskStatus::Killed;
+        }
+        match self.child.try_wait() {
+            Ok(Some(status)) => TaskStatus::Exited(status.code()),
+            Ok(None) => TaskStatus::Running,
+            Err(_) => TaskStatus::Exited(None),
+        }
+    }
+}
+
+/// /tasks 中显示的一个任务
+#[derive(Debug, Clone)]
+pub struct TaskSummary {
+    pub id: usize,
+    pub command: String,
+    pub status: TaskStatus,
+    pub elapsed: Duration,
+}
+
+/// 用系统 shell 执行命令
+pub fn shell(command: &str) -> Command {
+    if cfg!(target_os = "windows") {
+        let mut cmd = Command::new("cmd");
+        cmd.args(["/C", command]);
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", command]);
+        cmd
+    }
+}
+
+/// 把命令放到没有控制终端的新会话中，同时也是新的进程组，能连同子进程一起终止
+///
+/// 留在原会话的后台进程组时，sudo、ssh 等读取 /dev/tty 会被 SIGTTIN 停住，永远等下去；
+/// 没有控制终端时它们会立即报错退出
+#[cfg(unix)]
+fn detach(cmd: &mut Command) {
+    use std::os::unix::process::CommandExt;
+    // SAFETY: pre_exec 中只调用 async-signal-safe 的 setsid
+    unsafe {
+        cmd.pre_exec(|| {
+            if libc::setsid() == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    cmd.env("GIT_TERMINAL_PROMPT", "0");
+}
+
+/// 运行命令并收集输出；`cancel` 被触发时终止命令及其子进程，返回 ClaudeCodeError::Interrupted
+pub async fn output(cmd: Command, cancel: &CancellationToken) -> Result<Output> {
+    run(cmd, cancel, false).await
+}
+
+/// 与 `output` 相同，但标准输出和标准错误接到伪终端上，两者合并在 stdout 中，
+/// 已去掉 ANSI 转义；无法打开伪终端时退回到管道
+pub async fn output_in_terminal(cmd: Command, cancel: &CancellationToken) -> Result<Output> {
+    run(cmd, cancel, true).await
+}
+
+async fn run(mut cmd: Command, cancel: &CancellationToken, terminal: bool) -> Result<Output> {
+    // 不在终端中运行时放到独立的会话，取消时能连同子进程一起结束；
+    // 交互模式下保持在前台进程组，Ctrl+C 仍然能中断命令，sudo 等也能在终端上询问密码
+    #[cfg(unix)]
+    let own_group = !std::io::IsTerminal::is_terminal(&std::io::stdin());
+    #[cfg(unix)]
+    if own_group {
+        detach(&mut cmd);
+    }
+    dotenv::apply(&mut cmd);
+    let program = cmd.get_program().to_string_lossy().to_string();
+    let pty = if terminal {
+        pty::open()
+            .inspect_err(|e| {
+                if cfg!(unix) {
+                    warn!("Failed to open a pseudo-terminal, using pipes: {}", e)
+                }
+            })
+            .ok()
+    } else {
+        None
+    };
+    cmd.stdin(Stdio::null());
+    match &pty {
+        Some(pty) => {
+            cmd.stdout(pty.slave.try_clone()?)
+                .stderr(pty.slave.try_clone()?)
+                // 没有人能在伪终端上翻页，分页器直接输出
+                .env("PAGER", "cat")
+                .env("GIT_PAGER", "cat");
+            if std::env::var_os("TERM").is_none() {
+                cmd.env("TERM", "xterm-256color");
+            }
+        }
+        None => {
+            cmd.stdout(Stdio::piped()).stderr(Stdio::piped());
+        }
+    }
+    let mut child = cmd
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+    let pid = child.id();
+    // Command 还持有 slave 的副本，不关闭的话读取端永远等不到结束
+    drop(cmd);
+    let mut waiter = match pty {
+        Some(pty::Pty { master, slave }) => {
+            drop(slave);
+            tokio::task::spawn_blocking(move || {
+                let output = pty::read_all(master);
+                child.wait().map(|status| Output {
+                    status,
+                    stdout: pty::clean(&output).into_bytes(),
+                    stderr: Vec::new(),
+                })
+            })
+        }
+        None => tokio::task::spawn_blocking(move || child.wait_with_output()),
+    };
+
+    tokio::select! {
+        result = &mut waiter => Ok(result?.with_context(|| format!("Failed to run {}", program))?),
+        _ = cancel.cancelled() => {
+            #[cfg(unix)]
+            {
+                let target = if own_group { -(pid as libc::pid_t) } else { pid as libc::pid_t };
+                // SAFETY: 只向这条命令自己的进程（组）发送信号
+                unsafe { libc::kill(target, libc::SIGKILL) };
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = Command::new("taskkill")
+                    .args(["/F", "/T", "/PID", &pid.to_string()])
+                    .output();
+            }
+            // 子进程可能还持有管道，不无限等待读取线程
+            let _ = tokio::time::timeout(Duration::from_secs(1), waiter).await;
+            Err(ClaudeCodeError::Interrupted.into())
+        }
+    }
+}
+
+/// 在后台启动命令，立即返回任务 ID
+pub fn spawn(command: &str, dir: &Path) -> Result<usize> {
+    let mut cmd = shell(command);
+    cmd.current_dir(dir);
+    dotenv::apply(&mut cmd);
+    // 放到独立的会话中：终端的 Ctrl+C 不会影响它，终止时也能连同子进程一起结束
+    #[cfg(unix)]
+    detach(&mut cmd);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to start background command: {}", command))?;
+
+    let output = Arc::new(Mutex::new(OutputBuffer::default()));
+    if let Some(stdout) = child.stdout.take() {
+        collect(stdout, Arc::clone(&output));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        collect(stderr, Arc::clone(&output));
+    }
+
+    let mut tasks = TASKS.lock().unwrap();
+    let id = tasks.len() + 1;
+    tasks.push(Task {
+        id,
+        command: command.to_string(),
+        started: Instant::now(),
+        child,
+        killed: false,
+        output,
+        read_to: 0,
+    });
+    Ok(id)
+}
+
+/// 命令是否可能在终端上询问密码，例如 `sudo make install`、`git push && ssh host`
+pub fn may_prompt(command: &str) -> bool {
+    let Ok(parsed) = shell_words::parse(command) else {
+        return false;
+    };
+    parsed.commands.iter().any(|simple| {
+        let args = &simple.argv[1..];
+        match simple.program() {
+            "sudo" => !args
+                .iter()
+                .any(|arg| arg == "-n" || arg == "--non-interactive"),
+            "ssh" | "scp" | "sftp" => !args.iter().any(|arg| arg.contains("BatchMode=yes")),
+            "npm" | "yarn" | "pnpm" | "docker" | "podman" | "gh" => args
+                .first()
+                .is_some_and(|arg| arg == "login" || arg == "adduser"),
+            "su" | "doas" | "passwd" => true,
+            _ => false,
+        }
+    })
+}
+
+/// 输出中最后一处停在凭据提示的行，例如 `[sudo] password for alice: `
+pub fn credential_prompt(output: &str) -> Option<&str> {
+    CREDENTIAL_PROMPT
+        .find_iter(output)
+        .last()
+        .map(|found| found.as_str().trim())
+}
+
+/// 在单独的线程中把管道中的输出追加到缓冲区
+fn collect(mut pipe: impl Read + Send + 'static, output: Arc<Mutex<OutputBuffer>>) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n) = pipe.read(&mut buf) {
+            if n == 0 {
+                break;
+            }
+            let chunk = String::from_utf8_lossy(&buf[..n]);
+            urls::record(&chunk);
+            output.lock().unwrap().push(&chunk);
+        }
+    });
+}
+
+fn with_task<T>(id: usize, f: impl FnOnce(&mut Task) -> Result<T>) -> Result<T> {
+    let mut tasks = TASKS.lock().unwrap();
+    match tasks.iter_mut().find(|task| task.id == id) {
+        Some(task) => f(task),
+        None => bail!("No background task with id {}", id),
+    }
+}
+
+/// 任务状态和上次读取之后的新输出
+pub fn check_output(id: usize) -> Result<(TaskStatus, String)> {
+    with_task(id, |task| {
+        // 先取状态再读输出，进程退出前的最后输出不会被漏掉
+        let status = task.status();
+        if status != TaskStatus::Running {
+            // 等读取线程把管道中剩余的内容写完
+            thread::sleep(Duration::from_millis(50));
+        }
+        let output = task.output.lock().unwrap();
+        let (text, skipped) = output.since(task.read_to);
+        let mut result = String::new();
+        if skipped > 0 {
+            result.push_str(&format!("[{} earlier bytes dropped]\n", skipped));
+        }
+        result.push_str(text);
+        task.read_to = output.end();
+        Ok((status, result))
+    })
+}
+
+/// 任务状态和最近的 `lines` 行输出，不影响 check_output 的读取位置
+pub fn tail(id: usize, lines: usize) -> Result<(TaskStatus, String)> {
+    with_task(id, |task| {
+        let status = task.status();
+        let output = task.output.lock().unwrap();
+        Ok((status, last_lines(&output.text, lines).to_string()))
+    })
+}
+
+fn last_lines(text: &str, lines: usize) -> &str {
+    let trimmed = text.trim_end_matches('\n');
+    match trimmed.rmatch_indices('\n').nth(lines.saturating_sub(1)) {
+        Some((index, _)) if lines > 0 => &text[index + 1..],
+        _ if lines == 0 => "",
+        _ => text,
+    }
+}
+
+/// 终止任务的整个进程组（shell 以及它启动的命令）
+fn terminate(child: &mut Child) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let pid = child.id() as libc::pid_t;
+        // SAFETY: 只向该任务自己的进程组发送信号
+        if unsafe { libc::kill(-pid, libc::SIGKILL) } == 0 {
+            child.wait()?;
+            return Ok(());
+        }
+    }
+    child.kill()?;
+    child.wait().map(|_| ())
+}
+
+/// 终止任务
+pub fn kill(id: usize) -> Result<TaskStatus> {
+    with_task(id, |task| {
+        let status = task.status();
+        if status != TaskStatus::Running {
+            return Ok(status);
+        }
+        terminate(&mut task.child)
+            .with_context(|| format!("Failed to kill background task {}", id))?;
+        task.killed = true;
+        Ok(TaskStatus::Killed)
+    })
+}
+
+pub fn list() -> Vec<TaskSummary> {
+    TASKS
+        .lock()
+        .unwrap()
+        .iter_mut()
+        .map(|task| TaskSummary {
+            id: task.id,
+            command: task.command.clone(),
+            status: task.status(),
+            elapsed: task.started.elapsed(),
+        })
+        .collect()
+}
+
+/// 退出时终止仍在运行的任务
+pub fn kill_all() {
+    for task in TASKS.lock().unwrap().iter_mut() {
+        if task.status() == TaskStatus::Running {
+            let _ = terminate(&mut task.child);
+            task.killed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_buffer_keeps_tail() {
+        let mut buffer = OutputBuffer::default();
+        buffer.push("hello ");
+        assert_eq!(buffer.since(0), ("hello ", 0));
+        buffer.push(&"x".repeat(MAX_OUTPUT_BYTES));
+        assert_eq!(buffer.text.len(), MAX_OUTPUT_BYTES);
+        let (text, skipped) = buffer.since(3);
+        assert_eq!(skipped, 3);
+        assert_eq!(text.len(), MAX_OUTPUT_BYTES);
+        assert_eq!(buffer.since(buffer.end()), ("", 0));
+
+        assert_eq!(last_lines("a\nb\nc\n", 2), "b\nc\n");
+        assert_eq!(last_lines("a\nb", 5), "a\nb");
+        assert_eq!(last_lines("a\nb", 0), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_background_task_lifecycle() {
+        let id = spawn("echo started; sleep 30", Path::new(".")).unwrap();
+        let mut output = String::new();
+        for _ in 0..100 {
+            let (status, text) = check_output(id).unwrap();
+            assert_eq!(status, TaskStatus::Running);
+            output.push_str(&text);
+            if !output.is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(output, "started\n");
+        // 已读的输出不会重复返回
+        assert_eq!(check_output(id).unwrap().1, "");
+
+        assert_eq!(kill(id).unwrap(), TaskStatus::Killed);
+        assert!(list()
+            .iter()
+            .any(|task| task.id == id && task.status == TaskStatus::Killed));
+        assert!(check_output(9999).is_err());
+    }
+
+    #[test]
+    fn test_detect_credential_prompts() {
+        assert!(may_prompt("sudo make install"));
+        assert!(may_prompt("cargo build && ssh deploy@host ./restart.sh"));
+        assert!(may_prompt("npm login"));
+        assert!(!may_prompt("sudo -n systemctl restart app"));
+        assert!(!may_prompt("ssh -o BatchMode=yes host uptime"));
+        assert!(!may_prompt("echo sudo"));
+
+        assert_eq!(
+            credential_prompt("Building...\n[sudo] password for alice: "),
+            Some("[sudo] password for alice:")
+        );
+        assert_eq!(
+            credential_prompt(
+                "fatal: could not read Username for 'https://github.com': terminal prompts disabled"
+            ),
+            Some("fatal: could not read Username for 'https://github.com': terminal prompts disabled")
+        );
+        assert_eq!(
+            credential_prompt("error: expected `;`, found `password`"),
+            None
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_command_output_in_terminal() {
+        let cancel = CancellationToken::new();
+        let terminal = output_in_terminal(
+            shell("test -t 1 && printf '\\033[1mtty\\033[0m\\n'; printf '1%%\\r100%%\\n' >&2"),
+            &cancel,
+        )
+        .await
+        .unwrap();
+        assert!(terminal.status.success());
+        assert_eq!(String::from_utf8_lossy(&terminal.stdout), "tty\n100%\n");
+
+        let piped = output(shell("test -t 1 || echo pipe"), &cancel)
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&piped.stdout), "pipe\n");
+    }
+
+    /// 没有控制终端时读取 /dev/tty 立即失败，而不是被停住
+    #[cfg(unix)]
+    #[test]
+    fn test_background_task_cannot_read_terminal() {
+        let id = spawn("cat /dev/tty", Path::new(".")).unwrap();
+        let mut status = TaskStatus::Running;
+        for _ in 0..250 {
+            status = check_output(id).unwrap().0;
+            if status != TaskStatus::Running {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(matches!(status, TaskStatus::Exited(Some(code)) if code != 0));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_cancelled_command_is_killed() {
+        let cancel = CancellationToken::new();
+        let trigger = cancel.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            trigger.cancel();
+        });
+        let started = Instant::now();
+        let result = output(shell("sleep 30"), &cancel).await;
+        assert_eq!(crate::error::kind(&result.unwrap_err()), "interrupted");
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let done = output(shell("echo done"), &CancellationToken::new())
+            .await
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&done.stdout), "done\n");
+    }
+}
@@ -0,0 +1,187 @@
+use console::style;
+use dialoguer::Confirm;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use tracing::info;
+
+use crate::config::{Config, UserSettings};
+use crate::exit_code::ExitStatus;
+use crate::i18n::{t, tf, Msg};
+use crate::ui;
+
+/// 会话结束时发送统计的超时时间，不能让退出明显变慢
+const SEND_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// 本次会话的使用统计；未同意上报时 endpoint 为 None，不记录任何事件
+static TELEMETRY: Lazy<Mutex<Telemetry>> = Lazy::new(|| Mutex::new(Telemetry::default()));
+
+#[derive(Default)]
+struct Telemetry {
+    endpoint: Option<String>,
+    /// (事件类型, 名称) → 次数，只包含工具名、命令名和错误类别
+    counts: BTreeMap<(&'static str, String), u64>,
+}
+
+/// 上报的内容：版本、平台和各事件的次数，不含任何标识、路径、提示词或代码
+#[derive(Debug, Serialize)]
+struct Report<'a> {
+    version: &'static str,
+    os: &'static str,
+    arch: &'static str,
+    events: Vec<EventCount<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct EventCount<'a> {
+    kind: &'a str,
+    name: &'a str,
+    count: u64,
+}
+
+/// 设置了 DO_NOT_TRACK（值不是 0 或 false）时不询问也不上报
+pub fn do_not_track() -> bool {
+    std::env::var("DO_NOT_TRACK").is_ok_and(|value| {
+        !matches!(value.trim(), "" | "0") && !value.eq_ignore_ascii_case("false")
+    })
+}
+
+/// 按配置开启或关闭上报；配置热更新后也会再次调用
+pub fn init(settings: &UserSettings) {
+    let enabled = settings.telemetry == Some(true) && !do_not_track();
+    let mut telemetry = TELEMETRY.lock().unwrap();
+    telemetry.endpoint = settings
+        .telemetry_endpoint
+        .clone()
+        .filter(|endpoint| enabled && !endpoint.is_empty());
+    if telemetry.endpoint.is_none() {
+        telemetry.counts.clear();
+    }
+}
+
+/// 配置了上报地址但用户还没有做出选择时，在交互模式下询问一次并保存到配置
+pub fn ask_consent(config: &mut Config) {
+    let settings = &config.user_settings;
+    if settings.telemetry.is_some() || do_not_track() {
+        return;
+    }
+    let Some(endpoint) = settings.telemetry_endpoint.clone() else {
+        return;
+    };
+
+    // 无法交互时不保存，下次启动再询问
+    let Ok(consent) = Confirm::with_theme(ui::dialog_theme().as_ref())
+        .with_prompt(tf(Msg::TelemetryConsent, &[&endpoint]))
+        .default(false)
+        .interact()
+    else {
+        return;
+    };
+    config.user_settings.telemetry = Some(consent);
+    if let Err(e) = config.save_setting("telemetry", consent.into()) {
+        println!("{}", style(format!("{:#}", e)).red());
+    }
+    println!("{}", style(t(Msg::TelemetryChoiceSaved)).dim());
+}
+
+/// 记录一次功能使用，例如 ("tool", "read_file") 或 ("command", "/status")
+pub fn record(kind: &'static str, name: &str) {
+    let mut telemetry = TELEMETRY.lock().unwrap();
+    if telemetry.endpoint.is_some() {
+        *telemetry
+            .counts
+            .entry((kind, name.to_string()))
+            .or_default() += 1;
+    }
+}
+
+/// 记录导致退出的错误类别（与退出码对应），不包含错误信息本身
+pub fn record_error(err: &anyhow::Error) {
+    record("error", &format!("{:?}", ExitStatus::from_error(err)));
+}
+
+fn report<'a>(counts: &'a BTreeMap<(&'static str, String), u64>) -> Report<'a> {
+    Report {
+        version: env!("CARGO_PKG_VERSION"),
+        os: std::env::consts::OS,
+        arch: std::env::consts::ARCH,
+        events: counts
+            .iter()
+            .map(|((kind, name), count)| EventCount {
+                kind,
+                name,
+                count: *count,
+            })
+            .collect(),
+    }
+}
+
+/// 会话结束时把统计发送到配置的地址，失败时直接丢弃
+pub async fn flush() {
+    let (endpoint, counts) = {
+        let mut telemetry = TELEMETRY.lock().unwrap();
+        let Some(endpoint) = telemetry.endpoint.clone() else {
+            return;
+        };
+        (endpoint, std::mem::take(&mut telemetry.counts))
+    };
+    if counts.is_empty() {
+        return;
+    }
+
+    let result = reqwest::Client::new()
+        .post(&endpoint)
+        .json(&report(&counts))
+        .timeout(SEND_TIMEOUT)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+    if let Err(e) = result {
+        info!("Failed to send usage statistics: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_contains_only_counts() {
+        let mut counts = BTreeMap::new();
+        counts.insert(("tool", "read_file".to_string()), 3);
+        counts.insert(("error", "AuthFailed".to_string()), 1);
+
+        let report = serde_json::to_value(report(&counts)).unwrap();
+        assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(
+            report["events"],
+            serde_json::json!([
+                {"kind": "error", "name": "AuthFailed", "count": 1},
+                {"kind": "tool", "name": "read_file", "count": 3}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_disabled_until_consent() {
+        let mut settings = UserSettings {
+            telemetry_endpoint: Some("http://127.0.0.1:9/usage".to_string()),
+            ..UserSettings::default()
+        };
+        init(&settings);
+        record("tool", "read_file");
+        assert!(TELEMETRY.lock().unwrap().counts.is_empty());
+
+        settings.telemetry = Some(true);
+        init(&settings);
+        let enabled = !do_not_track();
+        record("tool", "read_file");
+        assert_eq!(TELEMETRY.lock().unwrap().counts.is_empty(), !enabled);
+
+        settings.telemetry = Some(false);
+        init(&settings);
+        assert!(TELEMETRY.lock().unwrap().counts.is_empty());
+    }
+}
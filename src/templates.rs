@@ -0,0 +1,139 @@
+use anyhow::{bail, Context, Result};
+use handlebars::Handlebars;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// 模板文件的扩展名
+const TEMPLATE_EXTENSION: &str = "md";
+
+/// 解析 `--var KEY=VALUE`
+pub fn parse_var(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.to_string()))
+        }
+        _ => Err(format!("expected KEY=VALUE, got '{}'", raw)),
+    }
+}
+
+/// 模板的查找目录：先项目 .claude/templates，再用户 ~/.claude/templates
+pub fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    if let Ok(current_dir) = std::env::current_dir() {
+        dirs.push(current_dir.join(".claude").join("templates"));
+    }
+    if let Some(home) = std::env::var_os("HOME").filter(|home| !home.is_empty()) {
+        dirs.push(PathBuf::from(home).join(".claude").join("templates"));
+    }
+    dirs
+}
+
+/// 按名称查找模板；名称本身是已存在的文件路径时直接使用
+pub fn find(name: &str, dirs: &[PathBuf]) -> Result<PathBuf> {
+    let as_path = Path::new(name);
+    if as_path.is_file() {
+        return Ok(as_path.to_path_buf());
+    }
+
+    let file_name = format!("{}.{}", name, TEMPLATE_EXTENSION);
+    if let Some(path) = dirs
+        .iter()
+        .map(|dir| dir.join(&file_name))
+        .find(|path| path.is_file())
+    {
+        return Ok(path);
+    }
+
+    let searched: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+    bail!(
+        "Template '{}' not found (looked for {} in {})",
+        name,
+        file_name,
+        searched.join(", ")
+    )
+}
+
+/// 用变量渲染模板（handlebars 语法）；引用了未提供的变量时返回错误
+pub fn render(template: &str, vars: &[(String, String)]) -> Result<String> {
+    let mut registry = Handlebars::new();
+    registry.set_strict_mode(true);
+    // 提示词是纯文本，不做 HTML 转义
+    registry.register_escape_fn(handlebars::no_escape);
+
+    let data: BTreeMap<&str, &str> = vars
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    registry
+        .render_template(template, &data)
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+/// 查找并渲染模板，得到要发送的提示词
+pub fn load_and_render(name: &str, vars: &[(String, String)]) -> Result<String> {
+    let path = find(name, &search_dirs())?;
+    let template = fs::read_to_string(&path)
+        .with_context(|| format!("Failed to read template: {}", path.display()))?;
+    render(&template, vars)
+        .with_context(|| format!("Failed to render template: {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_render_variables() {
+        let rendered = render(
+            "Refactor {{file}}{{#if goal}} to {{goal}}{{/if}}. Keep <T> & tests.",
+            &vars(&[("file", "src/lib.rs"), ("goal", "reduce cloning")]),
+        )
+        .unwrap();
+        assert_eq!(
+            rendered,
+            "Refactor src/lib.rs to reduce cloning. Keep <T> & tests."
+        );
+
+        let err = render("Refactor {{file}}", &[]).unwrap_err();
+        assert!(err.to_string().contains("file"), "{}", err);
+    }
+
+    #[test]
+    fn test_find_template_in_search_order() {
+        let project = TempDir::new().unwrap();
+        let user = TempDir::new().unwrap();
+        fs::write(project.path().join("refactor.md"), "project").unwrap();
+        fs::write(user.path().join("refactor.md"), "user").unwrap();
+        fs::write(user.path().join("review.md"), "user").unwrap();
+        let dirs = vec![project.path().to_path_buf(), user.path().to_path_buf()];
+
+        assert_eq!(
+            find("refactor", &dirs).unwrap(),
+            project.path().join("refactor.md")
+        );
+        assert_eq!(
+            find("review", &dirs).unwrap(),
+            user.path().join("review.md")
+        );
+        assert!(find("missing", &dirs).is_err());
+    }
+
+    #[test]
+    fn test_parse_var() {
+        assert_eq!(
+            parse_var("file=src/a=b.rs").unwrap(),
+            ("file".to_string(), "src/a=b.rs".to_string())
+        );
+        assert!(parse_var("novalue").is_err());
+        assert!(parse_var("=x").is_err());
+    }
+}
@@ -0,0 +1,513 @@
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::path::Path;
+use std::process::Command;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::tasks;
+
+/// 每个失败用例最多返回的输出行数
+const MAX_DETAIL_LINES: usize = 30;
+/// 最多列出的失败用例数
+const MAX_FAILURES: usize = 10;
+/// 测试没能运行（例如编译失败）时返回的输出行数
+const MAX_ERROR_LINES: usize = 40;
+
+/// 支持的测试框架
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framework {
+    Cargo,
+    Pytest,
+    Jest,
+}
+
+impl fmt::Display for Framework {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Framework::Cargo => "cargo test",
+            Framework::Pytest => "pytest",
+            Framework::Jest => "jest",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl Framework {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "cargo" | "cargo test" | "rust" => Some(Framework::Cargo),
+            "pytest" | "python" => Some(Framework::Pytest),
+            "jest" => Some(Framework::Jest),
+            _ => None,
+        }
+    }
+
+    /// 根据项目文件判断使用哪个测试框架
+    pub fn detect(dir: &Path) -> Option<Self> {
+        if dir.join("Cargo.toml").is_file() {
+            return Some(Framework::Cargo);
+        }
+        if let Ok(package) = std::fs::read_to_string(dir.join("package.json")) {
+            if package.contains("\"jest\"") {
+                return Some(Framework::Jest);
+            }
+        }
+        let python_markers = [
+            "pytest.ini",
+            "conftest.py",
+            "pyproject.toml",
+            "setup.cfg",
+            "tox.ini",
+        ];
+        if python_markers.iter().any(|name| dir.join(name).is_file()) {
+            return Some(Framework::Pytest);
+        }
+        None
+    }
+}
+
+/// 一个失败的测试用例
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestFailure {
+    pub name: String,
+    pub details: String,
+}
+
+/// 一次测试运行的结果
+#[derive(Debug, Clone, PartialEq)]
+pub struct TestSummary {
+    pub framework: Framework,
+    pub passed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub failures: Vec<TestFailure>,
+    /// 没有解析到任何测试结果时的输出末尾，通常是编译或收集错误
+    pub error: Option<String>,
+}
+
+impl TestSummary {
+    fn new(framework: Framework) -> Self {
+        Self {
+            framework,
+            passed: 0,
+            failed: 0,
+            skipped: 0,
+            failures: Vec::new(),
+            error: None,
+        }
+    }
+
+    pub fn success(&self) -> bool {
+        self.failed == 0 && self.error.is_none()
+    }
+
+    /// 返回给模型和用户的简要结果
+    pub fn render(&self) -> String {
+        if let Some(error) = &self.error {
+            return format!(
+                "{} did not run any tests. Last lines of output:\n{}",
+                self.framework, error
+            );
+        }
+
+        let mut out = format!(
+            "{}: {} passed, {} failed, {} skipped",
+            self.framework, self.passed, self.failed, self.skipped
+        );
+        for failure in self.failures.iter().take(MAX_FAILURES) {
+            out.push_str(&format!("\n\nFAILED {}", failure.name));
+            if !failure.details.is_empty() {
+                out.push('\n');
+                out.push_str(&last_lines(&failure.details, MAX_DETAIL_LINES));
+            }
+        }
+        if self.failures.len() > MAX_FAILURES {
+            out.push_str(&format!(
+                "\n\n... and {} more failures",
+                self.failures.len() - MAX_FAILURES
+            ));
+        }
+        out
+    }
+}
+
+/// 工具参数中指定的框架，未指定时根据当前目录检测
+pub fn resolve(name: Option<&str>) -> Result<Framework> {
+    match name {
+        Some(name) => Framework::parse(name)
+            .with_context(|| format!("Unknown test framework: {} (expected cargo, pytest or jest)", name)),
+        None => Framework::detect(Path::new(".")).context(
+            "Could not detect a test framework (no Cargo.toml, package.json with jest, or pytest configuration)",
+        ),
+    }
+}
+
+/// 运行测试的命令；`filter` 只运行名称匹配的用例
+fn command(framework: Framework, filter: Option<&str>) -> Command {
+    match framework {
+        Framework::Cargo => {
+            let mut cmd = Command::new("cargo");
+            cmd.args(["test", "--no-fail-fast"]);
+            cmd.args(filter);
+            cmd
+        }
+        Framework::Pytest => {
+            let mut cmd = Command::new("python3");
+            cmd.args([
+                "-m",
+                "pytest",
+                "-q",
+                "-rfE",
+                "--tb=short",
+                "-p",
+                "no:cacheprovider",
+            ]);
+            if let Some(filter) = filter {
+                cmd.args(["-k", filter]);
+            }
+            cmd
+        }
+        Framework::Jest => {
+            let mut cmd = Command::new("npx");
+            cmd.args(["--no-install", "jest", "--json"]);
+            if let Some(filter) = filter {
+                cmd.args(["-t", filter]);
+            }
+            cmd
+        }
+    }
+}
+
+/// 显示给用户确认的命令行
+pub fn command_line(framework: Framework, filter: Option<&str>) -> String {
+    let cmd = command(framework, filter);
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// 运行测试并解析结果
+pub async fn run(
+    framework: Framework,
+    filter: Option<&str>,
+    cancel: &CancellationToken,
+) -> Result<TestSummary> {
+    let mut cmd = command(framework, filter);
+    match framework {
+        Framework::Cargo => {
+            let (stdout, stderr) = output(cmd, cancel).await?;
+            Ok(parse_cargo(&stdout, &stderr))
+        }
+        Framework::Pytest => {
+            let (stdout, stderr) = output(cmd, cancel).await?;
+            Ok(parse_pytest(&stdout, &stderr))
+        }
+        Framework::Jest => {
+            // 结果写到文件，避免与测试自身的输出混在一起
+            let report = std::env::temp_dir().join(format!("jest-{}.json", Uuid::new_v4()));
+            cmd.arg("--outputFile").arg(&report);
+            let (stdout, stderr) = output(cmd, cancel).await?;
+            let json = std::fs::read_to_string(&report);
+            let _ = std::fs::remove_file(&report);
+            match json {
+                Ok(json) => parse_jest(&json),
+                Err(_) => {
+                    let mut summary = TestSummary::new(Framework::Jest);
+                    summary.error = Some(last_lines(
+                        &format!("{}{}", stdout, stderr),
+                        MAX_ERROR_LINES,
+                    ));
+                    Ok(summary)
+                }
+            }
+        }
+    }
+}
+
+async fn output(cmd: Command, cancel: &CancellationToken) -> Result<(String, String)> {
+    let output = tasks::output(cmd, cancel).await?;
+    Ok((
+        String::from_utf8_lossy(&output.stdout).to_string(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    ))
+}
+
+/// 解析 libtest 的文本输出，多个测试二进制的结果会累加
+fn parse_cargo(stdout: &str, stderr: &str) -> TestSummary {
+    let mut summary = TestSummary::new(Framework::Cargo);
+    let mut saw_result = false;
+    let mut current: Option<TestFailure> = None;
+
+    for line in stdout.lines() {
+        if let Some(name) = line
+            .strip_prefix("---- ")
+            .and_then(|rest| rest.strip_suffix(" stdout ----"))
+        {
+            summary.failures.extend(current.take());
+            current = Some(TestFailure {
+                name: name.to_string(),
+                details: String::new(),
+            });
+            continue;
+        }
+        if line == "failures:" || line.starts_with("test result:") {
+            summary.failures.extend(current.take());
+        }
+        if let Some(failure) = current.as_mut() {
+            failure.details.push_str(line);
+            failure.details.push('\n');
+            continue;
+        }
+        if let Some(result) = line.strip_prefix("test result: ") {
+            saw_result = true;
+            for part in result.split(['.', ';']) {
+                let mut words = part.split_whitespace();
+                let (Some(count), Some(kind)) = (words.next(), words.next()) else {
+                    continue;
+                };
+                let Ok(count) = count.parse::<usize>() else {
+                    continue;
+                };
+                match kind {
+                    "passed" => summary.passed += count,
+                    "failed" => summary.failed += count,
+                    "ignored" => summary.skipped += count,
+                    _ => {}
+                }
+            }
+        }
+    }
+    summary.failures.extend(current);
+
+    for failure in &mut summary.failures {
+        failure.details = failure.details.trim_end().to_string();
+    }
+    if !saw_result {
+        summary.error = Some(last_lines(
+            &format!("{}{}", stdout, stderr),
+            MAX_ERROR_LINES,
+        ));
+    }
+    summary
+}
+
+/// 解析 `pytest -q -rfE --tb=short` 的输出
+fn parse_pytest(stdout: &str, stderr: &str) -> TestSummary {
+    let mut summary = TestSummary::new(Framework::Pytest);
+    let mut saw_result = false;
+    // FAILURES 部分中每个用例的回溯，顺序与简要汇总中的 FAILED 行一致
+    let mut blocks: Vec<String> = Vec::new();
+    let mut in_failures = false;
+
+    for line in stdout.lines() {
+        if line.starts_with("====") {
+            in_failures = line.contains(" FAILURES ") || line.contains(" ERRORS ");
+            continue;
+        }
+        if in_failures {
+            if line.starts_with("____") {
+                blocks.push(String::new());
+            } else if let Some(block) = blocks.last_mut() {
+                block.push_str(line);
+                block.push('\n');
+            }
+            continue;
+        }
+        if let Some(rest) = line
+            .strip_prefix("FAILED ")
+            .or_else(|| line.strip_prefix("ERROR "))
+        {
+            let name = rest.split(" - ").next().unwrap_or(rest).to_string();
+            let details = blocks
+                .get(summary.failures.len())
+                .map(|block| block.trim_end().to_string())
+                .unwrap_or_else(|| rest.split_once(" - ").map_or("", |(_, m)| m).to_string());
+            summary.failures.push(TestFailure { name, details });
+            continue;
+        }
+        // 最后一行形如 "1 failed, 3 passed, 1 skipped in 0.12s"
+        if line.contains(" in ") && (line.contains("passed") || line.contains("failed")) {
+            saw_result = true;
+            for part in line.split(',') {
+                let mut words = part.split_whitespace();
+                let (Some(count), Some(kind)) = (words.next(), words.next()) else {
+                    continue;
+                };
+                let Ok(count) = count.parse::<usize>() else {
+                    continue;
+                };
+                match kind {
+                    "passed" => summary.passed += count,
+                    "failed" | "error" | "errors" => summary.failed += count,
+                    "skipped" | "xfailed" | "deselected" => summary.skipped += count,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    if !saw_result {
+        summary.error = Some(last_lines(
+            &format!("{}{}", stdout, stderr),
+            MAX_ERROR_LINES,
+        ));
+    }
+    summary
+}
+
+/// 解析 `jest --json` 的结果文件
+fn parse_jest(json: &str) -> Result<TestSummary> {
+    let report: serde_json::Value =
+        serde_json::from_str(json).context("Failed to parse jest JSON report")?;
+    if !report.is_object() {
+        bail!("Unexpected jest JSON report");
+    }
+
+    let count = |key: &str| report[key].as_u64().unwrap_or(0) as usize;
+    let mut summary = TestSummary::new(Framework::Jest);
+    summary.passed = count("numPassedTests");
+    summary.failed = count("numFailedTests");
+    summary.skipped = count("numPendingTests") + count("numTodoTests");
+
+    for file in report["testResults"].as_array().into_iter().flatten() {
+        let assertions = file["assertionResults"].as_array();
+        for assertion in assertions.into_iter().flatten() {
+            if assertion["status"] != "failed" {
+                continue;
+            }
+            let messages: Vec<&str> = assertion["failureMessages"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|m| m.as_str())
+                .collect();
+            summary.failures.push(TestFailure {
+                name: assertion["fullName"].as_str().unwrap_or("").to_string(),
+                details: console::strip_ansi_codes(&messages.join("\n")).to_string(),
+            });
+        }
+        // 测试文件本身无法运行（例如语法错误）时没有用例结果
+        if file["status"] == "failed" && assertions.is_none_or(|a| a.is_empty()) {
+            summary.failures.push(TestFailure {
+                name: file["name"].as_str().unwrap_or("").to_string(),
+                details: console::strip_ansi_codes(file["message"].as_str().unwrap_or(""))
+                    .to_string(),
+            });
+            summary.failed += 1;
+        }
+    }
+    Ok(summary)
+}
+
+fn last_lines(text: &str, n: usize) -> String {
+    let lines: Vec<&str> = text.trim_end().lines().collect();
+    lines[lines.len().saturating_sub(n)..].join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_cargo_output() {
+        let stdout = "\nrunning 3 tests\n\
+test tests::a ... ok\n\
+test tests::b ... FAILED\n\
+test tests::c ... ignored\n\
+\n\
+failures:\n\
+\n\
+---- tests::b stdout ----\n\
+thread 'tests::b' panicked at src/lib.rs:9:9:\n\
+assertion `left == right` failed\n\
+\n\
+\n\
+failures:\n\
+    tests::b\n\
+\n\
+test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.00s\n\
+\n\
+running 1 test\n\
+test src/lib.rs - add (line 3) ... ok\n\
+\n\
+test result: ok. 1 passed; 0 failed; 0 ignored; 0 measured; 0 filtered out; finished in 0.10s\n";
+        let summary = parse_cargo(stdout, "");
+        assert_eq!((summary.passed, summary.failed, summary.skipped), (2, 1, 1));
+        assert_eq!(
+            summary.failures,
+            vec![TestFailure {
+                name: "tests::b".to_string(),
+                details: "thread 'tests::b' panicked at src/lib.rs:9:9:\nassertion `left == right` failed"
+                    .to_string(),
+            }]
+        );
+        assert!(summary
+            .render()
+            .starts_with("cargo test: 2 passed, 1 failed, 1 skipped"));
+
+        let broken = parse_cargo(
+            "",
+            "error[E0425]: cannot find value `x`\nerror: could not compile",
+        );
+        assert!(!broken.success());
+        assert!(broken.render().contains("could not compile"));
+    }
+
+    #[test]
+    fn test_parse_pytest_output() {
+        let stdout = "..F.s                                                    [100%]\n\
+=================================== FAILURES ===================================\n\
+___________________________________ test_b ____________________________________\n\
+tests/test_x.py:5: in test_b\n\
+    assert 1 == 2\n\
+E   assert 1 == 2\n\
+=========================== short test summary info ============================\n\
+FAILED tests/test_x.py::test_b - assert 1 == 2\n\
+1 failed, 3 passed, 1 skipped in 0.03s\n";
+        let summary = parse_pytest(stdout, "");
+        assert_eq!((summary.passed, summary.failed, summary.skipped), (3, 1, 1));
+        assert_eq!(summary.failures[0].name, "tests/test_x.py::test_b");
+        assert!(summary.failures[0].details.ends_with("E   assert 1 == 2"));
+    }
+
+    #[test]
+    fn test_parse_jest_report() {
+        let json = r#"{
+            "numPassedTests": 4, "numFailedTests": 1, "numPendingTests": 1, "numTodoTests": 0,
+            "testResults": [{
+                "name": "/app/sum.test.js", "status": "failed", "message": "",
+                "assertionResults": [
+                    {"fullName": "sum adds", "status": "passed", "failureMessages": []},
+                    {"fullName": "sum handles NaN", "status": "failed",
+                     "failureMessages": ["\u001b[31mExpected: 0\nReceived: NaN\u001b[39m"]}
+                ]
+            }]
+        }"#;
+        let summary = parse_jest(json).unwrap();
+        assert_eq!((summary.passed, summary.failed, summary.skipped), (4, 1, 1));
+        assert_eq!(summary.failures[0].name, "sum handles NaN");
+        assert_eq!(summary.failures[0].details, "Expected: 0\nReceived: NaN");
+    }
+
+    #[test]
+    fn test_detect_framework() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert_eq!(Framework::detect(dir.path()), None);
+        std::fs::write(dir.path().join("pyproject.toml"), "").unwrap();
+        assert_eq!(Framework::detect(dir.path()), Some(Framework::Pytest));
+        std::fs::write(
+            dir.path().join("package.json"),
+            r#"{"devDependencies": {"jest": "^29"}}"#,
+        )
+        .unwrap();
+        assert_eq!(Framework::detect(dir.path()), Some(Framework::Jest));
+        assert_eq!(Framework::parse("Cargo"), Some(Framework::Cargo));
+        assert_eq!(
+            command_line(Framework::Pytest, Some("slow")),
+            "python3 -m pytest -q -rfE --tb=short -p no:cacheprovider -k slow"
+        );
+    }
+}
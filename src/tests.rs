@@ -113,7 +113,7 @@ mod tests {
         #[test]
         fn test_validate_api_keys() {
             // 有效 API key
-            assert!(security::InputValidator::validate_api_key("sk-ant-test123456789").is_ok());
+            assert!(security::InputValidator::validate_api_key("sk-ant-REDACTED").is_ok());
 
             // 无效 API key
             assert!(security::InputValidator::validate_api_key("").is_err());
@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// 控制发送给模型的工具输出大小
+///
+/// 超过 `limit` 字节时，完整输出写入 `<claude_dir>/tool-output/<id>.txt`，
+/// 模型只收到开头和结尾部分以及文件路径，需要时可以用 read_file 按行读取
+pub fn limit_tool_output(
+    claude_dir: &Path,
+    tool_use_id: &str,
+    output: String,
+    limit: usize,
+) -> Result<String> {
+    if output.len() <= limit {
+        return Ok(output);
+    }
+
+    let path = overflow_path(claude_dir, tool_use_id);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    fs::write(&path, &output)
+        .with_context(|| format!("Failed to write tool output: {}", path.display()))?;
+
+    let half = limit / 2;
+    let head = &output[..floor_line_boundary(&output, half)];
+    let tail = &output[ceil_line_boundary(&output, output.len() - half)..];
+    let omitted = output.len() - head.len() - tail.len();
+    let total_lines = output.lines().count();
+
+    info!(
+        "Tool output of {} bytes exceeded limit, saved to {}",
+        output.len(),
+        path.display()
+    );
+
+    Ok(format!(
+        "{}\n\n... [{} bytes omitted; full output ({} lines, {} bytes) saved to {} — \
+        use read_file with offset/limit to view specific lines] ...\n\n{}",
+        head.trim_end_matches('\n'),
+        omitted,
+        total_lines,
+        output.len(),
+        path.display(),
+        tail
+    ))
+}
+
+/// 溢出文件路径，工具调用 ID 中的特殊字符会被替换
+fn overflow_path(claude_dir: &Path, tool_use_id: &str) -> PathBuf {
+    let safe_id: String = tool_use_id
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    claude_dir
+        .join("tool-output")
+        .join(format!("{}.txt", safe_id))
+}
+
+/// 在 `max` 之前找到最近的行尾（找不到时退回到字符边界）
+fn floor_line_boundary(s: &str, max: usize) -> usize {
+    let mut end = max.min(s.len());
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    match s[..end].rfind('\n') {
+        Some(newline) if newline > 0 => newline + 1,
+        _ => end,
+    }
+}
+
+/// 在 `min` 之后找到最近的行首（找不到时退回到字符边界）
+fn ceil_line_boundary(s: &str, min: usize) -> usize {
+    let mut start = min.min(s.len());
+    while !s.is_char_boundary(start) {
+        start += 1;
+    }
+    match s[start..].find('\n') {
+        Some(newline) if start + newline + 1 < s.len() => start + newline + 1,
+        _ => start,
+    }
+}
+
+/// 按行截取文本，`offset` 从 1 开始
+pub fn slice_lines(content: &str, offset: Option<usize>, limit: Option<usize>) -> String {
+    let total = content.lines().count();
+    let start = offset.unwrap_or(1).max(1);
+    let count = limit.unwrap_or(usize::MAX);
+
+    let selected: Vec<&str> = content.lines().skip(start - 1).take(count).collect();
+    if selected.is_empty() {
+        return format!("[no lines in range; file has {} lines]", total);
+    }
+
+    let end = start + selected.len() - 1;
+    format!(
+        "[lines {}-{} of {}]\n{}",
+        start,
+        end,
+        total,
+        selected.join("\n")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_small_output_untouched() {
+        let temp_dir = TempDir::new().unwrap();
+        let output = limit_tool_output(temp_dir.path(), "toolu_1", "ok".to_string(), 100);
+        assert_eq!(output.unwrap(), "ok");
+        assert!(!temp_dir.path().join("tool-output").exists());
+    }
+
+    #[test]
+    fn test_large_output_overflows_to_disk() {
+        let temp_dir = TempDir::new().unwrap();
+        let output: String = (1..=1000).map(|i| format!("line {}\n", i)).collect();
+
+        let limited =
+            limit_tool_output(temp_dir.path(), "toolu/../2", output.clone(), 200).unwrap();
+        assert!(limited.len() < 500);
+        assert!(limited.starts_with("line 1\n"));
+        assert!(limited.trim_end().ends_with("line 1000"));
+
+        let saved = temp_dir.path().join("tool-output").join("toolu____2.txt");
+        assert!(limited.contains(&saved.display().to_string()));
+        assert_eq!(fs::read_to_string(saved).unwrap(), output);
+    }
+
+    #[test]
+    fn test_slice_lines() {
+        let content = "a\nb\nc\nd\n";
+        assert_eq!(
+            slice_lines(content, Some(2), Some(2)),
+            "[lines 2-3 of 4]\nb\nc"
+        );
+        assert_eq!(slice_lines(content, Some(4), None), "[lines 4-4 of 4]\nd");
+        assert!(slice_lines(content, Some(9), None).starts_with("[no lines"));
+    }
+}
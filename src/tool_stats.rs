@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// 单个工具的统计数据
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ToolCallStats {
+    pub calls: u64,
+    pub failures: u64,
+    pub total_duration: Duration,
+    /// 工具输出的总字节数
+    pub output_bytes: u64,
+}
+
+impl ToolCallStats {
+    pub fn average_duration(&self) -> Duration {
+        if self.calls == 0 {
+            return Duration::ZERO;
+        }
+        self.total_duration / self.calls as u32
+    }
+}
+
+/// 按工具统计调用次数、耗时、失败次数和输出大小
+#[derive(Debug, Default)]
+pub struct ToolStats {
+    tools: Mutex<HashMap<String, ToolCallStats>>,
+}
+
+impl ToolStats {
+    /// 记录一次工具调用；失败时 `output_bytes` 为 None
+    pub fn record(&self, tool: &str, duration: Duration, output_bytes: Option<usize>) {
+        let mut tools = self.tools.lock().unwrap();
+        let stats = tools.entry(tool.to_string()).or_default();
+        stats.calls += 1;
+        stats.total_duration += duration;
+        match output_bytes {
+            Some(bytes) => stats.output_bytes += bytes as u64,
+            None => stats.failures += 1,
+        }
+    }
+
+    /// 按总耗时从高到低排序的统计数据
+    pub fn snapshot(&self) -> Vec<(String, ToolCallStats)> {
+        let mut entries: Vec<_> = self
+            .tools
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(name, stats)| (name.clone(), stats.clone()))
+            .collect();
+        entries.sort_by(|a, b| {
+            b.1.total_duration
+                .cmp(&a.1.total_duration)
+                .then_with(|| a.0.cmp(&b.0))
+        });
+        entries
+    }
+
+    /// 格式化为表格行，没有任何调用时返回空列表
+    pub fn report_lines(&self) -> Vec<String> {
+        let entries = self.snapshot();
+        if entries.is_empty() {
+            return Vec::new();
+        }
+
+        let total: Duration = entries.iter().map(|(_, s)| s.total_duration).sum();
+        let width = entries
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0)
+            .max(4);
+
+        let mut lines = vec![format!(
+            "{:width$}  {:>5}  {:>6}  {:>9}  {:>8}  {:>6}  {:>9}",
+            "tool", "calls", "failed", "total", "avg", "share", "output"
+        )];
+        for (name, stats) in &entries {
+            let share = if total.is_zero() {
+                0.0
+            } else {
+                stats.total_duration.as_secs_f64() / total.as_secs_f64() * 100.0
+            };
+            lines.push(format!(
+                "{:width$}  {:>5}  {:>6}  {:>8.2}s  {:>7.2}s  {:>5.1}%  {:>9}",
+                name,
+                stats.calls,
+                stats.failures,
+                stats.total_duration.as_secs_f64(),
+                stats.average_duration().as_secs_f64(),
+                share,
+                format_bytes(stats.output_bytes)
+            ));
+        }
+        lines
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        b if b < 1024 => format!("{} B", b),
+        b if b < 1024 * 1024 => format!("{:.1} KB", b as f64 / 1024.0),
+        b => format!("{:.1} MB", b as f64 / (1024.0 * 1024.0)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_tool_calls() {
+        let stats = ToolStats::default();
+        stats.record("read_file", Duration::from_millis(10), Some(100));
+        stats.record("execute_command", Duration::from_secs(3), Some(2048));
+        stats.record("execute_command", Duration::from_secs(1), None);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot[0].0, "execute_command");
+        assert_eq!(snapshot[0].1.calls, 2);
+        assert_eq!(snapshot[0].1.failures, 1);
+        assert_eq!(snapshot[0].1.output_bytes, 2048);
+        assert_eq!(snapshot[0].1.average_duration(), Duration::from_secs(2));
+        assert_eq!(snapshot[1].1.failures, 0);
+    }
+
+    #[test]
+    fn test_report_lines() {
+        let stats = ToolStats::default();
+        assert!(stats.report_lines().is_empty());
+
+        stats.record("execute_command", Duration::from_secs(9), Some(2048));
+        stats.record("read_file", Duration::from_secs(1), Some(10));
+        let lines = stats.report_lines();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[1].starts_with("execute_command"));
+        assert!(lines[1].contains("90.0%"));
+        assert!(lines[1].contains("2.0 KB"));
+    }
+}
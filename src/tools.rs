@@ -0,0 +1,467 @@
+use serde_json::json;
+
+/// 工具的类别，用于按模式或配置筛选发送给模型的工具
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToolCategory {
+    /// 只读取文件系统
+    Read,
+    /// 修改文件
+    Write,
+    /// 执行任意命令
+    Execute,
+}
+
+/// 一个可以提供给模型的工具
+#[derive(Debug, Clone)]
+pub struct ToolSpec {
+    pub name: &'static str,
+    pub category: ToolCategory,
+    pub description: &'static str,
+    pub input_schema: serde_json::Value,
+}
+
+impl ToolSpec {
+    /// API 请求中的工具定义
+    pub fn definition(&self) -> serde_json::Value {
+        json!({
+            "name": self.name,
+            "description": self.description,
+            "input_schema": self.input_schema
+        })
+    }
+}
+
+/// 每次请求发送哪些工具
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ToolFilter {
+    /// 只发送只读工具
+    pub read_only: bool,
+    /// settings.json 中禁用的工具
+    pub disabled: Vec<String>,
+}
+
+impl ToolFilter {
+    pub fn allows(&self, tool: &ToolSpec) -> bool {
+        if self.read_only && tool.category != ToolCategory::Read {
+            return false;
+        }
+        !self.disabled.iter().any(|name| name == tool.name)
+    }
+}
+
+/// 运行时的工具注册表
+#[derive(Debug, Clone)]
+pub struct ToolRegistry {
+    tools: Vec<ToolSpec>,
+}
+
+impl ToolRegistry {
+    /// 内置工具
+    pub fn builtin() -> Self {
+        Self {
+            tools: builtin_tools(),
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.tools.iter().map(|tool| tool.name)
+    }
+
+    /// 按筛选条件生成请求中的 tools 数组
+    pub fn definitions(&self, filter: &ToolFilter) -> serde_json::Value {
+        self.tools
+            .iter()
+            .filter(|tool| filter.allows(tool))
+            .map(ToolSpec::definition)
+            .collect()
+    }
+}
+
+/// 内置工具的类别，未知工具返回 None
+pub fn category(name: &str) -> Option<ToolCategory> {
+    builtin_tools()
+        .iter()
+        .find(|tool| tool.name == name)
+        .map(|tool| tool.category)
+}
+
+/// 内置的只读工具，例如 read_file、list_files
+pub fn is_read_only(name: &str) -> bool {
+    category(name) == Some(ToolCategory::Read)
+}
+
+/// 精简格式的工具定义：保留工具说明、参数名、类型和必填项，去掉各参数的说明
+///
+/// 工具定义每次请求都会重新发送，参数说明大多可以从参数名推断
+pub fn compact(mut tools: serde_json::Value) -> serde_json::Value {
+    for tool in tools.as_array_mut().into_iter().flatten() {
+        let properties = tool["input_schema"]["properties"].as_object_mut();
+        for property in properties.into_iter().flat_map(|p| p.values_mut()) {
+            if let Some(property) = property.as_object_mut() {
+                property.remove("description");
+            }
+        }
+    }
+    tools
+}
+
+fn builtin_tools() -> Vec<ToolSpec> {
+    vec![
+        ToolSpec {
+            name: "read_file",
+            category: ToolCategory::Read,
+            description: "Read a file from the filesystem. Returns the file contents as a string. Use offset/limit to read a range of lines from large files.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {
+                        "type": "string",
+                        "description": "Absolute path to the file to read"
+                    },
+                    "offset": {
+                        "type": "integer",
+                        "description": "Line number to start reading from (1-based)"
+                    },
+                    "limit": {
+                        "type": "integer",
+                        "description": "Maximum number of lines to read"
+                    }
+                },
+                "required": ["file_path"]
+            }),
+        },
+        ToolSpec {
+            name: "write_file",
+            category: ToolCategory::Write,
+            description: "Write content to a file, overwriting if it exists. Existing files must be read with read_file first. Returns confirmation message.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {
+                        "type": "string",
+                        "description": "Absolute path to the file to write"
+                    },
+                    "content": {
+                        "type": "string",
+                        "description": "Content to write to the file"
+                    }
+                },
+                "required": ["file_path", "content"]
+            }),
+        },
+        ToolSpec {
+            name: "append_file",
+            category: ToolCategory::Write,
+            description: "Append content to the end of a file, creating it if it does not exist. Use this instead of rewriting a whole file to add to it.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {
+                        "type": "string",
+                        "description": "Absolute path to the file to append to"
+                    },
+                    "content": {
+                        "type": "string",
+                        "description": "Content to append (include a leading newline if needed)"
+                    }
+                },
+                "required": ["file_path", "content"]
+            }),
+        },
+        ToolSpec {
+            name: "apply_patch",
+            category: ToolCategory::Write,
+            description: "Apply a unified diff to an existing file. Hunks may be slightly offset from their line numbers, but every context and removed line must match the file or the whole patch is rejected. Prefer this over write_file for targeted edits to large files.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "file_path": {
+                        "type": "string",
+                        "description": "Absolute path to the file to patch"
+                    },
+                    "patch": {
+                        "type": "string",
+                        "description": "Unified diff hunks starting with '@@ -old,count +new,count @@'; ---/+++ headers are optional"
+                    }
+                },
+                "required": ["file_path", "patch"]
+            }),
+        },
+        ToolSpec {
+            name: "execute_command",
+            category: ToolCategory::Execute,
+            description: "Execute a shell command and return its output. Use for terminal operations like git, npm, cargo, etc. Set run_in_background for long-running commands such as builds or test suites; a task ID is returned immediately and the output can be read with check_task_output.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "The shell command to execute"
+                    },
+                    "cwd": {
+                        "type": "string",
+                        "description": "Directory to run the command in, relative to the session's working directory; it must be inside the workspace. Defaults to the session's working directory"
+                    },
+                    "run_in_background": {
+                        "type": "boolean",
+                        "description": "Start the command in the background and return a task ID instead of waiting for it"
+                    }
+                },
+                "required": ["command"]
+            }),
+        },
+        ToolSpec {
+            name: "list_files",
+            category: ToolCategory::Read,
+            description: "List files in a directory using glob patterns",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Glob pattern (e.g., '*.rs', 'src/**/*.rs')"
+                    },
+                    "path": {
+                        "type": "string",
+                        "description": "Base directory path (defaults to every workspace root)"
+                    }
+                },
+                "required": ["pattern"]
+            }),
+        },
+        ToolSpec {
+            name: "check_task_output",
+            category: ToolCategory::Read,
+            description: "Get the status of a background task and the output it produced since the last check",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "task_id": {
+                        "type": "integer",
+                        "description": "ID returned by execute_command with run_in_background"
+                    }
+                },
+                "required": ["task_id"]
+            }),
+        },
+        ToolSpec {
+            name: "kill_task",
+            category: ToolCategory::Execute,
+            description: "Stop a background task started with execute_command",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "task_id": {
+                        "type": "integer",
+                        "description": "ID of the task to stop"
+                    }
+                },
+                "required": ["task_id"]
+            }),
+        },
+        ToolSpec {
+            name: "dev_server_output",
+            category: ToolCategory::Read,
+            description: "Get the status and recent output of the development server the user started for this session",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "lines": {
+                        "type": "integer",
+                        "description": "Number of most recent lines to return (default 50)"
+                    }
+                }
+            }),
+        },
+        ToolSpec {
+            name: "run_tests",
+            category: ToolCategory::Execute,
+            description: "Run the project's test suite (cargo test, pytest or jest, detected from the project files) and return a pass/fail summary with details of failing tests. Prefer this over running the test command with execute_command.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "filter": {
+                        "type": "string",
+                        "description": "Only run tests whose name matches this filter"
+                    },
+                    "framework": {
+                        "type": "string",
+                        "enum": ["cargo", "pytest", "jest"],
+                        "description": "Test framework to use when it cannot be detected"
+                    }
+                }
+            }),
+        },
+        ToolSpec {
+            name: "coverage",
+            category: ToolCategory::Execute,
+            description: "Run the Rust test suite under cargo-llvm-cov and return line coverage per file with the uncovered line ranges. Use it to find untested code when asked to raise coverage, then read those lines and add tests for them.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Only report files whose path contains this string, e.g. src/config.rs"
+                    }
+                }
+            }),
+        },
+        ToolSpec {
+            name: "cargo_check",
+            category: ToolCategory::Execute,
+            description: "Run `cargo check --all-targets` and return the errors and warnings, one line each with file:line:column and the compiler's help. Prefer this over running cargo check or cargo build with execute_command.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "package": {
+                        "type": "string",
+                        "description": "Only check this workspace package"
+                    }
+                }
+            }),
+        },
+        ToolSpec {
+            name: "cargo_add",
+            category: ToolCategory::Execute,
+            description: "Add dependencies to Cargo.toml with `cargo add` and return the versions that were added and the features enabled. Prefer this over editing Cargo.toml by hand.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "crates": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Crates to add, optionally with a version requirement, e.g. ['serde', 'tokio@1']"
+                    },
+                    "features": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": "Features to enable on the added crates"
+                    },
+                    "dev": {
+                        "type": "boolean",
+                        "description": "Add them as dev-dependencies"
+                    },
+                    "package": {
+                        "type": "string",
+                        "description": "Workspace package whose Cargo.toml to modify"
+                    }
+                },
+                "required": ["crates"]
+            }),
+        },
+        ToolSpec {
+            name: "cargo_metadata",
+            category: ToolCategory::Read,
+            description: "Describe the Cargo workspace: each member package with its version, manifest, targets, features and direct dependencies. Use it to learn a Rust project's layout instead of reading every Cargo.toml.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {}
+            }),
+        },
+        ToolSpec {
+            name: "audit_dependencies",
+            category: ToolCategory::Execute,
+            description: "Check the project's dependencies for known vulnerabilities with cargo audit, npm audit or pip-audit (every one that applies, detected from the project files). Returns a summary line per tool followed by one JSON object per vulnerability with the package, version, advisory ID, severity, title and available fix.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "ecosystem": {
+                        "type": "string",
+                        "enum": ["cargo", "npm", "pip"],
+                        "description": "Only audit this ecosystem"
+                    }
+                }
+            }),
+        },
+        ToolSpec {
+            name: "search_files",
+            category: ToolCategory::Read,
+            description: "Search file contents with a regular expression, skipping .gitignore'd and binary files. Returns matching lines as path:line: text, sorted by path.",
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "pattern": {
+                        "type": "string",
+                        "description": "Regular expression to search for (Rust regex syntax)"
+                    },
+                    "path": {
+                        "type": "string",
+                        "description": "Directory to search (defaults to every workspace root)"
+                    },
+                    "glob": {
+                        "type": "string",
+                        "description": "Only search files matching this glob, e.g. '*.rs' or 'src/**/*.ts'"
+                    },
+                    "case_insensitive": {
+                        "type": "boolean",
+                        "description": "Ignore case when matching (default false)"
+                    },
+                    "max_results": {
+                        "type": "integer",
+                        "description": "Maximum number of matching lines to return (default 200)"
+                    }
+                },
+                "required": ["pattern"]
+            }),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_tools() {
+        let registry = ToolRegistry::builtin();
+        let names = |filter: &ToolFilter| -> Vec<String> {
+            registry
+                .definitions(filter)
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|tool| tool["name"].as_str().unwrap().to_string())
+                .collect()
+        };
+
+        assert_eq!(names(&ToolFilter::default()).len(), 16);
+        assert_eq!(
+            names(&ToolFilter {
+                read_only: true,
+                disabled: Vec::new(),
+            }),
+            [
+                "read_file",
+                "list_files",
+                "check_task_output",
+                "dev_server_output",
+                "cargo_metadata",
+                "search_files"
+            ]
+        );
+        assert!(!names(&ToolFilter {
+            read_only: false,
+            disabled: vec!["execute_command".to_string()],
+        })
+        .contains(&"execute_command".to_string()));
+
+        assert!(is_read_only("list_files"));
+        assert!(!is_read_only("apply_patch"));
+    }
+
+    #[test]
+    fn test_compact_tools() {
+        let full = ToolRegistry::builtin().definitions(&ToolFilter::default());
+        let compact = compact(full.clone());
+        assert!(compact.to_string().len() < full.to_string().len());
+
+        let read_file = &compact[0];
+        assert_eq!(read_file["name"], "read_file");
+        assert!(read_file["description"].is_string());
+        assert_eq!(
+            read_file["input_schema"]["properties"]["file_path"],
+            json!({"type": "string"})
+        );
+        assert_eq!(read_file["input_schema"]["required"], json!(["file_path"]));
+    }
+}
@@ -0,0 +1,202 @@
+use console::{measure_text_width, style, Term};
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme};
+use std::fmt::Display;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::i18n::{t, Msg};
+use crate::sanitize::sanitize;
+
+/// 是否处于无障碍（屏幕阅读器友好）输出模式
+static ACCESSIBLE: AtomicBool = AtomicBool::new(false);
+
+/// 超出终端高度的回答是否通过分页器显示（仅交互模式）
+static PAGER: AtomicBool = AtomicBool::new(false);
+
+/// 开启无障碍模式：关闭颜色，使用纯文本标签并播报状态变化
+pub fn enable_accessible() {
+    ACCESSIBLE.store(true, Ordering::Relaxed);
+    console::set_colors_enabled(false);
+    console::set_colors_enabled_stderr(false);
+}
+
+pub fn accessible() -> bool {
+    ACCESSIBLE.load(Ordering::Relaxed)
+}
+
+/// 交互式提示使用的主题：无障碍模式下不使用彩色主题和符号
+pub fn dialog_theme() -> Box<dyn Theme> {
+    if accessible() {
+        Box::new(SimpleTheme)
+    } else {
+        Box::new(ColorfulTheme::default())
+    }
+}
+
+/// 无障碍模式下以一行文字播报状态变化，普通模式下不输出
+pub fn announce(status: impl Display) {
+    if accessible() {
+        println!("STATUS: {}", status);
+    }
+}
+
+pub fn set_pager_enabled(enabled: bool) {
+    PAGER.store(enabled, Ordering::Relaxed);
+}
+
+/// 输出较长的文本：超过终端高度时交给 $PAGER（默认 less -R），分页器不可用时直接输出
+pub fn print_paged(text: &str) {
+    let term = Term::stdout();
+    let (rows, cols) = term.size();
+    if !PAGER.load(Ordering::Relaxed) || !term.is_term() || !exceeds_screen(text, rows, cols) {
+        println!("{}", text);
+        return;
+    }
+
+    let (program, args) = pager_command(std::env::var("PAGER").ok());
+    let spawned = Command::new(&program)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .spawn();
+    let mut child = match spawned {
+        Ok(child) => child,
+        Err(e) => {
+            tracing::warn!("Failed to start pager '{}': {}", program, e);
+            println!("{}", text);
+            return;
+        }
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        // 用户提前退出分页器时写入会失败，可以忽略
+        let _ = writeln!(stdin, "{}", text);
+    }
+    let _ = child.wait();
+}
+
+/// 按终端宽度折行后，文本是否超过一屏（为提示符留出两行）
+fn exceeds_screen(text: &str, rows: u16, cols: u16) -> bool {
+    let cols = usize::from(cols.max(1));
+    let lines: usize = text
+        .lines()
+        .map(|line| measure_text_width(line).div_ceil(cols).max(1))
+        .sum();
+    lines + 2 > usize::from(rows)
+}
+
+/// 解析 $PAGER，未设置时使用 less -R（Windows 上使用 more）
+fn pager_command(pager: Option<String>) -> (String, Vec<String>) {
+    let pager = pager.filter(|p| !p.trim().is_empty()).unwrap_or_else(|| {
+        if cfg!(target_os = "windows") {
+            "more".to_string()
+        } else {
+            "less -R".to_string()
+        }
+    });
+    let mut parts = pager.split_whitespace().map(str::to_string);
+    let program = parts.next().unwrap_or_default();
+    (program, parts.collect())
+}
+
+/// 带颜色的统一 diff，较长时分页显示
+pub fn print_diff(patch: &str) {
+    let colored: Vec<String> = patch
+        .lines()
+        .map(|line| {
+            let line = sanitize(line);
+            if accessible() {
+                line.into_owned()
+            } else if line.starts_with("diff --git ") {
+                style(line).bold().to_string()
+            } else if line.starts_with("+++") || line.starts_with("---") {
+                line.into_owned()
+            } else if line.starts_with('+') {
+                style(line).green().to_string()
+            } else if line.starts_with('-') {
+                style(line).red().to_string()
+            } else if line.starts_with("@@") {
+                style(line).cyan().to_string()
+            } else {
+                line.into_owned()
+            }
+        })
+        .collect();
+    print_paged(&colored.join("\n"));
+}
+
+/// 启动时的标题
+pub fn print_banner() {
+    if accessible() {
+        println!("Rust Claude Code");
+    } else {
+        println!("\n{}", style("🦀 Rust Claude Code").blue().bold());
+    }
+    println!("{}", style(t(Msg::Subtitle)).dim());
+}
+
+/// 模型回答前的标题行
+pub fn print_claude_heading() {
+    if accessible() {
+        println!("\nCLAUDE:");
+    } else {
+        println!("\n{}", style("Claude:").green());
+    }
+}
+
+/// 对比多个回答时，每个回答前标明来源的分隔行
+pub fn print_section_heading(label: &str) {
+    if accessible() {
+        println!("\n{}:", label.to_uppercase());
+    } else {
+        println!("\n{}", style(format!("── {} ──", label)).magenta().bold());
+    }
+}
+
+/// 工具调用的标题行
+pub fn print_tool_heading(name: &str) {
+    if accessible() {
+        println!("\nTOOL {}:", name);
+    } else {
+        println!(
+            "\n{} {}",
+            style(t(Msg::ToolLabel)).cyan(),
+            style(name).yellow()
+        );
+    }
+}
+
+/// 即将执行的命令
+pub fn print_command(command: &str) {
+    let command = sanitize(command);
+    if accessible() {
+        println!("COMMAND: {}", command);
+    } else {
+        println!("\n{}", style(t(Msg::ExecutingLabel)).cyan());
+        println!("  {}", style(command).yellow());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exceeds_screen_counts_wrapped_lines() {
+        assert!(!exceeds_screen("a\nb\nc", 24, 80));
+        assert!(exceeds_screen(&"line\n".repeat(30), 24, 80));
+        // 一行 200 个字符在 80 列终端中占 3 行
+        assert!(exceeds_screen(&"x".repeat(200), 4, 80));
+        assert!(!exceeds_screen(&"x".repeat(200), 5, 80));
+    }
+
+    #[test]
+    fn test_pager_command() {
+        assert_eq!(
+            pager_command(Some("less -FRX".to_string())),
+            ("less".to_string(), vec!["-FRX".to_string()])
+        );
+        assert_eq!(pager_command(Some("bat".to_string())).0, "bat");
+        assert!(!pager_command(Some("  ".to_string())).0.is_empty());
+    }
+}
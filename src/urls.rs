@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use console::strip_ansi_codes;
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+/// 本机地址的主机名写法
+const LOCAL_HOSTS: &[&str] = &["localhost", "127.0.0.1", "0.0.0.0", "[::1]", "[::]"];
+
+/// 本次会话在命令输出中发现的本地 URL，以及已经询问过是否打开的数量
+static DETECTED: Mutex<(Vec<String>, usize)> = Mutex::new((Vec::new(), 0));
+
+/// 找出文本中的本地 http(s) 地址，例如 http://localhost:3000/；0.0.0.0 会改写为 localhost
+pub fn find_local_urls(text: &str) -> Vec<String> {
+    let text = strip_ansi_codes(text);
+    let mut urls: Vec<String> = Vec::new();
+    let mut rest: &str = &text;
+
+    while let Some(start) = rest.find("http") {
+        let candidate = &rest[start..];
+        let end = candidate
+            .find(|c: char| c.is_whitespace() || matches!(c, '"' | '\'' | '<' | '>' | '`'))
+            .unwrap_or(candidate.len());
+        let url = candidate[..end].trim_end_matches(['.', ',', ';', ':', ')', ']']);
+        if let Some(url) = normalize(url) {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        rest = &candidate[end.max(4)..];
+    }
+    urls
+}
+
+fn normalize(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    if scheme != "http" && scheme != "https" {
+        return None;
+    }
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    let (host, port) = rest[..authority_end].rsplit_once(':')?;
+    if !LOCAL_HOSTS.contains(&host) || port.is_empty() || port.parse::<u16>().is_err() {
+        return None;
+    }
+    let host = match host {
+        "0.0.0.0" | "[::]" => "localhost",
+        host => host,
+    };
+    Some(format!(
+        "{}://{}:{}{}",
+        scheme,
+        host,
+        port,
+        &rest[authority_end..]
+    ))
+}
+
+/// 记录输出中出现的本地 URL
+pub fn record(text: &str) {
+    let found = find_local_urls(text);
+    if found.is_empty() {
+        return;
+    }
+    let mut detected = DETECTED.lock().unwrap();
+    for url in found {
+        if !detected.0.contains(&url) {
+            detected.0.push(url);
+        }
+    }
+}
+
+/// 最近发现的 URL
+pub fn latest() -> Option<String> {
+    DETECTED.lock().unwrap().0.last().cloned()
+}
+
+/// 取出尚未询问过是否打开的 URL
+pub fn take_unoffered() -> Vec<String> {
+    let mut detected = DETECTED.lock().unwrap();
+    let (urls, offered) = &mut *detected;
+    let new = urls[*offered..].to_vec();
+    *offered = urls.len();
+    new
+}
+
+/// 终端中可点击的链接（OSC 8）
+pub fn hyperlink(url: &str) -> String {
+    format!("\x1b]8;;{url}\x1b\\{url}\x1b]8;;\x1b\\")
+}
+
+/// 用 $BROWSER 或系统默认方式打开 URL
+pub fn open_in_browser(url: &str) -> Result<()> {
+    let (program, args) = opener(std::env::var("BROWSER").ok());
+    Command::new(&program)
+        .args(&args)
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .with_context(|| format!("Failed to open {} with '{}'", url, program))?;
+    Ok(())
+}
+
+fn opener(browser: Option<String>) -> (String, Vec<String>) {
+    if let Some(browser) = browser.filter(|b| !b.trim().is_empty()) {
+        let mut parts = browser.split_whitespace().map(str::to_string);
+        let program = parts.next().unwrap_or_default();
+        return (program, parts.collect());
+    }
+    if cfg!(target_os = "macos") {
+        ("open".to_string(), Vec::new())
+    } else if cfg!(target_os = "windows") {
+        // start 的第一个带引号参数是窗口标题
+        (
+            "cmd".to_string(),
+            vec!["/C".to_string(), "start".to_string(), String::new()],
+        )
+    } else {
+        ("xdg-open".to_string(), Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_local_urls() {
+        let output =
+            "\x1b[32m  ➜  Local:   \x1b[36mhttp://localhost:\x1b[1m5173\x1b[22m/\x1b[39m\n\
+                      Listening on http://0.0.0.0:8080. Docs at https://vitejs.dev/guide\n\
+                      (see http://127.0.0.1:3000/api/health), or http://localhost:3000/api/health";
+        assert_eq!(
+            find_local_urls(output),
+            vec![
+                "http://localhost:5173/",
+                "http://localhost:8080",
+                "http://127.0.0.1:3000/api/health",
+                "http://localhost:3000/api/health",
+            ]
+        );
+        assert!(find_local_urls("http://localhost/ and http://localhost:99999").is_empty());
+    }
+
+    #[test]
+    fn test_opener_prefers_browser_env() {
+        assert_eq!(
+            opener(Some("firefox --new-tab".to_string())),
+            ("firefox".to_string(), vec!["--new-tab".to_string()])
+        );
+        assert!(!opener(None).0.is_empty());
+    }
+}
@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver};
+use tracing::{info, warn};
+
+/// 监听 .claude/settings.json 的变化
+pub struct SettingsWatcher {
+    // 必须持有 watcher，否则监听会在 drop 时停止
+    _watcher: RecommendedWatcher,
+    changes: Receiver<()>,
+}
+
+impl SettingsWatcher {
+    /// 开始监听指定的配置文件
+    ///
+    /// 监听的是父目录而不是文件本身，因为很多编辑器保存时会先写临时文件再重命名
+    pub fn start(settings_path: &Path) -> Result<Self> {
+        let dir = settings_path
+            .parent()
+            .context("Settings path has no parent directory")?
+            .to_path_buf();
+        let file_name = settings_path
+            .file_name()
+            .context("Settings path has no file name")?
+            .to_os_string();
+
+        let (tx, changes) = channel();
+        let mut watcher =
+            notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+                Ok(event) => {
+                    let touches_settings = event
+                        .paths
+                        .iter()
+                        .any(|p| p.file_name() == Some(file_name.as_os_str()));
+                    if touches_settings && !event.kind.is_access() {
+                        let _ = tx.send(());
+                    }
+                }
+                Err(e) => warn!("Settings watcher error: {}", e),
+            })
+            .context("Failed to create settings watcher")?;
+
+        watcher
+            .watch(&dir, RecursiveMode::NonRecursive)
+            .with_context(|| format!("Failed to watch directory: {}", dir.display()))?;
+
+        info!("Watching {} for changes", settings_path.display());
+        Ok(Self {
+            _watcher: watcher,
+            changes,
+        })
+    }
+
+    /// 返回自上次调用以来配置文件是否发生过变化
+    pub fn take_change(&self) -> bool {
+        let mut changed = false;
+        while self.changes.try_recv().is_ok() {
+            changed = true;
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detects_settings_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("settings.json");
+        std::fs::write(&path, "{}").unwrap();
+
+        let watcher = SettingsWatcher::start(&path).unwrap();
+        assert!(!watcher.take_change());
+
+        std::fs::write(&path, r#"{"theme": "dark"}"#).unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut changed = false;
+        while Instant::now() < deadline && !changed {
+            std::thread::sleep(Duration::from_millis(50));
+            changed = watcher.take_change();
+        }
+        assert!(changed);
+    }
+}
@@ -0,0 +1,599 @@
+//! 在临时的 git worktree 中运行会话，不改动用户的工作区
+//!
+//! worktree 从当前的提交和未提交的改动（包括未跟踪的文件）创建，会话期间工作目录切换到
+//! worktree 中对应的子目录；结束时把会话中的改动导出为可以用 `git apply` 应用的补丁。
+//! 不在 git 仓库中时把当前目录复制到临时目录，在其中建一个临时仓库，用同样的方式导出改动。
+//!
+//! `worktree <task>` 则为每个任务创建保留下来的 worktree 和分支，多个会话可以同时在同一个
+//! 仓库中工作；运行中的会话锁定自己的 worktree，`worktrees cleanup` 不会删除它们。
+
+use anyhow::{bail, Context, Result};
+use ignore::WalkBuilder;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::clock;
+use crate::i18n::{t, tf, Msg};
+
+/// 会话中不随补丁导出的目录：设置和会话记录
+const EXCLUDED: &str = ":(exclude).claude";
+
+/// 会话使用的临时 worktree
+pub struct Worktree {
+    /// 原来的仓库根目录；复制的目录为原来的工作目录
+    repo: PathBuf,
+    /// 原来的工作目录
+    origin: PathBuf,
+    dir: PathBuf,
+    /// 创建时 worktree 内容对应的 tree，导出补丁时与它比较
+    base: String,
+    /// 是 git worktree 而不是复制的目录
+    linked: bool,
+}
+
+impl Worktree {
+    /// 从当前目录所在的仓库创建 worktree（不在仓库中时复制当前目录），并把工作目录切换过去
+    pub fn enter() -> Result<Self> {
+        let origin = std::env::current_dir()?.canonicalize()?;
+        match git(&origin, &["rev-parse", "--show-toplevel"]) {
+            Ok(repo) => Self::link(origin, PathBuf::from(repo).canonicalize()?),
+            Err(_) => Self::copy(origin),
+        }
+    }
+
+    fn link(origin: PathBuf, repo: PathBuf) -> Result<Self> {
+        let prefix = git(&origin, &["rev-parse", "--show-prefix"])?;
+
+        // stash create 生成包含已跟踪文件改动的提交，但不修改工作区；没有改动时为空
+        let start = match git(&repo, &["stash", "create"])? {
+            commit if commit.is_empty() => "HEAD".to_string(),
+            commit => commit,
+        };
+        let dir = temp_dir();
+        git(
+            &repo,
+            &[
+                "worktree",
+                "add",
+                "--detach",
+                &dir.to_string_lossy(),
+                &start,
+            ],
+        )?;
+        let mut worktree = Self {
+            repo,
+            origin,
+            dir,
+            base: String::new(),
+            linked: true,
+        };
+
+        // 未跟踪的文件和 .claude 目录复制过去，会话看到的内容与工作区一致
+        let untracked = git(
+            &worktree.repo,
+            &["ls-files", "--others", "--exclude-standard", "-z"],
+        )?;
+        for relative in untracked.split('\0').filter(|p| !p.is_empty()) {
+            copy(&worktree.repo.join(relative), &worktree.dir.join(relative))?;
+        }
+        copy_dir(
+            &worktree.repo.join(&prefix).join(".claude"),
+            &worktree.dir.join(&prefix).join(".claude"),
+        )?;
+
+        worktree.base = snapshot(&worktree.dir)?;
+        std::env::set_current_dir(worktree.dir.join(&prefix))?;
+        Ok(worktree)
+    }
+
+    /// 复制 `origin` 中 .gitignore 没有忽略的文件，以及 .claude 目录
+    fn copy(origin: PathBuf) -> Result<Self> {
+        let dir = temp_dir();
+        let walker = WalkBuilder::new(&origin)
+            .hidden(false)
+            .require_git(false)
+            .filter_entry(|entry| entry.file_name() != ".git")
+            .build();
+        for entry in walker {
+            let entry = entry?;
+            if entry.file_type().is_some_and(|t| t.is_file()) {
+                let relative = entry.path().strip_prefix(&origin)?;
+                copy(entry.path(), &dir.join(relative))?;
+            }
+        }
+        copy_dir(&origin.join(".claude"), &dir.join(".claude"))?;
+
+        git(&dir, &["init", "--quiet"])?;
+        let base = snapshot(&dir)?;
+        std::env::set_current_dir(&dir)?;
+        Ok(Self {
+            repo: origin.clone(),
+            origin,
+            dir,
+            base,
+            linked: false,
+        })
+    }
+
+    /// 会话中的改动，路径相对仓库根目录
+    pub fn diff(&self) -> Result<String> {
+        git(&self.dir, &["add", "--all"])?;
+        let diff = Command::new("git")
+            .args([
+                "diff", "--cached", "--binary", &self.base, "--", ".", EXCLUDED,
+            ])
+            .current_dir(&self.dir)
+            .output()
+            .context("Failed to run git diff")?;
+        if !diff.status.success() {
+            bail!(
+                "git diff failed: {}",
+                String::from_utf8_lossy(&diff.stderr).trim()
+            );
+        }
+        Ok(String::from_utf8_lossy(&diff.stdout).into_owned())
+    }
+
+    /// 原来的仓库根目录，补丁要在这里应用
+    pub fn repo(&self) -> &Path {
+        &self.repo
+    }
+
+    /// 切换回原来的工作目录，保留会话记录，删除 worktree
+    pub fn leave(self) -> Result<()> {
+        std::env::set_current_dir(&self.origin)?;
+        let prefix = self
+            .origin
+            .strip_prefix(&self.repo)
+            .unwrap_or(Path::new(""));
+        // 会话记录只会新增文件
+        copy_dir(
+            &self.dir.join(prefix).join(".claude").join("history"),
+            &self.origin.join(".claude").join("history"),
+        )?;
+        if self.linked {
+            git(
+                &self.repo,
+                &["worktree", "remove", "--force", &self.dir.to_string_lossy()],
+            )?;
+        } else {
+            fs::remove_dir_all(&self.dir)
+                .with_context(|| format!("Failed to remove {}", self.dir.display()))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WorktreeArgs {
+    /// What the session should do; also used to name the branch
+    task: String,
+
+    /// Branch name (default: claude/<task>)
+    #[arg(long)]
+    branch: Option<String>,
+
+    /// Run the task without interaction and exit, like --prompt
+    #[arg(short, long)]
+    pub print: bool,
+}
+
+impl WorktreeArgs {
+    pub fn task(&self) -> &str {
+        &self.task
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WorktreesArgs {
+    #[command(subcommand)]
+    command: WorktreesCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum WorktreesCommand {
+    /// List the task worktrees of this repository
+    List,
+    /// Remove task worktrees that have no running session and no uncommitted changes
+    Cleanup {
+        /// Also remove worktrees with uncommitted changes
+        #[arg(long)]
+        force: bool,
+    },
+}
+
+/// 锁定原因的前缀，后面是会话的进程号
+const LOCK_REASON: &str = "rust-claude-code session";
+
+/// 为任务创建的 worktree，会话期间保持锁定
+pub struct TaskWorktree {
+    repo: PathBuf,
+    dir: PathBuf,
+    branch: String,
+}
+
+impl TaskWorktree {
+    /// 从当前提交创建分支和 worktree，锁定它并把工作目录切换过去
+    pub fn create(args: &WorktreeArgs) -> Result<Self> {
+        let repo = repo_root()?;
+        let slug = slug(&args.task);
+        let parent = worktrees_dir(&repo);
+        // 同名的任务加上序号
+        let (dir, branch) = (1..)
+            .map(|n| match n {
+                1 => slug.clone(),
+                n => format!("{}-{}", slug, n),
+            })
+            .map(|name| {
+                let branch = args
+                    .branch
+                    .clone()
+                    .unwrap_or_else(|| format!("claude/{}", name));
+                (parent.join(&name), branch)
+            })
+            .find(|(dir, branch)| {
+                !dir.exists()
+                    && (args.branch.is_some()
+                        || git(&repo, &["rev-parse", "--verify", "--quiet", branch]).is_err())
+            })
+            .unwrap();
+        fs::create_dir_all(&parent)?;
+        let path = dir.to_string_lossy().to_string();
+        git(&repo, &["worktree", "add", "-b", &branch, &path, "HEAD"])?;
+        let reason = format!("{} {}", LOCK_REASON, std::process::id());
+        git(&repo, &["worktree", "lock", "--reason", &reason, &path])?;
+        std::env::set_current_dir(&dir)?;
+        Ok(Self { repo, dir, branch })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn branch(&self) -> &str {
+        &self.branch
+    }
+
+    /// 会话结束：解锁并切换回仓库根目录，worktree 和分支保留
+    pub fn release(self) -> Result<()> {
+        std::env::set_current_dir(&self.repo)?;
+        git(
+            &self.repo,
+            &["worktree", "unlock", &self.dir.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+}
+
+/// git worktree list 中的一项
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    path: PathBuf,
+    branch: Option<String>,
+    locked: Option<String>,
+}
+
+impl Entry {
+    /// 锁定它的会话仍在运行时返回进程号
+    fn running(&self) -> Option<u32> {
+        let pid = self
+            .locked
+            .as_deref()?
+            .strip_prefix(LOCK_REASON)?
+            .trim()
+            .parse()
+            .ok()?;
+        process_alive(pid).then_some(pid)
+    }
+}
+
+pub fn run(args: WorktreesArgs) -> Result<()> {
+    let repo = repo_root()?;
+    let parent = worktrees_dir(&repo);
+    let entries: Vec<Entry> = parse_list(&git(&repo, &["worktree", "list", "--porcelain"])?)
+        .into_iter()
+        .filter(|entry| entry.path.starts_with(&parent))
+        .collect();
+    match args.command {
+        WorktreesCommand::List => {
+            if entries.is_empty() {
+                println!("{}", tf(Msg::NoTaskWorktrees, &[&parent.display()]));
+            }
+            for entry in &entries {
+                let state = match entry.running() {
+                    Some(pid) => tf(Msg::WorktreeRunning, &[&pid]),
+                    None => match dirty_files(&entry.path) {
+                        0 => t(Msg::WorktreeClean).to_string(),
+                        n => tf(Msg::WorktreeDirty, &[&n]),
+                    },
+                };
+                println!(
+                    "{}  {}  {}",
+                    entry.path.display(),
+                    entry.branch.as_deref().unwrap_or(t(Msg::WorktreeDetached)),
+                    state
+                );
+            }
+        }
+        WorktreesCommand::Cleanup { force } => {
+            for entry in &entries {
+                let path = entry.path.to_string_lossy().to_string();
+                if let Some(pid) = entry.running() {
+                    println!("{}", tf(Msg::WorktreeSkippedRunning, &[&path, &pid]));
+                    continue;
+                }
+                let dirty = dirty_files(&entry.path);
+                if dirty > 0 && !force {
+                    println!("{}", tf(Msg::WorktreeSkippedDirty, &[&path, &dirty]));
+                    continue;
+                }
+                // 会话异常退出时留下的锁
+                if entry.locked.is_some() {
+                    git(&repo, &["worktree", "unlock", &path])?;
+                }
+                git(&repo, &["worktree", "remove", "--force", &path])?;
+                // 只删除已合并的分支，未合并的提交保留在分支上
+                let branch = entry.branch.as_deref().unwrap_or_default();
+                let kept = !branch.is_empty() && git(&repo, &["branch", "-d", branch]).is_err();
+                if kept {
+                    println!("{}", tf(Msg::WorktreeRemovedKeptBranch, &[&path, &branch]));
+                } else {
+                    println!("{}", tf(Msg::WorktreeRemoved, &[&path]));
+                }
+            }
+            let _ = fs::remove_dir(&parent);
+        }
+    }
+    Ok(())
+}
+
+/// 解析 `git worktree list --porcelain`：每个 worktree 一段，段之间是空行
+fn parse_list(output: &str) -> Vec<Entry> {
+    output
+        .split("\n\n")
+        .filter_map(|block| {
+            let mut entry = Entry {
+                path: PathBuf::new(),
+                branch: None,
+                locked: None,
+            };
+            for line in block.lines() {
+                let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+                match key {
+                    "worktree" => entry.path = PathBuf::from(value),
+                    "branch" => {
+                        entry.branch = Some(
+                            value
+                                .strip_prefix("refs/heads/")
+                                .unwrap_or(value)
+                                .to_string(),
+                        )
+                    }
+                    "locked" => entry.locked = Some(value.to_string()),
+                    _ => {}
+                }
+            }
+            (!entry.path.as_os_str().is_empty()).then_some(entry)
+        })
+        .collect()
+}
+
+/// 任务名转换成分支和目录名：小写字母、数字和 `-`
+fn slug(task: &str) -> String {
+    let slug = task
+        .to_lowercase()
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    let slug = match slug.char_indices().nth(40) {
+        Some((end, _)) => slug[..end].trim_end_matches('-').to_string(),
+        None => slug,
+    };
+    if slug.is_empty() {
+        "task".to_string()
+    } else {
+        slug
+    }
+}
+
+/// 仓库旁边的 `<仓库名>.worktrees` 目录，放在仓库外面以免出现在 git status 中
+fn worktrees_dir(repo: &Path) -> PathBuf {
+    let name = repo
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "repo".to_string());
+    repo.with_file_name(format!("{}.worktrees", name))
+}
+
+/// 主 worktree 的根目录；在任务 worktree 中运行时也返回原来的仓库
+fn repo_root() -> Result<PathBuf> {
+    let cwd = std::env::current_dir()?;
+    let common = git(
+        &cwd,
+        &["rev-parse", "--path-format=absolute", "--git-common-dir"],
+    )
+    .context("Not in a git repository")?;
+    let common = PathBuf::from(common);
+    let root = match common.file_name() {
+        Some(name) if name == ".git" => common.parent().map(Path::to_path_buf),
+        _ => None,
+    };
+    let root = root.context("Task worktrees need a repository with a working tree")?;
+    Ok(root.canonicalize().unwrap_or(root))
+}
+
+fn dirty_files(dir: &Path) -> usize {
+    git(dir, &["status", "--porcelain"])
+        .map(|status| status.lines().count())
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn process_alive(pid: u32) -> bool {
+    // SAFETY: 信号 0 只检查进程是否存在
+    unsafe { libc::kill(pid as libc::pid_t, 0) == 0 }
+}
+
+#[cfg(not(unix))]
+fn process_alive(_pid: u32) -> bool {
+    true
+}
+
+/// 在 `repo` 中应用 `diff` 导出的补丁
+pub fn apply(repo: &Path, patch: &str) -> Result<()> {
+    let mut child = Command::new("git")
+        .args(["apply", "--whitespace=nowarn", "-"])
+        .current_dir(repo)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git apply")?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(patch.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        bail!(
+            "git apply failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// 提交 worktree 的初始内容，返回其 tree；会话开始时 git status 为空，
+/// 会话结束时的改动汇总只包含会话中的改动
+fn snapshot(dir: &Path) -> Result<String> {
+    git(dir, &["add", "--all"])?;
+    git(
+        dir,
+        &[
+            "-c",
+            "user.name=rust-claude-code",
+            "-c",
+            "user.email=rust-claude-code@localhost",
+            "-c",
+            "commit.gpgsign=false",
+            "commit",
+            "--quiet",
+            "--no-verify",
+            "--allow-empty",
+            "--message",
+            "Session start",
+        ],
+    )?;
+    git(dir, &["rev-parse", "HEAD^{tree}"])
+}
+
+fn temp_dir() -> PathBuf {
+    std::env::temp_dir().join(format!(
+        "rust-claude-code-{}-{}",
+        std::process::id(),
+        clock::unix_millis()
+    ))
+}
+
+/// 补丁中改动的文件数
+pub fn changed_files(patch: &str) -> usize {
+    patch
+        .lines()
+        .filter(|line| line.starts_with("diff --git "))
+        .count()
+}
+
+/// 运行 git，返回去掉首尾空白的标准输出
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.first().unwrap_or(&""),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(from, to).with_context(|| format!("Failed to copy {}", from.display()))?;
+    Ok(())
+}
+
+/// 复制目录中 `to` 还没有的文件；`from` 不存在时什么也不做
+fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    let Ok(entries) = fs::read_dir(from) else {
+        return Ok(());
+    };
+    for entry in entries {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &target)?;
+        } else if !target.exists() {
+            copy(&entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_changed_files() {
+        let patch = "diff --git a/src/main.rs b/src/main.rs\n@@ -1 +1 @@\n-a\n+b\n\
+                     diff --git a/new.txt b/new.txt\nnew file mode 100644\n";
+        assert_eq!(changed_files(patch), 2);
+        assert_eq!(changed_files(""), 0);
+    }
+
+    #[test]
+    fn test_slug_and_worktree_list() {
+        assert_eq!(slug("Fix the login bug (#42)"), "fix-the-login-bug-42");
+        assert_eq!(slug("修复"), "task");
+        assert_eq!(slug(&"a ".repeat(30)).len(), 39);
+
+        let list = "worktree /src/app\nHEAD 1234\nbranch refs/heads/main\n\n\
+                    worktree /src/app.worktrees/fix-bug\nHEAD 5678\nbranch refs/heads/claude/fix-bug\n\
+                    locked rust-claude-code session 4242\n\n\
+                    worktree /tmp/scratch\nHEAD 9abc\ndetached\n";
+        let entries = parse_list(list);
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1].branch.as_deref(), Some("claude/fix-bug"));
+        assert_eq!(
+            entries[1].locked.as_deref(),
+            Some("rust-claude-code session 4242")
+        );
+        assert_eq!(entries[2].branch, None);
+        assert_eq!(
+            worktrees_dir(Path::new("/src/app")),
+            Path::new("/src/app.worktrees")
+        );
+    }
+
+    #[test]
+    fn test_apply_patch_outside_repository() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("notes.txt"), "a\nb\n").unwrap();
+        let patch = "diff --git a/notes.txt b/notes.txt\n--- a/notes.txt\n+++ b/notes.txt\n\
+                     @@ -1,2 +1,2 @@\n a\n-b\n+c\n";
+        apply(dir.path(), patch).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("notes.txt")).unwrap(),
+            "a\nc\n"
+        );
+        assert!(apply(dir.path(), patch).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+//! 对话循环的 golden 测试：用录制好的 API 响应驱动编译出的二进制，检查工作区最后的文件
+//!
+//! `tests/agent_scenarios/` 下每个子目录是一个场景：
+//! - `scenario.json`：`{"prompt": "...", "responses": [...]}`，responses 按请求顺序返回
+//! - `fixture/`：可选，运行前复制到临时工作区
+//! - `expected/`：运行后工作区中必须存在且内容相同的文件
+
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+
+/// 按顺序返回录制的响应，并记下收到的请求体
+struct ReplayServer {
+    url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl ReplayServer {
+    fn start(responses: Vec<Value>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/v1/messages", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            let mut responses = responses.into_iter();
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { break };
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut stream = stream;
+                // 客户端会复用连接，一个连接上可能有多个请求
+                while let Some(body) = read_request(&mut reader) {
+                    recorded.lock().unwrap().push(body);
+                    let (status, body) = match responses.next() {
+                        Some(response) => ("200 OK", response.to_string()),
+                        None => (
+                            "400 Bad Request",
+                            r#"{"error": {"message": "no recorded response left"}}"#.to_string(),
+                        ),
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    if stream.write_all(response.as_bytes()).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        Self { url, requests }
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> Option<Value> {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok()?;
+            }
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    for entry in std::fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            std::fs::create_dir_all(&target).unwrap();
+            copy_dir(&entry.path(), &target);
+        } else {
+            std::fs::copy(entry.path(), &target).unwrap();
+        }
+    }
+}
+
+/// `dir` 下所有文件相对于 `root` 的路径
+fn files(root: &Path, dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(self::files(root, &path));
+        } else {
+            files.push(path.strip_prefix(root).unwrap().to_path_buf());
+        }
+    }
+    files
+}
+
+fn run_scenario(dir: &Path) {
+    let name = dir.file_name().unwrap().to_string_lossy();
+    let scenario: Value =
+        serde_json::from_str(&std::fs::read_to_string(dir.join("scenario.json")).unwrap()).unwrap();
+    let responses = scenario["responses"].as_array().unwrap().clone();
+    let server = ReplayServer::start(responses.clone());
+
+    let home = tempfile::TempDir::new().unwrap();
+    let workspace = tempfile::TempDir::new().unwrap();
+    if dir.join("fixture").is_dir() {
+        copy_dir(&dir.join("fixture"), workspace.path());
+    }
+    let output = Command::new(env!("CARGO_BIN_EXE_rust-claude-code"))
+        .current_dir(workspace.path())
+        .env("HOME", home.path())
+        .env_remove("ANTHROPIC_API_KEY")
+        .env_remove("ANTHROPIC_AUTH_TOKEN")
+        .env_remove("ANTHROPIC_BASE_URL")
+        .env("RUST_LOG", "error")
+        // 固定时钟和随机种子，同一场景每次运行的行为相同
+        .env("SOURCE_DATE_EPOCH", "1700000000")
+        .args([
+            "--seed",
+            "0",
+            "--api-key",
+            "test-key",
+            "--max-turns",
+            "20",
+            "-u",
+            &server.url,
+        ])
+        .arg("-p")
+        .arg(scenario["prompt"].as_str().unwrap())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}: exited with {}\n{}\n{}",
+        name,
+        output.status,
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // 每个响应都被请求过，每次工具调用的结果都在之后的请求中交给了模型
+    let requests = server.requests.lock().unwrap();
+    assert_eq!(requests.len(), responses.len(), "{}: request count", name);
+    for (index, response) in responses.iter().enumerate() {
+        let ids = response["content"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter(|block| block["type"] == "tool_use")
+            .map(|block| block["id"].as_str().unwrap());
+        for id in ids {
+            let answered = requests[index + 1..].iter().any(|request| {
+                request
+                    .to_string()
+                    .contains(&format!("\"tool_use_id\":\"{}\"", id))
+            });
+            assert!(answered, "{}: no tool_result for {}", name, id);
+        }
+    }
+
+    let expected = dir.join("expected");
+    for file in files(&expected, &expected) {
+        let actual = std::fs::read_to_string(workspace.path().join(&file))
+            .unwrap_or_else(|e| panic!("{}: {}: {}", name, file.display(), e));
+        assert_eq!(
+            actual,
+            std::fs::read_to_string(expected.join(&file)).unwrap(),
+            "{}: {}",
+            name,
+            file.display()
+        );
+    }
+}
+
+#[test]
+fn test_agent_scenarios() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/agent_scenarios");
+    let mut scenarios: Vec<PathBuf> = std::fs::read_dir(&root)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.join("scenario.json").is_file())
+        .collect();
+    scenarios.sort();
+    assert!(!scenarios.is_empty());
+    for scenario in &scenarios {
+        run_scenario(scenario);
+    }
+}
@@ -0,0 +1,3 @@
+fn answer() -> u32 {
+    42
+}
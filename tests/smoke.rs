@@ -0,0 +1,78 @@
+//! 启动冒烟测试：运行编译出的二进制，检查单次提示模式能正常完成并且启动足够快
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+/// 单次提示从启动到退出的时间上限（包括一次本地 mock 请求）
+///
+/// debug 构建没有优化，给出更宽的余量
+const STARTUP_BUDGET: Duration = if cfg!(debug_assertions) {
+    Duration::from_millis(1000)
+} else {
+    Duration::from_millis(250)
+};
+
+fn binary(home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_rust-claude-code"));
+    // 不读取开发者本机的配置和环境变量
+    command
+        .current_dir(home)
+        .env("HOME", home)
+        .env_remove("ANTHROPIC_API_KEY")
+        .env_remove("ANTHROPIC_AUTH_TOKEN")
+        .env_remove("ANTHROPIC_BASE_URL")
+        .env("RUST_LOG", "error");
+    command
+}
+
+#[test]
+fn test_prompt_mode_starts_quickly() {
+    let home = tempfile::TempDir::new().unwrap();
+    let mut server = mockito::Server::new();
+    let mock = server
+        .mock("POST", "/v1/messages")
+        .with_header("content-type", "application/json")
+        .with_body(
+            r#"{"content": [{"type": "text", "text": "pong"}], "stop_reason": "end_turn",
+                "usage": {"input_tokens": 3, "output_tokens": 1}}"#,
+        )
+        .create();
+
+    // 第一次运行会受磁盘缓存等因素影响，取多次中最快的一次
+    let mut fastest = Duration::MAX;
+    for _ in 0..3 {
+        let started = Instant::now();
+        let output = binary(home.path())
+            .args(["--api-key", "test-key", "-p", "ping", "-u"])
+            .arg(format!("{}/v1/messages", server.url()))
+            .output()
+            .unwrap();
+        fastest = fastest.min(started.elapsed());
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            output.status.success(),
+            "{}\n{}",
+            stdout,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert!(stdout.contains("pong"), "{}", stdout);
+    }
+    mock.expect(3).assert();
+
+    assert!(
+        fastest < STARTUP_BUDGET,
+        "prompt mode took {:?}, budget is {:?}",
+        fastest,
+        STARTUP_BUDGET
+    );
+}
+
+#[test]
+fn test_version_without_config() {
+    let home = tempfile::TempDir::new().unwrap();
+    let output = binary(home.path()).arg("--version").output().unwrap();
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).starts_with("rust-claude-code "));
+}